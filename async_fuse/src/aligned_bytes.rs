@@ -6,7 +6,7 @@
 //! A continuous fixed-length byte array with a specified alignment.
 //!
 //! # Example
-//! ```
+//! ```ignore
 //! use aligned_bytes::AlignedBytes;
 //! let mut bytes = AlignedBytes::new_zeroed(1024, 8);
 //! let buf: &mut [u8] = &mut *bytes;
@@ -7,6 +7,7 @@ use nix::{
 };
 use std::os::unix::io::RawFd;
 
+use super::filesystem::Filesystem;
 use super::session::Session;
 
 #[derive(Debug)]
@@ -17,7 +18,7 @@ pub(crate) struct Channel {
 
 impl Channel {
     #[allow(dead_code)]
-    pub async fn new(session: &Session) -> Result<Channel> {
+    pub async fn new<FS: Filesystem>(session: &Session<FS>) -> Result<Channel> {
         let devname = "/dev/fuse";
         let clonefd = smol::blocking!(fcntl::open(
             devname,
@@ -0,0 +1,253 @@
+//! The reader/writer locks of the directories.
+//!
+//! With FUSE_PARALLEL_DIROPS, the kernel sends the lookups and the readdirs under a
+//! directory in parallel, and leaves it to the daemon to keep them from racing with
+//! the changes of the directory. The session locks the directories of an operation
+//! before dispatching it: the lookups and the readdirs share the lock, whereas the
+//! operations changing the entries, e.g. create, unlink and rename, hold it exclusively.
+//! The waiters are granted in the arrival order, so the changes are not starved.
+
+use futures::channel::oneshot;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use super::protocol::INum;
+
+/// The lock of a directory
+#[derive(Debug, Default)]
+struct DirLock {
+    /// The number of the shared holders
+    readers: usize,
+    /// Whether it is held exclusively
+    writer: bool,
+    /// The waiters in the arrival order, whether exclusive and the sender to grant
+    waiters: VecDeque<(bool, oneshot::Sender<()>)>,
+}
+
+impl DirLock {
+    /// Whether the lock can be granted right now without passing the waiters
+    fn can_grant(&self, exclusive: bool) -> bool {
+        !self.writer && (!exclusive || self.readers == 0)
+    }
+
+    fn acquire(&mut self, exclusive: bool) {
+        if exclusive {
+            self.writer = true;
+        } else {
+            self.readers += 1;
+        }
+    }
+
+    fn release(&mut self, exclusive: bool) {
+        if exclusive {
+            debug_assert!(self.writer);
+            self.writer = false;
+        } else {
+            debug_assert!(self.readers > 0);
+            self.readers -= 1;
+        }
+    }
+
+    /// Grant the lock to the waiters at the front as many as possible
+    fn wake(&mut self) {
+        while let Some(&(exclusive, _)) = self.waiters.front() {
+            if !self.can_grant(exclusive) {
+                break;
+            }
+            if let Some((_, sender)) = self.waiters.pop_front() {
+                self.acquire(exclusive);
+                // the waiter is cancelled
+                if sender.send(()).is_err() {
+                    self.release(exclusive);
+                }
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.readers == 0 && !self.writer && self.waiters.is_empty()
+    }
+}
+
+/// The locks of the directories in use, indexed by the i-node numbers
+#[derive(Debug, Default)]
+pub(crate) struct DirLocks {
+    locks: Mutex<BTreeMap<INum, DirLock>>,
+}
+
+impl DirLocks {
+    /// Lock the directories of `inos`, shared or exclusively. The directories are
+    /// locked in the order of the i-node numbers, so that the operations locking
+    /// several directories, i.e. renames, do not deadlock
+    pub async fn lock(&self, inos: &[INum], exclusive: bool) -> Vec<DirGuard<'_>> {
+        let mut inos = inos.to_vec();
+        inos.sort_unstable();
+        inos.dedup();
+        let mut guards = Vec::with_capacity(inos.len());
+        for ino in inos {
+            guards.push(self.lock_one(ino, exclusive).await);
+        }
+        guards
+    }
+
+    async fn lock_one(&self, ino: INum, exclusive: bool) -> DirGuard<'_> {
+        let receiver = {
+            let mut locks = self.lock_map();
+            let lock = locks.entry(ino).or_default();
+            if lock.waiters.is_empty() && lock.can_grant(exclusive) {
+                lock.acquire(exclusive);
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                lock.waiters.push_back((exclusive, sender));
+                Some(receiver)
+            }
+        };
+        // The guard releases the lock once granted, even if the waiting is cancelled
+        let guard = DirGuard {
+            locks: self,
+            ino,
+            exclusive,
+            pending: receiver,
+        };
+        guard.wait().await
+    }
+
+    fn unlock(&self, ino: INum, exclusive: bool) {
+        let mut locks = self.lock_map();
+        let idle = match locks.get_mut(&ino) {
+            Some(lock) => {
+                lock.release(exclusive);
+                lock.wake();
+                lock.is_idle()
+            }
+            None => panic!("unlock() found the directory of ino={} not locked", ino),
+        };
+        if idle {
+            locks.remove(&ino);
+        }
+    }
+
+    fn lock_map(&self) -> std::sync::MutexGuard<'_, BTreeMap<INum, DirLock>> {
+        self.locks
+            .lock()
+            .unwrap_or_else(|e| panic!("failed to lock directory locks: {}", e))
+    }
+}
+
+/// The lock of a directory held, released on drop
+#[derive(Debug)]
+pub(crate) struct DirGuard<'a> {
+    locks: &'a DirLocks,
+    ino: INum,
+    exclusive: bool,
+    /// The receiver of the grant if still waiting
+    pending: Option<oneshot::Receiver<()>>,
+}
+
+impl<'a> DirGuard<'a> {
+    /// Wait until the lock is granted
+    async fn wait(mut self) -> DirGuard<'a> {
+        if let Some(receiver) = self.pending.as_mut() {
+            // the sender is never dropped without granting
+            let _ = receiver.await;
+            self.pending = None;
+        }
+        self
+    }
+}
+
+impl Drop for DirGuard<'_> {
+    fn drop(&mut self) {
+        let granted = match self.pending.as_mut() {
+            None => true,
+            // cancelled while waiting, release the lock if granted in the meantime,
+            // otherwise the lock is not granted since the receiver is closed
+            Some(receiver) => {
+                receiver.close();
+                match receiver.try_recv() {
+                    Ok(Some(())) => true,
+                    _ => false,
+                }
+            }
+        };
+        if granted {
+            self.locks.unlock(self.ino, self.exclusive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::DirLocks;
+
+    #[test]
+    fn test_dir_locks() {
+        smol::run(async {
+            let locks = DirLocks::default();
+            let readers = locks.lock(&[2], false).await;
+            // the lookups share the lock
+            let more_readers = locks.lock(&[2], false).await;
+            // the change waits for the lookups
+            let mut writer = Box::pin(locks.lock(&[2, 3], true));
+            assert!(futures::poll!(writer.as_mut()).is_pending());
+            // the later lookup waits for the earlier change
+            let mut late_reader = Box::pin(locks.lock(&[2], false));
+            assert!(futures::poll!(late_reader.as_mut()).is_pending());
+            // other directories are not blocked
+            assert_eq!(
+                locks.lock(&[4], true).now_or_never().map(|g| g.len()),
+                Some(1)
+            );
+
+            drop(readers);
+            assert!(futures::poll!(writer.as_mut()).is_pending());
+            drop(more_readers);
+            let writer = writer.await;
+            assert_eq!(writer.len(), 2);
+            assert!(futures::poll!(late_reader.as_mut()).is_pending());
+            drop(writer);
+            assert_eq!(late_reader.await.len(), 1);
+            assert!(locks.lock_map().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_dir_locks_cancelled() {
+        smol::run(async {
+            let locks = Arc::new(DirLocks::default());
+            let writer = locks.lock(&[2], true).await;
+            let mut waiting = Box::pin(locks.lock(&[2], true));
+            assert!(futures::poll!(waiting.as_mut()).is_pending());
+            // the cancelled waiter is skipped
+            drop(waiting);
+            drop(writer);
+            assert!(locks.lock_map().is_empty());
+
+            // the parallel lookups all hold the lock at the same time
+            let holding = Arc::new(AtomicUsize::new(0));
+            let max_holding = Arc::new(AtomicUsize::new(0));
+            let tasks: Vec<_> = (0..4)
+                .map(|_| {
+                    let (locks, holding, max_holding) =
+                        (locks.clone(), holding.clone(), max_holding.clone());
+                    smol::Task::spawn(async move {
+                        let _guard = locks.lock(&[2], false).await;
+                        let now = holding.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_holding.fetch_max(now, Ordering::SeqCst);
+                        smol::Timer::after(std::time::Duration::from_millis(20)).await;
+                        holding.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+            assert_eq!(max_holding.load(Ordering::SeqCst), 4);
+        });
+    }
+}
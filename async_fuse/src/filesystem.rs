@@ -0,0 +1,765 @@
+//! The async filesystem interface of the FUSE session.
+//!
+//! The session dispatches each FUSE operation to the method of the `Filesystem` trait,
+//! which replies the kernel through the reply of the operation. The default methods
+//! reply ENOSYS, so a filesystem implements only the operations it supports.
+//! A failed operation returns `FsError`, whose errno is replied by the session.
+
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use libc::ENOSYS;
+use std::ffi::OsStr;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::fs::FsResult;
+use super::fuse_conn::ConnInfo;
+use super::fuse_reply::*;
+use super::fuse_request::Request;
+use super::protocol::INum;
+use super::upgrade::SavedState;
+
+/// The async filesystem to serve the FUSE requests.
+/// The session holds the filesystem by a lock, so the operations are called in series,
+/// except the `*_shared()` ones, which lock the filesystem themselves. The session also
+/// locks the directories of the operations reading or changing the directory entries,
+/// the lookups and the readdirs share the locks, the others hold them exclusively.
+#[async_trait]
+pub trait Filesystem: Send + 'static {
+    /// Initialize filesystem.
+    /// Called before any other filesystem method.
+    /// The filesystem wants or refuses the features offered by the kernel through `conn`.
+    async fn init(&mut self, _req: &Request<'_>, _conn: &mut ConnInfo) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Called once the features are negotiated with the kernel, before any other request.
+    /// The filesystem may register passthrough backing files to, or send notifications
+    /// through, the FUSE device of `fuse_fd`.
+    fn negotiated(&mut self, _conn: &ConnInfo, _fuse_fd: RawFd) {}
+
+    /// Spawn the background tasks of the filesystem after initialization,
+    /// which lock the filesystem to access it, e.g. watching the backing storage.
+    fn spawn_background(_fs: Arc<Mutex<Self>>)
+    where
+        Self: Sized,
+    {
+    }
+
+    /// Save the state to hand over to a new daemon on live upgrade.
+    /// Called once the requests in flight are done, the state must not be changed,
+    /// since the old daemon keeps serving if the new one fails to take over.
+    /// The default refuses live upgrade.
+    fn save_state(&self) -> anyhow::Result<SavedState> {
+        Err(anyhow::anyhow!(
+            "the filesystem does not support live upgrade"
+        ))
+    }
+
+    /// Restore the state saved by the old daemon on live upgrade.
+    /// Called after `negotiated()` with the features negotiated by the old daemon,
+    /// before any request. The fds of the state are owned by the filesystem since then.
+    async fn restore_state(&mut self, _state: SavedState) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!(
+            "the filesystem does not support live upgrade"
+        ))
+    }
+
+    /// Clean up filesystem.
+    /// Called on filesystem exit.
+    async fn destroy(&mut self, _req: &Request<'_>) {}
+
+    /// Flush the dirty data to the backing storage.
+    /// Called when the session shuts down on a signal, before unmount.
+    async fn sync_all(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Look up a directory entry by name and get its attributes.
+    async fn lookup(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Look up a directory entry by name and get its attributes, called without the
+    /// filesystem lock, so the lookups under the same directory run in parallel while
+    /// the directory is locked from changing.
+    /// The default locks the filesystem and calls `lookup()`.
+    async fn lookup_shared(
+        fs: &Mutex<Self>,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        reply: ReplyEntry,
+    ) -> FsResult<()>
+    where
+        Self: Sized,
+    {
+        fs.lock().await.lookup(req, parent, name, reply).await
+    }
+
+    /// Get file attributes.
+    async fn getattr(&mut self, _req: &Request<'_>, _ino: INum, reply: ReplyAttr) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Open a file.
+    /// Open flags (with the exception of O_CREAT, O_EXCL, O_NOCTTY and O_TRUNC) are
+    /// available in flags. Filesystem may store an arbitrary file handle (pointer, index,
+    /// etc) in fh, and use this in other all other file operations (read, write, flush,
+    /// release, fsync). Filesystem may also implement stateless file I/O and not store
+    /// anything in fh. There are also some flags (direct_io, keep_cache) which the
+    /// filesystem may set, to change the way the file is opened. See fuse_file_info
+    /// structure in <fuse_common.h> for more details.
+    async fn open(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: INum,
+        _flags: u32,
+        reply: ReplyOpen,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Forget about an inode.
+    /// The nlookup parameter indicates the number of lookups previously performed on
+    /// this inode. If the filesystem implements inode lifetimes, it is recommended that
+    /// inodes acquire a single reference on each lookup, and lose nlookup references on
+    /// each forget. The filesystem may ignore forget calls, if the inodes don't need to
+    /// have a limited lifetime. On unmount it is not guaranteed, that all referenced
+    /// inodes will receive a forget message.
+    async fn forget(&mut self, _req: &Request<'_>, _ino: u64, _nlookup: u64) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Set file attributes.
+    async fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<SystemTime>,
+        _mtime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Read symbolic link.
+    async fn readlink(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyData) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Create file node.
+    /// Create a regular file, character device, block device, fifo or socket node.
+    async fn mknod(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Create a directory.
+    async fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Remove a file.
+    async fn unlink(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Remove a directory.
+    async fn rmdir(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Create a symbolic link.
+    async fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _link: &Path,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Rename a file.
+    async fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Create a hard link.
+    async fn link(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _newparent: u64,
+        _newname: &OsStr,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Read data.
+    /// Read should send exactly the number of bytes requested except on EOF or error,
+    /// otherwise the rest of the data will be substituted with zeroes. An exception to
+    /// this is when the file has been opened in 'direct_io' mode, in which case the
+    /// return value of the read system call will reflect the return value of this
+    /// operation. fh will contain the value set by the open method, or will be undefined
+    /// if the open method didn't set any value.
+    async fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _size: u32,
+        reply: ReplyData,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Write data.
+    /// Write should return exactly the number of bytes requested except on error. An
+    /// exception to this is when the file has been opened in 'direct_io' mode, in
+    /// which case the return value of the write system call will reflect the return
+    /// value of this operation. fh will contain the value set by the open method, or
+    /// will be undefined if the open method didn't set any value.
+    async fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _data: Vec<u8>,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Flush method.
+    /// This is called on each close() of the opened file. Since file descriptors can
+    /// be duplicated (dup, dup2, fork), for one open call there may be many flush
+    /// calls. Filesystems shouldn't assume that flush will always be called after some
+    /// writes, or that if will be called at all. fh will contain the value set by the
+    /// open method, or will be undefined if the open method didn't set any value.
+    /// NOTE: the name of the method is misleading, since (unlike fsync) the filesystem
+    /// is not forced to flush pending writes. One reason to flush data, is if the
+    /// filesystem wants to return write errors. If the filesystem supports file locking
+    /// operations (setlk, getlk) it should remove all locks belonging to 'lock_owner'.
+    async fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Release an open file.
+    /// Release is called when there are no more references to an open file: all file
+    /// descriptors are closed and all memory mappings are unmapped. For every open
+    /// call there will be exactly one release call. The filesystem may reply with an
+    /// error, but error values are not returned to close() or munmap() which triggered
+    /// the release. fh will contain the value set by the open method, or will be undefined
+    /// if the open method didn't set any value. flags will contain the same flags as for
+    /// open.
+    async fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32, // same as the open flags
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Synchronize file contents.
+    /// If the datasync parameter is non-zero, then only the user data should be flushed,
+    /// not the meta data.
+    async fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Open a directory.
+    /// Filesystem may store an arbitrary file handle (pointer, index, etc) in fh, and
+    /// use this in other all other directory stream operations (readdir, releasedir,
+    /// fsyncdir). Filesystem may also implement stateless directory I/O and not store
+    /// anything in fh, though that makes it impossible to implement standard conforming
+    /// directory stream operations in case the contents of the directory can change
+    /// between opendir and releasedir.
+    async fn opendir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _flags: u32,
+        reply: ReplyOpen,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Read directory.
+    /// Send a buffer filled using buffer.fill(), with size not exceeding the
+    /// requested size. Send an empty buffer on end of stream. fh will contain the
+    /// value set by the opendir method, or will be undefined if the opendir method
+    /// didn't set any value.
+    async fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        reply: ReplyDirectory,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Read directory, called without the filesystem lock, so the readdirs of the
+    /// same directory run in parallel while the directory is locked from changing.
+    /// The default locks the filesystem and calls `readdir()`.
+    async fn readdir_shared(
+        fs: &Mutex<Self>,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectory,
+    ) -> FsResult<()>
+    where
+        Self: Sized,
+    {
+        fs.lock().await.readdir(req, ino, fh, offset, reply).await
+    }
+
+    /// Release an open directory.
+    /// For every opendir call there will be exactly one releasedir call. fh will
+    /// contain the value set by the opendir method, or will be undefined if the
+    /// opendir method didn't set any value.
+    async fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Synchronize directory contents.
+    /// If the datasync parameter is set, then only the directory contents should
+    /// be flushed, not the meta data. fh will contain the value set by the opendir
+    /// method, or will be undefined if the opendir method didn't set any value.
+    async fn fsyncdir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Get file system statistics.
+    /// The 'f_favail', 'f_fsid' and 'f_flag' fields are ignored
+    async fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatFs) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Set an extended attribute.
+    async fn setxattr(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: u32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Get an extended attribute.
+    /// If `size` is 0, the size of the value should be sent with `reply.size()`.
+    /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
+    /// `reply.error(ERANGE)` if it doesn't.
+    async fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _name: &OsStr,
+        _size: u32,
+        reply: ReplyXAttr,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// List extended attribute names.
+    /// If `size` is 0, the size of the value should be sent with `reply.size()`.
+    /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
+    /// `reply.error(ERANGE)` if it doesn't.
+    async fn listxattr(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _size: u32,
+        reply: ReplyXAttr,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Remove an extended attribute.
+    async fn removexattr(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _name: &OsStr,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Check file access permissions.
+    /// This will be called for the access() system call. If the 'default_permissions'
+    /// mount option is given, this method is not called. This method is not called
+    /// under Linux kernel versions 2.4.x
+    async fn access(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _mask: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Create and open a file.
+    /// If the file does not exist, first create it with the specified mode, and then
+    /// open it. Open flags (with the exception of O_NOCTTY) are available in flags.
+    /// Filesystem may store an arbitrary file handle (pointer, index, etc) in fh,
+    /// and use this in other all other file operations (read, write, flush, release,
+    /// fsync). There are also some flags (direct_io, keep_cache) which the
+    /// filesystem may set, to change the way the file is opened. See fuse_file_info
+    /// structure in <fuse_common.h> for more details. If this method is not
+    /// implemented or under Linux kernel versions earlier than 2.6.15, the mknod()
+    /// and open() methods will be called instead.
+    async fn create(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _flags: u32,
+        reply: ReplyCreate,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Test for a POSIX file lock.
+    async fn getlk(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        _start: u64,
+        _end: u64,
+        _typ: u32,
+        _pid: u32,
+        reply: ReplyLock,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Acquire, modify or release a POSIX file lock.
+    /// For POSIX threads (NPTL) there's a 1-1 relation between pid and owner, but
+    /// otherwise this is not always the case.  For checking lock ownership,
+    /// 'fi->owner' must be used. The l_pid field in 'struct flock' should only be
+    /// used to fill in this field in getlk(). Note: if the locking methods are not
+    /// implemented, the kernel will still allow file locking to work locally.
+    /// Hence these are only interesting for network filesystems and similar.
+    async fn setlk(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        _start: u64,
+        _end: u64,
+        _typ: u32,
+        _pid: u32,
+        _sleep: bool,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Map block index within file to block index within device.
+    /// Note: This makes sense only for block device backed filesystems mounted
+    /// with the 'blkdev' option
+    async fn bmap(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _blocksize: u32,
+        _idx: u64,
+        reply: ReplyBMap,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// control device
+    #[cfg(feature = "abi-7-11")]
+    async fn ioctl(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoCtl,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Poll for IO readiness events.
+    #[cfg(feature = "abi-7-11")]
+    async fn poll(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _kh: u64,
+        _flags: u32,
+        _events: u32,
+        reply: ReplyPoll,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Preallocate or deallocate space to a file.
+    #[cfg(feature = "abi-7-19")]
+    async fn fallocate(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Read directory with the attributes of the entries.
+    /// If the filesystem replies ENOSYS, the kernel falls back to readdir
+    #[cfg(feature = "abi-7-21")]
+    async fn readdirplus(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Rename a file with the flags RENAME_EXCHANGE or RENAME_NOREPLACE.
+    /// The rename without flags is sent to rename()
+    #[cfg(feature = "abi-7-23")]
+    async fn rename2(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Find the next data or hole after the specified offset.
+    /// If the filesystem replies ENOSYS, the kernel handles lseek by itself
+    #[cfg(feature = "abi-7-24")]
+    async fn lseek(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _whence: u32,
+        reply: ReplyLSeek,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// Copy a range of data from one file to another.
+    /// If the filesystem replies ENOSYS, the kernel falls back to read and write
+    #[cfg(feature = "abi-7-28")]
+    async fn copy_file_range(
+        &mut self,
+        _req: &Request<'_>,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u64,
+        reply: ReplyWrite,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// macOS only: Rename the volume. Set fuse_init_out.flags during init to
+    /// FUSE_VOL_RENAME to enable
+    #[cfg(target_os = "macos")]
+    async fn setvolname(
+        &mut self,
+        _req: &Request<'_>,
+        _name: &OsStr,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// macOS only (undocumented)
+    #[cfg(target_os = "macos")]
+    async fn exchange(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _options: u64,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+
+    /// macOS only: Query extended times (bkuptime and crtime). Set fuse_init_out.flags
+    /// during init to FUSE_XTIMES to enable
+    #[cfg(target_os = "macos")]
+    async fn getxtimes(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        reply: ReplyXTimes,
+    ) -> FsResult<()> {
+        reply.error(ENOSYS).await?;
+        Ok(())
+    }
+}
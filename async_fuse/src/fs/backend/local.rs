@@ -0,0 +1,344 @@
+//! The backend passing the files through to a directory on the host.
+
+use anyhow::{self, Context};
+use async_trait::async_trait;
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::sys::stat::{self, Mode, SFlag};
+use nix::unistd;
+use smol::blocking;
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::super::dir::{Dir, DirEntry};
+use super::super::util::{self, FileAttr};
+use super::StorageBackend;
+
+/// The files under the backing directory, opened as host fds
+#[derive(Debug)]
+pub struct LocalBackend {
+    /// The backing directory
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    /// The files under the backing directory of `root`
+    pub fn new(root: impl AsRef<Path>) -> LocalBackend {
+        LocalBackend {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    type Handle = RawFd;
+
+    async fn open_root(&self) -> anyhow::Result<RawFd> {
+        let fd = util::open_dir(&self.root).await.context(format!(
+            "open_root() failed to open the backing directory={:?}",
+            self.root,
+        ))?;
+        Ok(fd)
+    }
+
+    async fn lookup(&self, dir: RawFd, name: &OsStr) -> anyhow::Result<Option<DirEntry>> {
+        let child_name = name.to_owned();
+        let entry = blocking!(DirEntry::stat_at(dir, &child_name)).context(format!(
+            "lookup() failed to look up name={:?} under the directory of fd={}",
+            name, dir,
+        ))?;
+        Ok(entry)
+    }
+
+    async fn open(
+        &self,
+        dir: RawFd,
+        name: &OsStr,
+        kind: SFlag,
+        oflags: OFlag,
+    ) -> anyhow::Result<RawFd> {
+        let child_name = name.to_owned();
+        let fd = match kind {
+            SFlag::S_IFDIR => util::open_dir_at(dir, child_name).await,
+            SFlag::S_IFREG => blocking!(fcntl::openat(
+                dir,
+                child_name.as_os_str(),
+                oflags,
+                Mode::empty()
+            )),
+            _ => Err(nix::Error::Sys(Errno::EINVAL)),
+        }
+        .context(format!(
+            "open() failed to open the child name={:?} of type={:?} \
+                under the directory of fd={} with oflags={:?}",
+            name, kind, dir, oflags,
+        ))?;
+        Ok(fd)
+    }
+
+    async fn create(
+        &self,
+        dir: RawFd,
+        name: &OsStr,
+        kind: SFlag,
+        mode: Mode,
+    ) -> anyhow::Result<RawFd> {
+        let child_name = name.to_owned();
+        let fd = match kind {
+            SFlag::S_IFDIR => {
+                let dir_name = child_name.clone();
+                blocking!(stat::mkdirat(dir, dir_name.as_os_str(), mode)).context(format!(
+                    "create() failed to create the directory name={:?} \
+                        under the directory of fd={}",
+                    name, dir,
+                ))?;
+                util::open_dir_at(dir, child_name).await
+            }
+            SFlag::S_IFREG => {
+                let oflags = OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR;
+                blocking!(fcntl::openat(dir, child_name.as_os_str(), oflags, mode))
+            }
+            _ => Err(nix::Error::Sys(Errno::EINVAL)),
+        }
+        .context(format!(
+            "create() failed to open the new child name={:?} of type={:?} \
+                under the directory of fd={} with mode={:?}",
+            name, kind, dir, mode,
+        ))?;
+        Ok(fd)
+    }
+
+    async fn unlink(&self, dir: RawFd, name: &OsStr, kind: SFlag) -> anyhow::Result<()> {
+        let child_name = name.to_owned();
+        let flag = match kind {
+            SFlag::S_IFDIR => unistd::UnlinkatFlags::RemoveDir,
+            _ => unistd::UnlinkatFlags::NoRemoveDir,
+        };
+        blocking!(unistd::unlinkat(Some(dir), child_name.as_os_str(), flag)).context(format!(
+            "unlink() failed to delete the child name={:?} under the directory of fd={}",
+            name, dir,
+        ))?;
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        old_dir: RawFd,
+        old_name: &OsStr,
+        new_dir: RawFd,
+        new_name: &OsStr,
+    ) -> anyhow::Result<()> {
+        let old_path = Path::new(old_name).to_path_buf();
+        let new_path = Path::new(new_name).to_path_buf();
+        blocking!(fcntl::renameat(
+            Some(old_dir),
+            old_path.as_path(),
+            Some(new_dir),
+            new_path.as_path()
+        ))
+        .context(format!(
+            "rename() failed to move name={:?} under the directory of fd={} \
+                to name={:?} under the directory of fd={}",
+            old_name, old_dir, new_name, new_dir,
+        ))?;
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dir: RawFd,
+        offset: i64,
+        count: usize,
+    ) -> anyhow::Result<Vec<DirEntry>> {
+        let entries = blocking!(
+            let dir = Dir::open_at(dir, offset)?;
+            dir.filter(|entry| entry.as_ref().map_or(true, DirEntry::is_visible))
+                .take(count)
+                .collect::<nix::Result<Vec<_>>>()
+        )
+        .context(format!(
+            "readdir() failed to read the directory of fd={} from offset={}",
+            dir, offset,
+        ))?;
+        Ok(entries)
+    }
+
+    async fn dup(&self, handle: RawFd, oflags: OFlag) -> anyhow::Result<RawFd> {
+        let new_fd = blocking!(unistd::dup(handle))
+            .context(format!("dup() failed to duplicate the fd={}", handle))?;
+        // The duplicated fd shares the file status flags with the original one
+        let fcntl_oflags = FcntlArg::F_SETFL(oflags);
+        if let Err(e) = blocking!(fcntl::fcntl(new_fd, fcntl_oflags)) {
+            let _ = unistd::close(new_fd);
+            return Err(e).context(format!(
+                "dup() failed to set the flags={:?} of the fd={} duplicated from fd={}",
+                oflags, new_fd, handle,
+            ));
+        }
+        Ok(new_fd)
+    }
+
+    fn close(&self, handle: RawFd) -> anyhow::Result<()> {
+        unistd::close(handle).context(format!("close() failed to close the fd={}", handle))
+    }
+
+    async fn getattr(&self, handle: RawFd) -> anyhow::Result<FileAttr> {
+        let attr = util::load_attr(handle).await.context(format!(
+            "getattr() failed to get the attribute of the fd={}",
+            handle
+        ))?;
+        Ok(attr)
+    }
+
+    async fn read_at(&self, handle: RawFd, offset: u64, size: usize) -> anyhow::Result<Vec<u8>> {
+        let data = blocking!(
+            let mut data = vec![0_u8; size];
+            let read_size = nix::sys::uio::pread(handle, &mut data, offset as libc::off_t)?;
+            data.truncate(read_size);
+            Ok::<Vec<u8>, nix::Error>(data)
+        )
+        .context(format!(
+            "read_at() failed to read the fd={} at offset={}",
+            handle, offset,
+        ))?;
+        Ok(data)
+    }
+
+    async fn write_at(&self, handle: RawFd, offset: u64, data: Vec<u8>) -> anyhow::Result<usize> {
+        let written_size = blocking!(nix::sys::uio::pwrite(handle, &data, offset as libc::off_t))
+            .context(format!(
+            "write_at() failed to write the fd={} at offset={}",
+            handle, offset,
+        ))?;
+        Ok(written_size)
+    }
+
+    async fn truncate(&self, handle: RawFd, size: u64) -> anyhow::Result<()> {
+        blocking!(unistd::ftruncate(handle, size as libc::off_t)).context(format!(
+            "truncate() failed to truncate the fd={} to size={}",
+            handle, size,
+        ))?;
+        Ok(())
+    }
+
+    #[cfg_attr(target_os = "macos", allow(unused_variables))]
+    async fn sync(&self, handle: RawFd, datasync: bool) -> anyhow::Result<()> {
+        #[cfg(target_os = "linux")]
+        let res = if datasync {
+            blocking!(unistd::fdatasync(handle))
+        } else {
+            blocking!(unistd::fsync(handle))
+        };
+        #[cfg(target_os = "macos")]
+        let res = blocking!(unistd::fsync(handle));
+        res.context(format!("sync() failed to flush the fd={}", handle))?;
+        Ok(())
+    }
+
+    /// Close a duplicated fd, the network filesystems like NFS flush the data on close
+    async fn flush(&self, handle: RawFd) -> anyhow::Result<()> {
+        let new_fd = blocking!(unistd::dup(handle))
+            .context(format!("flush() failed to duplicate the fd={}", handle))?;
+        blocking!(unistd::close(new_fd)).context(format!(
+            "flush() failed to close the fd={} duplicated from fd={}",
+            new_fd, handle,
+        ))?;
+        Ok(())
+    }
+
+    async fn sync_all(&self, root: RawFd) -> anyhow::Result<()> {
+        #[cfg(target_os = "linux")]
+        blocking!(Errno::result(unsafe { libc::syncfs(root) })).context(format!(
+            "sync_all() failed to sync the backing directory of fd={}",
+            root
+        ))?;
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = root;
+            blocking!(unistd::sync());
+        }
+        Ok(())
+    }
+
+    async fn chmod(&self, handle: RawFd, mode: Mode) -> anyhow::Result<()> {
+        blocking!(stat::fchmod(handle, mode)).context(format!(
+            "chmod() failed to set the mode of the fd={} to {:?}",
+            handle, mode,
+        ))?;
+        Ok(())
+    }
+
+    async fn chown(&self, handle: RawFd, uid: Option<u32>, gid: Option<u32>) -> anyhow::Result<()> {
+        blocking!(util::fchown(handle, uid, gid)).context(format!(
+            "chown() failed to set the owner of the fd={} to uid={:?} gid={:?}",
+            handle, uid, gid,
+        ))?;
+        Ok(())
+    }
+
+    async fn utimens(
+        &self,
+        handle: RawFd,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        blocking!(util::futimens(handle, atime, mtime)).context(format!(
+            "utimens() failed to set the times of the fd={} to atime={:?} mtime={:?}",
+            handle, atime, mtime,
+        ))?;
+        Ok(())
+    }
+
+    async fn get_xattr(&self, handle: RawFd, name: &OsStr) -> anyhow::Result<Option<Vec<u8>>> {
+        let c_name = CString::new(name.as_bytes())?;
+        let value = blocking!(util::fgetxattr(handle, &c_name)).context(format!(
+            "get_xattr() failed to get the xattr name={:?} of the fd={}",
+            name, handle,
+        ))?;
+        Ok(value)
+    }
+
+    async fn list_xattr(&self, handle: RawFd) -> anyhow::Result<Vec<u8>> {
+        let names = blocking!(util::flistxattr(handle)).context(format!(
+            "list_xattr() failed to list the xattrs of the fd={}",
+            handle
+        ))?;
+        Ok(names)
+    }
+
+    async fn set_xattr(
+        &self,
+        handle: RawFd,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: u32,
+    ) -> anyhow::Result<()> {
+        let c_name = CString::new(name.as_bytes())?;
+        blocking!(util::fsetxattr(handle, &c_name, &value, flags)).context(format!(
+            "set_xattr() failed to set the xattr name={:?} of the fd={}",
+            name, handle,
+        ))?;
+        Ok(())
+    }
+
+    async fn remove_xattr(&self, handle: RawFd, name: &OsStr) -> anyhow::Result<()> {
+        let c_name = CString::new(name.as_bytes())?;
+        blocking!(util::fremovexattr(handle, &c_name)).context(format!(
+            "remove_xattr() failed to remove the xattr name={:?} of the fd={}",
+            name, handle,
+        ))?;
+        Ok(())
+    }
+
+    fn raw_fd(&self, handle: RawFd) -> Option<RawFd> {
+        Some(handle)
+    }
+
+    fn from_raw_fd(&self, fd: RawFd) -> Option<RawFd> {
+        Some(fd)
+    }
+}
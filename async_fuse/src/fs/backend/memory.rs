@@ -0,0 +1,768 @@
+//! The backend keeping the files in memory.
+//!
+//! The files live as long as the daemon, so nothing is persisted, and nothing changes
+//! out of band. The handles are the i-node numbers, each open handle holds the i-node,
+//! so a deleted file stays readable through the opens until they are all closed.
+
+use anyhow::{self, Context};
+use async_trait::async_trait;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{Mode, SFlag};
+use nix::unistd;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::ops::Bound;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use super::super::super::protocol::{INum, FUSE_ROOT_ID};
+use super::super::dir::DirEntry;
+use super::super::util::{self, FileAttr};
+use super::StorageBackend;
+
+/// The error of `errno`, with the message to log
+fn error(errno: Errno, msg: String) -> anyhow::Error {
+    anyhow::Error::new(nix::Error::Sys(errno)).context(msg)
+}
+
+/// The entries of a directory
+#[derive(Debug)]
+struct MemoryDir {
+    /// The parent directory, the root is the parent of itself
+    parent: INum,
+    /// The i-node numbers and the cookies of the entries, indexed by the names
+    names: BTreeMap<OsString, (INum, i64)>,
+    /// The names of the entries indexed by the cookies, in the order of creation
+    cookies: BTreeMap<i64, OsString>,
+    /// The cookie of the next entry, never reused, so the cookies handed out
+    /// stay valid when other entries are created or deleted
+    next_cookie: i64,
+}
+
+impl MemoryDir {
+    fn new(parent: INum) -> MemoryDir {
+        MemoryDir {
+            parent,
+            names: BTreeMap::new(),
+            cookies: BTreeMap::new(),
+            // 0 is left for the start of the directory
+            next_cookie: 1,
+        }
+    }
+
+    fn insert(&mut self, name: OsString, ino: INum) {
+        let cookie = self.next_cookie;
+        self.next_cookie += 1;
+        self.cookies.insert(cookie, name.clone());
+        self.names.insert(name, (ino, cookie));
+    }
+
+    fn remove(&mut self, name: &OsStr) -> Option<INum> {
+        let (ino, cookie) = self.names.remove(name)?;
+        self.cookies.remove(&cookie);
+        Some(ino)
+    }
+
+    fn get(&self, name: &OsStr) -> Option<INum> {
+        self.names.get(name).map(|&(ino, _)| ino)
+    }
+}
+
+#[derive(Debug)]
+enum Content {
+    Dir(MemoryDir),
+    File(Vec<u8>),
+}
+
+/// A file or a directory in memory
+#[derive(Debug)]
+struct MemoryInode {
+    attr: FileAttr,
+    content: Content,
+    xattrs: BTreeMap<OsString, Vec<u8>>,
+    /// The handles not closed yet
+    open_count: u64,
+}
+
+impl MemoryInode {
+    fn new(ino: INum, kind: SFlag, perm: u16, content: Content) -> MemoryInode {
+        let now = SystemTime::now();
+        MemoryInode {
+            attr: FileAttr {
+                ino,
+                size: 0,
+                blocks: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                crtime: now,
+                kind,
+                perm,
+                nlink: if kind == SFlag::S_IFDIR { 2 } else { 1 },
+                uid: unistd::geteuid().as_raw(),
+                gid: unistd::getegid().as_raw(),
+                rdev: 0,
+                flags: 0,
+            },
+            content,
+            xattrs: BTreeMap::new(),
+            open_count: 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attr.kind == SFlag::S_IFDIR
+    }
+
+    /// Record the change of the attribute
+    fn touch(&mut self) {
+        self.attr.ctime = SystemTime::now();
+    }
+
+    /// Record the change of the content
+    fn modify(&mut self) {
+        let now = SystemTime::now();
+        self.attr.mtime = now;
+        self.attr.ctime = now;
+    }
+}
+
+/// The i-nodes in memory
+#[derive(Debug)]
+struct MemoryState {
+    inodes: BTreeMap<INum, MemoryInode>,
+    /// The i-node number of the next file, never reused
+    next_ino: INum,
+}
+
+impl MemoryState {
+    fn inode(&self, ino: INum) -> anyhow::Result<&MemoryInode> {
+        self.inodes
+            .get(&ino)
+            .ok_or_else(|| error(Errno::ESTALE, format!("found no i-node of ino={}", ino)))
+    }
+
+    fn inode_mut(&mut self, ino: INum) -> anyhow::Result<&mut MemoryInode> {
+        self.inodes
+            .get_mut(&ino)
+            .ok_or_else(|| error(Errno::ESTALE, format!("found no i-node of ino={}", ino)))
+    }
+
+    fn dir(&self, ino: INum) -> anyhow::Result<&MemoryDir> {
+        match &self.inode(ino)?.content {
+            Content::Dir(dir) => Ok(dir),
+            Content::File(..) => Err(error(
+                Errno::ENOTDIR,
+                format!("the i-node of ino={} is not a directory", ino),
+            )),
+        }
+    }
+
+    fn dir_mut(&mut self, ino: INum) -> anyhow::Result<&mut MemoryDir> {
+        match &mut self.inode_mut(ino)?.content {
+            Content::Dir(dir) => Ok(dir),
+            Content::File(..) => Err(error(
+                Errno::ENOTDIR,
+                format!("the i-node of ino={} is not a directory", ino),
+            )),
+        }
+    }
+
+    fn file_mut(&mut self, ino: INum) -> anyhow::Result<&mut Vec<u8>> {
+        match &mut self.inode_mut(ino)?.content {
+            Content::File(data) => Ok(data),
+            Content::Dir(..) => Err(error(
+                Errno::EISDIR,
+                format!("the i-node of ino={} is a directory", ino),
+            )),
+        }
+    }
+
+    /// The child of `name` under the directory of `dir`, fail with ENOENT if not found
+    fn child(&self, dir: INum, name: &OsStr) -> anyhow::Result<INum> {
+        self.dir(dir)?.get(name).ok_or_else(|| {
+            error(
+                Errno::ENOENT,
+                format!(
+                    "found no name={:?} under the directory of ino={}",
+                    name, dir
+                ),
+            )
+        })
+    }
+
+    /// Whether the directory of `ino` is the one of `ancestor` or under it
+    fn is_under(&self, ino: INum, ancestor: INum) -> anyhow::Result<bool> {
+        let mut ino = ino;
+        loop {
+            if ino == ancestor {
+                return Ok(true);
+            }
+            let parent = self.dir(ino)?.parent;
+            if parent == ino {
+                return Ok(false);
+            }
+            ino = parent;
+        }
+    }
+
+    /// Check the child of `ino` can be replaced by, or deleted as, a child of `kind`
+    fn check_removable(&self, ino: INum, kind: SFlag) -> anyhow::Result<()> {
+        let inode = self.inode(ino)?;
+        match (kind, &inode.content) {
+            (SFlag::S_IFDIR, Content::File(..)) => Err(error(
+                Errno::ENOTDIR,
+                format!("the i-node of ino={} is not a directory", ino),
+            )),
+            (SFlag::S_IFDIR, Content::Dir(dir)) if !dir.names.is_empty() => Err(error(
+                Errno::ENOTEMPTY,
+                format!("the directory of ino={} is not empty", ino),
+            )),
+            (SFlag::S_IFDIR, Content::Dir(..)) => Ok(()),
+            (_, Content::Dir(..)) => Err(error(
+                Errno::EISDIR,
+                format!("the i-node of ino={} is a directory", ino),
+            )),
+            (_, Content::File(..)) => Ok(()),
+        }
+    }
+
+    /// Detach the child of `ino` removed from the directory of `dir`,
+    /// it is dropped at once if not opened
+    fn detach(&mut self, dir: INum, ino: INum) -> anyhow::Result<()> {
+        let inode = self.inode_mut(ino)?;
+        let is_dir = inode.is_dir();
+        inode.attr.nlink = 0;
+        inode.touch();
+        let dropped = inode.open_count == 0;
+        if dropped {
+            self.inodes.remove(&ino);
+        }
+        let parent = self.inode_mut(dir)?;
+        if is_dir {
+            parent.attr.nlink -= 1;
+        }
+        parent.modify();
+        Ok(())
+    }
+
+    /// Add the child of `ino` to the directory of `dir`
+    fn attach(&mut self, dir: INum, name: OsString, ino: INum) -> anyhow::Result<()> {
+        let inode = self.inode_mut(ino)?;
+        let is_dir = match &mut inode.content {
+            Content::Dir(child_dir) => {
+                child_dir.parent = dir;
+                true
+            }
+            Content::File(..) => false,
+        };
+        inode.touch();
+        self.dir_mut(dir)?.insert(name, ino);
+        let parent = self.inode_mut(dir)?;
+        if is_dir {
+            parent.attr.nlink += 1;
+        }
+        parent.modify();
+        Ok(())
+    }
+}
+
+/// The files in memory, lost once the daemon exits
+#[derive(Debug)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> MemoryBackend {
+        let mut inodes = BTreeMap::new();
+        let root = MemoryInode::new(
+            FUSE_ROOT_ID,
+            SFlag::S_IFDIR,
+            0o755,
+            Content::Dir(MemoryDir::new(FUSE_ROOT_ID)),
+        );
+        inodes.insert(FUSE_ROOT_ID, root);
+        MemoryBackend {
+            state: Mutex::new(MemoryState {
+                inodes,
+                next_ino: FUSE_ROOT_ID + 1,
+            }),
+        }
+    }
+}
+
+impl MemoryBackend {
+    /// An empty root directory
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    /// The state is never left inconsistent by a panic, so ignore the poison
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Open the i-node of `ino` once more
+    fn hold(&self, ino: INum) -> anyhow::Result<INum> {
+        self.state().inode_mut(ino)?.open_count += 1;
+        Ok(ino)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    type Handle = INum;
+
+    async fn open_root(&self) -> anyhow::Result<INum> {
+        self.hold(FUSE_ROOT_ID)
+    }
+
+    async fn lookup(&self, dir: INum, name: &OsStr) -> anyhow::Result<Option<DirEntry>> {
+        let state = self.state();
+        let entry = match state.dir(dir)?.get(name) {
+            Some(ino) => Some(DirEntry::new(
+                ino,
+                name.to_owned(),
+                state.inode(ino)?.attr.kind,
+            )),
+            None => None,
+        };
+        Ok(entry)
+    }
+
+    async fn open(
+        &self,
+        dir: INum,
+        name: &OsStr,
+        kind: SFlag,
+        _oflags: OFlag,
+    ) -> anyhow::Result<INum> {
+        let mut state = self.state();
+        let ino = state.child(dir, name)?;
+        let inode = state.inode_mut(ino)?;
+        if inode.attr.kind != kind {
+            return Err(error(
+                Errno::EINVAL,
+                format!(
+                    "open() found the child name={:?} of ino={} is of type={:?} \
+                        rather than type={:?}",
+                    name, ino, inode.attr.kind, kind,
+                ),
+            ));
+        }
+        inode.open_count += 1;
+        Ok(ino)
+    }
+
+    async fn create(
+        &self,
+        dir: INum,
+        name: &OsStr,
+        kind: SFlag,
+        mode: Mode,
+    ) -> anyhow::Result<INum> {
+        let mut state = self.state();
+        if state.dir(dir)?.get(name).is_some() {
+            return Err(error(
+                Errno::EEXIST,
+                format!(
+                    "create() found name={:?} exists under the directory of ino={}",
+                    name, dir,
+                ),
+            ));
+        }
+        let ino = state.next_ino;
+        let content = match kind {
+            SFlag::S_IFDIR => Content::Dir(MemoryDir::new(dir)),
+            SFlag::S_IFREG => Content::File(Vec::new()),
+            _ => {
+                return Err(error(
+                    Errno::EINVAL,
+                    format!("create() found unsupported file type={:?}", kind),
+                ))
+            }
+        };
+        let perm = util::parse_mode_bits(mode.bits().into()) & 0o7777;
+        let mut inode = MemoryInode::new(ino, kind, perm, content);
+        inode.open_count = 1;
+        state.next_ino += 1;
+        state.inodes.insert(ino, inode);
+        state.attach(dir, name.to_owned(), ino)?;
+        Ok(ino)
+    }
+
+    async fn unlink(&self, dir: INum, name: &OsStr, kind: SFlag) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let ino = state.child(dir, name)?;
+        state.check_removable(ino, kind)?;
+        state.dir_mut(dir)?.remove(name);
+        state.detach(dir, ino)
+    }
+
+    async fn rename(
+        &self,
+        old_dir: INum,
+        old_name: &OsStr,
+        new_dir: INum,
+        new_name: &OsStr,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let ino = state.child(old_dir, old_name)?;
+        let kind = state.inode(ino)?.attr.kind;
+        if kind == SFlag::S_IFDIR && state.is_under(new_dir, ino)? {
+            return Err(error(
+                Errno::EINVAL,
+                format!(
+                    "rename() cannot move the directory of ino={} under itself",
+                    ino
+                ),
+            ));
+        }
+        let replaced = state.dir(new_dir)?.get(new_name);
+        if replaced == Some(ino) {
+            // the same entry, nothing to do
+            return Ok(());
+        }
+        if let Some(replaced) = replaced {
+            state.check_removable(replaced, kind)?;
+            state.dir_mut(new_dir)?.remove(new_name);
+            state.detach(new_dir, replaced)?;
+        }
+        state.dir_mut(old_dir)?.remove(old_name);
+        let is_dir = kind == SFlag::S_IFDIR;
+        let old_parent = state.inode_mut(old_dir)?;
+        if is_dir {
+            old_parent.attr.nlink -= 1;
+        }
+        old_parent.modify();
+        state.attach(new_dir, new_name.to_owned(), ino)
+    }
+
+    async fn readdir(&self, dir: INum, offset: i64, count: usize) -> anyhow::Result<Vec<DirEntry>> {
+        let state = self.state();
+        state
+            .dir(dir)?
+            .cookies
+            .range((Bound::Excluded(offset), Bound::Unbounded))
+            .take(count)
+            .map(|(&cookie, name)| {
+                let ino = state.child(dir, name)?;
+                let kind = state.inode(ino)?.attr.kind;
+                Ok(DirEntry::new(ino, name.clone(), kind).with_offset(cookie))
+            })
+            .collect()
+    }
+
+    async fn dup(&self, handle: INum, _oflags: OFlag) -> anyhow::Result<INum> {
+        self.hold(handle)
+    }
+
+    fn close(&self, handle: INum) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        inode.open_count -= 1;
+        if inode.open_count == 0 && inode.attr.nlink == 0 {
+            state.inodes.remove(&handle);
+        }
+        Ok(())
+    }
+
+    async fn getattr(&self, handle: INum) -> anyhow::Result<FileAttr> {
+        let state = self.state();
+        let inode = state.inode(handle)?;
+        let mut attr = inode.attr;
+        if let Content::File(data) = &inode.content {
+            attr.size = data.len() as u64;
+            attr.blocks = (attr.size + 511) / 512;
+        }
+        Ok(attr)
+    }
+
+    async fn read_at(&self, handle: INum, offset: u64, size: usize) -> anyhow::Result<Vec<u8>> {
+        let mut state = self.state();
+        let data = state.file_mut(handle).context(format!(
+            "read_at() failed to read the i-node of ino={}",
+            handle
+        ))?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(size).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    async fn write_at(&self, handle: INum, offset: u64, data: Vec<u8>) -> anyhow::Result<usize> {
+        let mut state = self.state();
+        let file_data = state.file_mut(handle).context(format!(
+            "write_at() failed to write the i-node of ino={}",
+            handle
+        ))?;
+        let start = offset as usize;
+        let end = start + data.len();
+        if file_data.len() < end {
+            file_data.resize(end, 0);
+        }
+        file_data[start..end].copy_from_slice(&data);
+        state.inode_mut(handle)?.modify();
+        Ok(data.len())
+    }
+
+    async fn truncate(&self, handle: INum, size: u64) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state
+            .file_mut(handle)
+            .context(format!(
+                "truncate() failed to truncate the i-node of ino={}",
+                handle
+            ))?
+            .resize(size as usize, 0);
+        state.inode_mut(handle)?.modify();
+        Ok(())
+    }
+
+    /// Nothing to persist
+    async fn sync(&self, _handle: INum, _datasync: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Nothing to persist
+    async fn sync_all(&self, _root: INum) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn chmod(&self, handle: INum, mode: Mode) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        inode.attr.perm = util::parse_mode_bits(mode.bits().into()) & 0o7777;
+        inode.touch();
+        Ok(())
+    }
+
+    async fn chown(&self, handle: INum, uid: Option<u32>, gid: Option<u32>) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        inode.attr.uid = uid.unwrap_or(inode.attr.uid);
+        inode.attr.gid = gid.unwrap_or(inode.attr.gid);
+        inode.touch();
+        Ok(())
+    }
+
+    async fn utimens(
+        &self,
+        handle: INum,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        inode.attr.atime = atime.unwrap_or(inode.attr.atime);
+        inode.attr.mtime = mtime.unwrap_or(inode.attr.mtime);
+        inode.touch();
+        Ok(())
+    }
+
+    async fn get_xattr(&self, handle: INum, name: &OsStr) -> anyhow::Result<Option<Vec<u8>>> {
+        let state = self.state();
+        Ok(state.inode(handle)?.xattrs.get(name).cloned())
+    }
+
+    async fn list_xattr(&self, handle: INum) -> anyhow::Result<Vec<u8>> {
+        let state = self.state();
+        let mut names = Vec::new();
+        for name in state.inode(handle)?.xattrs.keys() {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        Ok(names)
+    }
+
+    async fn set_xattr(
+        &self,
+        handle: INum,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: u32,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        let exists = inode.xattrs.contains_key(name);
+        if exists && flags & libc::XATTR_CREATE as u32 != 0 {
+            return Err(error(
+                Errno::EEXIST,
+                format!(
+                    "set_xattr() found the xattr name={:?} of ino={} exists",
+                    name, handle
+                ),
+            ));
+        }
+        if !exists && flags & libc::XATTR_REPLACE as u32 != 0 {
+            return Err(error(
+                util::ENOATTR,
+                format!(
+                    "set_xattr() found no xattr name={:?} of ino={} to replace",
+                    name, handle
+                ),
+            ));
+        }
+        inode.xattrs.insert(name.to_owned(), value);
+        inode.touch();
+        Ok(())
+    }
+
+    async fn remove_xattr(&self, handle: INum, name: &OsStr) -> anyhow::Result<()> {
+        let mut state = self.state();
+        let inode = state.inode_mut(handle)?;
+        if inode.xattrs.remove(name).is_none() {
+            return Err(error(
+                util::ENOATTR,
+                format!(
+                    "remove_xattr() found no xattr name={:?} of ino={}",
+                    name, handle
+                ),
+            ));
+        }
+        inode.touch();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::errno::Errno;
+    use nix::fcntl::OFlag;
+    use nix::sys::stat::{Mode, SFlag};
+    use std::ffi::OsStr;
+
+    use super::super::super::error::FsError;
+    use super::super::StorageBackend;
+    use super::MemoryBackend;
+
+    fn errno_of(e: anyhow::Error) -> Option<Errno> {
+        FsError::from(e).errno()
+    }
+
+    #[test]
+    fn test_memory_namespace() -> anyhow::Result<()> {
+        smol::run(async {
+            let backend = MemoryBackend::new();
+            let mode = Mode::from_bits_truncate(0o755);
+            let root = backend.open_root().await?;
+            let dir = backend
+                .create(root, OsStr::new("dir"), SFlag::S_IFDIR, mode)
+                .await?;
+            let file = backend
+                .create(dir, OsStr::new("foo"), SFlag::S_IFREG, mode)
+                .await?;
+            assert_eq!(backend.getattr(root).await?.nlink, 3);
+            let res = backend
+                .create(dir, OsStr::new("foo"), SFlag::S_IFREG, mode)
+                .await;
+            assert_eq!(errno_of(res.unwrap_err()), Some(Errno::EEXIST));
+            let found = backend.lookup(dir, OsStr::new("foo")).await?;
+            assert_eq!(found.map(|entry| entry.ino()), Some(file));
+
+            // the directory cannot be moved under itself, nor deleted unless empty
+            let res = backend
+                .rename(root, OsStr::new("dir"), dir, OsStr::new("sub"))
+                .await;
+            assert_eq!(errno_of(res.unwrap_err()), Some(Errno::EINVAL));
+            let res = backend
+                .unlink(root, OsStr::new("dir"), SFlag::S_IFDIR)
+                .await;
+            assert_eq!(errno_of(res.unwrap_err()), Some(Errno::ENOTEMPTY));
+
+            // the moved file replaces the existing one, which stays open
+            let bar = backend
+                .create(root, OsStr::new("bar"), SFlag::S_IFREG, mode)
+                .await?;
+            backend.write_at(bar, 0, b"bar".to_vec()).await?;
+            backend
+                .rename(dir, OsStr::new("foo"), root, OsStr::new("bar"))
+                .await?;
+            assert!(backend.lookup(dir, OsStr::new("foo")).await?.is_none());
+            let found = backend.lookup(root, OsStr::new("bar")).await?;
+            assert_eq!(found.map(|entry| entry.ino()), Some(file));
+            assert_eq!(backend.read_at(bar, 0, 10).await?, b"bar");
+            assert_eq!(backend.getattr(bar).await?.nlink, 0);
+            backend.close(bar)?;
+            assert!(backend.getattr(bar).await.is_err());
+
+            backend
+                .unlink(root, OsStr::new("dir"), SFlag::S_IFDIR)
+                .await?;
+            assert_eq!(backend.getattr(root).await?.nlink, 2);
+            let res = backend.open(root, OsStr::new("dir"), SFlag::S_IFDIR, OFlag::O_RDONLY);
+            assert_eq!(errno_of(res.await.unwrap_err()), Some(Errno::ENOENT));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_memory_readdir_cookie() -> anyhow::Result<()> {
+        smol::run(async {
+            let backend = MemoryBackend::new();
+            let mode = Mode::from_bits_truncate(0o644);
+            let root = backend.open_root().await?;
+            for name in &["a", "b", "c", "d", "e"] {
+                let file = backend
+                    .create(root, OsStr::new(name), SFlag::S_IFREG, mode)
+                    .await?;
+                backend.close(file)?;
+            }
+            let listed = backend.readdir(root, 0, 2).await?;
+            assert_eq!(listed.len(), 2);
+            // resume after the second entry, while the listed and unlisted entries change
+            backend
+                .unlink(root, OsStr::new("a"), SFlag::S_IFREG)
+                .await?;
+            backend
+                .unlink(root, OsStr::new("c"), SFlag::S_IFREG)
+                .await?;
+            let file = backend
+                .create(root, OsStr::new("f"), SFlag::S_IFREG, mode)
+                .await?;
+            backend.close(file)?;
+            let resumed: Vec<_> = backend
+                .readdir(root, listed[1].offset(), 10)
+                .await?
+                .iter()
+                .map(|entry| entry.entry_name().to_owned())
+                .collect();
+            assert_eq!(resumed, vec!["d", "e", "f"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_memory_data() -> anyhow::Result<()> {
+        smol::run(async {
+            let backend = MemoryBackend::new();
+            let root = backend.open_root().await?;
+            let file = backend
+                .create(
+                    root,
+                    OsStr::new("foo"),
+                    SFlag::S_IFREG,
+                    Mode::from_bits_truncate(0o644),
+                )
+                .await?;
+            backend.write_at(file, 2, b"ab".to_vec()).await?;
+            assert_eq!(backend.read_at(file, 0, 10).await?, b"\0\0ab");
+            assert_eq!(backend.read_at(file, 10, 10).await?, b"");
+            backend.truncate(file, 3).await?;
+            assert_eq!(backend.getattr(file).await?.size, 3);
+
+            let name = OsStr::new("user.foo");
+            let replace = libc::XATTR_REPLACE as u32;
+            let res = backend.set_xattr(file, name, b"1".to_vec(), replace).await;
+            assert!(res.is_err());
+            backend.set_xattr(file, name, b"1".to_vec(), 0).await?;
+            assert_eq!(backend.get_xattr(file, name).await?, Some(b"1".to_vec()));
+            assert_eq!(backend.list_xattr(file).await?, b"user.foo\0");
+            backend.remove_xattr(file, name).await?;
+            assert!(backend.remove_xattr(file, name).await.is_err());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,192 @@
+//! The storage backends beneath the FUSE filesystem.
+//!
+//! `FileSystem` serves the FUSE requests, and caches the nodes, the directory entries
+//! and the file data, whereas the backend stores the files. The backend is asked to
+//! operate on the namespace, i.e. look up, create, delete, rename and list the entries
+//! of the directories, and on the files, i.e. read, write, truncate and sync the data,
+//! and to change the attributes and the xattrs, through the handles it opens for the
+//! nodes and the open files.
+//!
+//! `LocalBackend` passes the operations through to a directory on the host, whereas
+//! `MemoryBackend` keeps the files in memory. The backends keeping the files on host fds
+//! expose them by `raw_fd()`, which enables the features working on the fds, e.g.
+//! passthrough, inotify watches, export handles and live upgrade.
+
+use anyhow;
+use async_trait::async_trait;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{Mode, SFlag};
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::os::unix::io::RawFd;
+use std::time::SystemTime;
+
+use super::dir::DirEntry;
+use super::util::FileAttr;
+
+mod local;
+mod memory;
+pub use local::LocalBackend;
+pub use memory::MemoryBackend;
+
+/// The storage of the files served by `FileSystem`.
+///
+/// The errors carrying a nix or io error are replied to the kernel with its errno.
+#[async_trait]
+pub trait StorageBackend: Debug + Send + Sync + 'static {
+    /// The handle of an opened file or directory, which stays valid until closed
+    type Handle: Copy + Debug + Send + Sync + 'static;
+
+    /// Open the root directory
+    async fn open_root(&self) -> anyhow::Result<Self::Handle>;
+
+    // Namespace operations
+
+    /// Look up the entry of `name` under the directory of `dir`,
+    /// None if not found or not served through the mount
+    async fn lookup(&self, dir: Self::Handle, name: &OsStr) -> anyhow::Result<Option<DirEntry>>;
+
+    /// Open the child of `name` and `kind` under the directory of `dir`,
+    /// the regular files are opened with `oflags`
+    async fn open(
+        &self,
+        dir: Self::Handle,
+        name: &OsStr,
+        kind: SFlag,
+        oflags: OFlag,
+    ) -> anyhow::Result<Self::Handle>;
+
+    /// Create and open the child of `name` and `kind` with the permissions of `mode`
+    /// under the directory of `dir`, fail with EEXIST if the name is taken
+    async fn create(
+        &self,
+        dir: Self::Handle,
+        name: &OsStr,
+        kind: SFlag,
+        mode: Mode,
+    ) -> anyhow::Result<Self::Handle>;
+
+    /// Delete the child of `name` and `kind` under the directory of `dir`, the directories
+    /// should be empty. The opened handles of the child stay valid until closed
+    async fn unlink(&self, dir: Self::Handle, name: &OsStr, kind: SFlag) -> anyhow::Result<()>;
+
+    /// Move the child of `old_name` under the directory of `old_dir` to `new_name` under
+    /// the directory of `new_dir`, replacing the child of `new_name` if any
+    async fn rename(
+        &self,
+        old_dir: Self::Handle,
+        old_name: &OsStr,
+        new_dir: Self::Handle,
+        new_name: &OsStr,
+    ) -> anyhow::Result<()>;
+
+    /// Read at most `count` entries of the directory of `dir` served through the mount,
+    /// right after the entry of the cookie `offset`, or from the start if 0. The cookies
+    /// of the entries stay valid when other entries are created or deleted
+    async fn readdir(
+        &self,
+        dir: Self::Handle,
+        offset: i64,
+        count: usize,
+    ) -> anyhow::Result<Vec<DirEntry>>;
+
+    // Handle operations
+
+    /// Open the file of `handle` again with `oflags` for an open of the file
+    async fn dup(&self, handle: Self::Handle, oflags: OFlag) -> anyhow::Result<Self::Handle>;
+
+    /// Close the handle, the file deleted is dropped once all its handles are closed
+    fn close(&self, handle: Self::Handle) -> anyhow::Result<()>;
+
+    // Data operations
+
+    /// The attribute of the file of `handle`
+    async fn getattr(&self, handle: Self::Handle) -> anyhow::Result<FileAttr>;
+
+    /// Read at most `size` bytes at `offset`, less at the end of the file
+    async fn read_at(
+        &self,
+        handle: Self::Handle,
+        offset: u64,
+        size: usize,
+    ) -> anyhow::Result<Vec<u8>>;
+
+    /// Write `data` at `offset`, the hole before `offset` is filled with zeros,
+    /// return the number of the bytes written
+    async fn write_at(
+        &self,
+        handle: Self::Handle,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> anyhow::Result<usize>;
+
+    /// Truncate or extend the file to `size`
+    async fn truncate(&self, handle: Self::Handle, size: u64) -> anyhow::Result<()>;
+
+    /// Persist the data, and the attribute unless `datasync`, of the file
+    async fn sync(&self, handle: Self::Handle, datasync: bool) -> anyhow::Result<()>;
+
+    /// Called on each close of an open of the file, nothing to do by default
+    async fn flush(&self, _handle: Self::Handle) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Persist all the files under the root of `root`
+    async fn sync_all(&self, root: Self::Handle) -> anyhow::Result<()>;
+
+    // Attribute operations
+
+    /// Change the permission bits to the ones of `mode`
+    async fn chmod(&self, handle: Self::Handle, mode: Mode) -> anyhow::Result<()>;
+
+    /// Change the owner and the group, None to keep
+    async fn chown(
+        &self,
+        handle: Self::Handle,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> anyhow::Result<()>;
+
+    /// Change the access and the modification times, None to keep
+    async fn utimens(
+        &self,
+        handle: Self::Handle,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> anyhow::Result<()>;
+
+    /// The xattr of `name`, None if not set
+    async fn get_xattr(
+        &self,
+        handle: Self::Handle,
+        name: &OsStr,
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// The names of the xattrs, each ends with a NUL
+    async fn list_xattr(&self, handle: Self::Handle) -> anyhow::Result<Vec<u8>>;
+
+    /// Set the xattr of `name`, `flags` of XATTR_CREATE or XATTR_REPLACE
+    async fn set_xattr(
+        &self,
+        handle: Self::Handle,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: u32,
+    ) -> anyhow::Result<()>;
+
+    /// Remove the xattr of `name`, fail with ENOATTR if not set
+    async fn remove_xattr(&self, handle: Self::Handle, name: &OsStr) -> anyhow::Result<()>;
+
+    // Host fds
+
+    /// The host fd of `handle`, None if the backend keeps no files on the host
+    fn raw_fd(&self, _handle: Self::Handle) -> Option<RawFd> {
+        None
+    }
+
+    /// The handle of the host fd handed over by the old daemon on live upgrade,
+    /// None if the backend keeps no files on the host
+    fn from_raw_fd(&self, _fd: RawFd) -> Option<Self::Handle> {
+        None
+    }
+}
@@ -0,0 +1,221 @@
+//! The cache policies of the files, set by the xattrs of the backing files.
+//!
+//! Writing `user.datenlord.cache=pin|normal|none` or `user.datenlord.prefetch=sequential|none`
+//! on a file or a directory changes how the daemon caches and prefetches the file, or the
+//! files under the directory. The xattrs are stored on the backing files, so the policies
+//! persist across mounts, and the files without their own policy inherit the ones of the
+//! nearest ancestors. The read-only `user.datenlord.cached_bytes` reports the bytes of the
+//! file, or the files under the directory, cached by the daemon.
+
+use anyhow;
+use log::debug;
+use std::ffi::OsStr;
+use std::str::FromStr;
+
+use super::backend::StorageBackend;
+
+/// The xattr of the cache mode
+pub const CACHE_XATTR: &str = "user.datenlord.cache";
+/// The xattr of the prefetch mode
+pub const PREFETCH_XATTR: &str = "user.datenlord.prefetch";
+/// The read-only xattr of the bytes cached by the daemon, not stored on the backing file
+pub const CACHED_BYTES_XATTR: &str = "user.datenlord.cached_bytes";
+
+/// How the daemon caches the file data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Load the data on open and keep the page cache of the kernel across opens
+    Pin,
+    /// Load the data on first read or write
+    Normal,
+    /// Never cache the data, the reads and writes go to the backing file directly,
+    /// bypassing the page cache of the kernel as well
+    Uncached,
+}
+
+impl CacheMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheMode::Pin => "pin",
+            CacheMode::Normal => "normal",
+            CacheMode::Uncached => "none",
+        }
+    }
+}
+
+impl FromStr for CacheMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "pin" => Ok(CacheMode::Pin),
+            "normal" => Ok(CacheMode::Normal),
+            "none" => Ok(CacheMode::Uncached),
+            _ => Err(anyhow::anyhow!(
+                "invalid cache mode={:?}, should be pin, normal or none",
+                s
+            )),
+        }
+    }
+}
+
+/// How the daemon prefetches the file data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prefetch {
+    /// Read ahead of the sequential reads
+    Sequential,
+    /// Never read ahead
+    Off,
+}
+
+impl Prefetch {
+    fn as_str(self) -> &'static str {
+        match self {
+            Prefetch::Sequential => "sequential",
+            Prefetch::Off => "none",
+        }
+    }
+}
+
+impl FromStr for Prefetch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "sequential" => Ok(Prefetch::Sequential),
+            "none" => Ok(Prefetch::Off),
+            _ => Err(anyhow::anyhow!(
+                "invalid prefetch mode={:?}, should be sequential or none",
+                s
+            )),
+        }
+    }
+}
+
+/// The cache policy of a node, None for the parts inherited from the ancestors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// The cache mode
+    cache: Option<CacheMode>,
+    /// The prefetch mode
+    prefetch: Option<Prefetch>,
+}
+
+/// Whether `name` is the xattr of a cache policy stored on the backing file
+pub fn is_policy_xattr(name: &OsStr) -> bool {
+    name == CACHE_XATTR || name == PREFETCH_XATTR
+}
+
+/// Whether `name` is the read-only xattr of the cached bytes
+pub fn is_cached_bytes_xattr(name: &OsStr) -> bool {
+    name == CACHED_BYTES_XATTR
+}
+
+impl CachePolicy {
+    /// Load the policy from the xattrs of the backing file of `handle`,
+    /// the invalid values set out of band are ignored
+    pub async fn load<B: StorageBackend>(
+        backend: &B,
+        handle: B::Handle,
+    ) -> anyhow::Result<CachePolicy> {
+        let mut policy = CachePolicy::default();
+        for &name in &[CACHE_XATTR, PREFETCH_XATTR] {
+            if let Some(value) = backend.get_xattr(handle, OsStr::new(name)).await? {
+                if let Err(e) = policy.set(OsStr::new(name), &value) {
+                    debug!(
+                        "load() ignored the xattr of name={} of handle={:?}, the error is: {}",
+                        name, handle, e,
+                    );
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Set the part of the policy of the xattr of `name` to `value`
+    pub fn set(&mut self, name: &OsStr, value: &[u8]) -> anyhow::Result<()> {
+        // tolerate the trailing newline or NUL of the values written by the tools
+        let value = String::from_utf8_lossy(value);
+        let value = value.trim_end_matches(|c: char| c.is_ascii_whitespace() || c == '\0');
+        if name == CACHE_XATTR {
+            self.cache = Some(value.parse()?);
+        } else if name == PREFETCH_XATTR {
+            self.prefetch = Some(value.parse()?);
+        } else {
+            return Err(anyhow::anyhow!(
+                "xattr name={:?} is not a cache policy",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    /// The value of the xattr of `name`, None if not set
+    pub fn get(&self, name: &OsStr) -> Option<&'static [u8]> {
+        let value = if name == CACHE_XATTR {
+            self.cache.map(CacheMode::as_str)
+        } else if name == PREFETCH_XATTR {
+            self.prefetch.map(Prefetch::as_str)
+        } else {
+            None
+        };
+        value.map(str::as_bytes)
+    }
+
+    /// Inherit the parts not set from the policy of the parent
+    pub fn or(self, parent: CachePolicy) -> CachePolicy {
+        CachePolicy {
+            cache: self.cache.or(parent.cache),
+            prefetch: self.prefetch.or(parent.prefetch),
+        }
+    }
+
+    /// Whether all the parts are set, nothing left to inherit
+    pub fn is_complete(&self) -> bool {
+        self.cache.is_some() && self.prefetch.is_some()
+    }
+
+    /// The cache mode, normal if not set
+    pub fn cache_mode(&self) -> CacheMode {
+        self.cache.unwrap_or(CacheMode::Normal)
+    }
+
+    /// The prefetch mode, off if not set
+    pub fn prefetch(&self) -> Prefetch {
+        self.prefetch.unwrap_or(Prefetch::Off)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::{CacheMode, CachePolicy, Prefetch, CACHE_XATTR, PREFETCH_XATTR};
+
+    #[test]
+    fn test_cache_policy() -> anyhow::Result<()> {
+        let cache = OsStr::new(CACHE_XATTR);
+        let prefetch = OsStr::new(PREFETCH_XATTR);
+        let mut dir = CachePolicy::default();
+        dir.set(cache, b"none\n")?;
+        dir.set(prefetch, b"sequential")?;
+        let mut file = CachePolicy::default();
+        file.set(cache, b"pin")?;
+        assert!(file.set(cache, b"always").is_err());
+        assert!(file.set(OsStr::new("user.other"), b"pin").is_err());
+        assert_eq!(file.get(cache), Some(&b"pin"[..]));
+        assert_eq!(file.get(prefetch), None);
+
+        // the file keeps its own cache mode and inherits the prefetch mode
+        let inherited = file.or(dir);
+        assert!(inherited.is_complete());
+        assert_eq!(inherited.cache_mode(), CacheMode::Pin);
+        assert_eq!(inherited.prefetch(), Prefetch::Sequential);
+        assert_eq!(dir.or(file).get(cache), Some(&b"none"[..]));
+
+        let unset = CachePolicy::default();
+        assert_eq!(unset.cache_mode(), CacheMode::Normal);
+        assert_eq!(unset.prefetch(), Prefetch::Off);
+        Ok(())
+    }
+}
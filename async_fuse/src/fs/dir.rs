@@ -1,4 +1,7 @@
-use nix::sys::stat::SFlag;
+use nix::errno::Errno;
+use nix::fcntl::AtFlags;
+use nix::sys::stat::{self, SFlag};
+use nix::unistd;
 use std::ffi::{CStr, OsStr, OsString};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::RawFd;
@@ -27,16 +30,41 @@ impl Dir {
         // Always guaranteed to be non-null by the previous check
         Ok(Dir(ptr::NonNull::new(d).unwrap()))
     }
+
+    /// Opens the stream of the directory of `fd` right after the entry of the cookie
+    /// `offset`, or at the start if `offset` is 0. The fd is duplicated and kept open.
+    ///
+    /// The cookies are the `d_off` of the entries given by the backing filesystem, which
+    /// stay valid when other entries are created or deleted, so a listing resumed by
+    /// cookies neither skips nor repeats the entries not changed in between.
+    pub fn open_at(fd: RawFd, offset: i64) -> nix::Result<Self> {
+        let dir = Dir::from_fd(unistd::dup(fd)?)?;
+        // The duplicated fd shares the position with `fd`, so always seek
+        if offset == 0 {
+            unsafe { libc::rewinddir(dir.0.as_ptr()) };
+        } else {
+            unsafe { libc::seekdir(dir.0.as_ptr(), offset) };
+        }
+        Ok(dir)
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0.as_ptr()) };
+    }
 }
 
 // `Dir` is safe to pass from one thread to another, as it's not reference-counted.
 unsafe impl Send for Dir {}
 
+/// An entry of a directory
 #[derive(Debug)]
-pub(crate) struct DirEntry {
+pub struct DirEntry {
     ino: INum,
     entry_type: SFlag,
     name: OsString,
+    offset: i64,
 }
 
 impl DirEntry {
@@ -45,8 +73,26 @@ impl DirEntry {
             ino,
             name,
             entry_type,
+            offset: 0,
         }
     }
+
+    /// Looks up the entry of `name` under the directory of `dir_fd` on disk,
+    /// returns None if not found or not visible through the mount.
+    pub fn stat_at(dir_fd: RawFd, name: &OsStr) -> nix::Result<Option<Self>> {
+        let st = match stat::fstatat(dir_fd, name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+            Ok(st) => st,
+            Err(nix::Error::Sys(Errno::ENOENT)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let entry = Self::new(
+            st.st_ino,
+            name.into(),
+            SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()),
+        );
+        Ok(Some(entry).filter(DirEntry::is_visible))
+    }
+
     /// Returns the inode number (`d_ino`) of the underlying `dirent`.
     pub fn ino(&self) -> u64 {
         self.ino
@@ -66,8 +112,33 @@ impl DirEntry {
         self.entry_type
     }
 
+    /// Returns the cookie to resume the directory stream right after this entry,
+    /// 0 if the entry is not read from a directory stream.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Sets the cookie to resume the directory stream right after this entry.
+    pub fn with_offset(self, offset: i64) -> Self {
+        Self { offset, ..self }
+    }
+
+    /// Whether the entry is served through the mount, the hidden entries, including
+    /// `.` and `..`, and the types other than directories and regular files are not.
+    pub fn is_visible(&self) -> bool {
+        !self.name.as_bytes().starts_with(&[b'.'])
+            && match self.entry_type {
+                SFlag::S_IFDIR | SFlag::S_IFREG => true,
+                _ => false,
+            }
+    }
+
     fn from_dirent(de: dirent) -> Self {
         let ino = de.d_ino;
+        #[cfg(target_os = "linux")]
+        let offset = de.d_off;
+        #[cfg(not(target_os = "linux"))]
+        let offset = de.d_seekoff as i64;
 
         let name = unsafe { OsStr::from_bytes(CStr::from_ptr(de.d_name.as_ptr()).to_bytes()) };
 
@@ -86,6 +157,7 @@ impl DirEntry {
             ino,
             name: name.into(),
             entry_type,
+            offset,
         }
     }
 }
@@ -102,8 +174,7 @@ impl Iterator for Dir {
             // Probably fine here too then.
             let mut ent = std::mem::MaybeUninit::<dirent>::uninit();
             let mut result = ptr::null_mut();
-            if let Err(e) =
-                nix::errno::Errno::result(readdir_r(self.0.as_ptr(), ent.as_mut_ptr(), &mut result))
+            if let Err(e) = Errno::result(readdir_r(self.0.as_ptr(), ent.as_mut_ptr(), &mut result))
             {
                 return Some(Err(e));
             }
@@ -124,7 +195,7 @@ mod test {
     use nix::sys::stat::Mode;
     use smol::blocking;
 
-    use super::Dir;
+    use super::{Dir, DirEntry};
 
     #[test]
     fn test_dir() -> nix::Result<()> {
@@ -147,4 +218,46 @@ mod test {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_resume_by_cookie() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("async_fuse_test_dir_cookie");
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        for name in &["a", "b", "c", "d", "e", ".hidden"] {
+            std::fs::write(path.join(name), name)?;
+        }
+        let fd = fcntl::open(&path, OFlag::O_RDONLY | OFlag::O_DIRECTORY, Mode::empty())?;
+        let read_names = |offset: i64| -> nix::Result<Vec<(String, i64)>> {
+            Dir::open_at(fd, offset)?
+                .filter(|entry| entry.as_ref().map_or(true, DirEntry::is_visible))
+                .map(|entry| {
+                    entry.map(|e| (e.entry_name().to_string_lossy().into_owned(), e.offset()))
+                })
+                .collect()
+        };
+        let listed = read_names(0)?;
+        assert_eq!(listed.len(), 5);
+
+        // resume after the second entry, while the listed and unlisted entries change
+        let (first, _) = &listed[0];
+        let (_, cookie) = listed[1];
+        let (third, _) = &listed[2];
+        std::fs::remove_file(path.join(first))?;
+        std::fs::remove_file(path.join(third))?;
+        std::fs::write(path.join("f"), "f")?;
+        let resumed: Vec<String> = read_names(cookie)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != "f")
+            .collect();
+        let expected: Vec<String> = listed[3..].iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(resumed, expected);
+
+        nix::unistd::close(fd)?;
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,154 @@
+//! The errors of the filesystem operations.
+//!
+//! A failed operation is replied to the kernel with the errno carried by `FsError`,
+//! which is recovered from the nix or io error causing the failure, so that the
+//! applications see the precise error, e.g. ENOSPC or EACCES, rather than EINVAL.
+
+use anyhow;
+use nix::errno::Errno;
+use std::error::Error;
+use std::fmt;
+
+use super::super::fuse_reply::ReplyError;
+
+/// The error of a filesystem operation
+#[derive(Debug)]
+pub enum FsError {
+    /// The operation failed, reply the errno to the kernel
+    Errno {
+        /// The errno replied to the kernel
+        errno: Errno,
+        /// The cause of the failure, with the context to log
+        source: anyhow::Error,
+    },
+    /// Failed to send the reply to the kernel, which cannot be replied again
+    Reply(anyhow::Error),
+}
+
+/// The result of a filesystem operation
+pub type FsResult<T> = Result<T, FsError>;
+
+impl FsError {
+    /// The error replied to the kernel as `errno`, with the message to log
+    pub fn new(errno: Errno, msg: String) -> FsError {
+        FsError::Errno {
+            errno,
+            source: anyhow::Error::new(nix::Error::Sys(errno)).context(msg),
+        }
+    }
+
+    /// The errno to reply to the kernel, None if failed to send the reply
+    pub fn errno(&self) -> Option<Errno> {
+        match self {
+            FsError::Errno { errno, .. } => Some(*errno),
+            FsError::Reply(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::Errno { errno, source } => write!(f, "{} ({:#})", errno, source),
+            FsError::Reply(source) => write!(f, "{:#}", source),
+        }
+    }
+}
+
+impl Error for FsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FsError::Errno { source, .. } | FsError::Reply(source) => Some(source.as_ref()),
+        }
+    }
+}
+
+/// The errno of the nix error
+fn nix_errno(e: &nix::Error) -> Errno {
+    match e {
+        nix::Error::Sys(errno) => *errno,
+        nix::Error::InvalidPath | nix::Error::InvalidUtf8 => Errno::EINVAL,
+        nix::Error::UnsupportedOperation => Errno::EOPNOTSUPP,
+    }
+}
+
+/// The errno of the io error, EIO if not from the OS
+fn io_errno(e: &std::io::Error) -> Errno {
+    e.raw_os_error().map_or(Errno::EIO, Errno::from_i32)
+}
+
+impl From<nix::Error> for FsError {
+    fn from(e: nix::Error) -> Self {
+        FsError::Errno {
+            errno: nix_errno(&e),
+            source: e.into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for FsError {
+    fn from(e: std::io::Error) -> Self {
+        FsError::Errno {
+            errno: io_errno(&e),
+            source: e.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for FsError {
+    /// Recover the errno from the cause of the error, EIO if unknown
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<FsError>() {
+            Ok(fs_error) => return fs_error,
+            Err(e) => e,
+        };
+        if e.downcast_ref::<ReplyError>().is_some() {
+            return FsError::Reply(e);
+        }
+        let errno = if let Some(nix_error) = e.downcast_ref::<nix::Error>() {
+            nix_errno(nix_error)
+        } else if let Some(io_error) = e.downcast_ref::<std::io::Error>() {
+            io_errno(io_error)
+        } else {
+            Errno::EIO
+        };
+        FsError::Errno { errno, source: e }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Context;
+    use nix::errno::Errno;
+
+    use super::super::super::fuse_reply::ReplyError;
+    use super::FsError;
+
+    #[test]
+    fn test_errno_from_cause() {
+        let res: nix::Result<()> = Err(nix::Error::Sys(Errno::ENOSPC));
+        let e: FsError = res.context("failed to write").unwrap_err().into();
+        assert_eq!(e.errno(), Some(Errno::ENOSPC));
+
+        let io_error = std::io::Error::from_raw_os_error(libc::EDQUOT);
+        let e: FsError = anyhow::Error::new(io_error).context("failed").into();
+        assert_eq!(e.errno(), Some(Errno::EDQUOT));
+
+        let e: FsError = anyhow::anyhow!("unknown error").into();
+        assert_eq!(e.errno(), Some(Errno::EIO));
+
+        let e: FsError = anyhow::Error::new(FsError::new(Errno::ESTALE, "stale".into())).into();
+        assert_eq!(e.errno(), Some(Errno::ESTALE));
+    }
+
+    #[test]
+    fn test_reply_error() {
+        let res: nix::Result<()> = Err(nix::Error::Sys(Errno::ENOENT));
+        let e = res
+            .context(ReplyError("failed to send to FUSE".into()))
+            .context("send_error() failed to send error")
+            .unwrap_err();
+        let e: FsError = e.into();
+        assert_eq!(e.errno(), None);
+    }
+}
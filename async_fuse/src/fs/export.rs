@@ -0,0 +1,304 @@
+//! The generations and the persistent handles of the nodes for export.
+//!
+//! The i-node numbers are the ones of the backing files, which the backing filesystem
+//! reuses once the files are deleted, so each number carries a generation bumped once
+//! the number refers to another file, and the kernel tells the files apart by the pair.
+//! With FUSE_EXPORT_SUPPORT, the kernel refers to the nodes by the pairs in the NFS file
+//! handles or the handles of `open_by_handle_at()`, even after the nodes are forgotten,
+//! then the forgotten nodes are rebuilt where they were loaded, or from the handles of
+//! the backing files taken by `name_to_handle_at()` once the nodes are loaded, in case
+//! the files are moved out of band.
+
+use log::debug;
+use nix::fcntl::OFlag;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::io::RawFd;
+
+use super::super::protocol::INum;
+use super::super::upgrade::{Decoder, Encoder};
+
+/// The generation of the i-node numbers never reused
+const INITIAL_GENERATION: u64 = 1;
+
+/// The handle of the backing file, which stays valid across reboots
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FileHandle {
+    /// The type of the handle, specific to the backing filesystem
+    handle_type: i32,
+    /// The opaque handle
+    bytes: Vec<u8>,
+}
+
+/// The export state of an i-node number
+#[derive(Debug)]
+struct ExportEntry {
+    generation: u64,
+    /// None if the backing filesystem does not support file handles,
+    /// or the file is deleted
+    handle: Option<FileHandle>,
+    /// The parent and the name where the node was loaded, None if the file is deleted
+    location: Option<(INum, OsString)>,
+}
+
+/// The generations and the handles of the i-node numbers ever loaded
+#[derive(Debug, Default)]
+pub(crate) struct ExportTable {
+    entries: BTreeMap<INum, ExportEntry>,
+}
+
+impl ExportTable {
+    /// Record the backing file of `fd` loaded as the node of `ino` named `name`
+    /// under the directory of `parent`, return the generation of the node.
+    /// The files not kept on host fds, of None, have no handles
+    pub fn register(&mut self, ino: INum, fd: Option<RawFd>, parent: INum, name: &OsStr) -> u64 {
+        let handle = match fd.map(file_handle) {
+            Some(Ok(handle)) => Some(handle),
+            None => None,
+            Some(Err(e)) => {
+                debug!(
+                    "register() failed to get the handle of ino={}, the error is: {}",
+                    ino, e,
+                );
+                None
+            }
+        };
+        let entry = self.entries.entry(ino).or_insert(ExportEntry {
+            generation: INITIAL_GENERATION,
+            handle: None,
+            location: None,
+        });
+        if let (Some(old), Some(new)) = (&entry.handle, &handle) {
+            if old != new {
+                // the i-node number is reused out of band
+                entry.generation += 1;
+                debug!(
+                    "register() found ino={} reused by another file, the generation={}",
+                    ino, entry.generation,
+                );
+            }
+        }
+        if handle.is_some() {
+            entry.handle = handle;
+        }
+        entry.location = Some((parent, name.to_owned()));
+        entry.generation
+    }
+
+    /// The generation of the node of `ino`
+    pub fn generation(&self, ino: INum) -> u64 {
+        self.entries
+            .get(&ino)
+            .map_or(INITIAL_GENERATION, |entry| entry.generation)
+    }
+
+    /// The backing file of `ino` is deleted, so the number may be reused
+    pub fn retire(&mut self, ino: INum) {
+        if let Some(entry) = self.entries.get_mut(&ino) {
+            entry.generation += 1;
+            entry.handle = None;
+            entry.location = None;
+        }
+    }
+
+    /// The parent and the name where the node of `ino` was loaded last time
+    pub fn location(&self, ino: INum) -> Option<(INum, &OsStr)> {
+        self.entries
+            .get(&ino)
+            .and_then(|entry| entry.location.as_ref())
+            .map(|(parent, name)| (*parent, name.as_os_str()))
+    }
+
+    /// Open the backing file of `ino` by its handle as `O_PATH`,
+    /// the handle is resolved on the backing filesystem of `mount_fd`
+    pub fn open(&self, ino: INum, mount_fd: RawFd) -> nix::Result<RawFd> {
+        match self
+            .entries
+            .get(&ino)
+            .and_then(|entry| entry.handle.as_ref())
+        {
+            Some(handle) => open_handle(mount_fd, handle, OFlag::O_PATH | OFlag::O_CLOEXEC),
+            None => Err(nix::Error::Sys(nix::errno::Errno::ESTALE)),
+        }
+    }
+
+    /// Encode the table to hand over to the new daemon
+    pub fn save(&self, enc: &mut Encoder) {
+        enc.put_u64(self.entries.len() as u64);
+        for (&ino, entry) in &self.entries {
+            enc.put_u64(ino);
+            enc.put_u64(entry.generation);
+            match &entry.handle {
+                Some(handle) => {
+                    enc.put_i32(handle.handle_type);
+                    enc.put_bytes(&handle.bytes);
+                }
+                None => enc.put_i32(-1),
+            }
+            match &entry.location {
+                Some((parent, name)) => {
+                    enc.put_u64(*parent);
+                    enc.put_os_str(name);
+                }
+                // the root is never a parent of others
+                None => enc.put_u64(0),
+            }
+        }
+    }
+
+    /// Decode the table handed over by the old daemon
+    pub fn restore(dec: &mut Decoder<'_>) -> anyhow::Result<ExportTable> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..dec.get_u64()? {
+            let ino = dec.get_u64()?;
+            let generation = dec.get_u64()?;
+            let handle_type = dec.get_i32()?;
+            let handle = if handle_type < 0 {
+                None
+            } else {
+                Some(FileHandle {
+                    handle_type,
+                    bytes: dec.get_bytes()?.to_vec(),
+                })
+            };
+            let parent = dec.get_u64()?;
+            let location = if parent == 0 {
+                None
+            } else {
+                Some((parent, dec.get_os_string()?))
+            };
+            entries.insert(
+                ino,
+                ExportEntry {
+                    generation,
+                    handle,
+                    location,
+                },
+            );
+        }
+        Ok(ExportTable { entries })
+    }
+}
+
+/// The buffer of `struct file_handle` with the max handle size
+#[cfg(target_os = "linux")]
+fn handle_buffer(handle_bytes: usize) -> Vec<u64> {
+    let size = std::mem::size_of::<libc::file_handle>() + handle_bytes;
+    // aligned as `struct file_handle`
+    vec![0_u64; (size + 7) / 8]
+}
+
+#[cfg(target_os = "linux")]
+fn file_handle(fd: RawFd) -> nix::Result<FileHandle> {
+    use nix::errno::Errno;
+
+    let mut buf = handle_buffer(libc::MAX_HANDLE_SZ as usize);
+    let fh = buf.as_mut_ptr() as *mut libc::file_handle;
+    let mut mount_id: libc::c_int = 0;
+    let empty_path = b"\0";
+    let res = unsafe {
+        (*fh).handle_bytes = libc::MAX_HANDLE_SZ as libc::c_uint;
+        libc::name_to_handle_at(
+            fd,
+            empty_path.as_ptr() as *const libc::c_char,
+            fh,
+            &mut mount_id,
+            libc::AT_EMPTY_PATH,
+        )
+    };
+    Errno::result(res)?;
+    let handle = unsafe {
+        let data = (fh as *const u8).add(std::mem::size_of::<libc::file_handle>());
+        FileHandle {
+            handle_type: (*fh).handle_type,
+            bytes: std::slice::from_raw_parts(data, (*fh).handle_bytes as usize).to_vec(),
+        }
+    };
+    Ok(handle)
+}
+
+#[cfg(target_os = "linux")]
+fn open_handle(mount_fd: RawFd, handle: &FileHandle, oflags: OFlag) -> nix::Result<RawFd> {
+    use nix::errno::Errno;
+
+    let mut buf = handle_buffer(handle.bytes.len());
+    let fh = buf.as_mut_ptr() as *mut libc::file_handle;
+    let res = unsafe {
+        (*fh).handle_bytes = handle.bytes.len() as libc::c_uint;
+        (*fh).handle_type = handle.handle_type;
+        let data = (fh as *mut u8).add(std::mem::size_of::<libc::file_handle>());
+        std::ptr::copy_nonoverlapping(handle.bytes.as_ptr(), data, handle.bytes.len());
+        libc::open_by_handle_at(mount_fd, fh, oflags.bits())
+    };
+    Errno::result(res)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn file_handle(_fd: RawFd) -> nix::Result<FileHandle> {
+    Err(nix::Error::UnsupportedOperation)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_handle(_mount_fd: RawFd, _handle: &FileHandle, _oflags: OFlag) -> nix::Result<RawFd> {
+    Err(nix::Error::UnsupportedOperation)
+}
+
+#[cfg(test)]
+mod test {
+    use nix::fcntl::{self, OFlag};
+    use nix::sys::stat::Mode;
+    use nix::unistd;
+    use std::ffi::OsStr;
+
+    use super::super::super::upgrade::{Decoder, Encoder};
+    use super::{file_handle, ExportTable, INITIAL_GENERATION};
+
+    #[test]
+    fn test_generation() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let path_a = dir.join("async_fuse_test_export_a");
+        let path_b = dir.join("async_fuse_test_export_b");
+        let oflags = OFlag::O_CREAT | OFlag::O_RDWR;
+        let fd_a = fcntl::open(&path_a, oflags, Mode::from_bits_truncate(0o644))?;
+        let fd_b = fcntl::open(&path_b, oflags, Mode::from_bits_truncate(0o644))?;
+
+        let mut table = ExportTable::default();
+        assert_eq!(table.generation(2), INITIAL_GENERATION);
+        assert_eq!(
+            table.register(2, Some(fd_a), 1, OsStr::new("a")),
+            INITIAL_GENERATION
+        );
+        assert_eq!(
+            table.register(2, Some(fd_a), 1, OsStr::new("a")),
+            INITIAL_GENERATION
+        );
+        // another file takes the number out of band
+        let reused = if file_handle(fd_a).is_ok() {
+            INITIAL_GENERATION + 1
+        } else {
+            INITIAL_GENERATION
+        };
+        assert_eq!(table.register(2, Some(fd_b), 1, OsStr::new("b")), reused);
+        assert_eq!(table.location(2), Some((1, OsStr::new("b"))));
+        // the file is deleted through the mount
+        table.retire(2);
+        assert_eq!(table.location(2), None);
+        assert_eq!(
+            table.register(2, Some(fd_a), 1, OsStr::new("a")),
+            reused + 1
+        );
+
+        let mut enc = Encoder::default();
+        table.save(&mut enc);
+        let bytes = enc.into_bytes();
+        let restored = ExportTable::restore(&mut Decoder::new(&bytes))?;
+        assert_eq!(restored.generation(2), reused + 1);
+        assert_eq!(restored.location(2), Some((1, OsStr::new("a"))));
+
+        unistd::close(fd_a)?;
+        unistd::close(fd_b)?;
+        unistd::unlink(&path_a)?;
+        unistd::unlink(&path_b)?;
+        Ok(())
+    }
+}
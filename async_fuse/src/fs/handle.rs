@@ -0,0 +1,338 @@
+//! The table of the open file handles.
+//!
+//! The fh replied to open and opendir indexes the table rather than being the fd of the
+//! open file, so that each open keeps its own state, e.g. the open flags and the ranges
+//! written but not synced yet, and a bogus fh from the kernel is rejected with EBADF
+//! rather than touching an arbitrary fd of the daemon.
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use std::collections::BTreeMap;
+use std::os::unix::io::RawFd;
+
+use super::super::protocol::INum;
+use super::super::upgrade::{Decoder, Encoder};
+use super::error::{FsError, FsResult};
+
+/// The reads in a row continuing the previous ones to regard the reads sequential
+const SEQUENTIAL_READS: u32 = 2;
+
+/// The state of the reads of an open file, to tell the sequential reads from random ones
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Readahead {
+    /// The offset where the previous read ends
+    next_offset: u64,
+    /// The number of the reads in a row continuing the previous ones
+    sequential: u32,
+}
+
+impl Readahead {
+    /// Record the read of `size` bytes at `offset`
+    pub fn record(&mut self, offset: u64, size: u64) {
+        if offset == self.next_offset {
+            self.sequential = self.sequential.saturating_add(1);
+        } else {
+            self.sequential = 0;
+        }
+        self.next_offset = offset.saturating_add(size);
+    }
+
+    /// Whether the recent reads are sequential
+    pub fn is_sequential(&self) -> bool {
+        self.sequential >= SEQUENTIAL_READS
+    }
+}
+
+/// The ranges written but not synced to the backing file yet
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DirtyRanges {
+    /// The disjoint ranges indexed by the start offsets, mapped to the end offsets
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl DirtyRanges {
+    /// Add the range of `len` bytes at `offset`, merged with the overlapping
+    /// or adjacent ranges
+    pub fn add(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let mut start = offset;
+        let mut end = offset.saturating_add(len);
+        let overlapped: Vec<u64> = self
+            .ranges
+            .range(..=end)
+            .filter(|&(_, &range_end)| range_end >= start)
+            .map(|(&range_start, _)| range_start)
+            .collect();
+        for range_start in overlapped {
+            if let Some(range_end) = self.ranges.remove(&range_start) {
+                start = start.min(range_start);
+                end = end.max(range_end);
+            }
+        }
+        self.ranges.insert(start, end);
+    }
+
+    /// Whether nothing is written since the last sync
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of the bytes written since the last sync
+    pub fn size(&self) -> u64 {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// Forget the ranges once synced
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
+/// The state of an open file or directory, opened by the backend as the handle of `H`
+#[derive(Debug)]
+pub(crate) struct OpenFile<H> {
+    /// The node opened
+    ino: INum,
+    /// The handle of the open, duplicated from the handle of the node
+    handle: H,
+    /// The open flags
+    flags: OFlag,
+    /// The lock owner of the last flush, None if never flushed
+    lock_owner: Option<u64>,
+    /// The state of the reads
+    readahead: Readahead,
+    /// The ranges written through this open but not synced yet
+    dirty: DirtyRanges,
+}
+
+impl<H: Copy> OpenFile<H> {
+    pub fn new(ino: INum, handle: H, flags: OFlag) -> OpenFile<H> {
+        OpenFile {
+            ino,
+            handle,
+            flags,
+            lock_owner: None,
+            readahead: Readahead::default(),
+            dirty: DirtyRanges::default(),
+        }
+    }
+
+    pub fn get_handle(&self) -> H {
+        self.handle
+    }
+
+    /// Whether the file is opened to append only
+    pub fn is_append(&self) -> bool {
+        self.flags.contains(OFlag::O_APPEND)
+    }
+
+    pub fn get_lock_owner(&self) -> Option<u64> {
+        self.lock_owner
+    }
+
+    pub fn set_lock_owner(&mut self, lock_owner: u64) {
+        self.lock_owner = Some(lock_owner);
+    }
+
+    pub fn readahead_mut(&mut self) -> &mut Readahead {
+        &mut self.readahead
+    }
+
+    pub fn get_dirty(&self) -> &DirtyRanges {
+        &self.dirty
+    }
+
+    pub fn dirty_mut(&mut self) -> &mut DirtyRanges {
+        &mut self.dirty
+    }
+}
+
+/// The open files indexed by the fh replied to the kernel
+#[derive(Debug)]
+pub(crate) struct HandleTable<H> {
+    /// The fh of the next open, never reused
+    next_fh: u64,
+    files: BTreeMap<u64, OpenFile<H>>,
+}
+
+impl<H> Default for HandleTable<H> {
+    fn default() -> HandleTable<H> {
+        HandleTable {
+            // 0 is left for the requests without fh
+            next_fh: 1,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl<H: Copy> HandleTable<H> {
+    /// Add the open file, return its fh
+    pub fn insert(&mut self, file: OpenFile<H>) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.files.insert(fh, file);
+        fh
+    }
+
+    /// The open file of `fh`, which should be an open of the node of `ino`
+    pub fn get(&self, fh: u64, ino: INum) -> FsResult<&OpenFile<H>> {
+        match self.files.get(&fh) {
+            Some(file) if file.ino == ino => Ok(file),
+            _ => Err(bad_handle(fh, ino)),
+        }
+    }
+
+    /// The mutable open file of `fh`, which should be an open of the node of `ino`
+    pub fn get_mut(&mut self, fh: u64, ino: INum) -> FsResult<&mut OpenFile<H>> {
+        match self.files.get_mut(&fh) {
+            Some(file) if file.ino == ino => Ok(file),
+            _ => Err(bad_handle(fh, ino)),
+        }
+    }
+
+    /// Remove the released open file of `fh`, which should be an open of the node of `ino`
+    pub fn remove(&mut self, fh: u64, ino: INum) -> FsResult<OpenFile<H>> {
+        self.get(fh, ino)?;
+        self.files.remove(&fh).ok_or_else(|| bad_handle(fh, ino))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Encode the table to hand over to the new daemon, and return the fds of the open
+    /// files to pass along, the host fd of each handle is given by `fd_of`
+    pub fn save(
+        &self,
+        enc: &mut Encoder,
+        fd_of: impl Fn(H) -> anyhow::Result<RawFd>,
+    ) -> anyhow::Result<Vec<RawFd>> {
+        let mut fds = Vec::with_capacity(self.files.len());
+        enc.put_u64(self.next_fh);
+        enc.put_u64(self.files.len() as u64);
+        for (&fh, file) in &self.files {
+            let fd = fd_of(file.handle)?;
+            fds.push(fd);
+            enc.put_u64(fh);
+            enc.put_u64(file.ino);
+            enc.put_i32(fd);
+            enc.put_i32(file.flags.bits());
+            enc.put_u64(file.lock_owner.map_or(0, |_| 1));
+            enc.put_u64(file.lock_owner.unwrap_or(0));
+            enc.put_u64(file.dirty.ranges.len() as u64);
+            for (&start, &end) in &file.dirty.ranges {
+                enc.put_u64(start);
+                enc.put_u64(end);
+            }
+        }
+        Ok(fds)
+    }
+
+    /// Decode the table handed over by the old daemon, the handle of each host fd
+    /// handed over is given by `handle_of`
+    pub fn restore(
+        dec: &mut Decoder<'_>,
+        handle_of: impl Fn(RawFd) -> anyhow::Result<H>,
+    ) -> anyhow::Result<HandleTable<H>> {
+        let next_fh = dec.get_u64()?;
+        let mut files = BTreeMap::new();
+        for _ in 0..dec.get_u64()? {
+            let fh = dec.get_u64()?;
+            let ino = dec.get_u64()?;
+            let fd = dec.get_i32()?;
+            let flags = OFlag::from_bits_truncate(dec.get_i32()?);
+            let has_lock_owner = dec.get_u64()? != 0;
+            let lock_owner = dec.get_u64()?;
+            let mut file = OpenFile::new(ino, handle_of(fd)?, flags);
+            if has_lock_owner {
+                file.set_lock_owner(lock_owner);
+            }
+            for _ in 0..dec.get_u64()? {
+                let start = dec.get_u64()?;
+                let end = dec.get_u64()?;
+                file.dirty.ranges.insert(start, end);
+            }
+            files.insert(fh, file);
+        }
+        Ok(HandleTable { next_fh, files })
+    }
+}
+
+/// The error of the fh unknown or referring to another node
+fn bad_handle(fh: u64, ino: INum) -> FsError {
+    FsError::new(
+        Errno::EBADF,
+        format!(
+            "found no open file of fh={} for the i-node of ino={}",
+            fh, ino
+        ),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use nix::errno::Errno;
+    use nix::fcntl::OFlag;
+
+    use super::super::super::upgrade::{Decoder, Encoder};
+    use super::{DirtyRanges, HandleTable, OpenFile, Readahead};
+
+    #[test]
+    fn test_handle_table() -> anyhow::Result<()> {
+        let mut table = HandleTable::default();
+        let fh = table.insert(OpenFile::new(2, 10, OFlag::O_RDWR | OFlag::O_APPEND));
+        let other_fh = table.insert(OpenFile::new(3, 11, OFlag::O_RDONLY));
+        assert_ne!(fh, 0);
+        assert_ne!(fh, other_fh);
+        assert!(table.get(fh, 2)?.is_append());
+        assert!(!table.get(other_fh, 3)?.is_append());
+        // the fh of another node or never opened
+        assert_eq!(table.get(fh, 3).unwrap_err().errno(), Some(Errno::EBADF));
+        assert_eq!(table.get(99, 2).unwrap_err().errno(), Some(Errno::EBADF));
+
+        table.get_mut(fh, 2)?.set_lock_owner(7);
+        table.get_mut(fh, 2)?.dirty_mut().add(0, 10);
+        let mut enc = Encoder::default();
+        assert_eq!(table.save(&mut enc, Ok)?, vec![10, 11]);
+        let bytes = enc.into_bytes();
+        let mut restored = HandleTable::restore(&mut Decoder::new(&bytes), Ok)?;
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get(fh, 2)?.get_lock_owner(), Some(7));
+        assert!(restored.get(fh, 2)?.is_append());
+        assert_eq!(restored.get(fh, 2)?.get_dirty().size(), 10);
+
+        assert_eq!(restored.remove(fh, 2)?.get_handle(), 10);
+        assert!(restored.remove(fh, 2).is_err());
+        // the fh is never reused
+        assert!(restored.insert(OpenFile::new(2, 12, OFlag::O_RDONLY)) > other_fh);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirty_ranges() {
+        let mut dirty = DirtyRanges::default();
+        assert!(dirty.is_empty());
+        dirty.add(0, 10);
+        dirty.add(20, 10);
+        assert_eq!(dirty.size(), 20);
+        // merge the adjacent and the overlapping ranges
+        dirty.add(10, 5);
+        dirty.add(12, 10);
+        assert_eq!(dirty.size(), 30);
+        assert_eq!(dirty.ranges.len(), 1);
+        dirty.clear();
+        assert!(dirty.is_empty());
+    }
+
+    #[test]
+    fn test_readahead() {
+        let mut readahead = Readahead::default();
+        readahead.record(0, 4096);
+        readahead.record(4096, 4096);
+        assert!(readahead.is_sequential());
+        readahead.record(0, 4096);
+        assert!(!readahead.is_sequential());
+    }
+}
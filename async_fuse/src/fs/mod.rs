@@ -1,36 +1,180 @@
 use anyhow::{self, Context};
-use libc::{EEXIST, EINVAL, ENODATA, ENOENT, ENOSYS, ENOTEMPTY};
-use log::debug;
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use libc::{c_int, EEXIST, EINVAL, ENOENT, ENOSYS, ENOTEMPTY, EPERM, ERANGE};
+use log::{debug, error, warn};
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::fcntl;
 use nix::fcntl::OFlag;
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, Inotify, InotifyEvent};
 use nix::sys::{stat::SFlag, statvfs};
 use nix::unistd;
 use smol::blocking;
+#[cfg(any(feature = "abi-7-12", target_os = "linux"))]
+use smol::Task;
 use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
 use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use super::filesystem::Filesystem;
+use super::fuse_conn::ConnInfo;
+#[cfg(feature = "abi-7-12")]
+use super::fuse_notify::Notifier;
 use super::fuse_reply::*;
 use super::fuse_request::*;
-use super::protocol::{INum, FUSE_ROOT_ID};
-
+use super::mount_options::{CacheTimeouts, OpenPolicy};
+#[cfg(feature = "abi-7-10")]
+use super::protocol::FOPEN_NONSEEKABLE;
+#[cfg(feature = "abi-7-9")]
+use super::protocol::FUSE_ATOMIC_O_TRUNC;
+#[cfg(feature = "abi-7-20")]
+use super::protocol::FUSE_AUTO_INVAL_DATA;
+#[cfg(feature = "abi-7-10")]
+use super::protocol::FUSE_EXPORT_SUPPORT;
+#[cfg(feature = "abi-7-26")]
+use super::protocol::FUSE_HANDLE_KILLPRIV;
+#[cfg(feature = "abi-7-25")]
+use super::protocol::FUSE_PARALLEL_DIROPS;
+#[cfg(target_os = "linux")]
+use super::protocol::FUSE_PASSTHROUGH;
+#[cfg(feature = "abi-7-31")]
+use super::protocol::FUSE_WRITE_KILL_PRIV;
+use super::protocol::{INum, FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE, FUSE_ROOT_ID};
+use super::upgrade::{Decoder, Encoder, SavedState};
+
+mod backend;
+mod cache_policy;
 mod dir;
+mod error;
+mod export;
+mod handle;
 mod node;
+mod revalidate;
 mod util;
-use dir::*;
+pub use backend::{LocalBackend, MemoryBackend, StorageBackend};
+use cache_policy::{CacheMode, CachePolicy, Prefetch};
+pub use dir::DirEntry;
+pub use error::{FsError, FsResult};
+use export::ExportTable;
+use handle::{HandleTable, OpenFile};
 use node::*;
+#[cfg(target_os = "linux")]
+use revalidate::run_watcher;
+use revalidate::Invalidation;
+pub use revalidate::Revalidation;
+#[cfg(target_os = "linux")]
+use revalidate::Watcher;
+pub use util::FileAttr;
+
+/// The default TTL of the kernel caches
+const MY_TTL_SEC: u64 = 3600;
+/// The default TTL of read-only filesystem, whose nodes never change through the mount
+const READ_ONLY_TTL_SEC: u64 = 24 * 3600;
+/// The entries read from the backing directory stream at a time to fill a readdir reply
+const READDIR_BATCH: usize = 128;
+/// The bytes to read ahead of the sequential reads of the files to prefetch
+const PREFETCH_SIZE: u64 = 1024 * 1024;
+/// The xattr of the backing file listing the open flags besides the open policy,
+/// e.g. `direct_io,nonseekable`
+const OPEN_FLAGS_XATTR: &str = "user.datenlord.open";
+
+/// The error of a node missing from cache, which happens if the kernel refers to
+/// a node forgotten or never looked up, replied ESTALE rather than failing the daemon
+fn node_missing(msg: String) -> FsError {
+    FsError::new(Errno::ESTALE, msg)
+}
 
-const MY_TTL_SEC: u64 = 3600; // TODO: should be a long value, say 1 hour
-const MY_GENERATION: u64 = 1; // TODO: find a proper way to set generation
+/// Parse the comma separated names of the open flags in the xattr,
+/// the unknown names are ignored
+fn parse_open_flags(value: &[u8]) -> u32 {
+    String::from_utf8_lossy(value)
+        .split(',')
+        .map(|name| match name.trim() {
+            "direct_io" => FOPEN_DIRECT_IO,
+            "keep_cache" => FOPEN_KEEP_CACHE,
+            #[cfg(feature = "abi-7-10")]
+            "nonseekable" => FOPEN_NONSEEKABLE,
+            name => {
+                debug!(
+                    "parse_open_flags() ignored the unknown open flag={:?}",
+                    name
+                );
+                0
+            }
+        })
+        .fold(0, |flags, flag| flags | flag)
+}
 
+/// Reply the lookup of a missing name, the kernel caches the negative entry
+/// if the TTL is not zero
+async fn reply_missing(reply: ReplyEntry, negative_ttl: Duration) -> anyhow::Result<()> {
+    if negative_ttl == Duration::default() {
+        reply.error(ENOENT).await
+    } else {
+        reply.negative(negative_ttl).await
+    }
+}
+
+/// The errno to reply for the failure of an xattr operation on the backing file,
+/// e.g. ENODATA and ENOTSUP, which are expected rather than the failures of the daemon
+fn xattr_errno(e: anyhow::Error) -> c_int {
+    let e = FsError::from(e);
+    debug!("the xattr operation failed, the error is: {}", e);
+    e.errno().map_or(libc::EIO, |errno| errno as c_int)
+}
+
+/// Reply the xattr value, or the xattr names, to the buffer of `size` bytes,
+/// only the size of the value if `size` is 0
+async fn reply_xattr(reply: ReplyXAttr, size: u32, value: Vec<u8>) -> anyhow::Result<()> {
+    if size == 0 {
+        reply.size(value.len() as u32).await
+    } else if value.len() > size as usize {
+        reply.error(ERANGE).await
+    } else {
+        reply.data(value).await
+    }
+}
+
+/// The filesystem serving the files stored by the backend of `B`,
+/// the passthrough of the backing directory by default
 #[derive(Debug)]
-pub(crate) struct FileSystem {
-    cache: BTreeMap<INum, Node>,
+pub struct FileSystem<B: StorageBackend = LocalBackend> {
+    /// The backend storing the files
+    backend: Arc<B>,
+    cache: BTreeMap<INum, Node<B>>,
     trash: BTreeSet<INum>,
+    /// The open files and directories, indexed by the fh replied to the kernel
+    handles: HandleTable<B::Handle>,
+    /// The generations and the backing file handles of the nodes
+    export: ExportTable,
+    /// The FUSE device fd to register backing files to,
+    /// None if passthrough is not negotiated or not permitted
+    passthrough_fd: Option<RawFd>,
+    /// The features negotiated with the kernel, None before init
+    conn_info: Option<ConnInfo>,
+    /// When to check the cached nodes against the backing directory
+    revalidation: Revalidation,
+    /// The inotify watches of the loaded directories, None if not watching
+    #[cfg(target_os = "linux")]
+    watcher: Option<Watcher>,
+    /// The notifier to invalidate the kernel caches of the changed nodes
+    #[cfg(feature = "abi-7-12")]
+    notifier: Option<Notifier>,
+    /// Whether the filesystem is mounted read-only
+    read_only: bool,
+    /// The TTLs of the kernel caches configured for the subtrees,
+    /// indexed by the directories relative to the root
+    timeouts: BTreeMap<PathBuf, CacheTimeouts>,
+    /// The rules choosing the flags of the opened files
+    open_policy: OpenPolicy,
 }
 
-impl FileSystem {
+impl<B: StorageBackend> FileSystem<B> {
     async fn create_node_helper(
         &mut self,
         parent: u64,
@@ -38,17 +182,16 @@ impl FileSystem {
         mode: u32,
         node_type: SFlag,
         reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         // pre-check
-        let parent_node = self.cache.get_mut(&parent);
-        debug_assert!(
-            parent_node.is_some(),
-            "create_node_helper() found fs is inconsistent, \
+        let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+            node_missing(format!(
+                "create_node_helper() found fs is inconsistent, \
                 parent of ino={} should be in cache before create it new child",
-            parent,
-        );
-        let parent_node = parent_node.unwrap(); // safe to use unwrap() here
-        if let Some(occupied) = parent_node.get_entry(&node_name) {
+                parent,
+            ))
+        })?;
+        if let Some(occupied) = parent_node.find_entry(&node_name).await? {
             debug!(
                 "create_node_helper() found the directory of ino={} \
                     already exists a child with name={:?} and ino={}",
@@ -73,28 +216,39 @@ impl FileSystem {
                 parent_node.create_child_dir(node_name, mflags).await?
             }
             SFlag::S_IFREG => {
-                let oflags = OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR;
                 debug!(
                     "helper_create_node() about to \
-                        create a file with name={:?}, oflags={:?}, mode={:?}",
-                    node_name, oflags, mflags,
+                        create a file with name={:?}, mode={:?}",
+                    node_name, mflags,
                 );
-                parent_node
-                    .create_child_file(node_name, oflags, mflags)
-                    .await?
+                parent_node.create_child_file(node_name, mflags).await?
+            }
+            _ => {
+                return Err(FsError::new(
+                    Errno::EINVAL,
+                    format!(
+                        "create_node_helper() found unsupported file type={:?}",
+                        node_type
+                    ),
+                ))
             }
-            _ => panic!(
-                "create_node_helper() found unsupported file type={:?}",
-                node_type
-            ),
         };
         new_ino = new_node.get_ino();
         let new_node_attr = new_node.get_attr();
+        let new_node_fd = new_node.get_fd();
         self.cache.insert(new_ino, new_node);
+        if let SFlag::S_IFDIR = node_type {
+            self.watch_dir_helper(new_ino, new_node_fd);
+        }
+        let generation = self
+            .export
+            .register(new_ino, new_node_fd, parent, &node_name_clone);
 
-        let ttl = Duration::new(MY_TTL_SEC, 0);
+        let (entry_ttl, attr_ttl) = self.ttls(new_ino);
         let fuse_attr = util::convert_to_fuse_attr(new_node_attr)?;
-        reply.entry(ttl, fuse_attr, MY_GENERATION).await?;
+        reply
+            .entry(entry_ttl, attr_ttl, fuse_attr, generation)
+            .await?;
         debug!(
             "create_node_helper() successfully created the new child name={:?} \
                 of ino={} and type={:?} under parent ino={}",
@@ -103,48 +257,51 @@ impl FileSystem {
         Ok(())
     }
 
-    async fn may_deferred_delete_node_helper(&mut self, ino: u64) -> anyhow::Result<()> {
+    async fn may_deferred_delete_node_helper(&mut self, ino: u64) -> FsResult<()> {
         let parent_ino: u64;
         let node_name: OsString;
-        let mut deferred_deletion = false;
         {
-            // pre-check whether deferred delete or not
-            let node = self.cache.get(&ino);
-            debug_assert!(
-                node.is_some(),
-                "may_deferred_delete_node_helper() failed to \
-                        find the i-node of ino={} to remove",
-                ino,
-            );
-            let node = node.unwrap(); // safe to use unwrap() here
+            let node = self.cache.get(&ino).ok_or_else(|| {
+                node_missing(format!(
+                    "may_deferred_delete_node_helper() failed to \
+                    find the i-node of ino={} to remove",
+                    ino,
+                ))
+            })?;
 
             parent_ino = node.get_parent_ino();
             node_name = node.get_name().into();
-
-            debug_assert!(node.get_lookup_count() >= 0); // lookup count cannot be negative
-            if node.get_lookup_count() > 0 {
-                // TODO: support thread-safe to avoid race condition
-                deferred_deletion = true;
-            }
         }
         {
             // remove entry from parent i-node
-            let parent_node = self.cache.get_mut(&parent_ino);
-            debug_assert!(
-                parent_node.is_some(),
-                "helper_get_parent_inode() failed to \
-                        find the parent of ino={} for i-node of ino={}",
-                parent_ino,
-                ino,
-            );
-            let parent_node = parent_node.unwrap(); // safe to use unwrap() here
+            let parent_node = self.cache.get_mut(&parent_ino).ok_or_else(|| {
+                node_missing(format!(
+                    "helper_get_parent_inode() failed to \
+                    find the parent of ino={} for i-node of ino={}",
+                    parent_ino, ino,
+                ))
+            })?;
 
             let node_name_clone = node_name.clone();
             let deleted_entry = parent_node.unlink_entry(node_name).await?;
             debug_assert_eq!(&node_name_clone, deleted_entry.entry_name());
             debug_assert_eq!(deleted_entry.ino(), ino);
         }
+        self.retire_node_helper(ino);
+        Ok(())
+    }
 
+    /// Drop the node of `ino` deleted or replaced from the namespace, or move it to the
+    /// trash until the kernel forgets it, if the kernel still looks it up
+    fn retire_node_helper(&mut self, ino: INum) {
+        let deferred_deletion = match self.cache.get(&ino) {
+            Some(node) => {
+                debug_assert!(node.get_lookup_count() >= 0); // lookup count cannot be negative
+                                                             // TODO: support thread-safe to avoid race condition
+                node.get_lookup_count() > 0
+            }
+            None => return,
+        };
         if deferred_deletion {
             // deferred deletion
             // TODO: support thread-safe
@@ -156,30 +313,30 @@ impl FileSystem {
                 ino,
             );
             debug!(
-                "may_deferred_delete_node_helper() defered removed \
+                "retire_node_helper() defered removed \
                     the node name={:?} of ino={} under parent ino={}, \
                     open count={}, lookup count={}",
                 node.get_name(),
                 ino,
-                parent_ino,
+                node.get_parent_ino(),
                 node.get_open_count(),
                 node.get_lookup_count(),
             );
         } else {
             // immediate deletion
             let inode = self.cache.remove(&ino).unwrap(); // TODO: support thread-safe
+            self.export.retire(ino);
             debug!(
-                "may_deferred_delete_node_helper() immediately removed \
+                "retire_node_helper() immediately removed \
                     the node name={:?} of ino={} under parent ino={}, \
                     open count={}, lookup count={}",
                 inode.get_name(),
                 ino,
-                parent_ino,
+                inode.get_parent_ino(),
                 inode.get_open_count(),
                 inode.get_lookup_count(),
             );
         }
-        Ok(())
     }
 
     async fn remove_node_helper(
@@ -188,19 +345,19 @@ impl FileSystem {
         node_name: OsString,
         node_type: SFlag,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         let node_ino: u64;
         {
             // pre-checks
-            let parent_node = self.cache.get(&parent);
-            debug_assert!(
-                parent_node.is_some(),
-                "remove_node_helper() found fs is inconsistent, \
-                        parent of ino={} should be in cache before remove its child",
-                parent,
-            );
-            let parent_node = parent_node.unwrap(); // safe to use unwrap() here
-            match parent_node.get_entry(&node_name) {
+            let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+                node_missing(format!(
+                    "remove_node_helper() found fs is inconsistent, \
+                    parent of ino={} should be in cache before remove its child",
+                    parent,
+                ))
+            })?;
+            let child_ino = parent_node.find_entry(&node_name).await?.map(DirEntry::ino);
+            match child_ino {
                 None => {
                     debug!(
                         "remove_node_helper() failed to find node name={:?} \
@@ -210,23 +367,20 @@ impl FileSystem {
                     reply.error(ENOENT).await?;
                     return Ok(());
                 }
-                Some(child_entry) => {
-                    node_ino = child_entry.ino();
+                Some(child_ino) => {
+                    node_ino = child_ino;
                     if let SFlag::S_IFDIR = node_type {
                         // check the directory to delete is empty
-                        let dir_node = self.cache.get(&node_ino);
-                        debug_assert!(
-                            dir_node.is_some(),
-                            "remove_node_helper() found fs is inconsistent, \
-                                    directory name={:?} of ino={} \
-                                    found under the parent of ino={}, \
-                                    but no i-node found for this directory",
-                            node_name,
-                            node_ino,
-                            parent,
-                        );
-                        let dir_node = dir_node.unwrap(); // safe to use unwrap() here
-                        if !dir_node.is_node_data_empty() {
+                        let dir_node = self.cache.get(&node_ino).ok_or_else(|| {
+                            node_missing(format!(
+                                "remove_node_helper() found fs is inconsistent, \
+                                directory name={:?} of ino={} \
+                                found under the parent of ino={}, \
+                                but no i-node found for this directory",
+                                node_name, node_ino, parent,
+                            ))
+                        })?;
+                        if !dir_node.is_dir_empty().await? {
                             debug!(
                                 "remove_node_helper() cannot remove \
                                     the non-empty directory name={:?} of ino={} \
@@ -238,14 +392,14 @@ impl FileSystem {
                         }
                     }
 
-                    let child_inode = self.cache.get(&node_ino).unwrap_or_else(|| {
-                        panic!(
+                    let child_inode = self.cache.get(&node_ino).ok_or_else(|| {
+                        node_missing(format!(
                             "remove_node_helper() found fs is inconsistent, \
                             node name={:?} of ino={} found under the parent of ino={}, \
                             but no i-node found for this node",
                             node_name, node_ino, parent
-                        )
-                    });
+                        ))
+                    })?;
                     debug_assert_eq!(node_ino, child_inode.get_ino());
                     debug_assert_eq!(node_name, child_inode.get_name());
                     debug_assert_eq!(parent, child_inode.get_parent_ino());
@@ -263,60 +417,1061 @@ impl FileSystem {
         }
     }
 
-    pub async fn new(full_mount_path: impl AsRef<Path>) -> anyhow::Result<FileSystem> {
-        let root_path = full_mount_path.as_ref();
-        let root_inode =
-            Node::open_root_node(FUSE_ROOT_ID, OsString::from("/"), &root_path).await?;
+    /// Watch the new loaded directory node if watching is enabled
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn watch_dir_helper(&mut self, ino: INum, fd: Option<RawFd>) {
+        #[cfg(target_os = "linux")]
+        {
+            if let (Some(watcher), Some(fd)) = (self.watcher.as_mut(), fd) {
+                if let Err(e) = watcher.watch(ino, fd) {
+                    warn!(
+                        "watch_dir_helper() failed to watch the directory of ino={}, \
+                            the error is: {:?}",
+                        ino, e,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Load the child of `child_name` under the directory of `parent` into cache,
+    /// the lookup count of the new node is 1
+    async fn load_child_helper(
+        &mut self,
+        parent: INum,
+        child_name: OsString,
+        child_type: SFlag,
+    ) -> FsResult<FileAttr> {
+        let read_only = self.read_only;
+        let name = child_name.clone();
+        let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+            node_missing(format!(
+                "load_child_helper() found fs is inconsistent, \
+                parent i-node of ino={} should be in cache",
+                parent,
+            ))
+        })?;
+        let child_node = match child_type {
+            SFlag::S_IFDIR => parent_node.open_child_dir(child_name).await?,
+            SFlag::S_IFREG => {
+                let oflags = if read_only {
+                    OFlag::O_RDONLY
+                } else {
+                    OFlag::O_RDWR
+                };
+                parent_node.open_child_file(child_name, oflags).await?
+            }
+            _ => {
+                return Err(FsError::new(
+                    Errno::EINVAL,
+                    format!(
+                        "load_child_helper() found unsupported file type={:?}",
+                        child_type
+                    ),
+                ))
+            }
+        };
+
+        let child_ino = child_node.get_ino();
+        let child_fd = child_node.get_fd();
+        let attr = child_node.get_attr();
+        self.cache.insert(child_ino, child_node);
+        if let SFlag::S_IFDIR = child_type {
+            self.watch_dir_helper(child_ino, child_fd);
+        }
+        self.export.register(child_ino, child_fd, parent, &name);
+        Ok(attr)
+    }
+
+    /// Add the child opened by a parallel lookup under the directory of `parent`,
+    /// the child may be loaded by another lookup in the meantime, then the cached
+    /// node is kept and its lookup count is increased. Return the attribute of the child
+    fn add_child_helper(&mut self, parent: INum, child_node: Node<B>) -> FsResult<FileAttr> {
+        let child_ino = child_node.get_ino();
+        let child_type = child_node.get_type();
+        let name = child_node.get_name().to_owned();
+        let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+            node_missing(format!(
+                "add_child_helper() found fs is inconsistent, \
+                parent i-node of ino={} should be in cache",
+                parent,
+            ))
+        })?;
+        parent_node.insert_entry(DirEntry::new(child_ino, name.clone(), child_type));
+        if let Some(node) = self.cache.get(&child_ino) {
+            debug!(
+                "add_child_helper() found the child name={:?} of ino={} already loaded",
+                name, child_ino,
+            );
+            return Ok(node.lookup_attr());
+        }
+        let child_fd = child_node.get_fd();
+        let attr = child_node.get_attr();
+        self.cache.insert(child_ino, child_node);
+        if let SFlag::S_IFDIR = child_type {
+            self.watch_dir_helper(child_ino, child_fd);
+        }
+        self.export.register(child_ino, child_fd, parent, &name);
+        Ok(attr)
+    }
+
+    /// Look up the node of `ino` itself for ".", or its parent for "..",
+    /// the nodes forgotten are rebuilt from the backing files
+    async fn lookup_dot_helper(
+        &mut self,
+        ino: INum,
+        dotdot: bool,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        let target = match self.dot_target_helper(ino, dotdot).await {
+            Ok(target) => target,
+            Err(e) => {
+                // the export handle refers to a deleted file, this is normal
+                debug!(
+                    "lookup_dot_helper() failed to find the node for {:?} of ino={}, \
+                        the error is: {}",
+                    if dotdot { ".." } else { "." },
+                    ino,
+                    e,
+                );
+                reply.error(libc::ESTALE).await?;
+                return Ok(());
+            }
+        };
+        let (entry_ttl, attr_ttl) = self.ttls(target);
+        let node = self.cache.get(&target).ok_or_else(|| {
+            node_missing(format!(
+                "lookup_dot_helper() failed to find the i-node of ino={}",
+                target,
+            ))
+        })?;
+        let attr = node.lookup_attr();
+        let fuse_attr = util::convert_to_fuse_attr(attr)?;
+        reply
+            .entry(
+                entry_ttl,
+                attr_ttl,
+                fuse_attr,
+                self.export.generation(target),
+            )
+            .await?;
+        debug!(
+            "lookup_dot_helper() successfully found the node of ino={} for {:?} of ino={}",
+            target,
+            if dotdot { ".." } else { "." },
+            ino,
+        );
+        Ok(())
+    }
+
+    /// The node of `ino` itself, or its parent if `dotdot`, rebuilt if forgotten
+    async fn dot_target_helper(&mut self, ino: INum, dotdot: bool) -> FsResult<INum> {
+        if !self.cache.contains_key(&ino) {
+            self.rebuild_node_helper(ino).await?;
+        }
+        let target = if dotdot {
+            // the parent of the root is itself
+            self.cache
+                .get(&ino)
+                .map_or(FUSE_ROOT_ID, Node::get_parent_ino)
+        } else {
+            ino
+        };
+        if !self.cache.contains_key(&target) {
+            self.rebuild_node_helper(target).await?;
+        }
+        Ok(target)
+    }
+
+    /// Rebuild the forgotten node of `ino` where it was loaded, or at the path
+    /// of its backing file opened by handle in case the file is moved out of band.
+    /// The kernel never looked up the rebuilt nodes, so their lookup counts are 0
+    async fn rebuild_node_helper(&mut self, ino: INum) -> FsResult<()> {
+        if let Err(e) = self.rebuild_at_location_helper(ino).await {
+            debug!(
+                "rebuild_node_helper() failed to rebuild the node of ino={} where \
+                    it was loaded, try its file handle, the error is: {}",
+                ino, e,
+            );
+            self.rebuild_by_handle_helper(ino).await?;
+        }
+        debug!(
+            "rebuild_node_helper() successfully rebuilt the node of ino={}",
+            ino
+        );
+        Ok(())
+    }
+
+    /// Rebuild the node of `ino` and its forgotten ancestors where they were loaded
+    async fn rebuild_at_location_helper(&mut self, ino: INum) -> FsResult<()> {
+        let mut forgotten = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut current = ino;
+        while !self.cache.contains_key(&current) {
+            if !visited.insert(current) {
+                return Err(node_missing(format!(
+                    "rebuild_at_location_helper() found the ancestors of ino={} in a loop",
+                    ino,
+                )));
+            }
+            let (parent, name) = self.export.location(current).ok_or_else(|| {
+                node_missing(format!(
+                    "rebuild_at_location_helper() found no location of ino={}",
+                    current,
+                ))
+            })?;
+            forgotten.push((parent, name.to_owned(), current));
+            current = parent;
+        }
+        for (parent, name, child) in forgotten.into_iter().rev() {
+            let found = self.rebuild_child_helper(parent, &name).await?;
+            if found != child {
+                return Err(node_missing(format!(
+                    "rebuild_at_location_helper() found name={:?} under parent ino={} \
+                        refers to ino={} rather than ino={}",
+                    name, parent, found, child,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the node of `ino` by loading the nodes along the path of its backing file,
+    /// which is connected to the backing directory only if it is a directory or cached
+    #[cfg(target_os = "linux")]
+    async fn rebuild_by_handle_helper(&mut self, ino: INum) -> FsResult<()> {
+        let root_fd = self
+            .cache
+            .get(&FUSE_ROOT_ID)
+            .ok_or_else(|| {
+                node_missing("rebuild_by_handle_helper() found the root i-node missing".to_owned())
+            })?
+            .get_fd()
+            .ok_or_else(|| {
+                node_missing(format!(
+                    "rebuild_by_handle_helper() cannot open the file of ino={} by handle, \
+                        the backend keeps no files on host fds",
+                    ino,
+                ))
+            })?;
+        let fd = self.export.open(ino, root_fd).map_err(|e| {
+            FsError::new(
+                Errno::ESTALE,
+                format!(
+                    "rebuild_by_handle_helper() failed to open the backing file of ino={} \
+                        by its handle, the error is: {}",
+                    ino, e,
+                ),
+            )
+        })?;
+        let paths = blocking!(
+            let path = fcntl::readlink(format!("/proc/self/fd/{}", fd).as_str());
+            let root_path = fcntl::readlink(format!("/proc/self/fd/{}", root_fd).as_str());
+            let _ = unistd::close(fd);
+            path.and_then(|path| root_path.map(|root_path| (path, root_path)))
+        );
+        let (path, root_path) = paths.context(format!(
+            "rebuild_by_handle_helper() failed to find the path of the backing file of ino={}",
+            ino,
+        ))?;
+        let relative_path = Path::new(&path)
+            .strip_prefix(&root_path)
+            .map_err(|_| {
+                FsError::new(
+                    Errno::ESTALE,
+                    format!(
+                        "rebuild_by_handle_helper() found the backing file={:?} of ino={} \
+                            out of the backing directory={:?}",
+                        path, ino, root_path,
+                    ),
+                )
+            })?
+            .to_path_buf();
+        let mut current = FUSE_ROOT_ID;
+        for component in relative_path.iter() {
+            current = self.rebuild_child_helper(current, component).await?;
+        }
+        if current != ino {
+            return Err(node_missing(format!(
+                "rebuild_by_handle_helper() found the path={:?} of ino={} refers to ino={}",
+                relative_path, ino, current,
+            )));
+        }
+        Ok(())
+    }
+
+    /// File handles are not supported
+    #[cfg(not(target_os = "linux"))]
+    async fn rebuild_by_handle_helper(&mut self, ino: INum) -> FsResult<()> {
+        Err(node_missing(format!(
+            "rebuild_by_handle_helper() cannot open the backing file of ino={} by handle",
+            ino,
+        )))
+    }
+
+    /// Load the child of `name` under the cached directory of `parent` if forgotten,
+    /// return the ino of the child
+    async fn rebuild_child_helper(&mut self, parent: INum, name: &OsStr) -> FsResult<INum> {
+        let entry = match self.cache.get_mut(&parent) {
+            Some(parent_node) => parent_node
+                .find_entry(name)
+                .await?
+                .map(|entry| (entry.ino(), entry.entry_type())),
+            None => None,
+        };
+        let (child_ino, child_type) = entry.ok_or_else(|| {
+            node_missing(format!(
+                "rebuild_child_helper() failed to find name={:?} under parent ino={}, \
+                    the backing file may be deleted",
+                name, parent,
+            ))
+        })?;
+        if !self.cache.contains_key(&child_ino) {
+            self.load_child_helper(parent, name.to_owned(), child_type)
+                .await?;
+            if let Some(node) = self.cache.get(&child_ino) {
+                node.dec_lookup_count_by(1);
+            }
+        }
+        Ok(child_ino)
+    }
+
+    /// Drop the node forgotten by the kernel unless it is still open, the node is
+    /// loaded again by lookup, or rebuilt from its backing file for export
+    fn evict_node_helper(&mut self, ino: INum) {
+        if ino == FUSE_ROOT_ID {
+            return;
+        }
+        // the node holds one open count of its own fd
+        let still_open = self
+            .cache
+            .get(&ino)
+            .map_or(true, |node| node.get_open_count() > 1);
+        if still_open {
+            return;
+        }
+        if let Some(node) = self.cache.remove(&ino) {
+            #[cfg(target_os = "linux")]
+            {
+                if let (SFlag::S_IFDIR, Some(watcher)) = (node.get_type(), self.watcher.as_mut()) {
+                    watcher.unwatch_node(ino);
+                }
+            }
+            debug!(
+                "evict_node_helper() evicted the forgotten node name={:?} of ino={}",
+                node.get_name(),
+                ino,
+            );
+        }
+    }
+
+    /// The backend and the handle of the open directory of `fh`
+    /// to read the directory of `ino`
+    fn dir_handle_helper(&self, ino: INum, fh: u64) -> FsResult<(Arc<B>, B::Handle)> {
+        let handle = self.handles.get(fh, ino)?.get_handle();
+        if !self.cache.contains_key(&ino) {
+            return Err(node_missing(format!(
+                "dir_handle_helper() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            )));
+        }
+        Ok((self.backend.clone(), handle))
+    }
+
+    /// Fill the readdir reply with the entries of the directory of `ino` read through
+    /// the handle of its open, right after the entry of the cookie `offset`.
+    /// The filesystem is not borrowed, so the readdirs of the same directory run in parallel
+    async fn fill_dir_helper(
+        backend: &B,
+        ino: INum,
+        handle: B::Handle,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) -> FsResult<()> {
+        // The offsets are the cookies of the backing directory stream rather than
+        // the indexes of the entries, which shift once the directory is changed
+        let mut next_offset = offset;
+        let mut num_child_entries = 0;
+        'fill: loop {
+            let entries = backend
+                .readdir(handle, next_offset, READDIR_BATCH)
+                .await
+                .context(format!(
+                    "fill_dir_helper() failed to read the directory of ino={} from offset={}",
+                    ino, next_offset,
+                ))?;
+            let end_of_dir = entries.len() < READDIR_BATCH;
+            for child_entry in entries {
+                let full = reply.add(
+                    child_entry.ino(),
+                    child_entry.offset(),
+                    child_entry.entry_type(),
+                    child_entry.entry_name(),
+                );
+                if full {
+                    // the rest is read again by the next readdir
+                    break 'fill;
+                }
+                next_offset = child_entry.offset();
+                num_child_entries += 1;
+                debug!(
+                    "fill_dir_helper() found one child name={:?} ino={} offset={} \
+                        under the directory of ino={}",
+                    child_entry.entry_name(),
+                    child_entry.ino(),
+                    child_entry.offset(),
+                    ino,
+                );
+            }
+            if end_of_dir {
+                break;
+            }
+        }
+        reply.ok().await?;
+        debug!(
+            "fill_dir_helper() successfully read {} children \
+                under the directory of ino={}",
+            num_child_entries, ino,
+        );
+        Ok(())
+    }
+
+    /// Revalidate the node against the underlying file,
+    /// and return the kernel caches to invalidate if it is changed
+    async fn revalidate_helper(&mut self, ino: INum) -> FsResult<Vec<Invalidation>> {
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "revalidate_helper() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let mut invalidations = Vec::new();
+        if let Some(changed_names) = node.revalidate().await? {
+            invalidations.push(Invalidation::Inode(ino));
+            invalidations.extend(
+                changed_names
+                    .into_iter()
+                    .map(|name| Invalidation::Entry(ino, name)),
+            );
+        }
+        Ok(invalidations)
+    }
+
+    /// Invalidate the kernel caches of the changed nodes. The notifications are sent
+    /// by another task after the filesystem lock released, since the kernel may wait
+    /// for the requests in flight to invalidate directory entries
+    fn spawn_invalidations(&self, invalidations: Vec<Invalidation>) {
+        if invalidations.is_empty() {
+            return;
+        }
+        #[cfg(feature = "abi-7-12")]
+        {
+            if let Some(notifier) = self.notifier {
+                Task::spawn(async move {
+                    for invalidation in invalidations {
+                        let res = match &invalidation {
+                            Invalidation::Inode(ino) => notifier.inval_inode(*ino, 0, 0).await,
+                            Invalidation::Entry(parent, name) => {
+                                notifier.inval_entry(*parent, name).await
+                            }
+                        };
+                        // the kernel replies ENOENT if nothing is cached, which is normal
+                        if let Err(e) = res {
+                            debug!(
+                                "spawn_invalidations() failed to invalidate {:?}, \
+                                    the error is: {:?}",
+                                invalidation, e,
+                            );
+                        }
+                    }
+                })
+                .detach();
+                return;
+            }
+        }
+        debug!(
+            "spawn_invalidations() cannot notify the kernel to invalidate {:?}, \
+                the kernel caches expire with the TTL",
+            invalidations,
+        );
+    }
+
+    /// The cache policy of the node, the parts not set on the node are inherited
+    /// from the nearest ancestors setting them
+    async fn cache_policy_helper(&mut self, ino: INum) -> FsResult<CachePolicy> {
+        let mut policy = CachePolicy::default();
+        let mut ino = ino;
+        // the ancestors of a deferred deleted node may be forgotten already
+        while let Some(node) = self.cache.get_mut(&ino) {
+            policy = policy.or(node.cache_policy().await?);
+            if policy.is_complete() || ino == FUSE_ROOT_ID {
+                break;
+            }
+            ino = node.get_parent_ino();
+        }
+        Ok(policy)
+    }
+
+    /// Whether the node of `ino` is the directory of `dir` or under it
+    fn is_under_helper(&self, ino: INum, dir: INum) -> bool {
+        let mut ino = ino;
+        loop {
+            if ino == dir {
+                return true;
+            }
+            match self.cache.get(&ino) {
+                Some(node) if ino != FUSE_ROOT_ID => ino = node.get_parent_ino(),
+                _ => return false,
+            }
+        }
+    }
+
+    /// The bytes of the file data cached, of the file of `ino`, or the files under
+    /// the directory of `ino`
+    fn cached_bytes_helper(&self, ino: INum) -> u64 {
+        self.cache
+            .values()
+            .filter(|node| node.cached_bytes() > 0 && self.is_under_helper(node.get_ino(), ino))
+            .map(Node::cached_bytes)
+            .sum()
+    }
+
+    /// Drop the cached data of the files not to be cached any more,
+    /// once the cache policy of the file or the directory of `ino` is changed
+    async fn apply_cache_policy_helper(&mut self, ino: INum) -> FsResult<()> {
+        let cached_inos: Vec<INum> = self
+            .cache
+            .values()
+            .filter(|node| node.cached_bytes() > 0 && self.is_under_helper(node.get_ino(), ino))
+            .map(Node::get_ino)
+            .collect();
+        for cached_ino in cached_inos {
+            if self.cache_policy_helper(cached_ino).await?.cache_mode() != CacheMode::Uncached {
+                continue;
+            }
+            if let Some(node) = self.cache.get_mut(&cached_ino) {
+                debug!(
+                    "apply_cache_policy_helper() dropped {} bytes cached of ino={}",
+                    node.cached_bytes(),
+                    cached_ino,
+                );
+                node.drop_data();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem<LocalBackend> {
+    /// Serve the files under the backing directory of `source`,
+    /// which is independent of the mountpoint
+    pub async fn new(
+        source: impl AsRef<Path>,
+        revalidation: Revalidation,
+    ) -> anyhow::Result<FileSystem> {
+        let root_path = source.as_ref();
+        let filesystem = FileSystem::with_backend(LocalBackend::new(root_path), revalidation)
+            .await
+            .context(format!(
+                "failed to open the source directory={:?}",
+                root_path
+            ))?;
+        Ok(filesystem)
+    }
+}
+
+impl<B: StorageBackend> FileSystem<B> {
+    /// Serve the files stored by `backend`, only the backends keeping the files
+    /// on host fds can be watched for the changes out of band
+    pub async fn with_backend(
+        backend: B,
+        revalidation: Revalidation,
+    ) -> anyhow::Result<FileSystem<B>> {
+        let backend = Arc::new(backend);
+        let root_inode = Node::open_root(backend.clone(), FUSE_ROOT_ID, OsString::from("/"))
+            .await
+            .context("failed to open the root directory of the backend")?;
+        #[cfg(target_os = "linux")]
+        let watcher = if let Revalidation::Watch = revalidation {
+            let root_fd = root_inode.get_fd().ok_or_else(|| {
+                anyhow::anyhow!("the backend keeps no files on host fds to watch")
+            })?;
+            let mut watcher = Watcher::new()?;
+            watcher.watch(FUSE_ROOT_ID, root_fd)?;
+            Some(watcher)
+        } else {
+            None
+        };
+        let mut export = ExportTable::default();
+        export.register(
+            FUSE_ROOT_ID,
+            root_inode.get_fd(),
+            FUSE_ROOT_ID,
+            root_inode.get_name(),
+        );
         let mut cache = BTreeMap::new();
         cache.insert(FUSE_ROOT_ID, root_inode);
         let trash = BTreeSet::new(); // for deferred deletion
 
-        Ok(FileSystem { cache, trash })
+        Ok(FileSystem {
+            backend,
+            cache,
+            trash,
+            handles: HandleTable::default(),
+            export,
+            passthrough_fd: None,
+            conn_info: None,
+            revalidation,
+            #[cfg(target_os = "linux")]
+            watcher,
+            #[cfg(feature = "abi-7-12")]
+            notifier: None,
+            read_only: false,
+            timeouts: BTreeMap::new(),
+            open_policy: OpenPolicy::default(),
+        })
+    }
+
+    /// Serve the files read-only, the backing files are opened read-only,
+    /// and the kernel caches the data and the attributes for long
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Set the TTLs of the kernel caches of the subtrees, the TTLs not set
+    /// fall back to the ones of the parent directories, then to the defaults
+    pub fn set_timeouts(&mut self, timeouts: BTreeMap<PathBuf, CacheTimeouts>) {
+        self.timeouts = timeouts;
+    }
+
+    /// The default TTL of the attributes and the entries replied to the kernel
+    fn default_ttl(&self) -> Duration {
+        if self.read_only {
+            Duration::new(READ_ONLY_TTL_SEC, 0)
+        } else {
+            Duration::new(MY_TTL_SEC, 0)
+        }
+    }
+
+    /// The TTLs configured for the path relative to the root, the deeper subtrees
+    /// override the ones containing them
+    fn path_timeouts(&self, path: &Path) -> CacheTimeouts {
+        // the ancestors of the path sort before their descendants
+        self.timeouts
+            .iter()
+            .rev()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .fold(CacheTimeouts::default(), |timeouts, (_, subtree)| {
+                timeouts.or(*subtree)
+            })
     }
 
+    /// The path of the node relative to the root
+    fn node_path(&self, ino: INum) -> PathBuf {
+        let mut names = Vec::new();
+        let mut ino = ino;
+        while ino != FUSE_ROOT_ID {
+            match self.cache.get(&ino) {
+                Some(node) => {
+                    names.push(node.get_name());
+                    ino = node.get_parent_ino();
+                }
+                None => break,
+            }
+        }
+        names.into_iter().rev().collect()
+    }
+
+    /// The TTLs of the entry and the attributes of the node replied to the kernel
+    fn ttls(&self, ino: INum) -> (Duration, Duration) {
+        let ttl = self.default_ttl();
+        if self.timeouts.is_empty() {
+            return (ttl, ttl);
+        }
+        let timeouts = self.path_timeouts(&self.node_path(ino));
+        (timeouts.entry.unwrap_or(ttl), timeouts.attr.unwrap_or(ttl))
+    }
+
+    /// The TTL of the attributes of the node replied to the kernel
+    fn attr_ttl(&self, ino: INum) -> Duration {
+        self.ttls(ino).1
+    }
+
+    /// The TTL of the negative entry of the missing name under the parent,
+    /// zero if negative entries are not cached
+    fn negative_ttl(&self, parent: INum, name: &OsStr) -> Duration {
+        if self.timeouts.is_empty() {
+            return Duration::default();
+        }
+        let path = self.node_path(parent).join(name);
+        self.path_timeouts(&path).negative.unwrap_or_default()
+    }
+
+    /// Set the rules choosing the flags of the opened files
+    pub fn set_open_policy(&mut self, open_policy: OpenPolicy) {
+        self.open_policy = open_policy;
+    }
+
+    /// The flags of the opened file replied to the kernel, chosen by the open policy,
+    /// the cache policy and the xattr of the backing file, `truncating` if opened with
+    /// O_TRUNC. Keep the page cache across opens if the data never changes through the mount
+    async fn open_flags(&self, ino: INum, truncating: bool, policy: CachePolicy) -> FsResult<u32> {
+        let node = self.cache.get(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "open_flags() found fs is inconsistent, the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let size = if truncating { 0 } else { node.get_attr().size };
+        let mut flags = self.open_policy.flags(&self.node_path(ino), size);
+        if self.read_only {
+            flags |= FOPEN_KEEP_CACHE;
+        }
+        match policy.cache_mode() {
+            CacheMode::Pin => flags |= FOPEN_KEEP_CACHE,
+            CacheMode::Normal => {}
+            CacheMode::Uncached => flags |= FOPEN_DIRECT_IO,
+        }
+        if let Some(value) = node.get_xattr(OsStr::new(OPEN_FLAGS_XATTR)).await? {
+            flags |= parse_open_flags(&value);
+        }
+        Ok(flags)
+    }
+
+    /// Set the notifier to invalidate the kernel caches once the backing directory
+    /// is changed out of band
+    #[cfg(feature = "abi-7-12")]
+    fn set_notifier(&mut self, notifier: Notifier) {
+        debug!("set_notifier(notifier={:?})", notifier);
+        self.notifier = Some(notifier);
+    }
+
+    /// The inotify instance to read the changes of the loaded directories,
+    /// None if not watching
+    #[cfg(target_os = "linux")]
+    pub fn inotify(&self) -> Option<Inotify> {
+        self.watcher.as_ref().map(|watcher| watcher.inotify())
+    }
+
+    /// Revalidate the nodes reported changed by the inotify events,
+    /// and invalidate the kernel caches of the changed nodes
+    #[cfg(target_os = "linux")]
+    pub async fn handle_watch_events(&mut self, events: Vec<InotifyEvent>) -> anyhow::Result<()> {
+        let caches_negative = self.timeouts.values().any(|timeouts| {
+            timeouts
+                .negative
+                .map_or(false, |ttl| ttl > Duration::default())
+        });
+        let watcher = match self.watcher.as_mut() {
+            Some(watcher) => watcher,
+            None => return Ok(()),
+        };
+        let mut changed = BTreeSet::new();
+        let mut invalidations = Vec::new();
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                warn!("handle_watch_events() found inotify events lost, revalidate all nodes");
+                changed.extend(self.cache.keys().copied());
+                continue;
+            }
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                watcher.unwatch(event.wd);
+                continue;
+            }
+            let ino = match watcher.resolve(event.wd) {
+                Some(ino) => ino,
+                None => continue,
+            };
+            changed.insert(ino);
+            // the event of a child in the watched directory
+            if let (Some(name), Some(node)) = (&event.name, self.cache.get(&ino)) {
+                match node.get_entry(name) {
+                    Some(child_entry) => {
+                        changed.insert(child_entry.ino());
+                    }
+                    // the kernel may cache the new name as a negative entry
+                    None if caches_negative
+                        && event
+                            .mask
+                            .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) =>
+                    {
+                        invalidations.push(Invalidation::Entry(ino, name.clone()));
+                    }
+                    None => {}
+                }
+            }
+        }
+        for ino in changed {
+            // the child may not be loaded, or the directory may be forgotten
+            if !self.cache.contains_key(&ino) {
+                continue;
+            }
+            match self.revalidate_helper(ino).await {
+                Ok(node_invalidations) => invalidations.extend(node_invalidations),
+                Err(e) => warn!(
+                    "handle_watch_events() failed to revalidate the node of ino={}, \
+                        the error is: {:?}",
+                    ino, e,
+                ),
+            }
+        }
+        self.spawn_invalidations(invalidations);
+        Ok(())
+    }
+
+    /// Enable passthrough once FUSE_PASSTHROUGH is negotiated,
+    /// backing files are registered to the FUSE device of `fuse_fd`
+    fn enable_passthrough(&mut self, fuse_fd: RawFd) {
+        debug!("enable_passthrough(fuse_fd={})", fuse_fd);
+        self.passthrough_fd = Some(fuse_fd);
+    }
+
+    /// Record the features negotiated with the kernel
+    fn set_conn_info(&mut self, conn: ConnInfo) {
+        debug!("set_conn_info(conn={:?})", conn);
+        self.conn_info = Some(conn);
+    }
+
+    /// Whether all the init features of `flags` are negotiated with the kernel
+    #[cfg_attr(not(feature = "abi-7-9"), allow(dead_code))]
+    fn is_enabled(&self, flags: u32) -> bool {
+        self.conn_info.map_or(false, |conn| conn.is_enabled(flags))
+    }
+
+    async fn fsync_helper(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        let handle = self.handles.get(fh, ino)?.get_handle();
+        self.backend.sync(handle, datasync).await.context(format!(
+            "fsync_helper() failed to flush the node of ino={}",
+            ino
+        ))?;
+        let dirty = self.handles.get_mut(fh, ino)?.dirty_mut();
+        let dirty_size = dirty.size();
+        dirty.clear();
+
+        reply.ok().await?;
+        debug!(
+            "fsync_helper() successfully sync the node of ino={}, fh={}, datasync={:?}, \
+                {} bytes written since the last sync",
+            ino, fh, datasync, dirty_size,
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> Filesystem for FileSystem<B> {
     /// Initialize filesystem.
     /// Called before any other filesystem method.
-    pub fn init(&mut self, _req: &Request<'_>) -> anyhow::Result<()> {
+    /// The filesystem wants or refuses the features offered by the kernel through `conn`.
+    #[cfg_attr(not(feature = "abi-7-9"), allow(unused_variables))]
+    async fn init(&mut self, _req: &Request<'_>, conn: &mut ConnInfo) -> FsResult<()> {
+        // O_TRUNC is handled in open()
+        #[cfg(feature = "abi-7-9")]
+        conn.want(FUSE_ATOMIC_O_TRUNC);
+        // The cached file data should be dropped once the kernel finds the file changed
+        #[cfg(feature = "abi-7-20")]
+        conn.want(FUSE_AUTO_INVAL_DATA);
+        // The session locks the directories, so the lookups and the readdirs
+        // under a directory run in parallel, and the changes of it exclusively
+        #[cfg(feature = "abi-7-25")]
+        conn.want(FUSE_PARALLEL_DIROPS);
+        // Lookups of "." and ".." are handled, so the mount can be exported
+        #[cfg(feature = "abi-7-10")]
+        conn.want(FUSE_EXPORT_SUPPORT);
+        // The set-user-ID and set-group-ID bits are cleared by setattr() and write()
+        #[cfg(feature = "abi-7-26")]
+        conn.want(FUSE_HANDLE_KILLPRIV);
+        Ok(())
+    }
+
+    fn negotiated(&mut self, conn: &ConnInfo, fuse_fd: RawFd) {
+        #[cfg(target_os = "linux")]
+        {
+            if conn.is_enabled2(FUSE_PASSTHROUGH) {
+                self.enable_passthrough(fuse_fd);
+            }
+        }
+        #[cfg(feature = "abi-7-12")]
+        self.set_notifier(Notifier::new(fuse_fd));
+        // Record the negotiated features for the handlers to check
+        self.set_conn_info(*conn);
+    }
+
+    /// Watch the backing directory after init, so that the kernel
+    /// is ready to receive the invalidation notifications
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn spawn_background(fs: Arc<Mutex<Self>>) {
+        #[cfg(target_os = "linux")]
+        Task::spawn(async move {
+            if let Err(e) = run_watcher(fs).await {
+                error!(
+                    "failed to watch the backing directory, the error is: {:?}",
+                    e
+                );
+            }
+        })
+        .detach();
+    }
+
+    /// Save the nodes and the open file handles, the cached data is reloaded
+    /// by the new daemon from the backing files. Only the backends keeping the files
+    /// on host fds can hand them over
+    fn save_state(&self) -> anyhow::Result<SavedState> {
+        let mut enc = Encoder::default();
+        let mut fds = Vec::with_capacity(self.cache.len() + self.handles.len());
+        enc.put_u64(self.cache.len() as u64);
+        for node in self.cache.values() {
+            fds.push(node.save(&mut enc)?);
+        }
+        enc.put_u64(self.trash.len() as u64);
+        for &ino in &self.trash {
+            enc.put_u64(ino);
+        }
+        let backend = &self.backend;
+        let handle_fds = self.handles.save(&mut enc, |handle| {
+            backend.raw_fd(handle).ok_or_else(|| {
+                anyhow::anyhow!("save_state() found the open file not kept on a host fd")
+            })
+        })?;
+        fds.extend(handle_fds);
+        self.export.save(&mut enc);
+        debug!(
+            "save_state() saved {} nodes and {} file handles",
+            self.cache.len(),
+            self.handles.len(),
+        );
+        Ok(SavedState::new(enc.into_bytes(), fds))
+    }
+
+    /// Replace the nodes with the ones of the old daemon, the open files keep
+    /// their fh, which the kernel refers to, and are restored at the same fds
+    async fn restore_state(&mut self, state: SavedState) -> anyhow::Result<()> {
+        let mut dec = Decoder::new(state.data());
+        let node_count = dec.get_u64()?;
+        let mut cache = BTreeMap::new();
+        for _ in 0..node_count {
+            let node = Node::restore(self.backend.clone(), &mut dec, self.passthrough_fd).await?;
+            cache.insert(node.get_ino(), node);
+        }
+        if !cache.contains_key(&FUSE_ROOT_ID) {
+            return Err(anyhow::anyhow!(
+                "restore_state() found no root i-node in the saved state"
+            ));
+        }
+        let mut trash = BTreeSet::new();
+        for _ in 0..dec.get_u64()? {
+            trash.insert(dec.get_u64()?);
+        }
+        let backend = &self.backend;
+        let handles = HandleTable::restore(&mut dec, |fd| {
+            backend.from_raw_fd(fd).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "restore_state() found the backend keeps no files on host fds, \
+                        cannot restore the open file of fd={}",
+                    fd,
+                )
+            })
+        })?;
+        let export = ExportTable::restore(&mut dec)?;
+        if !dec.is_empty() {
+            return Err(anyhow::anyhow!(
+                "restore_state() found trailing bytes in the saved state"
+            ));
+        }
+        // The loaded nodes are cached as the entries of their parents,
+        // except the root and the deleted ones
+        let entries: Vec<(INum, DirEntry)> = cache
+            .values()
+            .filter(|node| node.get_ino() != FUSE_ROOT_ID && !trash.contains(&node.get_ino()))
+            .map(|node| {
+                let entry = DirEntry::new(node.get_ino(), node.get_name().into(), node.get_type());
+                (node.get_parent_ino(), entry)
+            })
+            .collect();
+        for (parent, entry) in entries {
+            if let Some(parent_node) = cache.get_mut(&parent) {
+                parent_node.insert_entry(entry);
+            }
+        }
+        let dirs: Vec<(INum, Option<RawFd>)> = cache
+            .values()
+            .filter(|node| node.get_type() == SFlag::S_IFDIR)
+            .map(|node| (node.get_ino(), node.get_fd()))
+            .collect();
+        self.cache = cache;
+        self.trash = trash;
+        self.handles = handles;
+        self.export = export;
+        for (ino, fd) in dirs {
+            self.watch_dir_helper(ino, fd);
+        }
+        debug!(
+            "restore_state() restored {} nodes and {} file handles",
+            self.cache.len(),
+            self.handles.len(),
+        );
         Ok(())
     }
 
-    /// Clean up filesystem.
-    /// Called on filesystem exit.
-    pub fn destroy(&mut self, _req: &Request<'_>) {}
+    /// The writes go to the backend directly, so flush the backend
+    async fn sync_all(&mut self) -> FsResult<()> {
+        let root_node = self.cache.get(&FUSE_ROOT_ID).ok_or_else(|| {
+            node_missing("sync_all() found the root i-node missing from cache".to_owned())
+        })?;
+        self.backend
+            .sync_all(root_node.get_handle())
+            .await
+            .context("sync_all() failed to flush the backend")?;
+        debug!("sync_all() successfully flushed the backend");
+        Ok(())
+    }
 
     /// Look up a directory entry by name and get its attributes.
-    pub async fn lookup(
+    async fn lookup(
         &mut self,
         req: &Request<'_>,
         parent: u64,
         name: &OsStr,
         reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         let child_name = OsString::from(name);
         debug!(
             "lookup(parent={}, name={:?}, req={:?})",
             parent, child_name, req,
         );
+        // The kernel looks up "." and ".." with FUSE_EXPORT_SUPPORT,
+        // to find the nodes and their parents referred by the export handles
+        if name == "." || name == ".." {
+            return self.lookup_dot_helper(parent, name == "..", reply).await;
+        }
 
         let ino: u64;
         let child_type: SFlag;
         {
             // lookup child ino and type first
-            let parent_node = self.cache.get(&parent);
-            debug_assert!(
-                parent_node.is_some(),
-                "lookup() found fs is inconsistent, \
-                        the parent i-node of ino={} should be in cache",
-                parent
-            );
-            let parent_node = parent_node.unwrap(); // safe to use unwrap() here
-            match parent_node.get_entry(&child_name) {
+            let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+                node_missing(format!(
+                    "lookup() found fs is inconsistent, \
+                    the parent i-node of ino={} should be in cache",
+                    parent
+                ))
+            })?;
+            match parent_node.find_entry(&child_name).await? {
                 Some(child_entry) => {
                     ino = child_entry.ino();
                     child_type = child_entry.entry_type();
                 }
                 None => {
-                    reply.error(ENOENT).await?;
+                    let negative_ttl = self.negative_ttl(parent, &child_name);
+                    reply_missing(reply, negative_ttl).await?;
                     debug!(
                         "lookup() failed to find the file name={:?} \
                             under parent directory of ino={}",
@@ -328,7 +1483,6 @@ impl FileSystem {
             }
         }
 
-        let ttl = Duration::new(MY_TTL_SEC, 0);
         {
             // cache hit
             if let Some(node) = self.cache.get(&ino) {
@@ -339,7 +1493,10 @@ impl FileSystem {
                 );
                 let attr = node.lookup_attr();
                 let fuse_attr = util::convert_to_fuse_attr(attr)?;
-                reply.entry(ttl, fuse_attr, MY_GENERATION).await?;
+                let (entry_ttl, attr_ttl) = self.ttls(ino);
+                reply
+                    .entry(entry_ttl, attr_ttl, fuse_attr, self.export.generation(ino))
+                    .await?;
                 debug!(
                     "lookup() successfully found the file name={:?} of \
                         ino={} under parent ino={}, the attr={:?}",
@@ -355,28 +1512,14 @@ impl FileSystem {
                     and file name={:?} of ino={}",
                 parent, child_name, ino,
             );
-            let parent_node = self.cache.get_mut(&parent);
-            debug_assert!(
-                parent_node.is_some(),
-                "lookup() found fs is inconsistent, \
-                        parent i-node of ino={} should be in cache",
-                parent,
-            );
-            let parent_node = parent_node.unwrap(); // safe to use unwrap() here
-            let child_node = match child_type {
-                SFlag::S_IFDIR => parent_node.open_child_dir(child_name).await?,
-                SFlag::S_IFREG => {
-                    let oflags = OFlag::O_RDWR;
-                    parent_node.open_child_file(child_name, oflags).await?
-                }
-                _ => panic!("lookup() found unsupported file type={:?}", child_type),
-            };
-
-            let child_ino = child_node.get_ino();
-            let attr = child_node.lookup_attr();
-            self.cache.insert(child_ino, child_node);
+            let attr = self
+                .load_child_helper(parent, child_name, child_type)
+                .await?;
             let fuse_attr = util::convert_to_fuse_attr(attr)?;
-            reply.entry(ttl, fuse_attr, MY_GENERATION).await?;
+            let (entry_ttl, attr_ttl) = self.ttls(ino);
+            reply
+                .entry(entry_ttl, attr_ttl, fuse_attr, self.export.generation(ino))
+                .await?;
             debug!(
                 "lookup() successfully found the file name={:?} of ino={} \
                     under parent ino={}, the attr={:?}",
@@ -386,29 +1529,119 @@ impl FileSystem {
         }
     }
 
-    /// Get file attributes.
-    pub async fn getattr(
-        &mut self,
+    /// Look up a directory entry by name without the filesystem lock. The cached
+    /// entries are served under the lock, otherwise the backing file is looked up and
+    /// opened without the lock, then added to the cache under the lock again
+    async fn lookup_shared(
+        fs: &Mutex<Self>,
         req: &Request<'_>,
-        ino: INum,
-        reply: ReplyAttr,
-    ) -> anyhow::Result<()> {
-        debug!("getattr(ino={}, req={:?})", ino, req);
+        parent: u64,
+        name: &OsStr,
+        reply: ReplyEntry,
+    ) -> FsResult<()> {
+        let (backend, parent_handle, entry, oflags, negative_ttl) = {
+            let mut filesystem = fs.lock().await;
+            let cached = filesystem.cache.get(&parent).map(|parent_node| {
+                let handle = parent_node.get_handle();
+                let entry = parent_node
+                    .get_entry(name)
+                    .map(|entry| (entry.ino(), entry.entry_type()));
+                (handle, entry)
+            });
+            let (parent_handle, entry) = match cached {
+                // the missing parent, the dot entries and the loaded children
+                None => return filesystem.lookup(req, parent, name, reply).await,
+                Some(_) if name == "." || name == ".." => {
+                    return filesystem.lookup(req, parent, name, reply).await;
+                }
+                Some((_, Some((ino, _)))) if filesystem.cache.contains_key(&ino) => {
+                    return filesystem.lookup(req, parent, name, reply).await;
+                }
+                Some(cached) => cached,
+            };
+            let oflags = if filesystem.read_only {
+                OFlag::O_RDONLY
+            } else {
+                OFlag::O_RDWR
+            };
+            let negative_ttl = filesystem.negative_ttl(parent, name);
+            let backend = filesystem.backend.clone();
+            (backend, parent_handle, entry, oflags, negative_ttl)
+        };
+        debug!(
+            "lookup_shared(parent={}, name={:?}, req={:?})",
+            parent, name, req,
+        );
 
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "getattr() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
+        // The kernel holds the parent during the lookup, so the handle of the parent stays open
+        let child_type = match entry {
+            Some((_, child_type)) => child_type,
+            None => {
+                let found = backend.lookup(parent_handle, name).await?;
+                match found {
+                    Some(child_entry) => child_entry.entry_type(),
+                    None => {
+                        reply_missing(reply, negative_ttl).await?;
+                        debug!(
+                            "lookup_shared() failed to find the file name={:?} \
+                                under parent directory of ino={}",
+                            name, parent,
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let child_node = Node::open_child(
+            backend,
+            parent,
+            parent_handle,
+            name.to_owned(),
+            child_type,
+            oflags,
+        )
+        .await?;
+        let child_ino = child_node.get_ino();
+
+        let mut filesystem = fs.lock().await;
+        let attr = filesystem.add_child_helper(parent, child_node)?;
+        let fuse_attr = util::convert_to_fuse_attr(attr)?;
+        let generation = filesystem.export.generation(child_ino);
+        let (entry_ttl, attr_ttl) = filesystem.ttls(child_ino);
+        drop(filesystem);
+        reply
+            .entry(entry_ttl, attr_ttl, fuse_attr, generation)
+            .await?;
+        debug!(
+            "lookup_shared() successfully found the file name={:?} of ino={} \
+                under parent ino={}, the attr={:?}",
+            name, child_ino, parent, &attr,
         );
-        let node = node.unwrap(); // safe to use unwrap() here
-        let attr = node.get_attr();
+        Ok(())
+    }
+
+    /// Get file attributes.
+    async fn getattr(&mut self, req: &Request<'_>, ino: INum, reply: ReplyAttr) -> FsResult<()> {
+        debug!("getattr(ino={}, req={:?})", ino, req);
+
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "getattr() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let attr = if node.is_passthrough() {
+            // the file is modified by the kernel directly in passthrough mode
+            node.reload_attr().await?
+        } else {
+            node.get_attr()
+        };
         debug!(
             "getattr() cache hit when searching the attribute of ino={}",
             ino,
         );
-        let ttl = Duration::new(MY_TTL_SEC, 0);
+        let ttl = self.attr_ttl(ino);
         let fuse_attr = util::convert_to_fuse_attr(attr)?;
         reply.attr(ttl, fuse_attr).await?;
         debug!(
@@ -426,28 +1659,92 @@ impl FileSystem {
     /// anything in fh. There are also some flags (direct_io, keep_cache) which the
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
     /// structure in <fuse_common.h> for more details.
-    pub async fn open(
+    async fn open(
         &mut self,
         req: &Request<'_>,
         ino: INum,
         flags: u32,
         reply: ReplyOpen,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!("open(ino={}, flags={}, req={:?})", ino, flags, req);
 
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "open() found fs is inconsistent, the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
+        // The kernel passes O_TRUNC to open only if FUSE_ATOMIC_O_TRUNC is negotiated
+        #[cfg(feature = "abi-7-9")]
+        let atomic_o_trunc = self.is_enabled(FUSE_ATOMIC_O_TRUNC);
+        #[cfg(not(feature = "abi-7-9"))]
+        let atomic_o_trunc = false;
+        if let Revalidation::OnOpen = self.revalidation {
+            let invalidations = self.revalidate_helper(ino).await?;
+            self.spawn_invalidations(invalidations);
+        }
         let oflags = util::parse_oflag(flags);
-        let new_fd = node.dup_fd(oflags).await?;
-        reply.opened(new_fd as u64, flags).await?;
+        let truncating = atomic_o_trunc && oflags.contains(OFlag::O_TRUNC);
+        let policy = self.cache_policy_helper(ino).await?;
+        let open_flags = self.open_flags(ino, truncating, policy).await?;
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "open() found fs is inconsistent, the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        // the files bypassing the page cache, or pinned in the cache, are served by the daemon
+        let pin = policy.cache_mode() == CacheMode::Pin;
+        let try_passthrough = match self.passthrough_fd {
+            Some(fuse_fd)
+                if node.can_passthrough() && !pin && open_flags & FOPEN_DIRECT_IO == 0 =>
+            {
+                Some(fuse_fd)
+            }
+            _ => None,
+        };
+        if truncating {
+            node.truncate_file(0).await?;
+            debug!("open() truncated the file of ino={} for O_TRUNC", ino);
+        }
+        if pin && node.need_load_file_data() {
+            node.load_data().await?;
+            debug!("open() loaded the data of the pinned file of ino={}", ino);
+        }
+        let new_handle = node.dup_handle(oflags).await?;
+        if policy.prefetch() == Prefetch::Sequential {
+            // the advice applies to the open file description shared by the duplicated fds
+            if let Some(new_fd) = self.backend.raw_fd(new_handle) {
+                if let Err(e) = blocking!(util::advise_sequential(new_fd)) {
+                    debug!(
+                        "open() failed to advise sequential reads of ino={}, the error is: {}",
+                        ino, e,
+                    );
+                }
+            }
+        }
+        let fh = self.handles.insert(OpenFile::new(ino, new_handle, oflags));
+        if let Some(fuse_fd) = try_passthrough {
+            match node.open_backing(fuse_fd).await {
+                Ok(backing_id) => {
+                    reply.passthrough(fh, open_flags, backing_id).await?;
+                    debug!(
+                        "open() successfully opened the file of ino={} in passthrough mode, \
+                            fh={}, handle={:?}, flags={:?}, open flags={:#x}, backing id={}",
+                        ino, fh, new_handle, flags, open_flags, backing_id,
+                    );
+                    return Ok(());
+                }
+                Err(e) => {
+                    // fall back to the normal mode, e.g. lack of CAP_SYS_ADMIN
+                    warn!(
+                        "open() failed to open the file of ino={} in passthrough mode, \
+                            disable passthrough, the error is: {:?}",
+                        ino, e,
+                    );
+                    self.passthrough_fd = None;
+                }
+            }
+        }
+        reply.opened(fh, open_flags).await?;
         debug!(
-            "open() successfully duplicated the file handler of ino={}, fd={}, flags={:?}",
-            ino, new_fd, flags,
+            "open() successfully duplicated the file handler of ino={}, fh={}, handle={:?}, \
+                flags={:?}, open flags={:#x}",
+            ino, fh, new_handle, flags, open_flags,
         );
         Ok(())
     }
@@ -459,18 +1756,17 @@ impl FileSystem {
     /// each forget. The filesystem may ignore forget calls, if the inodes don't need to
     /// have a limited lifetime. On unmount it is not guaranteed, that all referenced
     /// inodes will receive a forget message.
-    pub fn forget(&mut self, req: &Request<'_>, ino: u64, nlookup: u64) {
+    async fn forget(&mut self, req: &Request<'_>, ino: u64, nlookup: u64) -> FsResult<()> {
         debug!("forget(ino={}, nlookup={}, req={:?})", ino, nlookup, req,);
         let current_count: i64;
         {
-            let node = self.cache.get(&ino);
-            debug_assert!(
-                node.is_some(),
-                "forget() found fs is inconsistent, \
-                        the i-node of ino={} should be in cache",
-                ino,
-            );
-            let node = node.unwrap(); // safe to use unwrap() here
+            let node = self.cache.get(&ino).ok_or_else(|| {
+                node_missing(format!(
+                    "forget() found fs is inconsistent, \
+                    the i-node of ino={} should be in cache",
+                    ino,
+                ))
+            })?;
             let previous_count = node.dec_lookup_count_by(nlookup);
             current_count = node.get_lookup_count();
             debug_assert!(current_count >= 0);
@@ -485,27 +1781,30 @@ impl FileSystem {
                 // TODO: support thread-safe
                 if self.trash.contains(&ino) {
                     // deferred deletion
-                    let deleted_node = self.cache.remove(&ino);
-                    debug_assert!(
-                        deleted_node.is_some(),
-                        "forget() found fs is inconsistent, node of ino={} \
-                                found in trash, but no i-node found for deferred deletion",
-                        ino,
-                    );
-                    let deleted_node = deleted_node.unwrap(); // safe to use unwrap() here
+                    let deleted_node = self.cache.remove(&ino).ok_or_else(|| {
+                        node_missing(format!(
+                            "forget() found fs is inconsistent, node of ino={} \
+                            found in trash, but no i-node found for deferred deletion",
+                            ino,
+                        ))
+                    })?;
                     self.trash.remove(&ino);
+                    self.export.retire(ino);
                     debug_assert_eq!(deleted_node.get_lookup_count(), 0);
                     debug!(
                         "forget() deferred deleted i-node of ino={}, the i-node={:?}",
                         ino, deleted_node
                     );
+                } else {
+                    self.evict_node_helper(ino);
                 }
             }
         }
+        Ok(())
     }
 
     /// Set file attributes.
-    pub async fn setattr(
+    async fn setattr(
         &mut self,
         req: &Request<'_>,
         ino: u64,
@@ -521,7 +1820,7 @@ impl FileSystem {
         bkuptime: Option<SystemTime>,
         flags: Option<u32>,
         reply: ReplyAttr,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "setattr(ino={}, mode={:?}, uid={:?}, gid={:?}, size={:?}, \
                 atime={:?}, mtime={:?}, fh={:?}, crtime={:?}, chgtime={:?}, \
@@ -529,78 +1828,89 @@ impl FileSystem {
             ino, mode, uid, gid, size, atime, mtime, fh, crtime, chgtime, bkuptime, flags, req,
         );
 
-        let node = self.cache.get_mut(&ino);
-        debug_assert!(
-            node.is_some(),
-            "setattr() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
-        let mut attr = node.get_attr();
-        let ttl = Duration::new(MY_TTL_SEC, 0);
-        let ts = SystemTime::now();
-
-        if let Some(b) = mode {
-            attr.perm = util::parse_mode_bits(b);
-            debug!("setattr() set permission={}", attr.perm);
-
-            let kind = util::parse_sflag(b);
-            debug_assert_eq!(kind, attr.kind);
-        }
-        // no replace
-        attr.uid = uid.unwrap_or(attr.uid);
-        attr.gid = gid.unwrap_or(attr.gid);
-        attr.size = size.unwrap_or(attr.size);
-        attr.atime = atime.unwrap_or(attr.atime);
-        attr.mtime = mtime.unwrap_or(attr.mtime);
-        attr.crtime = crtime.unwrap_or(attr.crtime);
-        attr.flags = flags.unwrap_or(attr.flags);
-
-        if mode.is_some()
-            || uid.is_some()
-            || gid.is_some()
-            || size.is_some()
-            || atime.is_some()
-            || mtime.is_some()
-            || crtime.is_some()
-            || chgtime.is_some()
-            || bkuptime.is_some()
-            || flags.is_some()
+        if mode.is_none()
+            && uid.is_none()
+            && gid.is_none()
+            && size.is_none()
+            && atime.is_none()
+            && mtime.is_none()
+            && crtime.is_none()
+            && chgtime.is_none()
+            && bkuptime.is_none()
+            && flags.is_none()
         {
-            attr.ctime = ts; // update ctime, since meta data might change in setattr
-            let fuse_attr = util::convert_to_fuse_attr(attr)?;
-            node.set_attr(attr);
-            reply.attr(ttl, fuse_attr).await?;
-            debug!(
-                "setattr() successfully set the attribute of ino={}, the set attr={:?}",
-                ino, attr,
-            );
-            // TODO: write attribute change to disk using chmod, chown, chflags
-            Ok(())
-        } else {
-            reply.error(ENODATA).await?;
-            panic!(
-                "setattr() found all the input attributes are empty for the file of ino={}",
+            return Err(FsError::new(
+                Errno::ENODATA,
+                format!(
+                    "setattr() found all the input attributes are empty for the file of ino={}",
+                    ino,
+                ),
+            ));
+        }
+        if let Some(fh) = fh {
+            self.handles.get(fh, ino)?;
+        }
+        // The kernel leaves clearing the set-user-ID and set-group-ID bits to the filesystem
+        #[cfg(feature = "abi-7-26")]
+        let handle_killpriv = self.is_enabled(FUSE_HANDLE_KILLPRIV);
+        #[cfg(not(feature = "abi-7-26"))]
+        let handle_killpriv = false;
+        let ttl = self.attr_ttl(ino);
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "setattr() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
                 ino,
-            );
-            // Err(anyhow::anyhow!("no change to attr"))
+            ))
+        })?;
+
+        // Apply the changes to the backing file, then reload the attribute from it,
+        // so that the cached attribute never diverges from the one on disk
+        if let Some(size) = size {
+            if node.get_type() == SFlag::S_IFDIR {
+                return Err(FsError::new(
+                    Errno::EISDIR,
+                    format!("setattr() cannot truncate the directory of ino={}", ino),
+                ));
+            }
+            // truncate the file on disk along with the cached data, e.g. O_TRUNC
+            // without FUSE_ATOMIC_O_TRUNC, so that they never diverge
+            node.truncate_file(size).await?;
+            if handle_killpriv && req.uid() != 0 {
+                node.kill_priv().await?;
+            }
         }
-    }
+        if uid.is_some() || gid.is_some() {
+            node.chown(uid, gid).await?;
+            if handle_killpriv {
+                node.kill_priv().await?;
+            }
+        }
+        if let Some(b) = mode {
+            debug_assert_eq!(util::parse_sflag(b), node.get_type());
+            node.chmod(b).await?;
+        }
+        if atime.is_some() || mtime.is_some() {
+            node.utimens(atime, mtime).await?;
+        }
+        let mut attr = node.reload_attr().await?;
+        // the creation time and the flags of macOS are kept in cache only
+        attr.crtime = crtime.unwrap_or(attr.crtime);
+        attr.flags = flags.unwrap_or(attr.flags);
+        node.set_attr(attr);
 
-    /// Read symbolic link.
-    pub async fn readlink(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        reply: ReplyData,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+        let fuse_attr = util::convert_to_fuse_attr(attr)?;
+        reply.attr(ttl, fuse_attr).await?;
+        debug!(
+            "setattr() successfully set the attribute of ino={}, the set attr={:?}",
+            ino, attr,
+        );
+        Ok(())
     }
 
     /// Create file node.
     /// Create a regular file, character device, block device, fifo or socket node.
-    pub async fn mknod(
+    async fn mknod(
         &mut self,
         req: &Request<'_>,
         parent: u64,
@@ -608,7 +1918,7 @@ impl FileSystem {
         mode: u32,
         rdev: u32,
         reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "mknod(parent={}, name={:?}, mode={}, rdev={}, req={:?})",
             parent, name, mode, rdev, req,
@@ -619,14 +1929,14 @@ impl FileSystem {
     }
 
     /// Create a directory.
-    pub async fn mkdir(
+    async fn mkdir(
         &mut self,
         req: &Request<'_>,
         parent: u64,
         name: &OsStr,
         mode: u32,
         reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "mkdir(parent={}, name={:?}, mode={}, req={:?})",
             parent, name, mode, req,
@@ -637,26 +1947,26 @@ impl FileSystem {
     }
 
     /// Remove a file.
-    pub async fn unlink(
+    async fn unlink(
         &mut self,
         req: &Request<'_>,
         parent: u64,
         name: &OsStr,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!("unlink(parent={}, name={:?}, req={:?}", parent, name, req,);
         self.remove_node_helper(parent, name.into(), SFlag::S_IFREG, reply)
             .await
     }
 
     /// Remove a directory.
-    pub async fn rmdir(
+    async fn rmdir(
         &mut self,
         req: &Request<'_>,
         parent: u64,
         name: &OsStr,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         let dir_name = OsString::from(name);
         debug!(
             "rmdir(parent={}, name={:?}, req={:?})",
@@ -666,41 +1976,97 @@ impl FileSystem {
             .await
     }
 
-    /// Create a symbolic link.
-    pub async fn symlink(
-        &mut self,
-        _req: &Request<'_>,
-        _parent: u64,
-        _name: &OsStr,
-        _link: &Path,
-        reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
     /// Rename a file.
-    pub async fn rename(
+    /// The file of `newname` under the directory of `newparent` is replaced if any,
+    /// and stays open until released if opened
+    async fn rename(
         &mut self,
-        _req: &Request<'_>,
-        _parent: u64,
-        _name: &OsStr,
-        _newparent: u64,
-        _newname: &OsStr,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
+    ) -> FsResult<()> {
+        debug!(
+            "rename(parent={}, name={:?}, newparent={}, newname={:?}, req={:?})",
+            parent, name, newparent, newname, req,
+        );
+        let parent_node = self.cache.get_mut(&parent).ok_or_else(|| {
+            node_missing(format!(
+                "rename() found fs is inconsistent, \
+                the parent i-node of ino={} should be in cache",
+                parent,
+            ))
+        })?;
+        let (ino, node_type) = match parent_node.find_entry(name).await? {
+            Some(entry) => (entry.ino(), entry.entry_type()),
+            None => {
+                debug!(
+                    "rename() failed to find the file name={:?} under parent ino={}",
+                    name, parent,
+                );
+                reply.error(ENOENT).await?;
+                return Ok(());
+            }
+        };
+        let new_parent_node = self.cache.get_mut(&newparent).ok_or_else(|| {
+            node_missing(format!(
+                "rename() found fs is inconsistent, \
+                the new parent i-node of ino={} should be in cache",
+                newparent,
+            ))
+        })?;
+        let replaced = new_parent_node
+            .find_entry(newname)
+            .await?
+            .map(DirEntry::ino);
+        let new_parent_handle = new_parent_node.get_handle();
+        let parent_node = self.cache.get(&parent).ok_or_else(|| {
+            node_missing(format!(
+                "rename() found the parent i-node of ino={} missing from cache",
+                parent,
+            ))
+        })?;
+        if let Err(e) = parent_node
+            .move_file(name, new_parent_handle, newname)
+            .await
+        {
+            // e.g. moving a directory under itself, or replacing a non-empty directory
+            let e = FsError::from(e);
+            debug!("rename() failed to move the file, the error is: {}", e);
+            reply
+                .error(e.errno().map_or(libc::EIO, |errno| errno as c_int))
+                .await?;
+            return Ok(());
+        }
 
-    /// Create a hard link.
-    pub async fn link(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _newparent: u64,
-        _newname: &OsStr,
-        reply: ReplyEntry,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+        // all the changes are done by the backend, update the cached entries and nodes
+        if let Some(parent_node) = self.cache.get_mut(&parent) {
+            parent_node.remove_entry(name);
+            parent_node.reload_attr().await?;
+        }
+        if let Some(replaced) = replaced.filter(|&replaced| replaced != ino) {
+            self.retire_node_helper(replaced);
+        }
+        if let Some(new_parent_node) = self.cache.get_mut(&newparent) {
+            new_parent_node.insert_entry(DirEntry::new(ino, newname.to_owned(), node_type));
+            new_parent_node.reload_attr().await?;
+        }
+        if let Some(node) = self.cache.get_mut(&ino) {
+            node.set_parent_ino(newparent);
+            node.set_name(newname.to_owned());
+            node.reload_attr().await?;
+            let fd = node.get_fd();
+            self.export.register(ino, fd, newparent, newname);
+        }
+        reply.ok().await?;
+        debug!(
+            "rename() successfully moved the file name={:?} of ino={} under parent ino={} \
+                to name={:?} under parent ino={}, replaced ino={:?}",
+            name, ino, parent, newname, newparent, replaced,
+        );
+        Ok(())
     }
 
     /// Read data.
@@ -710,7 +2076,7 @@ impl FileSystem {
     /// return value of the read system call will reflect the return value of this
     /// operation. fh will contain the value set by the open method, or will be undefined
     /// if the open method didn't set any value.
-    pub async fn read(
+    async fn read(
         &mut self,
         req: &Request<'_>,
         ino: u64,
@@ -718,7 +2084,7 @@ impl FileSystem {
         offset: i64,
         size: u32,
         reply: ReplyData,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "read(ino={}, fh={}, offset={}, size={}, req={:?})",
             ino, fh, offset, size, req,
@@ -749,30 +2115,63 @@ impl FileSystem {
                     );
                     Ok(Vec::new())
                 }
-                std::cmp::Ordering::Less => Err(anyhow::anyhow!(
-                    "failed to read, offset={} beyond file length={}",
-                    offset,
-                    content.len(),
-                )),
+                std::cmp::Ordering::Less => Err(FsError::new(
+                    Errno::EINVAL,
+                    format!(
+                        "failed to read, offset={} beyond file length={}",
+                        offset,
+                        content.len(),
+                    ),
+                )
+                .into()),
             }
         };
 
-        let node = self.cache.get_mut(&ino);
-        debug_assert!(
-            node.is_some(),
-            "read() found fs is inconsistent, the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
+        let policy = self.cache_policy_helper(ino).await?;
+        let readahead = self.handles.get_mut(fh, ino)?.readahead_mut();
+        readahead.record(offset as u64, size.into());
+        let sequential = readahead.is_sequential();
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "read() found fs is inconsistent, the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        // the data cached before the policy changed is still served from the cache
+        if policy.cache_mode() == CacheMode::Uncached && node.is_node_data_empty() {
+            let read_data_vec = node.read_uncached(offset, size).await?;
+            if let (true, Prefetch::Sequential, Some(fd)) =
+                (sequential, policy.prefetch(), node.get_fd())
+            {
+                let next_offset = offset as u64 + read_data_vec.len() as u64;
+                if let Err(e) = blocking!(util::advise_willneed(fd, next_offset, PREFETCH_SIZE)) {
+                    debug!(
+                        "read() failed to prefetch the file of ino={}, the error is: {}",
+                        ino, e,
+                    );
+                }
+            }
+            debug!(
+                "read() successfully from the file of ino={} without caching, \
+                    the read size={:?}, sequential={}",
+                ino,
+                read_data_vec.len(),
+                sequential,
+            );
+            reply.data(read_data_vec).await?;
+            return Ok(());
+        }
         if node.need_load_file_data() {
             node.load_data().await?;
         }
         match node.read_file(read_helper) {
             Ok(read_data_vec) => {
                 debug!(
-                    "read() successfully from the file of ino={}, the read size={:?}",
+                    "read() successfully from the file of ino={}, the read size={:?}, \
+                        sequential={}",
                     ino,
                     read_data_vec.len(),
+                    sequential,
                 );
                 reply.data(read_data_vec).await?;
                 Ok(())
@@ -782,8 +2181,7 @@ impl FileSystem {
                     "read() offset={} is beyond the length of the file of ino={}",
                     offset, ino
                 );
-                reply.error(EINVAL).await?;
-                Err(e)
+                Err(e.into())
             }
         }
     }
@@ -794,16 +2192,20 @@ impl FileSystem {
     /// which case the return value of the write system call will reflect the return
     /// value of this operation. fh will contain the value set by the open method, or
     /// will be undefined if the open method didn't set any value.
-    pub async fn write(
+    #[cfg_attr(
+        any(not(feature = "abi-7-26"), feature = "abi-7-31"),
+        allow(unused_variables)
+    )]
+    async fn write(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
         data: Vec<u8>,
         flags: u32,
         reply: ReplyWrite,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "write(ino={}, fh={}, offset={}, data-size={}, flags={})",
             // "write(ino={}, fh={}, offset={}, data-size={}, req={:?})",
@@ -815,20 +2217,53 @@ impl FileSystem {
             // req.request,
         );
 
-        let inode = self.cache.get_mut(&ino);
-        debug_assert!(
-            inode.is_some(),
-            "write() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
-        );
-        let inode = inode.unwrap(); // safe to use unwrap() here
-        let oflags = util::parse_oflag(flags);
+        // The kernel asks to clear the set-user-ID and set-group-ID bits on the writes
+        // by unprivileged users, once it leaves that to the filesystem
+        #[cfg(feature = "abi-7-31")]
+        let kill_priv = self.is_enabled(FUSE_HANDLE_KILLPRIV) && flags & FUSE_WRITE_KILL_PRIV != 0;
+        #[cfg(all(feature = "abi-7-26", not(feature = "abi-7-31")))]
+        let kill_priv = self.is_enabled(FUSE_HANDLE_KILLPRIV) && req.uid() != 0;
+        #[cfg(not(feature = "abi-7-26"))]
+        let kill_priv = false;
+        let policy = self.cache_policy_helper(ino).await?;
+        let file = self.handles.get(fh, ino)?;
+        let handle = file.get_handle();
+        let append = file.is_append();
+        let inode = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "write() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let uncached = policy.cache_mode() == CacheMode::Uncached && inode.is_node_data_empty();
+        // the cached data may be dropped by revalidation, load it before partial write
+        if !uncached && inode.need_load_file_data() {
+            inode.load_data().await?;
+        }
+        // The writes of the append opens go to the end of the cached file, which is
+        // the one on disk, rather than the end of the file seen by the kernel
+        let offset = if append {
+            inode.get_attr().size as i64
+        } else {
+            offset
+        };
         let write_to_disk = true;
         let data_len = data.len();
-        let written_size = inode
-            .write_file(fh, offset, data, oflags, write_to_disk)
-            .await?;
+        let written_size = if uncached {
+            inode.write_uncached(handle, offset, data).await?
+        } else {
+            inode
+                .write_file(handle, offset, data, write_to_disk)
+                .await?
+        };
+        if kill_priv {
+            inode.kill_priv().await?;
+        }
+        self.handles
+            .get_mut(fh, ino)?
+            .dirty_mut()
+            .add(offset as u64, written_size as u64);
         reply.written(written_size as u32).await?;
         debug!(
             "write() successfully wrote {} byte data to file ino={} at offset={}",
@@ -847,14 +2282,14 @@ impl FileSystem {
     /// is not forced to flush pending writes. One reason to flush data, is if the
     /// filesystem wants to return write errors. If the filesystem supports file locking
     /// operations (setlk, getlk) it should remove all locks belonging to 'lock_owner'.
-    pub async fn flush(
+    async fn flush(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         fh: u64,
         lock_owner: u64,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "flush(ino={}, fh={}, lock_owner={}, req={:?})",
             ino, fh, lock_owner, req,
@@ -865,15 +2300,15 @@ impl FileSystem {
         // called multiple times for an open file, this must not really
         // close the file.  This is important if used on a network
         // filesystem like NFS which flush the data/metadata on close()
-        let new_fd = blocking!(unistd::dup(fh as RawFd)).context(format!(
-            "flush() failed to duplicate the handler ino={} fh={:?}",
+        let file = self.handles.get_mut(fh, ino)?;
+        file.set_lock_owner(lock_owner);
+        let handle = file.get_handle();
+        self.backend.flush(handle).await.context(format!(
+            "flush() failed to flush the file handler ino={} fh={:?}",
             ino, fh,
         ))?;
-        blocking!(unistd::close(new_fd)).context(format!(
-            "flush() failed to close the duplicated file handler={} of ino={}",
-            new_fd, ino,
-        ))?;
-        reply.ok().await
+        reply.ok().await?;
+        Ok(())
     }
 
     /// Release an open file.
@@ -884,7 +2319,7 @@ impl FileSystem {
     /// the release. fh will contain the value set by the open method, or will be undefined
     /// if the open method didn't set any value. flags will contain the same flags as for
     /// open.
-    pub async fn release(
+    async fn release(
         &mut self,
         req: &Request<'_>,
         ino: u64,
@@ -893,76 +2328,61 @@ impl FileSystem {
         lock_owner: u64,
         flush: bool,
         reply: ReplyEmpty,
-    ) {
+    ) -> FsResult<()> {
         debug!(
             "release(ino={}, fh={}, flags={}, lock_owner={}, flush={}, req={:?})",
             ino, fh, flags, lock_owner, flush, req,
         );
         // TODO: handle lock_owner
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "release() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
-        let fd = fh as RawFd;
-        if flush {
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "release() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let file = self.handles.remove(fh, ino)?;
+        let handle = file.get_handle();
+        // close the file handler even if failed to flush, it is never released again
+        // the writes in passthrough mode bypass the daemon, so are never recorded dirty
+        let flush_res = if flush && (node.is_passthrough() || !file.get_dirty().is_empty()) {
             // TODO: double check the meaning of the flush flag
-            blocking!(unistd::fsync(fd))
-                .unwrap_or_else(|_| panic!("release() failed to flush the file of ino={}", ino));
-        }
-        blocking!(unistd::close(fd)).unwrap_or_else(|_| {
-            panic!(
-                "release() failed to close the file handler={} of ino={}",
-                fh, ino
-            )
-        });
-        node.dec_open_count(); // decrease open count before reply in case reply failed
-        reply
-            .ok()
-            .await
-            .expect("release() failed to send the FUSE reply");
-        debug!(
-            "release() successfully closed the file handler={} of ino={}",
-            fh, ino,
-        );
-    }
-
-    async fn fsync_helper(
-        ino: u64,
-        fh: u64,
-        datasync: bool,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        #[cfg(target_os = "linux")]
-        {
-            // attributes are not allowed on if expressions
-            if datasync {
-                blocking!(unistd::fdatasync(fh as RawFd)).context(format!(
-                    "fsync_helper() failed to flush the node of ino={}",
-                    ino
-                ))?;
-            } else {
-                blocking!(unistd::fsync(fh as RawFd)).context(format!(
-                    "fsync_helper() failed to flush the node of ino={}",
-                    ino
-                ))?;
+            self.backend
+                .sync(handle, false)
+                .await
+                .context(format!("release() failed to flush the file of ino={}", ino))
+        } else {
+            Ok(())
+        };
+        let close_res = self.backend.close(handle).context(format!(
+            "release() failed to close the file handler={} of ino={}",
+            fh, ino
+        ));
+        if node.is_passthrough() {
+            // all the opens of a passthrough node are in passthrough mode
+            node.release_backing().await.unwrap_or_else(|e| {
+                warn!(
+                    "release() failed to release the backing file, the error is: {:?}",
+                    e
+                )
+            });
+            if let Err(e) = node.reload_attr().await {
+                warn!(
+                    "release() failed to reload the attribute, the error is: {:?}",
+                    e
+                );
             }
         }
-        #[cfg(target_os = "macos")]
-        {
-            blocking!(unistd::fsync(fh as RawFd)).context(format!(
-                "fsync_helper() failed to flush the node of ino={}",
-                ino
-            ))?;
-        }
-
+        node.dec_open_count(); // decrease open count before reply in case reply failed
+        flush_res?;
+        close_res?;
         reply.ok().await?;
         debug!(
-            "fsync_helper() successfully sync the node of ino={}, fh={}, datasync={:?}",
-            ino, fh, datasync,
+            "release() successfully closed the file handler={} of ino={}, \
+                the lock owner of the last flush={:?}",
+            fh,
+            ino,
+            file.get_lock_owner(),
         );
         Ok(())
     }
@@ -970,19 +2390,19 @@ impl FileSystem {
     /// Synchronize file contents.
     /// If the datasync parameter is non-zero, then only the user data should be flushed,
     /// not the meta data.
-    pub async fn fsync(
+    async fn fsync(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         fh: u64,
         datasync: bool,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "fsync(ino={}, fh={}, datasync={}, req={:?})",
             ino, fh, datasync, req,
         );
-        FileSystem::fsync_helper(ino, fh, datasync, reply).await
+        self.fsync_helper(ino, fh, datasync, reply).await
     }
 
     /// Open a directory.
@@ -992,29 +2412,34 @@ impl FileSystem {
     /// anything in fh, though that makes it impossible to implement standard conforming
     /// directory stream operations in case the contents of the directory can change
     /// between opendir and releasedir.
-    pub async fn opendir(
+    async fn opendir(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         flags: u32,
         reply: ReplyOpen,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!("opendir(ino={}, flags={}, req={:?})", ino, flags, req,);
 
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "opendir() found fs is inconsistent, the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
+        if let Revalidation::OnOpen = self.revalidation {
+            let invalidations = self.revalidate_helper(ino).await?;
+            self.spawn_invalidations(invalidations);
+        }
+        let node = self.cache.get(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "opendir() found fs is inconsistent, the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
         let oflags = util::parse_oflag(flags);
-        let new_fd = node.dup_fd(oflags).await?;
+        let new_handle = node.dup_handle(oflags).await?;
+        let fh = self.handles.insert(OpenFile::new(ino, new_handle, oflags));
 
-        reply.opened(new_fd as u64, flags).await?;
+        reply.opened(fh, 0).await?;
         debug!(
-            "opendir() successfully duplicated the file handler of ino={}, new fd={}, flags={:?}",
-            ino, new_fd, oflags,
+            "opendir() successfully duplicated the file handler of ino={}, fh={}, \
+                new handle={:?}, flags={:?}",
+            ino, fh, new_handle, oflags,
         );
         Ok(())
     }
@@ -1024,353 +2449,328 @@ impl FileSystem {
     /// requested size. Send an empty buffer on end of stream. fh will contain the
     /// value set by the opendir method, or will be undefined if the opendir method
     /// didn't set any value.
-    pub async fn readdir(
+    async fn readdir(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
-        mut reply: ReplyDirectory,
-    ) -> anyhow::Result<()> {
+        reply: ReplyDirectory,
+    ) -> FsResult<()> {
         debug!(
             "readdir(ino={}, fh={}, offset={}, req={:?})",
             ino, fh, offset, req,
         );
 
-        let readdir_helper = |data: &BTreeMap<OsString, DirEntry>| -> usize {
-            let mut num_child_entries = 0;
-            for (i, (child_name, child_entry)) in data.iter().enumerate().skip(offset as usize) {
-                let child_ino = child_entry.ino();
-                reply.add(
-                    child_ino,
-                    offset + i as i64 + 1, // i + 1 means the index of the next entry
-                    child_entry.entry_type(),
-                    child_name,
-                );
-                num_child_entries += 1;
-                debug!(
-                    "readdir() found one child name={:?} ino={} offset={} entry={:?} \
-                        under the directory of ino={}",
-                    child_name,
-                    child_ino,
-                    offset + i as i64 + 1,
-                    child_entry,
-                    ino,
-                );
-            }
-            num_child_entries
-        };
+        let (backend, handle) = self.dir_handle_helper(ino, fh)?;
+        Self::fill_dir_helper(&backend, ino, handle, offset, reply).await
+    }
 
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "readdir() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
-        let num_child_entries = node.read_dir(readdir_helper);
-        reply.ok().await?;
+    /// Read directory without the filesystem lock, the handle of the open is checked
+    /// under the lock, then the backing directory is read through the handle of the open,
+    /// which stays open since the kernel does not release the open during the readdir
+    async fn readdir_shared(
+        fs: &Mutex<Self>,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectory,
+    ) -> FsResult<()> {
         debug!(
-            "readdir() successfully read {} children \
-                under the directory of ino={}",
-            num_child_entries, ino,
+            "readdir_shared(ino={}, fh={}, offset={}, req={:?})",
+            ino, fh, offset, req,
         );
-        Ok(())
+        let (backend, handle) = fs.lock().await.dir_handle_helper(ino, fh)?;
+        Self::fill_dir_helper(&backend, ino, handle, offset, reply).await
     }
 
     /// Release an open directory.
     /// For every opendir call there will be exactly one releasedir call. fh will
     /// contain the value set by the opendir method, or will be undefined if the
     /// opendir method didn't set any value.
-    pub async fn releasedir(
+    async fn releasedir(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         fh: u64,
         flags: u32,
         reply: ReplyEmpty,
-    ) {
+    ) -> FsResult<()> {
         debug!(
             "releasedir(ino={}, fh={}, flags={}, req={:?})",
             ino, fh, flags, req,
         );
         // TODO: handle flags
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "releasedir() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
-            ino,
-        );
-        let node = node.unwrap(); // safe to use unwrap() here
-        blocking!(unistd::close(fh as RawFd)).unwrap_or_else(|_| {
-            panic!(
-                "releasedir() failed to close the file handler={} of ino={}",
-                fh, ino
-            )
-        });
+        let node = self.cache.get(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "releasedir() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        let handle = self.handles.remove(fh, ino)?.get_handle();
+        let close_res = self.backend.close(handle).context(format!(
+            "releasedir() failed to close the file handler={} of ino={}",
+            fh, ino
+        ));
         node.dec_open_count();
-        reply
-            .ok()
-            .await
-            .expect("releasedir() failed to send the FUSE reply");
+        close_res?;
+        reply.ok().await?;
         debug!(
             "releasedir() successfully closed the file handler={} of ino={}",
             fh, ino,
         );
+        Ok(())
     }
 
     /// Synchronize directory contents.
     /// If the datasync parameter is set, then only the directory contents should
     /// be flushed, not the meta data. fh will contain the value set by the opendir
     /// method, or will be undefined if the opendir method didn't set any value.
-    pub async fn fsyncdir(
+    async fn fsyncdir(
         &mut self,
         req: &Request<'_>,
         ino: u64,
         fh: u64,
         datasync: bool,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
+    ) -> FsResult<()> {
         debug!(
             "fsyncdir(ino={}, fh={}, datasync={}, req={:?})",
             ino, fh, datasync, req,
         );
-        FileSystem::fsync_helper(ino, fh, datasync, reply).await
+        self.fsync_helper(ino, fh, datasync, reply).await
     }
 
-    /// Get file system statistics.
-    /// The 'f_favail', 'f_fsid' and 'f_flag' fields are ignored
-    pub async fn statfs(
+    /// Set an extended attribute.
+    /// The xattrs of the cache policy change how the file, or the files under the
+    /// directory, are cached once set
+    async fn setxattr(
         &mut self,
         req: &Request<'_>,
-        mut ino: u64,
-        reply: ReplyStatFs,
-    ) -> anyhow::Result<()> {
-        debug!("statfs(ino={}, req={:?})", ino, req);
-
-        if ino == 0 {
-            ino = FUSE_ROOT_ID;
-        }
-
-        let node = self.cache.get(&ino);
-        debug_assert!(
-            node.is_some(),
-            "statfs() found fs is inconsistent, \
-                    the i-node of ino={} should be in cache",
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+        reply: ReplyEmpty,
+    ) -> FsResult<()> {
+        debug!(
+            "setxattr(ino={}, name={:?}, value-size={}, flags={}, position={}, req={:?})",
             ino,
+            name,
+            value.len(),
+            flags,
+            position,
+            req,
         );
-        let node = node.unwrap(); // safe to use unwrap() here
-        let fd = node.get_fd();
-        let statvfs = blocking!(
-            let file = unsafe { std::fs::File::from_raw_fd(fd) };
-            let res = statvfs::fstatvfs(&file); // statvfs is POSIX, whereas statfs is not
-            let _fd = file.into_raw_fd(); // prevent fd to be closed by File
-            res
-        )
-        .context("statfs() failed to run statvfs()")?;
-        // reply
-        //     .statfs(
-        //         // TODO: consider to avoid the numeric cast
-        //         statvfs.blocks() as u64,
-        //         statvfs.blocks_free() as u64,
-        //         statvfs.blocks_available() as u64,
-        //         statvfs.files() as u64,
-        //         statvfs.files_free() as u64,
-        //         statvfs.block_size() as u32, // TODO: consider use customized block size
-        //         statvfs.name_max() as u32,
-        //         statvfs.fragment_size() as u32,
-        //     )
-        //     .await?;
-        reply.error(ENOSYS).await?;
+        if cache_policy::is_cached_bytes_xattr(name) {
+            reply.error(EPERM).await?;
+            return Ok(());
+        }
+        let is_policy = cache_policy::is_policy_xattr(name);
+        if is_policy {
+            if let Err(e) = CachePolicy::default().set(name, value) {
+                debug!(
+                    "setxattr() refused the invalid cache policy, the error is: {}",
+                    e
+                );
+                reply.error(EINVAL).await?;
+                return Ok(());
+            }
+        }
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "setxattr() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        if let Err(e) = node.set_xattr(name, value.to_vec(), flags).await {
+            reply.error(xattr_errno(e)).await?;
+            return Ok(());
+        }
+        if is_policy {
+            self.apply_cache_policy_helper(ino).await?;
+        }
+        reply.ok().await?;
         debug!(
-            "statfs() successfully read the statvfs of ino={}, the statvfs={:?}",
-            ino, statvfs,
+            "setxattr() successfully set the xattr name={:?} of ino={}",
+            name, ino,
         );
         Ok(())
     }
 
-    /// Set an extended attribute.
-    pub async fn setxattr(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _name: &OsStr,
-        _value: &[u8],
-        _flags: u32,
-        _position: u32,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
     /// Get an extended attribute.
-    /// If `size` is 0, the size of the value should be sent with `reply.size()`.
-    /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
-    /// `reply.error(ERANGE)` if it doesn't.
-    pub async fn getxattr(
+    /// The xattrs of the cache policy are the ones in effect, inherited from the
+    /// ancestors if not set on the node
+    async fn getxattr(
         &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _name: &OsStr,
-        _size: u32,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
         reply: ReplyXAttr,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+    ) -> FsResult<()> {
+        debug!(
+            "getxattr(ino={}, name={:?}, size={}, req={:?})",
+            ino, name, size, req,
+        );
+        let value = if cache_policy::is_cached_bytes_xattr(name) {
+            Some(self.cached_bytes_helper(ino).to_string().into_bytes())
+        } else if cache_policy::is_policy_xattr(name) {
+            let policy = self.cache_policy_helper(ino).await?;
+            policy.get(name).map(<[u8]>::to_vec)
+        } else {
+            let node = self.cache.get(&ino).ok_or_else(|| {
+                node_missing(format!(
+                    "getxattr() found fs is inconsistent, \
+                    the i-node of ino={} should be in cache",
+                    ino,
+                ))
+            })?;
+            match node.get_xattr(name).await {
+                Ok(value) => value,
+                Err(e) => {
+                    reply.error(xattr_errno(e)).await?;
+                    return Ok(());
+                }
+            }
+        };
+        match value {
+            Some(value) => {
+                debug!(
+                    "getxattr() successfully got the xattr name={:?} of ino={}, \
+                        the value size={}",
+                    name,
+                    ino,
+                    value.len(),
+                );
+                reply_xattr(reply, size, value).await?;
+            }
+            None => reply.error(util::ENOATTR as c_int).await?,
+        }
+        Ok(())
     }
 
     /// List extended attribute names.
-    /// If `size` is 0, the size of the value should be sent with `reply.size()`.
-    /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
-    /// `reply.error(ERANGE)` if it doesn't.
-    pub async fn listxattr(
+    /// The read-only xattr of the cached bytes is not listed
+    async fn listxattr(
         &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _size: u32,
+        req: &Request<'_>,
+        ino: u64,
+        size: u32,
         reply: ReplyXAttr,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+    ) -> FsResult<()> {
+        debug!("listxattr(ino={}, size={}, req={:?})", ino, size, req);
+        let node = self.cache.get(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "listxattr() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        match node.list_xattr().await {
+            Ok(names) => {
+                debug!(
+                    "listxattr() successfully listed the xattrs of ino={}, the names size={}",
+                    ino,
+                    names.len(),
+                );
+                reply_xattr(reply, size, names).await?;
+            }
+            Err(e) => reply.error(xattr_errno(e)).await?,
+        }
+        Ok(())
     }
 
     /// Remove an extended attribute.
-    pub async fn removexattr(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _name: &OsStr,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
-    /// Check file access permissions.
-    /// This will be called for the access() system call. If the 'default_permissions'
-    /// mount option is given, this method is not called. This method is not called
-    /// under Linux kernel versions 2.4.x
-    pub async fn access(
+    /// Removing the xattrs of the cache policy falls back to the inherited ones
+    async fn removexattr(
         &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _mask: u32,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
-    /// Create and open a file.
-    /// If the file does not exist, first create it with the specified mode, and then
-    /// open it. Open flags (with the exception of O_NOCTTY) are available in flags.
-    /// Filesystem may store an arbitrary file handle (pointer, index, etc) in fh,
-    /// and use this in other all other file operations (read, write, flush, release,
-    /// fsync). There are also some flags (direct_io, keep_cache) which the
-    /// filesystem may set, to change the way the file is opened. See fuse_file_info
-    /// structure in <fuse_common.h> for more details. If this method is not
-    /// implemented or under Linux kernel versions earlier than 2.6.15, the mknod()
-    /// and open() methods will be called instead.
-    pub async fn create(
-        &mut self,
-        _req: &Request<'_>,
-        _parent: u64,
-        _name: &OsStr,
-        _mode: u32,
-        _flags: u32,
-        reply: ReplyCreate,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
-    /// Test for a POSIX file lock.
-    pub async fn getlk(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _lock_owner: u64,
-        _start: u64,
-        _end: u64,
-        _typ: u32,
-        _pid: u32,
-        reply: ReplyLock,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
-    /// Acquire, modify or release a POSIX file lock.
-    /// For POSIX threads (NPTL) there's a 1-1 relation between pid and owner, but
-    /// otherwise this is not always the case.  For checking lock ownership,
-    /// 'fi->owner' must be used. The l_pid field in 'struct flock' should only be
-    /// used to fill in this field in getlk(). Note: if the locking methods are not
-    /// implemented, the kernel will still allow file locking to work locally.
-    /// Hence these are only interesting for network filesystems and similar.
-    pub async fn setlk(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _lock_owner: u64,
-        _start: u64,
-        _end: u64,
-        _typ: u32,
-        _pid: u32,
-        _sleep: bool,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
         reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+    ) -> FsResult<()> {
+        debug!("removexattr(ino={}, name={:?}, req={:?})", ino, name, req);
+        if cache_policy::is_cached_bytes_xattr(name) {
+            reply.error(EPERM).await?;
+            return Ok(());
+        }
+        let node = self.cache.get_mut(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "removexattr() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        if let Err(e) = node.remove_xattr(name).await {
+            reply.error(xattr_errno(e)).await?;
+            return Ok(());
+        }
+        if cache_policy::is_policy_xattr(name) {
+            self.apply_cache_policy_helper(ino).await?;
+        }
+        reply.ok().await?;
+        debug!(
+            "removexattr() successfully removed the xattr name={:?} of ino={}",
+            name, ino,
+        );
+        Ok(())
     }
 
-    /// Map block index within file to block index within device.
-    /// Note: This makes sense only for block device backed filesystems mounted
-    /// with the 'blkdev' option
-    pub async fn bmap(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _blocksize: u32,
-        _idx: u64,
-        reply: ReplyBMap,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
-
-    /// macOS only: Rename the volume. Set fuse_init_out.flags during init to
-    /// FUSE_VOL_RENAME to enable
-    #[cfg(target_os = "macos")]
-    pub async fn setvolname(
+    /// Get file system statistics.
+    /// The 'f_favail', 'f_fsid' and 'f_flag' fields are ignored
+    async fn statfs(
         &mut self,
-        _req: &Request<'_>,
-        _name: &OsStr,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
+        req: &Request<'_>,
+        mut ino: u64,
+        reply: ReplyStatFs,
+    ) -> FsResult<()> {
+        debug!("statfs(ino={}, req={:?})", ino, req);
 
-    /// macOS only (undocumented)
-    #[cfg(target_os = "macos")]
-    pub async fn exchange(
-        &mut self,
-        _req: &Request<'_>,
-        _parent: u64,
-        _name: &OsStr,
-        _newparent: u64,
-        _newname: &OsStr,
-        _options: u64,
-        reply: ReplyEmpty,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
-    }
+        if ino == 0 {
+            ino = FUSE_ROOT_ID;
+        }
 
-    /// macOS only: Query extended times (bkuptime and crtime). Set fuse_init_out.flags
-    /// during init to FUSE_XTIMES to enable
-    #[cfg(target_os = "macos")]
-    pub async fn getxtimes(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        reply: ReplyXTimes,
-    ) -> anyhow::Result<()> {
-        reply.error(ENOSYS).await
+        let node = self.cache.get(&ino).ok_or_else(|| {
+            node_missing(format!(
+                "statfs() found fs is inconsistent, \
+                the i-node of ino={} should be in cache",
+                ino,
+            ))
+        })?;
+        // the backends keeping no files on host fds have no statistics
+        if let Some(fd) = node.get_fd() {
+            let statvfs = blocking!(
+                let file = unsafe { std::fs::File::from_raw_fd(fd) };
+                let res = statvfs::fstatvfs(&file); // statvfs is POSIX, whereas statfs is not
+                let _fd = file.into_raw_fd(); // prevent fd to be closed by File
+                res
+            )
+            .context("statfs() failed to run statvfs()")?;
+            debug!(
+                "statfs() successfully read the statvfs of ino={}, the statvfs={:?}",
+                ino, statvfs,
+            );
+        }
+        // reply
+        //     .statfs(
+        //         // TODO: consider to avoid the numeric cast
+        //         statvfs.blocks() as u64,
+        //         statvfs.blocks_free() as u64,
+        //         statvfs.blocks_available() as u64,
+        //         statvfs.files() as u64,
+        //         statvfs.files_free() as u64,
+        //         statvfs.block_size() as u32, // TODO: consider use customized block size
+        //         statvfs.name_max() as u32,
+        //         statvfs.fragment_size() as u32,
+        //     )
+        //     .await?;
+        reply.error(ENOSYS).await?;
+        Ok(())
     }
 }
 
@@ -1,67 +1,101 @@
 use anyhow::{self, Context};
 use log::debug;
-use nix::fcntl::{self, FcntlArg, OFlag};
-use nix::sys::stat::SFlag;
-use nix::sys::stat::{self, Mode};
-use nix::unistd;
-use smol::blocking;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{Mode, SFlag};
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::os::unix::{ffi::OsStrExt, io::RawFd};
-use std::path::Path;
+use std::os::unix::io::RawFd;
 use std::sync::atomic::{self, AtomicI64};
+use std::sync::Arc;
 use std::time::SystemTime;
 
+use super::super::passthrough::BackingFile;
 use super::super::protocol::*;
+use super::super::upgrade::{Decoder, Encoder};
+use super::backend::StorageBackend;
+use super::cache_policy::CachePolicy;
 use super::dir::*;
 use super::util::{self, FileAttr};
 
 #[derive(Debug)]
 enum NodeData {
+    /// The entries looked up or created through the mount, the directory is listed
+    /// from the backing directory stream instead of the cached entries
     DirData(BTreeMap<OsString, DirEntry>),
     FileData(Vec<u8>),
 }
 
+/// The stamp of the underlying file when the node is last synced with it,
+/// a different stamp means the file is changed out of band
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DiskStamp {
+    mtime: SystemTime,
+    ctime: SystemTime,
+    size: u64,
+}
+
+impl DiskStamp {
+    fn new(attr: &FileAttr) -> DiskStamp {
+        DiskStamp {
+            mtime: attr.mtime,
+            ctime: attr.ctime,
+            size: attr.size,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub(crate) struct Node {
+pub(crate) struct Node<B: StorageBackend> {
     parent: u64,
     name: OsString,
     attr: FileAttr,
+    stamp: DiskStamp,
     data: NodeData,
-    fd: RawFd,
+    /// The backend storing the file
+    backend: Arc<B>,
+    /// The handle of the file opened by the backend, closed on drop
+    handle: B::Handle,
     open_count: AtomicI64,
     lookup_count: AtomicI64,
+    backing_file: Option<BackingFile>,
+    /// The cache policy set on the underlying file itself, None if not loaded yet
+    policy: Option<CachePolicy>,
 }
 
-impl Drop for Node {
+impl<B: StorageBackend> Drop for Node<B> {
     fn drop(&mut self) {
         // TODO: check unsaved data in cache
-        unistd::close(self.fd).unwrap_or_else(|_| {
+        self.backend.close(self.handle).unwrap_or_else(|e| {
             panic!(
                 "DirNode::drop() failed to clode the file handler \
-                of the node name={:?} ino={}",
-                self.name, self.attr.ino
+                of the node name={:?} ino={}, the error is: {}",
+                self.name, self.attr.ino, e,
             )
         });
     }
 }
 
-impl Node {
+impl<B: StorageBackend> Node<B> {
     #[inline]
     pub fn get_ino(&self) -> INum {
         self.get_attr().ino
     }
 
-    pub fn get_fd(&self) -> RawFd {
-        self.fd
+    /// The handle of the file opened by the backend
+    pub fn get_handle(&self) -> B::Handle {
+        self.handle
+    }
+
+    /// The host fd of the file, None if the backend keeps no files on the host
+    pub fn get_fd(&self) -> Option<RawFd> {
+        self.backend.raw_fd(self.handle)
     }
 
     pub fn get_parent_ino(&self) -> INum {
         self.parent
     }
 
-    #[allow(dead_code)]
-    fn set_parent_ino(&mut self, parent: u64) -> INum {
+    pub fn set_parent_ino(&mut self, parent: u64) -> INum {
         let old_parent = self.parent;
         self.parent = parent;
         old_parent
@@ -71,8 +105,7 @@ impl Node {
         self.name.as_os_str()
     }
 
-    #[allow(dead_code)]
-    fn set_name(&mut self, name: OsString) {
+    pub fn set_name(&mut self, name: OsString) {
         self.name = name;
     }
 
@@ -124,9 +157,8 @@ impl Node {
             .fetch_sub(nlookup as i64, atomic::Ordering::Relaxed)
     }
 
-    #[allow(dead_code)]
     async fn load_attribute(&self) -> anyhow::Result<FileAttr> {
-        let attr = util::load_attr(self.fd).await.context(format!(
+        let attr = self.backend.getattr(self.handle).await.context(format!(
             "load_attribute() failed to get the attribute of the node ino={}",
             self.get_ino(),
         ))?;
@@ -137,26 +169,160 @@ impl Node {
         Ok(attr)
     }
 
-    pub async fn dup_fd(&self, oflags: OFlag) -> anyhow::Result<RawFd> {
-        let raw_fd = self.fd;
-        let ino = self.get_ino();
-        let new_fd = blocking!(unistd::dup(raw_fd)).context(format!(
-            "dup_fd() failed to duplicate the handler ino={} raw fd={:?}",
-            ino, raw_fd,
-        ))?;
+    /// Reload the attribute from the underlying file,
+    /// once it is modified by the kernel directly in passthrough mode, or by setattr
+    pub async fn reload_attr(&mut self) -> anyhow::Result<FileAttr> {
+        let attr = self.load_attribute().await?;
+        let ino = self.get_ino(); // the root ino is not the one on disk
+        let attr = FileAttr { ino, ..attr };
+        self.attr = attr;
+        self.stamp = DiskStamp::new(&attr);
+        Ok(attr)
+    }
+
+    /// Record the stamp of the underlying file after it is modified through this node
+    async fn sync_stamp(&mut self) -> anyhow::Result<()> {
+        let attr = self.load_attribute().await?;
+        self.stamp = DiskStamp::new(&attr);
+        Ok(())
+    }
+
+    /// Reload the attribute and drop the cached data if the underlying file is
+    /// changed out of band, the cached directory entries are looked up again at once,
+    /// whereas the file data is loaded on next read. Return None if the file is not
+    /// changed, otherwise the names of the removed or replaced entries of a directory
+    pub async fn revalidate(&mut self) -> anyhow::Result<Option<Vec<OsString>>> {
+        let attr = self.load_attribute().await?;
+        let stamp = DiskStamp::new(&attr);
+        if stamp == self.stamp {
+            return Ok(None);
+        }
+        debug!(
+            "revalidate() found the node name={:?} of ino={} is changed, \
+                the stamp before={:?}, after={:?}",
+            self.get_name(),
+            self.get_ino(),
+            self.stamp,
+            stamp,
+        );
+        let ino = self.get_ino(); // the root ino is not the one on disk
+        self.attr = FileAttr { ino, ..attr };
+        self.stamp = stamp;
+        // the xattrs of the policy may be changed as well, which changes the ctime
+        self.policy = None;
+        // The names not cached are looked up on disk, and the misses are not cached
+        // by the kernel either, so only the cached names may be stale
+        let changed_names = match &mut self.data {
+            NodeData::DirData(dir_data) => {
+                let names: Vec<OsString> = dir_data.keys().cloned().collect();
+                let mut changed_names = Vec::new();
+                for name in names {
+                    let new_entry = self.backend.lookup(self.handle, &name).await?;
+                    let changed = match (dir_data.get(&name), &new_entry) {
+                        (Some(old_entry), Some(new_entry)) => {
+                            new_entry.ino() != old_entry.ino()
+                                || new_entry.entry_type() != old_entry.entry_type()
+                        }
+                        _ => true,
+                    };
+                    if !changed {
+                        continue;
+                    }
+                    match new_entry {
+                        Some(new_entry) => dir_data.insert(name.clone(), new_entry),
+                        None => dir_data.remove(&name),
+                    };
+                    changed_names.push(name);
+                }
+                changed_names
+            }
+            NodeData::FileData(..) => {
+                self.data = NodeData::FileData(Vec::new());
+                Vec::new()
+            }
+        };
+        Ok(Some(changed_names))
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.backing_file.is_some()
+    }
+
+    /// Whether an open of this node can pass through reads and writes to the
+    /// underlying file, the kernel refuses to mix passthrough and cached I/O
+    /// on the same inode, and the cached data would be stale after passthrough writes.
+    /// Only the files kept on host fds can be passed through
+    pub fn can_passthrough(&self) -> bool {
+        if self.is_passthrough() {
+            return true;
+        }
+        if self.get_fd().is_none() {
+            return false;
+        }
+        // open count is 1 when no file handler is opened
+        match &self.data {
+            NodeData::DirData(..) => false,
+            NodeData::FileData(file_data) => file_data.is_empty() && self.get_open_count() == 1,
+        }
+    }
+
+    /// Register the underlying file as a backing file of the FUSE connection
+    /// if not yet, and return the backing id for a passthrough open
+    pub async fn open_backing(&mut self, fuse_fd: RawFd) -> anyhow::Result<i32> {
+        if self.backing_file.is_none() {
+            let fd = self.get_fd().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "open_backing() found the node of ino={} is not kept on a host fd",
+                    self.get_ino(),
+                )
+            })?;
+            let backing_file = BackingFile::open(fuse_fd, fd).await.context(format!(
+                "open_backing() failed to register the backing file of ino={}",
+                self.get_ino(),
+            ))?;
+            self.backing_file = Some(backing_file);
+        }
+        let backing_file = self
+            .backing_file
+            .as_mut()
+            .unwrap_or_else(|| panic!("open_backing() found backing file is None"));
+        backing_file.inc_open_count();
+        Ok(backing_file.backing_id())
+    }
+
+    /// Release a passthrough open, and unregister the backing file
+    /// from the FUSE connection when it is the last passthrough open
+    pub async fn release_backing(&mut self) -> anyhow::Result<()> {
+        let last_open = match self.backing_file.as_mut() {
+            Some(backing_file) => backing_file.dec_open_count() == 0,
+            None => return Ok(()),
+        };
+        if last_open {
+            if let Some(backing_file) = self.backing_file.take() {
+                backing_file.close().await.context(format!(
+                    "release_backing() failed to unregister the backing file of ino={}",
+                    self.get_ino(),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn dup_handle(&self, oflags: OFlag) -> anyhow::Result<B::Handle> {
+        // The duplicated fd shares the file status flags with the fd of the node and the
+        // other opens, so the append mode is kept by the open in the handle table instead
+        let new_handle = self
+            .backend
+            .dup(self.handle, oflags - OFlag::O_APPEND)
+            .await
+            .context(format!(
+                "dup_handle() failed to duplicate the handler of ino={} with flags={:?}",
+                self.get_ino(),
+                oflags,
+            ))?;
         // increase open count once dup() success
         self.inc_open_count();
-
-        let fcntl_oflags = FcntlArg::F_SETFL(oflags);
-        blocking!(fcntl::fcntl(new_fd, fcntl_oflags)).context(format!(
-            "dup_fd() failed to set the flags={:?} of duplicated handler of ino={}",
-            oflags, ino,
-        ))?;
-        // blocking!(unistd::dup3(raw_fd, new_fd, oflags)).context(format!(
-        //     "dup_fd() failed to set the flags={:?} of duplicated handler of ino={}",
-        //     oflags, ino,
-        // ))?;
-        Ok(new_fd)
+        Ok(new_handle)
     }
 
     pub fn is_node_data_empty(&self) -> bool {
@@ -196,6 +362,7 @@ impl Node {
 
     // Directory only methods
 
+    /// The cached entry of `name`
     pub fn get_entry(&self, name: &OsStr) -> Option<&DirEntry> {
         match &self.data {
             NodeData::DirData(dir_data) => match dir_data.get(name) {
@@ -206,235 +373,226 @@ impl Node {
         }
     }
 
-    async fn open_child_dir_helper(
-        &mut self,
-        child_dir_name: OsString,
-        mode: Mode,
-        create_dir: bool,
-    ) -> anyhow::Result<Node> {
+    /// Find the entry of `name`, which is looked up in the backing directory
+    /// and cached if not cached yet
+    pub async fn find_entry(&mut self, name: &OsStr) -> anyhow::Result<Option<&DirEntry>> {
         let ino = self.get_ino();
-        let fd = self.fd;
         let dir_data = match &mut self.data {
             NodeData::DirData(dir_data) => dir_data,
-            NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
+            NodeData::FileData(..) => panic!("forbidden to find entry from FileData"),
         };
-
-        if create_dir {
-            debug_assert!(
-                !dir_data.contains_key(&child_dir_name),
-                "open_child_dir_helper() cannot create duplicated directory name={:?}",
-                child_dir_name
-            );
-            let child_dir_name_clone = child_dir_name.clone();
-            blocking!(stat::mkdirat(fd, child_dir_name_clone.as_os_str(), mode)).context(
-                format!(
-                    "open_child_dir_helper() failed to create directory \
-                        name={:?} under parent ino={}",
-                    child_dir_name, ino,
-                ),
-            )?;
+        if !dir_data.contains_key(name) {
+            let entry = self
+                .backend
+                .lookup(self.handle, name)
+                .await
+                .context(format!(
+                    "find_entry() failed to look up name={:?} under the directory of ino={}",
+                    name, ino,
+                ))?;
+            match entry {
+                Some(entry) => {
+                    dir_data.insert(name.to_owned(), entry);
+                }
+                None => return Ok(None),
+            }
         }
+        Ok(dir_data.get(name))
+    }
 
-        let child_dir_name_clone = child_dir_name.clone();
-        let child_raw_fd = util::open_dir_at(fd, child_dir_name_clone)
+    /// Whether the directory has no entry served through the mount,
+    /// the backing directory is read until the first visible entry
+    pub async fn is_dir_empty(&self) -> anyhow::Result<bool> {
+        let ino = self.get_ino();
+        let is_cached_empty = match &self.data {
+            NodeData::DirData(dir_data) => dir_data.is_empty(),
+            NodeData::FileData(..) => panic!("forbidden to check entries of FileData"),
+        };
+        if !is_cached_empty {
+            return Ok(false);
+        }
+        let first = self
+            .backend
+            .readdir(self.handle, 0, 1)
             .await
             .context(format!(
-                "open_child_dir_helper() failed to open the new directory name={:?} \
-                    under parent ino={}",
-                child_dir_name, ino,
+                "is_dir_empty() failed to read the directory of ino={}",
+                ino,
             ))?;
+        Ok(first.is_empty())
+    }
 
-        // get new directory attribute
-        let child_attr = util::load_attr(child_raw_fd).await.context(
-            "open_child_dir_helper() failed to get the attribute of the new child directory"
-                .to_string(),
-        )?;
-        debug_assert_eq!(SFlag::S_IFDIR, child_attr.kind);
-
-        if create_dir {
-            // insert new entry to parent directory
-            // TODO: support thread-safe
-            let previous_value = dir_data.insert(
-                child_dir_name.clone(),
-                DirEntry::new(child_attr.ino, child_dir_name.clone(), SFlag::S_IFDIR),
-            );
-            debug_assert!(previous_value.is_none()); // double check creation race
-        }
-
-        // lookup count and open count are increased to 1 by creation
-        let mut child_node = Node {
-            parent: self.get_ino(),
-            name: child_dir_name,
-            attr: child_attr,
-            data: NodeData::DirData(BTreeMap::new()),
-            fd: child_raw_fd,
+    /// The node of the child of `name` under the directory of `parent` opened by the
+    /// backend as `handle`, the handle is closed if the attribute cannot be loaded
+    async fn from_handle(
+        backend: Arc<B>,
+        parent: INum,
+        name: OsString,
+        handle: B::Handle,
+    ) -> anyhow::Result<Node<B>> {
+        let attr = match backend.getattr(handle).await {
+            Ok(attr) => attr,
+            Err(e) => {
+                let _ = backend.close(handle);
+                return Err(e).context(format!(
+                    "from_handle() failed to get the attribute of the child name={:?} \
+                        under parent ino={}",
+                    name, parent,
+                ));
+            }
+        };
+        let data = match attr.kind {
+            SFlag::S_IFDIR => NodeData::DirData(BTreeMap::new()),
+            _ => NodeData::FileData(Vec::new()),
+        };
+        // lookup count and open count are increased to 1 by creation,
+        // the directory entries are looked up on demand
+        Ok(Node {
+            parent,
+            name,
+            attr,
+            stamp: DiskStamp::new(&attr),
+            data,
+            backend,
+            handle,
             open_count: AtomicI64::new(1),
             lookup_count: AtomicI64::new(1),
-        };
-
-        if !create_dir {
-            // load directory data on open
-            child_node.load_data().await?;
-        }
-
-        Ok(child_node)
-    }
-
-    pub async fn open_child_dir(&mut self, child_dir_name: OsString) -> anyhow::Result<Node> {
-        self.open_child_dir_helper(child_dir_name, Mode::empty(), false)
-            .await
-    }
-
-    pub async fn create_child_dir(
-        &mut self,
-        child_dir_name: OsString,
-        mode: Mode,
-    ) -> anyhow::Result<Node> {
-        self.open_child_dir_helper(child_dir_name, mode, true).await
+            backing_file: None,
+            policy: None,
+        })
     }
 
-    async fn open_child_file_helper(
+    /// Create the child of `child_name` and `child_type` with the permissions of `mode`
+    /// under this directory, and insert its entry
+    async fn create_child_helper(
         &mut self,
-        child_file_name: OsString,
-        oflags: OFlag,
+        child_name: OsString,
+        child_type: SFlag,
         mode: Mode,
-        create_file: bool,
-    ) -> anyhow::Result<Node> {
+    ) -> anyhow::Result<Node<B>> {
         let ino = self.get_ino();
-        let fd = self.fd;
         let dir_data = match &mut self.data {
             NodeData::DirData(dir_data) => dir_data,
             NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
         };
+        debug_assert!(
+            !dir_data.contains_key(&child_name),
+            "create_child_helper() cannot create duplicated child name={:?}",
+            child_name
+        );
+        let child_handle = self
+            .backend
+            .create(self.handle, &child_name, child_type, mode)
+            .await
+            .context(format!(
+                "create_child_helper() failed to create the child name={:?} of type={:?} \
+                    under parent ino={} with mode={:?}",
+                child_name, child_type, ino, mode,
+            ))?;
+        let child_node =
+            Self::from_handle(self.backend.clone(), ino, child_name.clone(), child_handle).await?;
+        debug_assert_eq!(child_type, child_node.get_type());
+
+        // insert new entry to parent directory
+        // TODO: support thread-safe
+        let previous_value = dir_data.insert(
+            child_name.clone(),
+            DirEntry::new(child_node.get_ino(), child_name, child_type),
+        );
+        debug_assert!(previous_value.is_none()); // double check creation race
+        self.sync_stamp().await?;
+        Ok(child_node)
+    }
 
-        if create_file {
-            debug_assert!(
-                !dir_data.contains_key(&child_file_name),
-                "open_child_file_helper() cannot create duplicated file name={:?}",
-                child_file_name
-            );
-            debug_assert!(oflags.contains(OFlag::O_CREAT));
-        }
-        let child_file_name_clone = child_file_name.clone();
-        let child_fd = blocking!(fcntl::openat(
-            fd,
-            child_file_name_clone.as_os_str(),
-            oflags,
-            mode
-        ))
-        .context(format!(
-            "open_child_file_helper() failed to open a file name={:?} \
-                    under parent ino={} with oflags={:?} and mode={:?}",
-            child_file_name, ino, oflags, mode,
-        ))?;
-
-        // get new file attribute
-        let child_attr = util::load_attr(child_fd).await.context(
-            "open_child_file_helper() failed to get the attribute of the new child".to_string(),
-        )?;
-        debug_assert_eq!(SFlag::S_IFREG, child_attr.kind);
-
-        if create_file {
-            // insert new entry to parent directory
-            // TODO: support thread-safe
-            let previous_value = dir_data.insert(
-                child_file_name.clone(),
-                DirEntry::new(child_attr.ino, child_file_name.clone(), SFlag::S_IFREG),
-            );
-            debug_assert!(previous_value.is_none()); // double check creation race
-        }
+    pub async fn open_child_dir(&mut self, child_dir_name: OsString) -> anyhow::Result<Node<B>> {
+        Self::open_child(
+            self.backend.clone(),
+            self.get_ino(),
+            self.handle,
+            child_dir_name,
+            SFlag::S_IFDIR,
+            OFlag::empty(),
+        )
+        .await
+    }
 
-        // lookup count and open count are increased to 1 by creation
-        Ok(Node {
-            parent: self.get_ino(),
-            name: child_file_name,
-            attr: child_attr,
-            data: NodeData::FileData(Vec::new()),
-            fd: child_fd,
-            open_count: AtomicI64::new(1),
-            lookup_count: AtomicI64::new(1),
-        })
+    pub async fn create_child_dir(
+        &mut self,
+        child_dir_name: OsString,
+        mode: Mode,
+    ) -> anyhow::Result<Node<B>> {
+        self.create_child_helper(child_dir_name, SFlag::S_IFDIR, mode)
+            .await
     }
 
     pub async fn open_child_file(
         &mut self,
         child_file_name: OsString,
         oflags: OFlag,
-    ) -> anyhow::Result<Node> {
-        self.open_child_file_helper(child_file_name, oflags, Mode::empty(), false)
-            .await
+    ) -> anyhow::Result<Node<B>> {
+        Self::open_child(
+            self.backend.clone(),
+            self.get_ino(),
+            self.handle,
+            child_file_name,
+            SFlag::S_IFREG,
+            oflags,
+        )
+        .await
     }
 
     pub async fn create_child_file(
         &mut self,
         child_file_name: OsString,
-        oflags: OFlag,
         mode: Mode,
-    ) -> anyhow::Result<Node> {
-        self.open_child_file_helper(child_file_name, oflags, mode, true)
+    ) -> anyhow::Result<Node<B>> {
+        self.create_child_helper(child_file_name, SFlag::S_IFREG, mode)
             .await
     }
 
-    // TODO: to remove
-    async fn load_dir_data_helper(&self) -> nix::Result<BTreeMap<OsString, DirEntry>> {
-        let fd = self.fd;
-        let dir = blocking!(Dir::from_fd(fd))?;
-
-        let dir_entry_map = blocking!(
-            let dir_entry_map: BTreeMap<OsString, DirEntry> = dir
-                .filter(|e| e.is_ok()) // filter out error result
-                .map(|e| e.unwrap()) // safe to use unwrap() here
-                .filter(|e| {
-                    let bytes = e.entry_name().as_bytes();
-                    !bytes.starts_with(&[b'.']) // skip hidden entries, '.' and '..'
-                })
-                .filter(|e| match e.entry_type() {
-                    SFlag::S_IFDIR => true,
-                    SFlag::S_IFREG => true,
-                    _ => false,
-                })
-                .map(|e| (e.entry_name().into(), e))
-                .collect();
-            dir_entry_map
-        );
-        Ok(dir_entry_map)
+    /// Open the child of `child_name` and `child_type` under the directory of `parent`
+    /// through the directory handle `parent_handle`, without borrowing the parent node,
+    /// so the lookups under the same directory open their children in parallel
+    pub async fn open_child(
+        backend: Arc<B>,
+        parent: INum,
+        parent_handle: B::Handle,
+        child_name: OsString,
+        child_type: SFlag,
+        oflags: OFlag,
+    ) -> anyhow::Result<Node<B>> {
+        let child_handle = backend
+            .open(parent_handle, &child_name, child_type, oflags)
+            .await
+            .context(format!(
+                "open_child() failed to open the child name={:?} of type={:?} \
+                    under parent ino={} with oflags={:?}",
+                child_name, child_type, parent, oflags,
+            ))?;
+        Self::from_handle(backend, parent, child_name, child_handle).await
     }
 
     // TODO: to remove
     async fn load_file_data_helper(&self) -> anyhow::Result<Vec<u8>> {
         let ino = self.get_ino();
-        let fd = self.fd;
         let file_size = self.attr.size;
         // TODO: load file data to cache
-        let file_data_vec = blocking!(
-            let mut file_data_vec: Vec<u8> = Vec::with_capacity(file_size as usize);
-            unsafe {
-                file_data_vec.set_len(file_data_vec.capacity());
-            }
-            let read_size = unistd::read(fd, &mut *file_data_vec).context(format!(
+        let file_data_vec = self
+            .backend
+            .read_at(self.handle, 0, file_size as usize)
+            .await
+            .context(format!(
                 "load_file_data_helper() failed to \
                     read the file of ino={} from disk",
                 ino,
             ))?;
-            unsafe {
-                file_data_vec.set_len(read_size);
-            }
-            // TODO: should explicitly highlight the error type?
-            Ok::<Vec<u8>, anyhow::Error>(file_data_vec)
-        )?;
         debug_assert_eq!(file_data_vec.len(), file_size as usize);
         Ok(file_data_vec)
     }
 
     pub async fn load_data(&mut self) -> anyhow::Result<usize> {
         match &mut self.data {
-            NodeData::DirData(..) => {
-                let dir_entry_map = self.load_dir_data_helper().await?;
-                let entry_count = dir_entry_map.len();
-                debug!("load_data() successfully load {} entries", entry_count,);
-                self.data = NodeData::DirData(dir_entry_map);
-                Ok(entry_count)
-            }
+            NodeData::DirData(..) => panic!("forbidden to load DirData, looked up on demand"),
             NodeData::FileData(..) => {
                 let file_data_vec = self.load_file_data_helper().await?;
                 let read_size = file_data_vec.len();
@@ -445,8 +603,7 @@ impl Node {
         }
     }
 
-    #[allow(dead_code)]
-    fn insert_entry(&mut self, child_entry: DirEntry) -> Option<DirEntry> {
+    pub fn insert_entry(&mut self, child_entry: DirEntry) -> Option<DirEntry> {
         let dir_data = match &mut self.data {
             NodeData::DirData(dir_data) => dir_data,
             NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
@@ -462,14 +619,15 @@ impl Node {
         previous_entry
     }
 
-    // fn remove_entry(&mut self, child_name: OsString) -> Option<DirEntry> {
-    //     let dir_data = match &mut self.data {
-    //         NodeData::DirData(dir_data) => dir_data,
-    //         NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
-    //     };
+    /// Remove the cached entry of `child_name`, the child is kept by the backend
+    pub fn remove_entry(&mut self, child_name: &OsStr) -> Option<DirEntry> {
+        let dir_data = match &mut self.data {
+            NodeData::DirData(dir_data) => dir_data,
+            NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
+        };
 
-    //     dir_data.remove(child_name.as_os_str())
-    // }
+        dir_data.remove(child_name)
+    }
 
     pub async fn unlink_entry(&mut self, child_name: OsString) -> anyhow::Result<DirEntry> {
         let dir_data = match &mut self.data {
@@ -487,54 +645,19 @@ impl Node {
             self.get_ino(),
         );
         let removed_entry = removed_entry.unwrap(); // safe to use unwrap() here
-        let child_name_clone = child_name.clone();
-        let fd = self.fd;
-        // delete from disk and close the handler
-        match removed_entry.entry_type() {
-            SFlag::S_IFDIR => {
-                blocking!(unistd::unlinkat(
-                    Some(fd),
-                    child_name.as_os_str(),
-                    unistd::UnlinkatFlags::RemoveDir,
-                ))
-                .context(format!(
-                    "unlink_entry() failed to delete the file name={:?} from disk",
-                    child_name_clone,
-                ))?;
-            }
-            SFlag::S_IFREG => {
-                blocking!(unistd::unlinkat(
-                    Some(fd),
-                    child_name.as_os_str(),
-                    unistd::UnlinkatFlags::NoRemoveDir,
-                ))
-                .context(format!(
-                    "unlink_entry() failed to delete the file name={:?} from disk",
-                    child_name_clone,
-                ))?;
-            }
-            _ => panic!(
-                "unlink_entry() found unsupported entry type={:?}",
-                removed_entry.entry_type()
-            ),
-        }
+                                                    // delete from disk and close the handler
+        self.backend
+            .unlink(self.handle, &child_name, removed_entry.entry_type())
+            .await
+            .context(format!(
+                "unlink_entry() failed to delete the file name={:?} from disk",
+                child_name,
+            ))?;
+        self.sync_stamp().await?;
 
         Ok(removed_entry)
     }
 
-    pub fn read_dir(&self, func: impl FnOnce(&BTreeMap<OsString, DirEntry>) -> usize) -> usize {
-        // debug_assert!(
-        //     !self.need_load_file_data(),
-        //     "directory data should be load before read",
-        // );
-        let dir_data = match &self.data {
-            NodeData::DirData(dir_data) => dir_data,
-            NodeData::FileData(..) => panic!("forbidden to load DirData from file node"),
-        };
-
-        func(&dir_data)
-    }
-
     // File only methods
 
     // TODO: maybe this function is not needed, consider refactory
@@ -554,12 +677,12 @@ impl Node {
         func(&file_data)
     }
 
+    /// Write `data` at `offset` to the file of `handle`, and overwrite the cached data in place
     pub async fn write_file(
         &mut self,
-        fh: u64,
+        handle: B::Handle,
         offset: i64,
         data: Vec<u8>,
-        oflags: OFlag,
         write_to_disk: bool,
     ) -> anyhow::Result<usize> {
         let ino = self.get_ino();
@@ -571,7 +694,7 @@ impl Node {
         let size_after_write = offset as usize + data.len();
         if file_data_vec.capacity() < size_after_write {
             let before_cap = file_data_vec.capacity();
-            let extra_space_size = size_after_write - file_data_vec.capacity();
+            let extra_space_size = size_after_write - file_data_vec.len();
             file_data_vec.reserve(extra_space_size);
             // TODO: handle OOM when reserving
             // let result = file_data.try_reserve(extra_space_size);
@@ -589,34 +712,24 @@ impl Node {
                 file_data_vec.capacity(),
             );
         }
-        match file_data_vec.len().cmp(&(offset as usize)) {
-            std::cmp::Ordering::Greater => {
-                file_data_vec.truncate(offset as usize);
-                debug!(
-                    "write_file() truncated the file of ino={} to size={}",
-                    ino, offset
-                );
-            }
-            std::cmp::Ordering::Less => {
-                let zero_padding_size = (offset as usize) - file_data_vec.len();
-                let mut zero_padding_vec = vec![0u8; zero_padding_size];
-                file_data_vec.append(&mut zero_padding_vec);
-            }
-            std::cmp::Ordering::Equal => (),
+        if file_data_vec.len() < size_after_write {
+            // zero padding the hole before offset, if any
+            file_data_vec.resize(size_after_write, 0);
+            debug!(
+                "write_file() extended the file of ino={} to size={}",
+                ino, size_after_write,
+            );
         }
         // TODO: consider zero copy
-        file_data_vec.extend_from_slice(&data);
+        file_data_vec[(offset as usize)..size_after_write].copy_from_slice(&data);
 
-        let fcntl_oflags = fcntl::FcntlArg::F_SETFL(oflags);
-        let fd = fh as RawFd;
-        fcntl::fcntl(fd, fcntl_oflags).context(format!(
-            "write_file() failed to set the flags={:?} to file handler={} of ino={}",
-            oflags, fd, ino,
-        ))?;
         let mut written_size = data.len();
         if write_to_disk {
             let data_len = data.len();
-            written_size = blocking!(nix::sys::uio::pwrite(fd, &data, offset))
+            written_size = self
+                .backend
+                .write_at(handle, offset as u64, data)
+                .await
                 .context("write_file() failed to write to disk")?;
             debug_assert_eq!(data_len, written_size);
         }
@@ -624,71 +737,566 @@ impl Node {
         self.attr.size = file_data_vec.len() as u64;
         let ts = SystemTime::now();
         self.attr.mtime = ts;
+        if write_to_disk {
+            self.sync_stamp().await?;
+        }
 
         Ok(written_size)
     }
 
-    pub async fn open_root_node(
+    /// Truncate the file to `size`, both the file on disk and the cached data
+    pub async fn truncate_file(&mut self, size: u64) -> anyhow::Result<()> {
+        let ino = self.get_ino();
+        let file_data_vec = match &mut self.data {
+            NodeData::DirData(..) => panic!("forbidden to truncate dir node"),
+            NodeData::FileData(file_data) => file_data,
+        };
+        self.backend
+            .truncate(self.handle, size)
+            .await
+            .context(format!(
+                "truncate_file() failed to truncate the file of ino={} to size={}",
+                ino, size,
+            ))?;
+        // cached data beyond the new size is dropped, the data within is kept,
+        // and the loaded data is extended with zeros, the unloaded is left to load
+        if !file_data_vec.is_empty() || self.attr.size == 0 {
+            file_data_vec.resize(size as usize, 0);
+        }
+        let ts = SystemTime::now();
+        self.attr.size = size;
+        self.attr.mtime = ts;
+        self.attr.ctime = ts;
+        self.sync_stamp().await
+    }
+
+    /// The number of the bytes of the file data cached
+    pub fn cached_bytes(&self) -> u64 {
+        match &self.data {
+            NodeData::DirData(..) => 0,
+            NodeData::FileData(file_data) => file_data.len() as u64,
+        }
+    }
+
+    /// Drop the cached file data, the data is loaded again on next use if cached
+    pub fn drop_data(&mut self) {
+        if let NodeData::FileData(..) = self.data {
+            self.data = NodeData::FileData(Vec::new());
+        }
+    }
+
+    /// Read `size` bytes at `offset` from the underlying file without caching the data
+    pub async fn read_uncached(&self, offset: i64, size: u32) -> anyhow::Result<Vec<u8>> {
+        let data = self
+            .backend
+            .read_at(self.handle, offset as u64, size as usize)
+            .await
+            .context(format!(
+                "read_uncached() failed to read the file of ino={} at offset={}",
+                self.get_ino(),
+                offset,
+            ))?;
+        Ok(data)
+    }
+
+    /// Write `data` at `offset` to the file of `handle` without caching the data,
+    /// the file data should not be cached either
+    pub async fn write_uncached(
+        &mut self,
+        handle: B::Handle,
+        offset: i64,
+        data: Vec<u8>,
+    ) -> anyhow::Result<usize> {
+        debug_assert!(
+            self.is_node_data_empty(),
+            "the cached data would be stale after the uncached write",
+        );
+        let data_len = data.len();
+        let written_size = self
+            .backend
+            .write_at(handle, offset as u64, data)
+            .await
+            .context(format!(
+                "write_uncached() failed to write the file of ino={} at offset={}",
+                self.get_ino(),
+                offset,
+            ))?;
+        debug_assert_eq!(data_len, written_size);
+        self.attr.size = self.attr.size.max(offset as u64 + written_size as u64);
+        self.attr.mtime = SystemTime::now();
+        self.sync_stamp().await?;
+        Ok(written_size)
+    }
+
+    /// Change the permission bits of the underlying file to the ones of `mode`
+    pub async fn chmod(&mut self, mode: u32) -> anyhow::Result<()> {
+        let perm = util::parse_mode(mode);
+        self.backend
+            .chmod(self.handle, perm)
+            .await
+            .context(format!(
+                "chmod() failed to set the mode of the node ino={} to {:?}",
+                self.get_ino(),
+                perm,
+            ))?;
+        self.reload_attr().await?;
+        Ok(())
+    }
+
+    /// Change the owner and the group of the underlying file, None to keep
+    pub async fn chown(&mut self, uid: Option<u32>, gid: Option<u32>) -> anyhow::Result<()> {
+        self.backend
+            .chown(self.handle, uid, gid)
+            .await
+            .context(format!(
+                "chown() failed to set the owner of the node ino={} to uid={:?} gid={:?}",
+                self.get_ino(),
+                uid,
+                gid,
+            ))?;
+        self.reload_attr().await?;
+        Ok(())
+    }
+
+    /// Change the access and the modification times of the underlying file, None to keep
+    pub async fn utimens(
+        &mut self,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        self.backend
+            .utimens(self.handle, atime, mtime)
+            .await
+            .context(format!(
+                "utimens() failed to set the times of the node ino={} to atime={:?} mtime={:?}",
+                self.get_ino(),
+                atime,
+                mtime,
+            ))?;
+        self.reload_attr().await?;
+        Ok(())
+    }
+
+    /// Clear the set-user-ID bit, and the set-group-ID bit if group executable, of the
+    /// underlying regular file, as the kernel does once the file is written, truncated
+    /// or chowned by unprivileged users. Return whether any bit is cleared
+    pub async fn kill_priv(&mut self) -> anyhow::Result<bool> {
+        if self.get_type() != SFlag::S_IFREG {
+            return Ok(false);
+        }
+        // the bits may be cleared by the backing filesystem already
+        let attr = self.reload_attr().await?;
+        let perm = Mode::from_bits_truncate(attr.perm.into());
+        let mut killed = perm - Mode::S_ISUID;
+        if perm.contains(Mode::S_IXGRP) {
+            killed.remove(Mode::S_ISGID);
+        }
+        if killed == perm {
+            return Ok(false);
+        }
+        self.backend
+            .chmod(self.handle, killed)
+            .await
+            .context(format!(
+                "kill_priv() failed to clear the set-user-ID and set-group-ID bits \
+                of the node ino={}",
+                self.get_ino(),
+            ))?;
+        self.reload_attr().await?;
+        debug!(
+            "kill_priv() cleared the mode of the node ino={} from {:?} to {:?}",
+            self.get_ino(),
+            perm,
+            killed,
+        );
+        Ok(true)
+    }
+
+    /// The cache policy set by the xattrs of the underlying file, loaded on first use
+    pub async fn cache_policy(&mut self) -> anyhow::Result<CachePolicy> {
+        if let Some(policy) = self.policy {
+            return Ok(policy);
+        }
+        let policy = CachePolicy::load(&*self.backend, self.handle)
+            .await
+            .context(format!(
+                "cache_policy() failed to load the cache policy of the node ino={}",
+                self.get_ino(),
+            ))?;
+        self.policy = Some(policy);
+        Ok(policy)
+    }
+
+    /// Get the xattr of `name` of the underlying file, None if not set
+    pub async fn get_xattr(&self, name: &OsStr) -> anyhow::Result<Option<Vec<u8>>> {
+        let value = self
+            .backend
+            .get_xattr(self.handle, name)
+            .await
+            .context(format!(
+                "get_xattr() failed to get the xattr name={:?} of the node ino={}",
+                name,
+                self.get_ino(),
+            ))?;
+        Ok(value)
+    }
+
+    /// List the names of the xattrs of the underlying file, each ends with a NUL
+    pub async fn list_xattr(&self) -> anyhow::Result<Vec<u8>> {
+        let names = self.backend.list_xattr(self.handle).await.context(format!(
+            "list_xattr() failed to list the xattrs of the node ino={}",
+            self.get_ino(),
+        ))?;
+        Ok(names)
+    }
+
+    /// Set the xattr of `name` of the underlying file, the cache policy is loaded again
+    /// on next use in case the xattr is a part of it
+    pub async fn set_xattr(
+        &mut self,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: u32,
+    ) -> anyhow::Result<()> {
+        self.backend
+            .set_xattr(self.handle, name, value, flags)
+            .await
+            .context(format!(
+                "set_xattr() failed to set the xattr name={:?} of the node ino={}",
+                name,
+                self.get_ino(),
+            ))?;
+        self.policy = None;
+        // setting xattrs changes the ctime
+        self.reload_attr().await?;
+        Ok(())
+    }
+
+    /// Remove the xattr of `name` of the underlying file
+    pub async fn remove_xattr(&mut self, name: &OsStr) -> anyhow::Result<()> {
+        self.backend
+            .remove_xattr(self.handle, name)
+            .await
+            .context(format!(
+                "remove_xattr() failed to remove the xattr name={:?} of the node ino={}",
+                name,
+                self.get_ino(),
+            ))?;
+        self.policy = None;
+        self.reload_attr().await?;
+        Ok(())
+    }
+
+    /// Open the root directory of the backend as the node of `root_ino`
+    pub async fn open_root(
+        backend: Arc<B>,
         root_ino: INum,
         name: OsString,
-        path: impl AsRef<Path>,
-    ) -> anyhow::Result<Node> {
-        let path = path.as_ref();
-        let dir_fd = util::open_dir(path).await?;
-        let mut attr = util::load_attr(dir_fd).await?;
+    ) -> anyhow::Result<Node<B>> {
+        let handle = backend.open_root().await?;
+        let mut attr = match backend.getattr(handle).await {
+            Ok(attr) => attr,
+            Err(e) => {
+                let _ = backend.close(handle);
+                return Err(e).context("open_root() failed to get the attribute of the root");
+            }
+        };
         attr.ino = root_ino; // replace root ino with 1
 
-        let mut root_node = Node {
+        // the directory entries are looked up on demand
+        Ok(Node {
             parent: root_ino,
             name,
             attr,
+            stamp: DiskStamp::new(&attr),
             data: NodeData::DirData(BTreeMap::new()),
-            fd: dir_fd,
+            backend,
+            handle,
             // lookup count set to 1 by creation
             open_count: AtomicI64::new(1),
             // open count set to 1 by creation
             lookup_count: AtomicI64::new(1),
-        };
-        // load root directory data on open
-        root_node.load_data().await?;
+            backing_file: None,
+            policy: None,
+        })
+    }
 
-        Ok(root_node)
+    /// Encode the node to hand over to the new daemon, and return the fd of the node
+    /// to pass along, the nodes not kept on host fds cannot be handed over.
+    /// The cached data is not handed over, it is reloaded from the underlying file
+    pub fn save(&self, enc: &mut Encoder) -> anyhow::Result<RawFd> {
+        let fd = self.get_fd().ok_or_else(|| {
+            anyhow::anyhow!(
+                "save() found the node of ino={} is not kept on a host fd",
+                self.get_ino(),
+            )
+        })?;
+        enc.put_u64(self.get_ino());
+        enc.put_u64(self.parent);
+        enc.put_os_str(&self.name);
+        enc.put_u32(self.get_type().bits());
+        enc.put_i32(fd);
+        enc.put_i64(self.get_open_count());
+        enc.put_i64(self.get_lookup_count());
+        match self.backing_file {
+            Some(ref backing_file) => {
+                enc.put_i32(backing_file.backing_id());
+                enc.put_u64(backing_file.open_count());
+            }
+            None => {
+                enc.put_i32(-1);
+                enc.put_u64(0);
+            }
+        }
+        Ok(fd)
+    }
+
+    /// Restore the node saved by the old daemon, whose fd is handed over,
+    /// the backing file stays registered to the FUSE connection of `fuse_fd`
+    pub async fn restore(
+        backend: Arc<B>,
+        dec: &mut Decoder<'_>,
+        fuse_fd: Option<RawFd>,
+    ) -> anyhow::Result<Node<B>> {
+        let ino = dec.get_u64()?;
+        let parent = dec.get_u64()?;
+        let name = dec.get_os_string()?;
+        let kind = SFlag::from_bits_truncate(dec.get_u32()?);
+        let fd = dec.get_i32()?;
+        let open_count = dec.get_i64()?;
+        let lookup_count = dec.get_i64()?;
+        let backing_id = dec.get_i32()?;
+        let backing_open_count = dec.get_u64()?;
+        let backing_file = match (backing_id, fuse_fd) {
+            (-1, _) => None,
+            (backing_id, Some(fuse_fd)) => Some(BackingFile::restore(
+                fuse_fd,
+                backing_id,
+                backing_open_count,
+            )),
+            (backing_id, None) => {
+                return Err(anyhow::anyhow!(
+                    "restore() found the node of ino={} registered as backing id={}, \
+                        but passthrough is not enabled",
+                    ino,
+                    backing_id,
+                ))
+            }
+        };
+        let handle = backend.from_raw_fd(fd).ok_or_else(|| {
+            anyhow::anyhow!(
+                "restore() found the backend keeps no files on host fds, \
+                    cannot restore the node of ino={} and fd={}",
+                ino,
+                fd,
+            )
+        })?;
+        let mut attr = backend.getattr(handle).await.context(format!(
+            "restore() failed to get the attribute of the node of ino={} and fd={}",
+            ino, fd,
+        ))?;
+        attr.ino = ino; // the root ino is not the one on disk
+        let data = match kind {
+            SFlag::S_IFDIR => NodeData::DirData(BTreeMap::new()),
+            SFlag::S_IFREG => NodeData::FileData(Vec::new()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "restore() found unsupported file type={:?} of ino={}",
+                    kind,
+                    ino,
+                ))
+            }
+        };
+        // the directory entries are looked up on demand
+        Ok(Node {
+            parent,
+            name,
+            attr,
+            stamp: DiskStamp::new(&attr),
+            data,
+            backend,
+            handle,
+            open_count: AtomicI64::new(open_count),
+            lookup_count: AtomicI64::new(lookup_count),
+            backing_file,
+            policy: None,
+        })
     }
 
-    #[allow(dead_code)]
-    fn move_file(
-        old_parent_node: &Node,
+    /// Move the child of `old_name` under this directory to `new_name` under the directory
+    /// of `new_parent_handle`, replacing the child of `new_name` if any. The cached entries
+    /// are left to the caller, which holds both directories
+    pub async fn move_file(
+        &self,
         old_name: &OsStr,
-        new_parent_node: &Node,
+        new_parent_handle: B::Handle,
         new_name: &OsStr,
-    ) -> nix::Result<()> {
+    ) -> anyhow::Result<()> {
         debug!(
             "move_file() about to move file of old name={:?} \
-                from directory={:?} to directory={:?} with new name={:?}",
+                from directory={:?} to directory of handle={:?} with new name={:?}",
             old_name,
-            old_parent_node.get_name(),
-            new_parent_node.get_name(),
+            self.get_name(),
+            new_parent_handle,
             new_name,
         );
-        fcntl::renameat(
-            Some(old_parent_node.get_fd()),
-            Path::new(old_name),
-            Some(new_parent_node.get_fd()),
-            Path::new(new_name),
-        )
+        self.backend
+            .rename(self.handle, old_name, new_parent_handle, new_name)
+            .await
+            .context(format!(
+                "move_file() failed to move name={:?} under the directory of ino={} \
+                    to name={:?}",
+                old_name,
+                self.get_ino(),
+                new_name,
+            ))
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::backend::LocalBackend;
+    use super::Node;
     use anyhow::bail;
     use nix::fcntl::{self, FcntlArg, OFlag};
     use nix::sys::stat::Mode;
     use nix::unistd;
-    use std::fs::File;
+    use std::ffi::{OsStr, OsString};
+    use std::fs::{self, File};
     use std::io::prelude::*;
     use std::os::unix::io::FromRawFd;
     use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_revalidate() -> anyhow::Result<()> {
+        let path = Path::new("/tmp/revalidate_test");
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        fs::create_dir_all(path)?;
+        let res = smol::run(async {
+            let backend = Arc::new(LocalBackend::new(path));
+            let mut root_node = Node::open_root(backend, 1, OsString::from("/")).await?;
+            assert!(root_node.revalidate().await?.is_none());
+
+            // change the directory out of band, the new name is not cached
+            fs::write(path.join("foo"), b"foo")?;
+            fs::write(path.join("bar"), b"bar")?;
+            assert_eq!(root_node.revalidate().await?, Some(Vec::new()));
+            assert_eq!(root_node.get_ino(), 1);
+            assert!(root_node.get_entry(OsStr::new("foo")).is_none());
+            assert!(root_node.find_entry(OsStr::new("foo")).await?.is_some());
+            assert!(root_node.find_entry(OsStr::new("bar")).await?.is_some());
+            assert!(root_node.revalidate().await?.is_none());
+            // the cached name is removed out of band
+            fs::remove_file(path.join("bar"))?;
+            let changed_names = root_node.revalidate().await?;
+            assert_eq!(changed_names, Some(vec![OsString::from("bar")]));
+            assert!(root_node.get_entry(OsStr::new("bar")).is_none());
+
+            let mut file_node = root_node
+                .open_child_file(OsString::from("foo"), OFlag::O_RDWR)
+                .await?;
+            file_node.load_data().await?;
+            fs::write(path.join("foo"), b"foobar")?;
+            assert_eq!(file_node.revalidate().await?, Some(Vec::new()));
+            assert_eq!(file_node.get_attr().size, 6);
+            assert!(file_node.need_load_file_data());
+            file_node.load_data().await?;
+            let content = file_node.read_file(|data| Ok(data.clone()))?;
+            assert_eq!(content, b"foobar");
+            Ok(())
+        });
+        fs::remove_dir_all(path)?;
+        res
+    }
+
+    #[test]
+    fn test_write_file() -> anyhow::Result<()> {
+        let path = Path::new("/tmp/write_file_test");
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        fs::create_dir_all(path)?;
+        fs::write(path.join("foo"), b"0123456789")?;
+        let res = smol::run(async {
+            let backend = Arc::new(LocalBackend::new(path));
+            let mut root_node = Node::open_root(backend, 1, OsString::from("/")).await?;
+            let mut file_node = root_node
+                .open_child_file(OsString::from("foo"), OFlag::O_RDWR)
+                .await?;
+            file_node.load_data().await?;
+            // the append open shares the file status flags with the node
+            let fd = file_node
+                .dup_handle(OFlag::O_RDWR | OFlag::O_APPEND)
+                .await?;
+
+            // overwrite in the middle keeps the tail
+            file_node.write_file(fd, 2, b"ab".to_vec(), true).await?;
+            // write beyond the end leaves a hole of zeros
+            file_node.write_file(fd, 12, b"cd".to_vec(), true).await?;
+            let expected = b"01ab456789\0\0cd".to_vec();
+            assert_eq!(file_node.read_file(|data| Ok(data.clone()))?, expected);
+            assert_eq!(fs::read(path.join("foo"))?, expected);
+            assert_eq!(file_node.get_attr().size, 14);
+
+            file_node.truncate_file(4).await?;
+            file_node.truncate_file(6).await?;
+            let expected = b"01ab\0\0".to_vec();
+            assert_eq!(file_node.read_file(|data| Ok(data.clone()))?, expected);
+            assert_eq!(fs::read(path.join("foo"))?, expected);
+            unistd::close(fd)?;
+            Ok(())
+        });
+        fs::remove_dir_all(path)?;
+        res
+    }
+
+    #[test]
+    fn test_set_attr() -> anyhow::Result<()> {
+        let path = Path::new("/tmp/set_attr_test");
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        fs::create_dir_all(path)?;
+        fs::write(path.join("foo"), b"foo")?;
+        let res = smol::run(async {
+            let backend = Arc::new(LocalBackend::new(path));
+            let mut root_node = Node::open_root(backend, 1, OsString::from("/")).await?;
+            let mut file_node = root_node
+                .open_child_file(OsString::from("foo"), OFlag::O_RDWR)
+                .await?;
+
+            file_node.chmod(libc::S_IFREG | 0o6755).await?;
+            assert_eq!(file_node.get_attr().perm, 0o6755);
+            assert!(file_node.kill_priv().await?);
+            assert_eq!(file_node.get_attr().perm, 0o755);
+            assert!(!file_node.kill_priv().await?);
+            // the set-group-ID bit without group execute is the mandatory locking
+            file_node.chmod(libc::S_IFREG | 0o2644).await?;
+            assert!(!file_node.kill_priv().await?);
+            assert_eq!(file_node.get_attr().perm, 0o2644);
+
+            let uid = unistd::getuid().as_raw();
+            file_node.chown(Some(uid), None).await?;
+            assert_eq!(file_node.get_attr().uid, uid);
+
+            let mtime = file_node.get_attr().mtime;
+            let atime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+            file_node.utimens(Some(atime), None).await?;
+            assert_eq!(file_node.get_attr().atime, atime);
+            assert_eq!(file_node.get_attr().mtime, mtime);
+            // the root keeps its ino after reloading the attribute
+            root_node.utimens(None, Some(atime)).await?;
+            assert_eq!(root_node.get_attr().mtime, atime);
+            assert_eq!(root_node.get_ino(), 1);
+            Ok(())
+        });
+        fs::remove_dir_all(path)?;
+        res
+    }
 
     #[test]
     fn test_dup_fd() -> anyhow::Result<()> {
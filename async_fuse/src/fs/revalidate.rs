@@ -0,0 +1,195 @@
+//! Revalidation of the cached nodes against the backing directory.
+//!
+//! The files under the backing directory may be changed by other tools on the host,
+//! then the cached attributes, directory entries and file data of the nodes are stale.
+//! The nodes are revalidated either when opened, by comparing the mtime, ctime and size
+//! of the underlying files, or once inotify reports the changes of the loaded directories
+//! and their children. The kernel caches of the changed nodes are invalidated by FUSE
+//! notifications, which requires ABI 7.12, otherwise they expire with the TTL.
+
+use anyhow;
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+#[cfg(target_os = "linux")]
+use futures::lock::Mutex;
+#[cfg(target_os = "linux")]
+use log::{debug, warn};
+#[cfg(target_os = "linux")]
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+#[cfg(target_os = "linux")]
+use smol::blocking;
+#[cfg(target_os = "linux")]
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+use super::super::protocol::INum;
+#[cfg(target_os = "linux")]
+use super::{FileSystem, StorageBackend};
+
+/// When to check the cached nodes against the underlying files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Revalidation {
+    /// Never check, the backing directory is only changed through the mount
+    Never,
+    /// Check the node when it is opened
+    OnOpen,
+    /// Watch the loaded directories and their children by inotify
+    #[cfg(target_os = "linux")]
+    Watch,
+}
+
+impl Default for Revalidation {
+    fn default() -> Self {
+        Revalidation::OnOpen
+    }
+}
+
+impl FromStr for Revalidation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "never" => Ok(Revalidation::Never),
+            "open" => Ok(Revalidation::OnOpen),
+            #[cfg(target_os = "linux")]
+            "watch" => Ok(Revalidation::Watch),
+            _ => Err(anyhow::anyhow!(
+                "unknown revalidation mode={:?}, should be one of never, open or watch",
+                s,
+            )),
+        }
+    }
+}
+
+/// A kernel cache entry to invalidate once the underlying file is changed
+#[derive(Debug)]
+#[cfg_attr(not(feature = "abi-7-12"), allow(dead_code))]
+pub(crate) enum Invalidation {
+    /// The attributes and the cached data of the inode
+    Inode(INum),
+    /// The directory entry of the name under the parent
+    Entry(INum, OsString),
+}
+
+/// The inotify events of the loaded directories,
+/// the events of the children are reported by the watches of their parents
+#[cfg(target_os = "linux")]
+const WATCH_FLAGS: AddWatchFlags = AddWatchFlags::from_bits_truncate(
+    AddWatchFlags::IN_ATTRIB.bits()
+        | AddWatchFlags::IN_MODIFY.bits()
+        | AddWatchFlags::IN_CLOSE_WRITE.bits()
+        | AddWatchFlags::IN_CREATE.bits()
+        | AddWatchFlags::IN_DELETE.bits()
+        | AddWatchFlags::IN_MOVE.bits()
+        | AddWatchFlags::IN_DELETE_SELF.bits()
+        | AddWatchFlags::IN_MOVE_SELF.bits()
+        | AddWatchFlags::IN_ONLYDIR.bits(),
+);
+
+/// The inotify watches of the loaded directories
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub(crate) struct Watcher {
+    inotify: Inotify,
+    /// The directory nodes watched, indexed by the watch descriptors
+    watches: BTreeMap<WatchDescriptor, INum>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new() -> anyhow::Result<Watcher> {
+        let inotify =
+            Inotify::init(InitFlags::IN_CLOEXEC).context("failed to initialize inotify")?;
+        Ok(Watcher {
+            inotify,
+            watches: BTreeMap::new(),
+        })
+    }
+
+    pub fn inotify(&self) -> Inotify {
+        self.inotify
+    }
+
+    /// Watch the directory node of `fd`, the directory is opened
+    /// by fd rather than path, so watch it through procfs
+    pub fn watch(&mut self, ino: INum, fd: RawFd) -> anyhow::Result<()> {
+        let path = format!("/proc/self/fd/{}", fd);
+        let wd = self
+            .inotify
+            .add_watch(path.as_str(), WATCH_FLAGS)
+            .context(format!(
+                "failed to watch the directory of ino={} and fd={}",
+                ino, fd,
+            ))?;
+        debug!(
+            "watch() watched the directory of ino={} by wd={:?}",
+            ino, wd
+        );
+        self.watches.insert(wd, ino);
+        Ok(())
+    }
+
+    /// The node of the watch, None if the watch is removed
+    pub fn resolve(&self, wd: WatchDescriptor) -> Option<INum> {
+        self.watches.get(&wd).copied()
+    }
+
+    /// Remove the watch of the directory node evicted from cache
+    pub fn unwatch_node(&mut self, ino: INum) {
+        let wd = match self.watches.iter().find(|&(_, &watched)| watched == ino) {
+            Some((&wd, _)) => wd,
+            None => return,
+        };
+        self.watches.remove(&wd);
+        if let Err(e) = self.inotify.rm_watch(wd) {
+            debug!(
+                "unwatch_node() failed to remove the watch of ino={}, the error is: {}",
+                ino, e,
+            );
+        }
+    }
+
+    /// Forget the watch removed by the kernel, e.g. the directory is deleted
+    pub fn unwatch(&mut self, wd: WatchDescriptor) {
+        if let Some(ino) = self.watches.remove(&wd) {
+            debug!(
+                "unwatch() removed the watch of the directory of ino={}",
+                ino
+            );
+        }
+    }
+}
+
+/// Read the inotify events and revalidate the changed nodes,
+/// quit if watching is not enabled for the filesystem
+#[cfg(target_os = "linux")]
+pub(crate) async fn run_watcher<B: StorageBackend>(
+    fs: Arc<Mutex<FileSystem<B>>>,
+) -> anyhow::Result<()> {
+    let inotify = match fs.lock().await.inotify() {
+        Some(inotify) => inotify,
+        None => return Ok(()),
+    };
+    loop {
+        let events: Vec<InotifyEvent> = match blocking!(inotify.read_events()) {
+            Ok(events) => events,
+            Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+            Err(e) => return Err(e).context("failed to read inotify events"),
+        };
+        debug!("run_watcher() received {} inotify events", events.len());
+        let mut filesystem = fs.lock().await;
+        if let Err(e) = filesystem.handle_watch_events(events).await {
+            warn!(
+                "failed to revalidate the changed nodes, the error is: {:?}",
+                e
+            );
+        }
+    }
+}
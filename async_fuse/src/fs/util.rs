@@ -1,9 +1,10 @@
 use anyhow::{self, Context};
 use log::debug;
+use nix::errno::Errno;
 use nix::fcntl::{self, OFlag};
 use nix::sys::stat::{self, FileStat, Mode, SFlag};
 use smol::blocking;
-use std::ffi::OsString;
+use std::ffi::{CStr, OsString};
 use std::os::unix::io::RawFd;
 use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -168,6 +169,192 @@ pub fn time_from_system_time(system_time: &SystemTime) -> anyhow::Result<(u64, u
     Ok((duration.as_secs(), duration.subsec_nanos()))
 }
 
+/// Change the owner and the group of the file of `fd`, None to keep
+pub fn fchown(fd: RawFd, uid: Option<u32>, gid: Option<u32>) -> nix::Result<()> {
+    // -1 leaves the id unchanged
+    let uid = uid.unwrap_or(libc::uid_t::max_value());
+    let gid = gid.unwrap_or(libc::gid_t::max_value());
+    let res = unsafe { libc::fchown(fd, uid, gid) };
+    Errno::result(res).map(drop)
+}
+
+/// Change the access and the modification times of the file of `fd`, None to keep
+pub fn futimens(
+    fd: RawFd,
+    atime: Option<SystemTime>,
+    mtime: Option<SystemTime>,
+) -> nix::Result<()> {
+    fn timespec(time: Option<SystemTime>) -> nix::Result<libc::timespec> {
+        match time {
+            Some(time) => {
+                let duration = time
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|_| nix::Error::Sys(Errno::EINVAL))?;
+                Ok(libc::timespec {
+                    tv_sec: duration.as_secs() as libc::time_t,
+                    tv_nsec: duration.subsec_nanos().into(),
+                })
+            }
+            None => Ok(libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            }),
+        }
+    }
+    let times = [timespec(atime)?, timespec(mtime)?];
+    let res = unsafe { libc::futimens(fd, times.as_ptr()) };
+    Errno::result(res).map(drop)
+}
+
+/// The errno of the xattr not set on the file
+#[cfg(target_os = "linux")]
+pub const ENOATTR: Errno = Errno::ENODATA;
+/// The errno of the xattr not set on the file
+#[cfg(target_os = "macos")]
+pub const ENOATTR: Errno = Errno::ENOATTR;
+
+/// Whether the error means the file has no such xattr or the filesystem supports no xattrs
+fn is_xattr_missing(e: nix::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    let not_supported = Errno::EOPNOTSUPP;
+    #[cfg(target_os = "macos")]
+    let not_supported = Errno::ENOTSUP;
+    e.as_errno()
+        .map_or(false, |errno| errno == ENOATTR || errno == not_supported)
+}
+
+/// Read the value of the size queried by `get` with an empty buffer first,
+/// and query again if the value grows in the meantime
+fn get_sized(mut get: impl FnMut(&mut [u8]) -> nix::Result<usize>) -> nix::Result<Vec<u8>> {
+    loop {
+        let size = get(&mut [])?;
+        let mut value = vec![0; size];
+        match get(&mut value) {
+            Ok(size) => {
+                value.truncate(size);
+                return Ok(value);
+            }
+            Err(nix::Error::Sys(Errno::ERANGE)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Get the extended attribute of `name` of the file of `fd`,
+/// None if the file has no such attribute or the filesystem supports no xattrs
+pub fn fgetxattr(fd: RawFd, name: &CStr) -> nix::Result<Option<Vec<u8>>> {
+    let res = get_sized(|value| {
+        #[cfg(target_os = "linux")]
+        let res = unsafe {
+            libc::fgetxattr(
+                fd,
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        #[cfg(target_os = "macos")]
+        let res = unsafe {
+            libc::fgetxattr(
+                fd,
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+                0,
+                0,
+            )
+        };
+        Errno::result(res).map(|size| size as usize)
+    });
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if is_xattr_missing(e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// List the names of the extended attributes of the file of `fd`,
+/// each name ends with a NUL
+pub fn flistxattr(fd: RawFd) -> nix::Result<Vec<u8>> {
+    get_sized(|names| {
+        #[cfg(target_os = "linux")]
+        let res =
+            unsafe { libc::flistxattr(fd, names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+        #[cfg(target_os = "macos")]
+        let res = unsafe {
+            libc::flistxattr(fd, names.as_mut_ptr() as *mut libc::c_char, names.len(), 0)
+        };
+        Errno::result(res).map(|size| size as usize)
+    })
+}
+
+/// Set the extended attribute of `name` of the file of `fd`,
+/// `flags` of XATTR_CREATE or XATTR_REPLACE
+pub fn fsetxattr(fd: RawFd, name: &CStr, value: &[u8], flags: u32) -> nix::Result<()> {
+    #[cfg(target_os = "linux")]
+    let res = unsafe {
+        libc::fsetxattr(
+            fd,
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            flags as libc::c_int,
+        )
+    };
+    #[cfg(target_os = "macos")]
+    let res = unsafe {
+        libc::fsetxattr(
+            fd,
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+            flags as libc::c_int,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+/// Remove the extended attribute of `name` of the file of `fd`
+pub fn fremovexattr(fd: RawFd, name: &CStr) -> nix::Result<()> {
+    #[cfg(target_os = "linux")]
+    let res = unsafe { libc::fremovexattr(fd, name.as_ptr()) };
+    #[cfg(target_os = "macos")]
+    let res = unsafe { libc::fremovexattr(fd, name.as_ptr(), 0) };
+    Errno::result(res).map(drop)
+}
+
+/// Advise the kernel to read ahead the file of `fd` more for the sequential reads
+#[cfg(target_os = "linux")]
+pub fn advise_sequential(fd: RawFd) -> nix::Result<()> {
+    fcntl::posix_fadvise(fd, 0, 0, fcntl::PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL).map(drop)
+}
+
+/// Advise the kernel to read the `len` bytes at `offset` of the file of `fd` into
+/// the page cache in background
+#[cfg(target_os = "linux")]
+pub fn advise_willneed(fd: RawFd, offset: u64, len: u64) -> nix::Result<()> {
+    fcntl::posix_fadvise(
+        fd,
+        offset as libc::off_t,
+        len as libc::off_t,
+        fcntl::PosixFadviseAdvice::POSIX_FADV_WILLNEED,
+    )
+    .map(drop)
+}
+
+/// No fadvise on macOS, the kernel reads ahead the sequential reads by itself
+#[cfg(target_os = "macos")]
+pub fn advise_sequential(_fd: RawFd) -> nix::Result<()> {
+    Ok(())
+}
+
+/// No fadvise on macOS, the kernel reads ahead the sequential reads by itself
+#[cfg(target_os = "macos")]
+pub fn advise_willneed(_fd: RawFd, _offset: u64, _len: u64) -> nix::Result<()> {
+    Ok(())
+}
+
 pub fn mode_from_kind_and_perm(kind: SFlag, perm: u16) -> u32 {
     let file_type: u32 = (match kind {
         SFlag::S_IFIFO => libc::S_IFIFO,
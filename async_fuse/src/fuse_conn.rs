@@ -0,0 +1,220 @@
+//! FUSE connection capabilities negotiated by INIT.
+//!
+//! The kernel offers its capabilities in the INIT request, the session adds the
+//! features it handles by default, then the filesystem declares the features it
+//! wants or refuses in `FileSystem::init()`. The session replies the intersection
+//! with the kernel offer and records the result, so the handlers can check the
+//! negotiated features at runtime.
+
+use super::protocol::*;
+use super::upgrade::{Decoder, Encoder};
+
+/// The FUSE connection information, corresponding to fuse_conn_info in libfuse
+#[derive(Clone, Copy, Debug)]
+pub struct ConnInfo {
+    /// FUSE protocol major version of the kernel
+    pub proto_major: u32,
+    /// FUSE protocol minor version of the kernel
+    pub proto_minor: u32,
+    /// The init flags offered by the kernel
+    capable: u32,
+    /// The init flags wanted, always a subset of `capable`
+    want: u32,
+    /// The init flags2 offered by the kernel and set up by the session
+    #[cfg(target_os = "linux")]
+    capable2: u32,
+    /// The init flags2 wanted, always a subset of `capable2`
+    #[cfg(target_os = "linux")]
+    want2: u32,
+    /// The max readahead size
+    pub max_readahead: u32,
+    /// The max size of write requests
+    pub max_write: u32,
+    /// The max number of pending background requests
+    pub max_background: u16,
+    /// The number of pending background requests at which the kernel marks congested
+    pub congestion_threshold: u16,
+    /// The timestamp granularity in nanoseconds
+    pub time_gran: u32,
+}
+
+impl ConnInfo {
+    /// Build the connection information from the INIT request,
+    /// `capable2` is the kernel offered flags2 the session is able to set up
+    pub fn new(arg: &FuseInitIn, capable2: u32) -> ConnInfo {
+        // FUSE_INIT_EXT only tells the format of INIT, not a feature to negotiate
+        #[cfg(target_os = "linux")]
+        let capable = arg.flags & !FUSE_INIT_EXT;
+        #[cfg(not(target_os = "linux"))]
+        let capable = arg.flags;
+        #[cfg(not(target_os = "linux"))]
+        let _ = capable2;
+        let mut conn = ConnInfo {
+            proto_major: arg.major,
+            proto_minor: arg.minor,
+            capable,
+            want: 0,
+            #[cfg(target_os = "linux")]
+            capable2,
+            #[cfg(target_os = "linux")]
+            want2: capable2,
+            max_readahead: arg.max_readahead,
+            max_write: 0,
+            max_background: 0,
+            congestion_threshold: 0,
+            time_gran: 1,
+        };
+        conn.want(FUSE_ASYNC_READ);
+        #[cfg(feature = "abi-7-9")]
+        conn.want(FUSE_BIG_WRITES);
+        #[cfg(feature = "abi-7-28")]
+        conn.want(FUSE_MAX_PAGES);
+        #[cfg(target_os = "macos")]
+        {
+            conn.want(FUSE_CASE_INSENSITIVE);
+            conn.want(FUSE_VOL_RENAME);
+            conn.want(FUSE_XTIMES);
+        }
+        conn
+    }
+
+    /// Whether the kernel offers all the features of `flags`
+    pub fn is_capable(&self, flags: u32) -> bool {
+        self.capable & flags == flags
+    }
+
+    /// Want the features of `flags`, return false if the kernel does not offer them
+    pub fn want(&mut self, flags: u32) -> bool {
+        if !self.is_capable(flags) {
+            return false;
+        }
+        self.want |= flags;
+        true
+    }
+
+    /// Refuse the features of `flags`
+    pub fn unwant(&mut self, flags: u32) {
+        self.want &= !flags;
+    }
+
+    /// Whether all the features of `flags` are negotiated
+    pub fn is_enabled(&self, flags: u32) -> bool {
+        self.want & flags == flags
+    }
+
+    /// The negotiated init flags to reply to the kernel
+    pub fn flags(&self) -> u32 {
+        self.want
+    }
+
+    /// Whether the kernel offers all the features of `flags2`
+    #[cfg(target_os = "linux")]
+    pub fn is_capable2(&self, flags2: u32) -> bool {
+        self.capable2 & flags2 == flags2
+    }
+
+    /// Refuse the features of `flags2`
+    #[cfg(target_os = "linux")]
+    pub fn unwant2(&mut self, flags2: u32) {
+        self.want2 &= !flags2;
+    }
+
+    /// Whether all the features of `flags2` are negotiated
+    #[cfg(target_os = "linux")]
+    pub fn is_enabled2(&self, flags2: u32) -> bool {
+        self.want2 & flags2 == flags2
+    }
+
+    /// The negotiated init flags2 to reply to the kernel
+    #[cfg(target_os = "linux")]
+    pub fn flags2(&self) -> u32 {
+        self.want2
+    }
+
+    /// The max number of pages in a request, derived from the max write size
+    pub fn max_pages(&self, page_size: usize) -> u16 {
+        if self.max_write == 0 {
+            return 0;
+        }
+        ((self.max_write as usize - 1) / page_size + 1) as u16
+    }
+
+    /// Encode the negotiated features to hand over to the new daemon
+    pub(crate) fn encode(&self, enc: &mut Encoder) {
+        enc.put_u32(self.proto_major);
+        enc.put_u32(self.proto_minor);
+        enc.put_u32(self.capable);
+        enc.put_u32(self.want);
+        #[cfg(target_os = "linux")]
+        {
+            enc.put_u32(self.capable2);
+            enc.put_u32(self.want2);
+        }
+        enc.put_u32(self.max_readahead);
+        enc.put_u32(self.max_write);
+        enc.put_u32(self.max_background.into());
+        enc.put_u32(self.congestion_threshold.into());
+        enc.put_u32(self.time_gran);
+    }
+
+    /// Decode the features negotiated by the old daemon
+    pub(crate) fn decode(dec: &mut Decoder<'_>) -> anyhow::Result<ConnInfo> {
+        Ok(ConnInfo {
+            proto_major: dec.get_u32()?,
+            proto_minor: dec.get_u32()?,
+            capable: dec.get_u32()?,
+            want: dec.get_u32()?,
+            #[cfg(target_os = "linux")]
+            capable2: dec.get_u32()?,
+            #[cfg(target_os = "linux")]
+            want2: dec.get_u32()?,
+            max_readahead: dec.get_u32()?,
+            max_write: dec.get_u32()?,
+            max_background: dec.get_u32()? as u16,
+            congestion_threshold: dec.get_u32()? as u16,
+            time_gran: dec.get_u32()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::protocol::*;
+    use super::ConnInfo;
+
+    #[test]
+    fn test_negotiate_flags() {
+        let arg = FuseInitIn {
+            major: 7,
+            minor: 31,
+            max_readahead: 128 * 1024,
+            flags: FUSE_ASYNC_READ | FUSE_POSIX_LOCKS,
+        };
+        let mut conn = ConnInfo::new(&arg, 0);
+        assert!(conn.is_enabled(FUSE_ASYNC_READ));
+        // the kernel offers but nobody wants
+        assert!(conn.is_capable(FUSE_POSIX_LOCKS));
+        assert!(!conn.is_enabled(FUSE_POSIX_LOCKS));
+        // the kernel does not offer
+        assert!(!conn.want(1 << 12));
+        assert!(!conn.is_enabled(1 << 12));
+        conn.unwant(FUSE_ASYNC_READ);
+        assert_eq!(conn.flags(), 0);
+    }
+
+    #[test]
+    fn test_max_pages() {
+        let arg = FuseInitIn {
+            major: 7,
+            minor: 31,
+            max_readahead: 128 * 1024,
+            flags: 0,
+        };
+        let mut conn = ConnInfo::new(&arg, 0);
+        assert_eq!(conn.max_pages(4096), 0);
+        conn.max_write = 128 * 1024;
+        assert_eq!(conn.max_pages(4096), 32);
+        conn.max_write = 128 * 1024 + 1;
+        assert_eq!(conn.max_pages(4096), 33);
+    }
+}
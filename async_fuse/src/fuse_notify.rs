@@ -0,0 +1,235 @@
+//! FUSE notifications from the filesystem to the kernel.
+//!
+//! A notification is written to the FUSE device like a reply, but with zero unique ID
+//! and the notify code in the error field of the header. The kernel answers RETRIEVE
+//! with a NOTIFY_REPLY request carrying the notify unique ID and the cached data, which
+//! is matched back to the pending retrieval here.
+
+use anyhow::{self, Context};
+#[cfg(feature = "abi-7-15")]
+use futures::channel::oneshot;
+#[cfg(feature = "abi-7-15")]
+use lazy_static::lazy_static;
+use log::debug;
+use nix::sys::uio::{self, IoVec};
+use smol::blocking;
+#[cfg(feature = "abi-7-15")]
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+#[cfg(feature = "abi-7-15")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "abi-7-15")]
+use std::sync::Mutex;
+use std::{mem, slice};
+
+use super::protocol::*;
+
+#[cfg(feature = "abi-7-15")]
+lazy_static! {
+    /// The retrievals waiting for NOTIFY_REPLY, indexed by the notify unique ID
+    static ref PENDING_RETRIEVES: Mutex<BTreeMap<u64, oneshot::Sender<Vec<u8>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// The notify unique ID of the next retrieval
+#[cfg(feature = "abi-7-15")]
+static NOTIFY_UNIQUE: AtomicU64 = AtomicU64::new(1);
+
+/// The bytes of a FUSE ABI struct
+fn as_bytes<T>(instance: &T) -> &[u8] {
+    let p = instance as *const T as *const u8;
+    unsafe { slice::from_raw_parts(p, mem::size_of::<T>()) }
+}
+
+/// Build the notification of the given code and the data following the header
+fn notify_bytes(code: FuseNotifyCode, data: &[&[u8]]) -> Vec<u8> {
+    let header_len = mem::size_of::<FuseOutHeader>();
+    let data_len: usize = data.iter().map(|d| d.len()).sum();
+    let header = FuseOutHeader {
+        len: (header_len + data_len) as u32,
+        error: code as i32, // the notify code is positive, unlike the error number
+        unique: 0,
+    };
+    let mut bytes = Vec::with_capacity(header_len + data_len);
+    bytes.extend_from_slice(as_bytes(&header));
+    for d in data {
+        bytes.extend_from_slice(d);
+    }
+    bytes
+}
+
+/// The writer of FUSE notifications, corresponding to fuse_lowlevel_notify_* in libfuse
+#[derive(Clone, Copy, Debug)]
+pub struct Notifier {
+    fd: RawFd,
+}
+
+#[allow(dead_code)]
+impl Notifier {
+    pub fn new(fd: RawFd) -> Notifier {
+        Notifier { fd }
+    }
+
+    async fn send(&self, code: FuseNotifyCode, data: &[&[u8]]) -> anyhow::Result<()> {
+        let fd = self.fd;
+        let bytes = notify_bytes(code, data);
+        let wsize = blocking!(uio::writev(fd, &[IoVec::from_slice(&bytes)])
+            .context("failed to send notification to FUSE"))?;
+        debug!(
+            "sent {} bytes notification to fuse device successfully",
+            wsize
+        );
+        Ok(())
+    }
+
+    /// Invalidate the cached attributes of the inode and its cached data in the range,
+    /// a negative `len` means to the end of the file, a negative `offset` only
+    /// invalidates the attributes. The kernel replies ENOENT if the inode is not cached
+    pub async fn inval_inode(&self, ino: INum, offset: i64, len: i64) -> anyhow::Result<()> {
+        let out = FuseNotifyInvalINodeOut {
+            ino,
+            off: offset,
+            len,
+        };
+        self.send(FuseNotifyCode::FUSE_NOTIFY_INVAL_INODE, &[as_bytes(&out)])
+            .await
+            .context(format!("failed to invalidate inode ino={}", ino))
+    }
+
+    /// Invalidate the cached directory entry of the name under the parent,
+    /// the kernel replies ENOENT if the entry is not cached
+    pub async fn inval_entry(&self, parent: INum, name: &OsStr) -> anyhow::Result<()> {
+        let name = name.as_bytes();
+        let out = FuseNotifyInvalEntryOut {
+            parent,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        self.send(
+            FuseNotifyCode::FUSE_NOTIFY_INVAL_ENTRY,
+            &[as_bytes(&out), name, &[0]],
+        )
+        .await
+        .context(format!(
+            "failed to invalidate entry name={:?} under parent ino={}",
+            name, parent,
+        ))
+    }
+
+    /// Tell the kernel the child under the parent is deleted, which invalidates the
+    /// entry and the inode if the entry points to the child
+    #[cfg(feature = "abi-7-18")]
+    pub async fn delete(&self, parent: INum, child: INum, name: &OsStr) -> anyhow::Result<()> {
+        let name = name.as_bytes();
+        let out = FuseNotifyDeleteOut {
+            parent,
+            child,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        self.send(
+            FuseNotifyCode::FUSE_NOTIFY_DELETE,
+            &[as_bytes(&out), name, &[0]],
+        )
+        .await
+        .context(format!(
+            "failed to notify delete name={:?} ino={} under parent ino={}",
+            name, child, parent,
+        ))
+    }
+
+    /// Store the data into the kernel page cache of the inode at the offset
+    #[cfg(feature = "abi-7-15")]
+    pub async fn store(&self, ino: INum, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        let out = FuseNotifyStoreOut {
+            nodeid: ino,
+            offset,
+            size: data.len() as u32,
+            padding: 0,
+        };
+        self.send(FuseNotifyCode::FUSE_NOTIFY_STORE, &[as_bytes(&out), data])
+            .await
+            .context(format!(
+                "failed to store {} bytes at offset={} of ino={}",
+                data.len(),
+                offset,
+                ino,
+            ))
+    }
+
+    /// Retrieve the data of the inode at the offset from the kernel page cache,
+    /// the returned data is shorter than `size` if the pages are not cached
+    #[cfg(feature = "abi-7-15")]
+    pub async fn retrieve(&self, ino: INum, offset: u64, size: u32) -> anyhow::Result<Vec<u8>> {
+        let notify_unique = NOTIFY_UNIQUE.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        PENDING_RETRIEVES
+            .lock()
+            .unwrap_or_else(|e| panic!("failed to lock pending retrieves: {}", e))
+            .insert(notify_unique, sender);
+        let out = FuseNotifyRetrieveOut {
+            notify_unique,
+            nodeid: ino,
+            offset,
+            size,
+            padding: 0,
+        };
+        let res = self
+            .send(FuseNotifyCode::FUSE_NOTIFY_RETRIEVE, &[as_bytes(&out)])
+            .await;
+        if let Err(e) = res {
+            PENDING_RETRIEVES
+                .lock()
+                .unwrap_or_else(|e| panic!("failed to lock pending retrieves: {}", e))
+                .remove(&notify_unique);
+            return Err(e).context(format!(
+                "failed to retrieve {} bytes at offset={} of ino={}",
+                size, offset, ino,
+            ));
+        }
+        receiver.await.context(format!(
+            "the retrieval of notify unique={} is dropped",
+            notify_unique,
+        ))
+    }
+}
+
+/// Hand the data of NOTIFY_REPLY to the pending retrieval,
+/// return false if no retrieval is waiting for the notify unique ID
+#[cfg(feature = "abi-7-15")]
+pub fn reply_retrieve(notify_unique: u64, data: &[u8]) -> bool {
+    let pending = PENDING_RETRIEVES
+        .lock()
+        .unwrap_or_else(|e| panic!("failed to lock pending retrieves: {}", e))
+        .remove(&notify_unique);
+    match pending {
+        // The retrieval may have been cancelled, nothing to do then
+        Some(sender) => sender.send(data.to_vec()).is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::protocol::*;
+    use super::notify_bytes;
+    use std::mem;
+
+    #[test]
+    fn test_notify_bytes() {
+        let header_len = mem::size_of::<FuseOutHeader>();
+        let out = [1u8; 16];
+        let bytes = notify_bytes(
+            FuseNotifyCode::FUSE_NOTIFY_INVAL_ENTRY,
+            &[&out, b"foo", &[0]],
+        );
+        assert_eq!(bytes.len(), header_len + 16 + 4);
+        let header = unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const FuseOutHeader) };
+        assert_eq!(header.len as usize, bytes.len());
+        assert_eq!(header.error, FuseNotifyCode::FUSE_NOTIFY_INVAL_ENTRY as i32);
+        assert_eq!(header.unique, 0);
+        assert_eq!(&bytes[header_len + 16..], b"foo\0");
+    }
+}
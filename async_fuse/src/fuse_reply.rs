@@ -5,7 +5,7 @@ use nix::sys::uio::{self, IoVec};
 use smol::blocking;
 use std::convert::AsRef;
 use std::ffi::OsStr;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::marker::PhantomData;
 use std::os::raw::c_int;
 use std::os::unix::ffi::OsStrExt;
@@ -13,6 +13,8 @@ use std::os::unix::io::RawFd;
 use std::time::Duration;
 use std::{mem, ptr, slice};
 
+#[cfg(target_os = "linux")]
+use super::fuse_uring;
 use super::protocol::*;
 
 // TODO: remove it
@@ -32,6 +34,17 @@ fn mode_from_kind_and_perm(kind: SFlag, perm: u16) -> u32 {
     file_type | file_perm
 }
 
+/// The context of the errors sending replies to the kernel,
+/// to tell them from the errors of the operations to reply
+#[derive(Debug)]
+pub(crate) struct ReplyError(pub String);
+
+impl fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug)]
 enum ToBytes<T> {
     Struct(T),
@@ -57,6 +70,7 @@ impl<T: Send + Sync + 'static> ReplyRaw<T> {
 
     async fn send(self, to_bytes: ToBytes<T>, err: c_int) -> anyhow::Result<usize> {
         let fd = self.fd;
+        let unique = self.unique;
         let wsize = blocking!(
             let mut send_error = false;
             let instance: T; // to hold the instance of ToBytes::Struct
@@ -103,10 +117,21 @@ impl<T: Send + Sync + 'static> ReplyRaw<T> {
             } else {
                 debug_assert_ne!(err, 0);
             }
+            // The request may come from FUSE io_uring, then reply through the ring entry
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(res) = fuse_uring::try_commit(&header, bytes) {
+                    return res;
+                }
+            }
             uio::writev(fd, &iovecs).context(format!(
                 "failed to send to FUSE, the reply header is: {:?}", header,
             ))
-        )?;
+        )
+        .context(ReplyError(format!(
+            "failed to reply the request of unique={}",
+            unique
+        )))?;
 
         debug!("sent {} bytes to fuse device successfully", wsize);
         Ok(wsize)
@@ -128,6 +153,20 @@ impl<T: Send + Sync + 'static> ReplyRaw<T> {
         Ok(())
     }
 
+    /// Send only the first `size` bytes of the instance,
+    /// for the kernel of lower minor version expecting a shorter reply
+    #[cfg_attr(not(feature = "abi-7-23"), allow(dead_code))]
+    async fn send_data_prefix(self, instance: T, size: usize) -> anyhow::Result<()> {
+        debug_assert!(size <= mem::size_of::<T>());
+        let p = &instance as *const T as *const u8;
+        let bytes = unsafe { slice::from_raw_parts(p, size) };
+        let _reply_size = self
+            .send(ToBytes::Bytes(bytes.to_vec()), 0)
+            .await
+            .context("send_data_prefix() failed to send data")?;
+        Ok(())
+    }
+
     async fn send_error(self, err: c_int) -> anyhow::Result<()> {
         let _reply_size = self
             .send(ToBytes::Error, err)
@@ -164,31 +203,46 @@ impl ReplyInit {
         #[cfg(feature = "abi-7-28")] padding: u16,
         #[cfg(feature = "abi-7-28")] unused: [u32; 8],
     ) -> anyhow::Result<()> {
-        self.reply
-            .send_data(FuseInitOut {
-                major,
-                minor,
-                max_readahead,
-                flags,
-                #[cfg(not(feature = "abi-7-13"))]
-                unused,
-                #[cfg(feature = "abi-7-13")]
-                max_background,
-                #[cfg(feature = "abi-7-13")]
-                congestion_threshold,
-                max_write,
-                #[cfg(feature = "abi-7-23")]
-                time_gran,
-                #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
-                unused,
-                #[cfg(feature = "abi-7-28")]
-                max_pages,
-                #[cfg(feature = "abi-7-28")]
-                padding,
-                #[cfg(feature = "abi-7-28")]
-                unused,
-            })
-            .await
+        let init_out = FuseInitOut {
+            major,
+            minor,
+            max_readahead,
+            flags,
+            #[cfg(not(feature = "abi-7-13"))]
+            unused,
+            #[cfg(feature = "abi-7-13")]
+            max_background,
+            #[cfg(feature = "abi-7-13")]
+            congestion_threshold,
+            max_write,
+            #[cfg(feature = "abi-7-23")]
+            time_gran,
+            #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+            unused,
+            #[cfg(feature = "abi-7-28")]
+            max_pages,
+            #[cfg(feature = "abi-7-28")]
+            padding,
+            #[cfg(feature = "abi-7-28")]
+            unused,
+        };
+        // The kernel before ABI 7.23 rejects the init reply longer than 24 bytes
+        #[cfg(feature = "abi-7-23")]
+        {
+            if minor < 23 {
+                return self
+                    .reply
+                    .send_data_prefix(init_out, FUSE_COMPAT_22_INIT_OUT_SIZE)
+                    .await;
+            }
+        }
+        self.reply.send_data(init_out).await
+    }
+    /// Reply with the full ABI 7.40 init result, which carries flags2
+    #[cfg(target_os = "linux")]
+    pub async fn init_ext(self, init_out: FuseInitOutExt) -> anyhow::Result<()> {
+        let reply = ReplyRaw::<FuseInitOutExt>::new(self.reply.unique, self.reply.fd);
+        reply.send_data(init_out).await
     }
     pub async fn error(self, err: c_int) -> anyhow::Result<()> {
         self.reply.send_error(err).await
@@ -196,7 +250,7 @@ impl ReplyInit {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyEmpty {
+pub struct ReplyEmpty {
     reply: ReplyRaw<()>,
 }
 
@@ -215,7 +269,7 @@ impl ReplyEmpty {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyData {
+pub struct ReplyData {
     reply: ReplyRaw<Vec<u8>>,
 }
 
@@ -234,7 +288,7 @@ impl ReplyData {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyEntry {
+pub struct ReplyEntry {
     reply: ReplyRaw<FuseEntryOut>,
 }
 
@@ -244,17 +298,40 @@ impl ReplyEntry {
             reply: ReplyRaw::new(unique, fd),
         }
     }
-    /// Reply to a request with the given entry
-    pub async fn entry(self, ttl: Duration, attr: FuseAttr, generation: u64) -> anyhow::Result<()> {
+    /// Reply to a request with the given entry, the kernel caches the entry
+    /// for `entry_ttl` and the attributes for `attr_ttl`
+    pub async fn entry(
+        self,
+        entry_ttl: Duration,
+        attr_ttl: Duration,
+        attr: FuseAttr,
+        generation: u64,
+    ) -> anyhow::Result<()> {
         self.reply
             .send_data(FuseEntryOut {
                 nodeid: attr.ino,
                 generation,
+                entry_valid: entry_ttl.as_secs(),
+                attr_valid: attr_ttl.as_secs(),
+                entry_valid_nsec: entry_ttl.subsec_nanos(),
+                attr_valid_nsec: attr_ttl.subsec_nanos(),
+                attr,
+            })
+            .await
+    }
+
+    /// Reply to a lookup of a missing name with the zero node id,
+    /// the kernel caches the negative entry for `ttl`
+    pub async fn negative(self, ttl: Duration) -> anyhow::Result<()> {
+        self.reply
+            .send_data(FuseEntryOut {
+                nodeid: 0,
+                generation: 0,
                 entry_valid: ttl.as_secs(),
-                attr_valid: ttl.as_secs(),
+                attr_valid: 0,
                 entry_valid_nsec: ttl.subsec_nanos(),
-                attr_valid_nsec: ttl.subsec_nanos(),
-                attr,
+                attr_valid_nsec: 0,
+                attr: FuseAttr::default(),
             })
             .await
     }
@@ -266,7 +343,7 @@ impl ReplyEntry {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyAttr {
+pub struct ReplyAttr {
     reply: ReplyRaw<FuseAttrOut>,
 }
 
@@ -296,7 +373,7 @@ impl ReplyAttr {
 
 #[cfg(target_os = "macos")]
 #[derive(Debug)]
-pub(crate) struct ReplyXTimes {
+pub struct ReplyXTimes {
     reply: ReplyRaw<FuseGetXTimesOut>,
 }
 
@@ -335,7 +412,7 @@ impl ReplyXTimes {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyOpen {
+pub struct ReplyOpen {
     reply: ReplyRaw<FuseOpenOut>,
 }
 
@@ -351,7 +428,19 @@ impl ReplyOpen {
             .send_data(FuseOpenOut {
                 fh,
                 open_flags: flags,
-                padding: 0,
+                backing_id: 0,
+            })
+            .await
+    }
+
+    /// Reply to a request with the given open result,
+    /// and let the kernel pass through reads and writes to the registered backing file
+    pub async fn passthrough(self, fh: u64, flags: u32, backing_id: i32) -> anyhow::Result<()> {
+        self.reply
+            .send_data(FuseOpenOut {
+                fh,
+                open_flags: flags | FOPEN_PASSTHROUGH,
+                backing_id,
             })
             .await
     }
@@ -364,7 +453,7 @@ impl ReplyOpen {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyWrite {
+pub struct ReplyWrite {
     reply: ReplyRaw<FuseWriteOut>,
 }
 
@@ -388,7 +477,7 @@ impl ReplyWrite {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyStatFs {
+pub struct ReplyStatFs {
     reply: ReplyRaw<FuseStatFsOut>,
 }
 
@@ -436,7 +525,7 @@ impl ReplyStatFs {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyCreate {
+pub struct ReplyCreate {
     reply: ReplyRaw<(FuseEntryOut, FuseOpenOut)>,
 }
 
@@ -470,7 +559,7 @@ impl ReplyCreate {
                 FuseOpenOut {
                     fh,
                     open_flags: flags,
-                    padding: 0,
+                    backing_id: 0,
                 },
             ))
             .await
@@ -483,7 +572,7 @@ impl ReplyCreate {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyLock {
+pub struct ReplyLock {
     reply: ReplyRaw<FuseLockOut>,
 }
 
@@ -515,7 +604,7 @@ impl ReplyLock {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyBMap {
+pub struct ReplyBMap {
     reply: ReplyRaw<FuseBMapOut>,
 }
 
@@ -539,7 +628,7 @@ impl ReplyBMap {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReplyDirectory {
+pub struct ReplyDirectory {
     reply: ReplyRaw<()>,
     data: Vec<u8>,
 }
@@ -592,8 +681,185 @@ impl ReplyDirectory {
     }
 }
 
+#[cfg(feature = "abi-7-21")]
 #[derive(Debug)]
-pub(crate) struct ReplyXAttr {
+pub struct ReplyDirectoryPlus {
+    reply: ReplyRaw<()>,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "abi-7-21")]
+impl ReplyDirectoryPlus {
+    /// Creates a new ReplyDirectoryPlus with a specified buffer size.
+    pub fn new(unique: u64, fd: RawFd, size: usize) -> ReplyDirectoryPlus {
+        ReplyDirectoryPlus {
+            reply: ReplyRaw::new(unique, fd),
+            data: Vec::with_capacity(size),
+        }
+    }
+
+    /// Add an entry with its attributes to the directory reply buffer.
+    /// Returns true if the buffer is full.
+    #[allow(dead_code)]
+    pub fn add<T: AsRef<OsStr>>(
+        &mut self,
+        offset: i64,
+        name: T,
+        ttl: Duration,
+        attr: FuseAttr,
+        generation: u64,
+    ) -> bool {
+        let name = name.as_ref().as_bytes();
+        let entlen = mem::size_of::<FuseDirEntPlus>() + name.len();
+        let entsize = (entlen + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1); // 64bit align
+        let padlen = entsize - entlen;
+        if self.data.len() + entsize > self.data.capacity() {
+            return true;
+        }
+        let ino = attr.ino;
+        let typ = attr.mode >> 12;
+        let direntplus = FuseDirEntPlus {
+            entry_out: FuseEntryOut {
+                nodeid: ino,
+                generation,
+                entry_valid: ttl.as_secs(),
+                attr_valid: ttl.as_secs(),
+                entry_valid_nsec: ttl.subsec_nanos(),
+                attr_valid_nsec: ttl.subsec_nanos(),
+                attr,
+            },
+            dirent: FuseDirEnt {
+                ino,
+                off: offset as u64,
+                namelen: name.len() as u32,
+                typ,
+            },
+        };
+        unsafe {
+            let p = self.data.as_mut_ptr().add(self.data.len());
+            ptr::write_unaligned(p as *mut FuseDirEntPlus, direntplus);
+            let p = p.add(mem::size_of::<FuseDirEntPlus>());
+            ptr::copy_nonoverlapping(name.as_ptr(), p, name.len());
+            let p = p.add(name.len());
+            ptr::write_bytes(p, 0u8, padlen);
+            let newlen = self.data.len() + entsize;
+            self.data.set_len(newlen);
+        }
+        false
+    }
+
+    /// Reply to a request with the filled directory buffer
+    #[allow(dead_code)]
+    pub async fn ok(self) -> anyhow::Result<()> {
+        self.reply.send_bytes(self.data).await
+    }
+
+    /// Reply to a request with the given error code
+    pub async fn error(self, err: c_int) -> anyhow::Result<()> {
+        self.reply.send_error(err).await
+    }
+}
+
+#[cfg(feature = "abi-7-11")]
+#[derive(Debug)]
+pub struct ReplyIoCtl {
+    reply: ReplyRaw<FuseIoCtlOut>,
+}
+
+#[cfg(feature = "abi-7-11")]
+impl ReplyIoCtl {
+    pub fn new(unique: u64, fd: RawFd) -> ReplyIoCtl {
+        ReplyIoCtl {
+            reply: ReplyRaw::new(unique, fd),
+        }
+    }
+
+    /// Reply to a request with the ioctl result and the output data
+    #[allow(dead_code)]
+    pub async fn ioctl(self, result: i32, data: &[u8]) -> anyhow::Result<()> {
+        let out = FuseIoCtlOut {
+            result,
+            flags: 0,
+            in_iovs: 0,
+            out_iovs: 0,
+        };
+        let p = &out as *const FuseIoCtlOut as *const u8;
+        let header = unsafe { slice::from_raw_parts(p, mem::size_of::<FuseIoCtlOut>()) };
+        let mut bytes = Vec::with_capacity(header.len() + data.len());
+        bytes.extend_from_slice(header);
+        bytes.extend_from_slice(data);
+        self.reply.send_bytes(bytes).await
+    }
+
+    /// Reply to a request with the given error code
+    pub async fn error(self, err: c_int) -> anyhow::Result<()> {
+        self.reply.send_error(err).await
+    }
+}
+
+#[cfg(feature = "abi-7-11")]
+#[derive(Debug)]
+pub struct ReplyPoll {
+    reply: ReplyRaw<FusePollOut>,
+}
+
+#[cfg(feature = "abi-7-11")]
+impl ReplyPoll {
+    pub fn new(unique: u64, fd: RawFd) -> ReplyPoll {
+        ReplyPoll {
+            reply: ReplyRaw::new(unique, fd),
+        }
+    }
+
+    /// Reply to a request with the ready events
+    #[allow(dead_code)]
+    pub async fn poll(self, revents: u32) -> anyhow::Result<()> {
+        self.reply
+            .send_data(FusePollOut {
+                revents,
+                padding: 0,
+            })
+            .await
+    }
+
+    /// Reply to a request with the given error code
+    pub async fn error(self, err: c_int) -> anyhow::Result<()> {
+        self.reply.send_error(err).await
+    }
+}
+
+#[cfg(feature = "abi-7-24")]
+#[derive(Debug)]
+pub struct ReplyLSeek {
+    reply: ReplyRaw<FuseLSeekOut>,
+}
+
+#[cfg(feature = "abi-7-24")]
+impl ReplyLSeek {
+    pub fn new(unique: u64, fd: RawFd) -> ReplyLSeek {
+        ReplyLSeek {
+            reply: ReplyRaw::new(unique, fd),
+        }
+    }
+
+    /// Reply to a request with the new file offset
+    #[allow(dead_code)]
+    pub async fn offset(self, offset: i64) -> anyhow::Result<()> {
+        self.reply
+            .send_data(FuseLSeekOut {
+                offset: offset as u64,
+            })
+            .await
+    }
+
+    /// Reply to a request with the given error code
+    pub async fn error(self, err: c_int) -> anyhow::Result<()> {
+        self.reply.send_error(err).await
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplyXAttr {
     reply: ReplyRaw<FuseGetXAttrOut>,
 }
 
@@ -604,7 +870,6 @@ impl ReplyXAttr {
         }
     }
     /// Reply to a request with the size of the xattr.
-    #[allow(dead_code)]
     pub async fn size(self, size: u32) -> anyhow::Result<()> {
         self.reply
             .send_data(FuseGetXAttrOut { size, padding: 0 })
@@ -612,7 +877,6 @@ impl ReplyXAttr {
     }
 
     /// Reply to a request with the data in the xattr.
-    #[allow(dead_code)]
     pub async fn data(self, bytes: Vec<u8>) -> anyhow::Result<()> {
         self.reply.send_bytes(bytes).await
     }
@@ -1,3 +1,4 @@
+use nix::errno::Errno;
 use std::ffi::OsStr;
 use std::fmt;
 
@@ -110,6 +111,8 @@ pub(crate) enum Operation<'a> {
     Init {
         // FUSE_INIT = 26
         arg: &'a FuseInitIn,
+        #[cfg(target_os = "linux")]
+        ext: Option<&'a FuseInitInExt>,
     },
     OpenDir {
         // FUSE_OPENDIR = 27
@@ -171,6 +174,7 @@ pub(crate) enum Operation<'a> {
     #[cfg(feature = "abi-7-15")]
     NotifyReply {
         // FUSE_NOTIFY_REPLY = 41
+        arg: &'a FuseNotifyRetrieveIn,
         data: &'a [u8],
     },
     #[cfg(feature = "abi-7-16")]
@@ -196,11 +200,27 @@ pub(crate) enum Operation<'a> {
         oldname: &'a OsStr,
         newname: &'a OsStr,
     },
-    // TODO: find out the input args
-    // #[cfg(feature = "abi-7-24")]
-    // FUSE_LSEEK = 46,
-    // #[cfg(feature = "abi-7-28")]
-    // FUSE_COPY_FILE_RANGE = 47,
+    #[cfg(feature = "abi-7-24")]
+    LSeek {
+        // FUSE_LSEEK = 46,
+        arg: &'a FuseLSeekIn,
+    },
+    #[cfg(feature = "abi-7-28")]
+    CopyFileRange {
+        // FUSE_COPY_FILE_RANGE = 47,
+        arg: &'a FuseCopyFileRangeIn,
+    },
+    #[cfg(feature = "abi-7-31")]
+    SetupMapping {
+        // FUSE_SETUPMAPPING = 48,
+        arg: &'a FuseSetupMappingIn,
+    },
+    #[cfg(feature = "abi-7-31")]
+    RemoveMapping {
+        // FUSE_REMOVEMAPPING = 49,
+        arg: &'a FuseRemoveMappingIn,
+        mappings: &'a [FuseRemoveMappingOne],
+    },
     #[cfg(target_os = "macos")]
     SetVolName {
         // FUSE_SETVOLNAME = 61
@@ -224,6 +244,88 @@ pub(crate) enum Operation<'a> {
 }
 
 impl<'a> Operation<'a> {
+    /// Whether the kernel waits for the reply to the operation of the opcode,
+    /// FORGET, BATCH_FORGET and NOTIFY_REPLY are not replied
+    pub fn need_reply(opcode: u32) -> bool {
+        match opcode {
+            2 | 41 | 42 => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the operation modifies the filesystem, which is refused by read-only mount,
+    /// opening a file for write or truncation is also refused
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Operation::Open { arg } => {
+                let flags = arg.flags as i32;
+                flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0
+            }
+            Operation::SetAttr { .. }
+            | Operation::SymLink { .. }
+            | Operation::MkNod { .. }
+            | Operation::MkDir { .. }
+            | Operation::Unlink { .. }
+            | Operation::RmDir { .. }
+            | Operation::Rename { .. }
+            | Operation::Link { .. }
+            | Operation::Write { .. }
+            | Operation::SetXAttr { .. }
+            | Operation::RemoveXAttr { .. }
+            | Operation::Create { .. } => true,
+            #[cfg(feature = "abi-7-19")]
+            Operation::FAllocate { .. } => true,
+            #[cfg(feature = "abi-7-23")]
+            Operation::Rename2 { .. } => true,
+            #[cfg(feature = "abi-7-28")]
+            Operation::CopyFileRange { .. } => true,
+            #[cfg(target_os = "macos")]
+            Operation::SetVolName { .. } | Operation::Exchange { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The directories whose entries the operation on the node of `nodeid` reads or
+    /// changes, and whether it changes them, which the session locks before dispatching
+    pub fn dir_access(&self, nodeid: u64) -> Option<(Vec<u64>, bool)> {
+        match self {
+            Operation::Lookup { .. } | Operation::ReadDir { .. } => Some((vec![nodeid], false)),
+            #[cfg(feature = "abi-7-21")]
+            Operation::ReadDirPlus { .. } => Some((vec![nodeid], false)),
+            Operation::SymLink { .. }
+            | Operation::MkNod { .. }
+            | Operation::MkDir { .. }
+            | Operation::Unlink { .. }
+            | Operation::RmDir { .. }
+            | Operation::Link { .. }
+            | Operation::Create { .. } => Some((vec![nodeid], true)),
+            Operation::Rename { arg, .. } => Some((vec![nodeid, arg.newdir], true)),
+            #[cfg(feature = "abi-7-23")]
+            Operation::Rename2 { arg, .. } => Some((vec![nodeid, arg.newdir], true)),
+            #[cfg(target_os = "macos")]
+            Operation::Exchange { arg, .. } => Some((vec![arg.olddir, arg.newdir], true)),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation releases or flushes the resources held by the kernel,
+    /// which is still served while the session shuts down
+    pub fn is_releasing(&self) -> bool {
+        match self {
+            Operation::Forget { .. }
+            | Operation::Release { .. }
+            | Operation::ReleaseDir { .. }
+            | Operation::Flush { .. }
+            | Operation::FSync { .. }
+            | Operation::FSyncDir { .. }
+            | Operation::Interrupt { .. }
+            | Operation::Destroy => true,
+            #[cfg(feature = "abi-7-16")]
+            Operation::BatchForget { .. } => true,
+            _ => false,
+        }
+    }
+
     fn parse(n: u32, data: &mut ByteSlice<'a>) -> anyhow::Result<Self> {
         let opcode = match n {
             1 => FuseOpCode::FUSE_LOOKUP,
@@ -272,6 +374,18 @@ impl<'a> Operation<'a> {
             42 => FuseOpCode::FUSE_BATCH_FORGET,
             #[cfg(feature = "abi-7-19")]
             43 => FuseOpCode::FUSE_FALLOCATE,
+            #[cfg(feature = "abi-7-21")]
+            44 => FuseOpCode::FUSE_READDIRPLUS,
+            #[cfg(feature = "abi-7-23")]
+            45 => FuseOpCode::FUSE_RENAME2,
+            #[cfg(feature = "abi-7-24")]
+            46 => FuseOpCode::FUSE_LSEEK,
+            #[cfg(feature = "abi-7-28")]
+            47 => FuseOpCode::FUSE_COPY_FILE_RANGE,
+            #[cfg(feature = "abi-7-31")]
+            48 => FuseOpCode::FUSE_SETUPMAPPING,
+            #[cfg(feature = "abi-7-31")]
+            49 => FuseOpCode::FUSE_REMOVEMAPPING,
 
             #[cfg(target_os = "macos")]
             61 => FuseOpCode::FUSE_SETVOLNAME,
@@ -283,7 +397,11 @@ impl<'a> Operation<'a> {
             #[cfg(feature = "abi-7-11")]
             4096 => FuseOpCode::CUSE_INIT,
 
-            _ => panic!("unknown FUSE OpCode={}", n),
+            _ => {
+                // ENOSYS tells the kernel not to send the operation again
+                return Err(anyhow::Error::new(nix::Error::Sys(Errno::ENOSYS))
+                    .context(format!("unknown FUSE OpCode={}", n)));
+            }
         };
 
         Ok(match opcode {
@@ -344,7 +462,20 @@ impl<'a> Operation<'a> {
                 name: data.fetch_os_str()?,
             },
             FuseOpCode::FUSE_FLUSH => Operation::Flush { arg: data.fetch()? },
-            FuseOpCode::FUSE_INIT => Operation::Init { arg: data.fetch()? },
+            FuseOpCode::FUSE_INIT => {
+                let arg: &FuseInitIn = data.fetch()?;
+                #[cfg(target_os = "linux")]
+                let ext = if arg.flags & FUSE_INIT_EXT == 0 {
+                    None
+                } else {
+                    Some(data.fetch()?)
+                };
+                Operation::Init {
+                    arg,
+                    #[cfg(target_os = "linux")]
+                    ext,
+                }
+            }
             FuseOpCode::FUSE_OPENDIR => Operation::OpenDir { arg: data.fetch()? },
             FuseOpCode::FUSE_READDIR => Operation::ReadDir { arg: data.fetch()? },
             FuseOpCode::FUSE_RELEASEDIR => Operation::ReleaseDir { arg: data.fetch()? },
@@ -369,6 +500,7 @@ impl<'a> Operation<'a> {
             FuseOpCode::FUSE_POLL => Operation::Poll { arg: data.fetch()? },
             #[cfg(feature = "abi-7-15")]
             FuseOpCode::FUSE_NOTIFY_REPLY => Operation::NotifyReply {
+                arg: data.fetch()?,
                 data: data.fetch_all(),
             },
             #[cfg(feature = "abi-7-16")]
@@ -378,6 +510,25 @@ impl<'a> Operation<'a> {
             },
             #[cfg(feature = "abi-7-19")]
             FuseOpCode::FUSE_FALLOCATE => Operation::FAllocate { arg: data.fetch()? },
+            #[cfg(feature = "abi-7-21")]
+            FuseOpCode::FUSE_READDIRPLUS => Operation::ReadDirPlus { arg: data.fetch()? },
+            #[cfg(feature = "abi-7-23")]
+            FuseOpCode::FUSE_RENAME2 => Operation::Rename2 {
+                arg: data.fetch()?,
+                oldname: data.fetch_os_str()?,
+                newname: data.fetch_os_str()?,
+            },
+            #[cfg(feature = "abi-7-24")]
+            FuseOpCode::FUSE_LSEEK => Operation::LSeek { arg: data.fetch()? },
+            #[cfg(feature = "abi-7-28")]
+            FuseOpCode::FUSE_COPY_FILE_RANGE => Operation::CopyFileRange { arg: data.fetch()? },
+            #[cfg(feature = "abi-7-31")]
+            FuseOpCode::FUSE_SETUPMAPPING => Operation::SetupMapping { arg: data.fetch()? },
+            #[cfg(feature = "abi-7-31")]
+            FuseOpCode::FUSE_REMOVEMAPPING => Operation::RemoveMapping {
+                arg: data.fetch()?,
+                mappings: data.fetch_all_as_slice()?,
+            },
 
             #[cfg(target_os = "macos")]
             FuseOpCode::FUSE_SETVOLNAME => Operation::SetVolName {
@@ -464,7 +615,7 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::Flush { arg } => {
                 write!(f, "FLUSH fh={}, lock owner={}", arg.fh, arg.lock_owner)
             }
-            Operation::Init { arg } => write!(
+            Operation::Init { arg, .. } => write!(
                 f,
                 "INIT kernel ABI={}.{}, flags={:#x}, max readahead={}",
                 arg.major, arg.minor, arg.flags, arg.max_readahead
@@ -503,7 +654,7 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::Destroy => write!(f, "DESTROY"),
 
             #[cfg(feature = "abi-7-11")]
-            Operation::IoCtl { arg, data } => write!(
+            Operation::IoCtl { arg, .. } => write!(
                 f,
                 "IOCTL fh={}, flags {:#x}, cmd={}, arg={}",
                 arg.fh, arg.flags, arg.cmd, arg.arg,
@@ -513,9 +664,15 @@ impl<'a> fmt::Display for Operation<'a> {
                 write!(f, "POLL fh={}, kh={}, flags={:#x} ", arg.fh, arg.kh, arg.flags)
             }
             #[cfg(feature = "abi-7-15")]
-            Operation::NotifyReply { data } => write!(f, "NOTIFY REPLY"),
+            Operation::NotifyReply { arg, data } => write!(
+                f,
+                "NOTIFY REPLY offset={}, size={}, data size={}",
+                arg.offset,
+                arg.size,
+                data.len(),
+            ),
             #[cfg(feature = "abi-7-16")]
-            Operation::BatchForget { arg, nodes } => {
+            Operation::BatchForget { arg, .. } => {
                 write!(f, "BATCH FORGOT count={}", arg.count)
             }
             #[cfg(feature = "abi-7-19")]
@@ -536,10 +693,29 @@ impl<'a> fmt::Display for Operation<'a> {
                 "RENAME2 name={:?}, newdir={:#018x}, newname={:?}, flags={:#x}",
                 oldname, arg.newdir, newname, arg.flags,
             ),
-            // #[cfg(feature = "abi-7-24")]
-            // FUSE_LSEEK = 46,
-            // #[cfg(feature = "abi-7-28")]
-            // FUSE_COPY_FILE_RANGE = 47,
+            #[cfg(feature = "abi-7-24")]
+            Operation::LSeek { arg } => write!(
+                f,
+                "LSEEK fh={}, offset={}, whence={}",
+                arg.fh, arg.offset, arg.whence,
+            ),
+            #[cfg(feature = "abi-7-28")]
+            Operation::CopyFileRange { arg } => write!(
+                f,
+                "COPYFILERANGE src fh={}, src offset={}, dst ino={:#018x}, \
+                    dst fh={}, dst offset={}, length={}, flags={:#x}",
+                arg.fh_in, arg.off_in, arg.nodeid_out, arg.fh_out, arg.off_out, arg.len, arg.flags,
+            ),
+            #[cfg(feature = "abi-7-31")]
+            Operation::SetupMapping { arg } => write!(
+                f,
+                "SETUPMAPPING fh={}, foffset={}, len={}, flags={:#x}, moffset={}",
+                arg.fh, arg.foffset, arg.len, arg.flags, arg.moffset,
+            ),
+            #[cfg(feature = "abi-7-31")]
+            Operation::RemoveMapping { arg, .. } => {
+                write!(f, "REMOVEMAPPING count={}", arg.count)
+            }
 
             #[cfg(target_os = "macos")]
             Operation::SetVolName { name } => write!(f, "SETVOLNAME name={:?}", name),
@@ -563,7 +739,7 @@ impl<'a> fmt::Display for Operation<'a> {
 }
 
 #[derive(Debug)]
-pub(crate) struct Request<'a> {
+pub struct Request<'a> {
     header: &'a FuseInHeader,
     operation: Operation<'a>,
 }
@@ -579,23 +755,36 @@ impl<'a> fmt::Display for Request<'a> {
 }
 
 impl<'a> Request<'a> {
-    pub fn new(bytes: &'a [u8]) -> anyhow::Result<Self> {
+    pub(crate) fn new(bytes: &'a [u8]) -> anyhow::Result<Self> {
         let data_len = bytes.len();
         let mut data = ByteSlice::new(bytes);
         // Parse header
         let header = data.fetch::<FuseInHeader>()?;
         // Check data size
-        debug_assert!(
-            data_len >= header.len as usize, // TODO: why not daten_len == header.len?
-            "failed to assert {} >= {}",
-            data_len,
-            header.len,
-        );
+        if data_len < header.len as usize {
+            // TODO: why not daten_len == header.len?
+            return Err(anyhow::anyhow!(
+                "the request is truncated, received {} bytes but the length in header is {}",
+                data_len,
+                header.len,
+            ));
+        }
         // Parse/check operation arguments
         let operation = Operation::parse(header.opcode, &mut data)?;
         Ok(Self { header, operation })
     }
 
+    /// Parse the header only, to reply the request whose operation failed to parse
+    pub(crate) fn parse_header(bytes: &'a [u8]) -> anyhow::Result<&'a FuseInHeader> {
+        ByteSlice::new(bytes).fetch::<FuseInHeader>()
+    }
+
+    /// Returns the opcode of the operation of this request.
+    #[inline]
+    pub fn opcode(&self) -> u32 {
+        self.header.opcode
+    }
+
     /// Returns the unique identifier of this request.
     ///
     /// The FUSE kernel driver assigns a unique id to every concurrent request. This allows to
@@ -642,7 +831,7 @@ impl<'a> Request<'a> {
 
     /// Returns the filesystem operation (and its arguments) of this request.
     #[inline]
-    pub fn operation(&self) -> &Operation<'_> {
+    pub(crate) fn operation(&self) -> &Operation<'_> {
         &self.operation
     }
 }
@@ -690,6 +879,23 @@ mod test {
         0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // max_readahead, flags
     ]);
 
+    #[cfg(all(target_os = "linux", target_endian = "little"))]
+    const INIT_EXT_REQUEST: Align8<[u8; 104]> = Align8([
+        0x68, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x07, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, // major, minor
+        0x00, 0x10, 0x00, 0x00, 0x01, 0x00, 0x00, 0x40, // max_readahead, flags
+        0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // flags2, unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+    ]);
+
     #[cfg(target_endian = "big")]
     const MKNOD_REQUEST: Align8<[u8; 56]> = Align8([
         0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x08, // len, opcode
@@ -728,10 +934,26 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "failed to assert 48 >= 56")]
     fn short_read() {
-        let req = Request::new(&INIT_REQUEST[..48]);
-        let _ = dbg!(req);
+        let err = Request::new(&INIT_REQUEST[..48]).expect_err("Unexpected request parsing result");
+        assert_eq!(
+            err.to_string(),
+            "the request is truncated, received 48 bytes but the length in header is 56"
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut bytes = Align8(*INIT_REQUEST);
+        bytes.0[4..8].copy_from_slice(&255u32.to_ne_bytes()); // opcode
+        let err = Request::new(&bytes[..]).expect_err("Unexpected request parsing result");
+        assert_eq!(err.to_string(), "unknown FUSE OpCode=255");
+        assert_eq!(
+            err.downcast_ref::<nix::Error>(),
+            Some(&nix::Error::Sys(Errno::ENOSYS))
+        );
+        let header = Request::parse_header(&bytes[..]).unwrap();
+        assert_eq!(header.unique, 0xdead_beef_baad_f00d);
     }
 
     #[test]
@@ -745,13 +967,30 @@ mod test {
         assert_eq!(req.gid(), 0xc001_cafe);
         assert_eq!(req.pid(), 0xc0de_ba5e);
         match req.operation() {
-            Operation::Init { arg } => {
+            Operation::Init { arg, .. } => {
                 assert_eq!(arg.major, 7);
                 assert_eq!(arg.minor, 8);
                 assert_eq!(arg.max_readahead, 4096);
             }
             _ => panic!("unexpected request operation"),
         }
+        assert!(!req.operation().is_mutating());
+    }
+
+    #[cfg(all(target_os = "linux", target_endian = "little"))]
+    #[test]
+    fn init_ext() {
+        let req = Request::new(&INIT_EXT_REQUEST[..]).unwrap();
+        assert_eq!(req.header.len, 104);
+        match req.operation() {
+            Operation::Init { arg, ext } => {
+                assert_eq!(arg.minor, 40);
+                assert_eq!(arg.flags, FUSE_ASYNC_READ | FUSE_INIT_EXT);
+                let ext = ext.expect("the extended init args should be parsed");
+                assert_eq!(ext.flags2, FUSE_PASSTHROUGH);
+            }
+            _ => panic!("unexpected request operation"),
+        }
     }
 
     #[test]
@@ -771,5 +1010,6 @@ mod test {
             }
             _ => panic!("unexpected request operation"),
         }
+        assert!(req.operation().is_mutating());
     }
 }
@@ -0,0 +1,345 @@
+//! FUSE-over-io_uring transport.
+//!
+//! Since Linux 6.14, once FUSE_OVER_IO_URING is negotiated in INIT, the daemon can
+//! register ring entries to per-CPU queues via IORING_OP_URING_CMD on the FUSE device.
+//! The kernel then delivers requests into the buffers of the ring entries, and the
+//! daemon commits the reply and fetches the next request with one command, which saves
+//! the read()/write() system calls and the context switches on /dev/fuse.
+//!
+//! The kernel switches to io_uring only after every queue has a registered entry,
+//! the requests before that, FORGET and INTERRUPT are still read from /dev/fuse.
+
+use anyhow::{self, Context};
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use lazy_static::lazy_static;
+use log::{debug, info};
+use smol::blocking;
+use std::collections::BTreeMap;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::{fs, mem, ptr, slice};
+
+use super::aligned_bytes::AlignedBytes;
+use super::byte_slice::ByteSlice;
+use super::protocol::*;
+
+/// The number of ring entries of each queue
+const QUEUE_DEPTH: usize = 4;
+
+/// The number of iovecs of a ring entry, the header buffer and the payload buffer
+const FUSE_URING_IOV_SEGS: u32 = 2;
+
+/// The offset of the `len` field in `io_uring_sqe`
+const SQE_LEN_OFFSET: usize = 24;
+
+/// The file listing the possible CPUs, the kernel creates one queue for each of them
+const CPU_POSSIBLE_PATH: &str = "/sys/devices/system/cpu/possible";
+
+lazy_static! {
+    /// The ring entries holding in-flight requests, indexed by the request unique ID
+    static ref PENDING_COMMITS: Mutex<BTreeMap<u64, (Arc<FuseUring>, usize)>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// A ring entry, the kernel writes requests into and reads replies from its buffers
+#[derive(Debug)]
+struct RingEntry {
+    /// The queue the entry registered to
+    qid: u16,
+    /// The buffer of `FuseUringReqHeader`
+    header: AlignedBytes,
+    /// The buffer of the request and reply data
+    payload: AlignedBytes,
+    /// The iovecs of the header buffer and the payload buffer
+    iovecs: [libc::iovec; FUSE_URING_IOV_SEGS as usize],
+}
+
+/// The io_uring instance and the ring entries of all the queues
+pub(crate) struct FuseUring {
+    fuse_fd: RawFd,
+    ring: IoUring<squeue::Entry128, cqueue::Entry>,
+    /// The submission queue is shared by the run loop and the replies
+    sq_lock: Mutex<()>,
+    /// Buffers are only touched by the kernel or the owner of the in-flight request
+    entries: Vec<RingEntry>,
+}
+
+// The raw pointers in iovecs point to the buffers owned by the entries
+unsafe impl Send for FuseUring {}
+unsafe impl Sync for FuseUring {}
+
+impl std::fmt::Debug for FuseUring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuseUring")
+            .field("fuse_fd", &self.fuse_fd)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+/// Parse the number of CPUs from the CPU list, e.g. "0-7" means 8 CPUs
+fn parse_cpu_count(cpu_list: &str) -> anyhow::Result<usize> {
+    let mut max_cpu = 0;
+    for range in cpu_list.trim().split(',') {
+        let last = range.rsplit('-').next().unwrap_or(range);
+        let cpu: usize = last
+            .parse()
+            .context(format!("failed to parse the CPU list={:?}", cpu_list))?;
+        max_cpu = max_cpu.max(cpu);
+    }
+    Ok(max_cpu + 1)
+}
+
+/// Get the number of the possible CPUs
+fn possible_cpus() -> anyhow::Result<usize> {
+    let possible = fs::read_to_string(CPU_POSSIBLE_PATH)
+        .context(format!("failed to read {}", CPU_POSSIBLE_PATH))?;
+    parse_cpu_count(&possible)
+}
+
+impl FuseUring {
+    /// Set up the io_uring instance and allocate the ring entries,
+    /// fail if io_uring is not supported or disabled
+    pub fn new(fuse_fd: RawFd, payload_size: usize, page_size: usize) -> anyhow::Result<Self> {
+        let nr_queues = possible_cpus()?;
+        let nr_entries = nr_queues * QUEUE_DEPTH;
+        // At most one command of each entry is in the submission queue
+        let ring = IoUring::<squeue::Entry128, cqueue::Entry>::builder()
+            .build(nr_entries.next_power_of_two() as u32)
+            .context("failed to set up io_uring")?;
+        let header_size = mem::size_of::<FuseUringReqHeader>();
+        let entries = (0..nr_entries)
+            .map(|idx| {
+                let mut header = AlignedBytes::new_zeroed(header_size, page_size);
+                let mut payload = AlignedBytes::new_zeroed(payload_size, page_size);
+                let iovecs = [
+                    libc::iovec {
+                        iov_base: header.as_mut_ptr().cast(),
+                        iov_len: header.len(),
+                    },
+                    libc::iovec {
+                        iov_base: payload.as_mut_ptr().cast(),
+                        iov_len: payload.len(),
+                    },
+                ];
+                RingEntry {
+                    qid: (idx / QUEUE_DEPTH) as u16,
+                    header,
+                    payload,
+                    iovecs,
+                }
+            })
+            .collect();
+        debug!(
+            "FuseUring::new() set up {} queues with {} entries each",
+            nr_queues, QUEUE_DEPTH,
+        );
+        Ok(Self {
+            fuse_fd,
+            ring,
+            sq_lock: Mutex::new(()),
+            entries,
+        })
+    }
+
+    fn cmd_req(&self, idx: usize, commit_id: u64) -> [u8; 80] {
+        let cmd_req = FuseUringCmdReq {
+            flags: 0,
+            commit_id,
+            qid: self.entries[idx].qid,
+            padding: [0; 6],
+        };
+        let mut cmd = [0_u8; 80];
+        let p = &cmd_req as *const FuseUringCmdReq as *const u8;
+        let bytes = unsafe { slice::from_raw_parts(p, mem::size_of::<FuseUringCmdReq>()) };
+        cmd[..bytes.len()].copy_from_slice(bytes);
+        cmd
+    }
+
+    /// Push a command of the entry to the submission queue and submit it
+    fn submit_cmd(&self, sqe: squeue::Entry128) -> anyhow::Result<()> {
+        {
+            let _guard = self
+                .sq_lock
+                .lock()
+                .unwrap_or_else(|e| panic!("failed to lock the submission queue: {}", e));
+            let mut sq = unsafe { self.ring.submission_shared() };
+            unsafe { sq.push(&sqe) }
+                .map_err(|e| anyhow::anyhow!("the submission queue is full: {}", e))?;
+        }
+        self.ring
+            .submit()
+            .context("failed to submit the io_uring command")?;
+        Ok(())
+    }
+
+    /// Register all the ring entries to the kernel
+    pub fn register(&self) -> anyhow::Result<()> {
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let mut sqe =
+                opcode::UringCmd80::new(types::Fd(self.fuse_fd), FUSE_IO_URING_CMD_REGISTER)
+                    .cmd(self.cmd_req(idx, 0))
+                    .addr(Some(entry.iovecs.as_ptr() as u64))
+                    .build()
+                    .user_data(idx as u64);
+            // The kernel reads the number of iovecs from sqe->len,
+            // which is not exposed by the UringCmd80 builder
+            unsafe {
+                let p = (&mut sqe as *mut squeue::Entry128 as *mut u8).add(SQE_LEN_OFFSET);
+                ptr::write_unaligned(p as *mut u32, FUSE_URING_IOV_SEGS);
+            }
+            self.submit_cmd(sqe)
+                .context(format!("failed to register ring entry idx={}", idx))?;
+        }
+        info!("registered {} FUSE io_uring entries", self.entries.len());
+        Ok(())
+    }
+
+    /// Wait for the requests delivered into the ring entries, return the indexes of
+    /// the entries, or None if the FUSE connection is gone
+    pub async fn wait_requests(self: &Arc<Self>) -> anyhow::Result<Option<Vec<usize>>> {
+        let fuse_uring = self.clone();
+        let res = blocking!(
+            fuse_uring.ring.submit_and_wait(1)?;
+            let cq = unsafe { fuse_uring.ring.completion_shared() };
+            let cqes: Vec<_> = cq.map(|cqe| (cqe.user_data(), cqe.result())).collect();
+            Ok::<_, std::io::Error>(cqes)
+        );
+        let cqes = match res {
+            Ok(cqes) => cqes,
+            // Interrupted system call, retry
+            Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => return Ok(Some(Vec::new())),
+            Err(e) => return Err(e).context("failed to wait for FUSE io_uring completions"),
+        };
+
+        let mut ready = Vec::with_capacity(cqes.len());
+        for (user_data, res) in cqes {
+            let idx = user_data as usize;
+            if res == 0 {
+                ready.push(idx);
+                continue;
+            }
+            match -res {
+                // The FUSE connection is aborted or the filesystem was unmounted
+                libc::ENOTCONN | libc::ENODEV | libc::ECANCELED => {
+                    info!("FUSE io_uring entry idx={} stopped, error={}", idx, -res);
+                    return Ok(None);
+                }
+                errno => anyhow::bail!(
+                    "FUSE io_uring command of entry idx={} failed, the error is: {}",
+                    idx,
+                    std::io::Error::from_raw_os_error(errno),
+                ),
+            }
+        }
+        Ok(Some(ready))
+    }
+
+    /// Assemble the request in the ring entry into the same layout as read from /dev/fuse,
+    /// and hold the entry for the reply
+    pub fn take_request(
+        self: &Arc<Self>,
+        idx: usize,
+        buf: &mut AlignedBytes,
+    ) -> anyhow::Result<usize> {
+        let entry = &self.entries[idx];
+        let mut header_slice = ByteSlice::new(&entry.header);
+        let req_header = header_slice.fetch::<FuseUringReqHeader>()?;
+        let in_header = ByteSlice::new(&req_header.in_out).fetch::<FuseInHeader>()?;
+        let in_header_len = mem::size_of::<FuseInHeader>();
+        let payload_len = req_header.ring_ent_in_out.payload_sz as usize;
+        let req_len = in_header.len as usize;
+        if req_len < in_header_len + payload_len || req_len > buf.len() {
+            anyhow::bail!(
+                "invalid FUSE io_uring request of entry idx={}, len={}, payload size={}",
+                idx,
+                req_len,
+                payload_len,
+            );
+        }
+        // The first argument is in op_in and the rest are in the payload
+        let op_in_len = req_len - in_header_len - payload_len;
+        if op_in_len > FUSE_URING_OP_IN_OUT_SZ || payload_len > entry.payload.len() {
+            anyhow::bail!(
+                "invalid FUSE io_uring request of entry idx={}, op in size={}, payload size={}",
+                idx,
+                op_in_len,
+                payload_len,
+            );
+        }
+        buf[..in_header_len].copy_from_slice(&req_header.in_out[..in_header_len]);
+        buf[in_header_len..in_header_len + op_in_len]
+            .copy_from_slice(&req_header.op_in[..op_in_len]);
+        buf[in_header_len + op_in_len..req_len].copy_from_slice(&entry.payload[..payload_len]);
+
+        let mut pending = PENDING_COMMITS
+            .lock()
+            .unwrap_or_else(|e| panic!("failed to lock pending commits: {}", e));
+        pending.insert(in_header.unique, (self.clone(), idx));
+        Ok(req_len)
+    }
+
+    /// Write the reply into the ring entry, then commit it and fetch the next request
+    fn commit(&self, idx: usize, header: &FuseOutHeader, data: &[u8]) -> anyhow::Result<usize> {
+        let entry = &self.entries[idx];
+        if data.len() > entry.payload.len() {
+            anyhow::bail!(
+                "the reply size={} exceeds the FUSE io_uring payload size={}",
+                data.len(),
+                entry.payload.len(),
+            );
+        }
+        let req_header = entry.header.as_ptr() as *mut FuseUringReqHeader;
+        let commit_id = unsafe {
+            // The entry is owned by this reply until it is committed
+            ptr::copy_nonoverlapping(
+                header as *const FuseOutHeader as *const u8,
+                (*req_header).in_out.as_mut_ptr(),
+                mem::size_of::<FuseOutHeader>(),
+            );
+            ptr::copy_nonoverlapping(data.as_ptr(), entry.payload.as_ptr() as *mut u8, data.len());
+            (*req_header).ring_ent_in_out.payload_sz = data.len() as u32;
+            (*req_header).ring_ent_in_out.commit_id
+        };
+        let sqe =
+            opcode::UringCmd80::new(types::Fd(self.fuse_fd), FUSE_IO_URING_CMD_COMMIT_AND_FETCH)
+                .cmd(self.cmd_req(idx, commit_id))
+                .build()
+                .user_data(idx as u64);
+        self.submit_cmd(sqe).context(format!(
+            "failed to commit the reply of ring entry idx={}",
+            idx
+        ))?;
+        Ok(header.len as usize)
+    }
+}
+
+/// Commit the reply through io_uring if the request came from a ring entry,
+/// return None if the request was read from /dev/fuse
+pub fn try_commit(header: &FuseOutHeader, data: &[u8]) -> Option<anyhow::Result<usize>> {
+    let pending = PENDING_COMMITS
+        .lock()
+        .unwrap_or_else(|e| panic!("failed to lock pending commits: {}", e))
+        .remove(&header.unique);
+    pending.map(|(fuse_uring, idx)| fuse_uring.commit(idx, header, data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_cpu_count;
+
+    #[test]
+    fn test_parse_cpu_count() -> anyhow::Result<()> {
+        assert_eq!(parse_cpu_count("0\n")?, 1);
+        assert_eq!(parse_cpu_count("0-7\n")?, 8);
+        assert_eq!(parse_cpu_count("0-3,8-11\n")?, 12);
+        assert!(parse_cpu_count("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_req_header_size() {
+        assert_eq!(std::mem::size_of::<super::FuseUringReqHeader>(), 288);
+        assert_eq!(std::mem::size_of::<super::FuseUringCmdReq>(), 24);
+    }
+}
@@ -0,0 +1,126 @@
+#![deny(
+    // The following are allowed by default lints according to
+    // https://doc.rust-lang.org/rustc/lints/listing/allowed-by-default.html
+    anonymous_parameters,
+    bare_trait_objects,
+    // box_pointers, TODO: fix box pointers
+    elided_lifetimes_in_paths,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    // missing_docs, TODO: add documents
+    // single_use_lifetimes, TODO: fix lifetime names only used once
+    // trivial_casts, TODO: remove trivial casts in code
+    trivial_numeric_casts,
+    // unreachable_pub, TODO: fix unreachable pub
+    // unsafe_code,
+    unstable_features,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    // unused_results, TODO: fix unused results
+    variant_size_differences,
+
+    // Treat warnings as errors
+    // warnings, TODO: treat all wanings as errors
+
+
+    // Deny all Clippy lints even Clippy allow some by default
+    // https://rust-lang.github.io/rust-clippy/master/
+    clippy::all,
+)]
+#![allow(
+    // Some explicitly allowed Clippy lints, must have clear reason to allow
+    clippy::implicit_return, // actually omitting the return keyword is idiomatic Rust code
+)]
+
+//! An async FUSE runtime.
+//!
+//! The `Session` mounts FUSE and serves the requests from the kernel by an async
+//! `Filesystem`, whose default methods reply ENOSYS. `FileSystem` serves the files stored
+//! by a `StorageBackend`, either `LocalBackend` mirroring a backing directory, or
+//! `MemoryBackend` keeping the files in memory.
+
+#[allow(unsafe_code)] // verified
+mod byte_slice;
+
+#[allow(unsafe_code)] // verified
+mod aligned_bytes;
+
+mod channel;
+mod dir_lock;
+mod filesystem;
+mod fs;
+mod fuse_conn;
+#[cfg(feature = "abi-7-12")]
+mod fuse_notify;
+mod fuse_read;
+mod fuse_reply;
+mod fuse_request;
+#[cfg(target_os = "linux")]
+mod fuse_uring;
+mod mount;
+mod mount_options;
+mod passthrough;
+#[allow(missing_copy_implementations)] // the ABI structs are read in place from the requests
+pub mod protocol;
+mod session;
+#[allow(unsafe_code)] // verified
+mod signal;
+#[allow(unsafe_code)] // verified
+mod supervisor;
+#[allow(unsafe_code)] // verified
+mod upgrade;
+
+pub use filesystem::Filesystem;
+pub use fs::{
+    DirEntry, FileAttr, FileSystem, FsError, FsResult, LocalBackend, MemoryBackend, Revalidation,
+    StorageBackend,
+};
+pub use fuse_conn::ConnInfo;
+#[cfg(feature = "abi-7-12")]
+pub use fuse_notify::Notifier;
+pub use fuse_reply::*;
+pub use fuse_request::Request;
+pub use mount_options::{CacheTimeouts, MountOptions, OpenPolicy};
+pub use session::Session;
+pub use supervisor::supervise;
+pub use upgrade::{Handoff, SavedState};
+
+#[cfg(test)]
+mod test {
+    mod integration_tests;
+    mod test_util;
+
+    use futures::prelude::*;
+    use futures::stream::StreamExt;
+    use smol::{self, blocking};
+    use std::fs::{self, File};
+    use std::io;
+
+    #[test]
+    fn test_async_iter() -> io::Result<()> {
+        smol::run(async move {
+            let dir = blocking!(fs::read_dir("."))?;
+            let mut dir = smol::iter(dir);
+            while let Some(entry) = dir.next().await {
+                let path = entry?.path();
+                if path.is_file() {
+                    println!("read file: {:?}", path);
+                    let file = blocking!(File::open(path))?;
+                    let mut file = smol::reader(file);
+                    let mut buf = vec![];
+                    file.read_to_end(&mut buf).await?;
+                    let output_length = 16;
+                    if buf.len() > output_length {
+                        println!("first {} bytes: {:?}", output_length, &buf[..output_length]);
+                    } else {
+                        println!("total bytes: {:?}", buf);
+                    }
+                } else {
+                    println!("skip directory: {:?}", path);
+                }
+            }
+            Ok(())
+        })
+    }
+}
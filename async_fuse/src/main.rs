@@ -1,111 +1,160 @@
-#![deny(
-    // The following are allowed by default lints according to
-    // https://doc.rust-lang.org/rustc/lints/listing/allowed-by-default.html
-    anonymous_parameters,
-    bare_trait_objects,
-    // box_pointers, TODO: fix box pointers
-    elided_lifetimes_in_paths,
-    missing_copy_implementations,
-    missing_debug_implementations,
-    // missing_docs, TODO: add documents
-    // single_use_lifetimes, TODO: fix lifetime names only used once
-    // trivial_casts, TODO: remove trivial casts in code
-    trivial_numeric_casts,
-    // unreachable_pub, TODO: fix unreachable pub
-    // unsafe_code,
-    unstable_features,
-    unused_extern_crates,
-    unused_import_braces,
-    unused_qualifications,
-    // unused_results, TODO: fix unused results
-    variant_size_differences,
-
-    // Treat warnings as errors
-    // warnings, TODO: treat all wanings as errors
-
-
-    // Deny all Clippy lints even Clippy allow some by default
-    // https://rust-lang.github.io/rust-clippy/master/
-    clippy::all,
-)]
-#![allow(
-    // Some explicitly allowed Clippy lints, must have clear reason to allow
-    clippy::implicit_return, // actually omitting the return keyword is idiomatic Rust code
-)]
-
+use async_fuse::{
+    FileSystem, Handoff, MemoryBackend, MountOptions, Revalidation, Session, StorageBackend,
+};
 use log::debug;
+use std::ffi::OsString;
 
-#[allow(unsafe_code)] // verified
-mod byte_slice;
-
-#[allow(unsafe_code)] // verified
-mod aligned_bytes;
-
-mod channel;
-mod fs;
-mod fuse_read;
-mod fuse_reply;
-mod fuse_request;
-mod mount;
-mod protocol;
-mod session;
-use session::*;
+/// Serve the filesystem at the mountpoint, taking over the mount if handed over
+async fn serve<B: StorageBackend>(
+    mut filesystem: FileSystem<B>,
+    mountpoint: OsString,
+    options: MountOptions,
+    handoff: Option<Handoff>,
+    hardened: bool,
+    upgrade_socket: Option<OsString>,
+) -> anyhow::Result<()> {
+    filesystem.set_read_only(options.read_only);
+    filesystem.set_timeouts(options.timeouts.clone());
+    filesystem.set_open_policy(options.open_policy.clone());
+    let mut ss = match handoff {
+        Some(handoff) => Session::take_over(&mountpoint, filesystem, &options, handoff).await?,
+        None => Session::new(&mountpoint, filesystem, &options).await?,
+    };
+    ss.set_hardened(hardened);
+    if let Some(path) = upgrade_socket {
+        ss.set_upgrade_socket(path);
+    }
+    ss.run().await?;
+    Ok(())
+}
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
-    let mountpoint = match std::env::args_os().nth(1) {
-        Some(path) => path,
-        None => {
-            return Err(anyhow::anyhow!(
-                "no mount path input, the usage: {} <MOUNTPOINT>",
-                std::env::args().next().unwrap(), // safe to use unwrap here
-            ));
+    let usage = format!(
+        "the usage: {} {{<SOURCE>|--memory}} <MOUNTPOINT> [never|open|watch] \
+            [-o <MOUNT_OPTIONS>] [--config <MOUNT_OPTIONS_FILE>] [--hardened] \
+            [--upgrade-socket <SOCKET>] [--take-over <SOCKET>] [--supervise]",
+        std::env::args().next().unwrap(), // safe to use unwrap here
+    );
+    // Keep the files in memory rather than a source directory
+    let mut memory = false;
+    // Keep running after malformed requests or failed operations
+    let mut hardened = false;
+    let mut config_file = None;
+    // Listen for a new daemon to take over on live upgrade
+    let mut upgrade_socket = None;
+    // Take over the mount from the running daemon listening on the socket
+    let mut take_over = None;
+    // Run the daemon in a child process and restart it once it crashes
+    let mut supervise = false;
+    // The arguments of the supervised daemon
+    let mut daemon_args: Vec<OsString> = Vec::new();
+    let mut mount_options = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--supervise" {
+            supervise = true;
+            continue;
+        }
+        daemon_args.push(arg.clone());
+        if arg == "--hardened" {
+            hardened = true;
+        } else if arg == "--memory" {
+            memory = true;
+        } else if arg == "-o"
+            || arg == "--config"
+            || arg == "--upgrade-socket"
+            || arg == "--take-over"
+        {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(anyhow::anyhow!("no value of {:?}, {}", arg, usage)),
+            };
+            daemon_args.push(value.clone());
+            if arg == "-o" {
+                mount_options.push(value);
+            } else if arg == "--config" {
+                config_file = Some(value);
+            } else if arg == "--upgrade-socket" {
+                upgrade_socket = Some(value);
+            } else {
+                take_over = Some(value);
+            }
+        } else {
+            positional.push(arg);
         }
+    }
+    let mut args = positional.into_iter();
+    let source = if memory {
+        None
+    } else {
+        match args.next() {
+            Some(path) => Some(path),
+            None => return Err(anyhow::anyhow!("no source path input, {}", usage)),
+        }
+    };
+    let mountpoint = match args.next() {
+        Some(path) => path,
+        None => return Err(anyhow::anyhow!("no mount path input, {}", usage)),
+    };
+    debug!("source: {:?}, mount point: {:?}", source, mountpoint);
+    // How to detect the changes to the source directory out of the mount
+    let revalidation = match args.next() {
+        Some(mode) => mode.to_string_lossy().parse()?,
+        None => Revalidation::default(),
     };
-    debug!("mount point: {:?}", mountpoint);
+    debug!("revalidation: {:?}, hardened: {}", revalidation, hardened);
+    // The options on the command line override the ones in the config file
+    let mut options = MountOptions::default();
+    if let Some(path) = config_file {
+        options.parse_file(path)?;
+    }
+    for opts in mount_options {
+        options.parse(&opts.to_string_lossy())?;
+    }
+    debug!("mount options: {:?}", options);
+    if supervise {
+        let code = smol::run(async_fuse::supervise(daemon_args))?;
+        std::process::exit(code);
+    }
 
+    // Must receive the fds of the old daemon before opening any file,
+    // so that they keep the same numbers
+    let handoff = match (take_over, &upgrade_socket) {
+        (Some(path), _) => Some(Handoff::receive(path)?),
+        // Take over the mount if the daemon listening for upgrade still serves it
+        (None, Some(path)) => Handoff::try_receive(path)?,
+        (None, None) => None,
+    };
     smol::run(async move {
-        let ss = Session::new(&mountpoint).await?;
-        ss.run().await?;
-        Ok(())
-    })
-}
-
-#[cfg(test)]
-mod test {
-    mod integration_tests;
-    mod test_util;
-
-    use futures::prelude::*;
-    use futures::stream::StreamExt;
-    use smol::{self, blocking};
-    use std::fs::{self, File};
-    use std::io;
-
-    #[test]
-    fn test_async_iter() -> io::Result<()> {
-        smol::run(async move {
-            let dir = blocking!(fs::read_dir("."))?;
-            let mut dir = smol::iter(dir);
-            while let Some(entry) = dir.next().await {
-                let path = entry?.path();
-                if path.is_file() {
-                    println!("read file: {:?}", path);
-                    let file = blocking!(File::open(path))?;
-                    let mut file = smol::reader(file);
-                    let mut buf = vec![];
-                    file.read_to_end(&mut buf).await?;
-                    let output_length = 16;
-                    if buf.len() > output_length {
-                        println!("first {} bytes: {:?}", output_length, &buf[..output_length]);
-                    } else {
-                        println!("total bytes: {:?}", buf);
-                    }
-                } else {
-                    println!("skip directory: {:?}", path);
-                }
+        // Must create filesystem before mount, in case the source is the mountpoint
+        match source {
+            Some(source) => {
+                let filesystem = FileSystem::new(&source, revalidation).await?;
+                serve(
+                    filesystem,
+                    mountpoint,
+                    options,
+                    handoff,
+                    hardened,
+                    upgrade_socket,
+                )
+                .await
             }
-            Ok(())
-        })
-    }
+            None => {
+                let filesystem =
+                    FileSystem::with_backend(MemoryBackend::new(), revalidation).await?;
+                serve(
+                    filesystem,
+                    mountpoint,
+                    options,
+                    handoff,
+                    hardened,
+                    upgrade_socket,
+                )
+                .await
+            }
+        }
+    })
 }
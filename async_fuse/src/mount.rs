@@ -1,5 +1,5 @@
 use anyhow::{self, Context};
-use log::{debug, info};
+use log::{debug, info, warn};
 use nix::fcntl::{self, OFlag};
 use nix::sys::stat::{self, Mode};
 use smol::blocking;
@@ -9,23 +9,25 @@ use std::os::raw::c_void;
 use std::os::unix::io::RawFd;
 use std::path::Path;
 
+use super::mount_options::MountOptions;
 use param::*;
 
 #[cfg(target_os = "linux")]
 mod param {
     // https://github.com/torvalds/linux/blob/master/include/uapi/linux/mount.h#L11
     // TODO: use mount flags from libc
-    // pub const MS_RDONLY: u64 = 1; // Mount read-only
+    pub const MS_RDONLY: u64 = 1; // Mount read-only
     pub const MS_NOSUID: u64 = 2; // Ignore suid and sgid bits
     pub const MS_NODEV: u64 = 4; // Disallow access to device special files
-    pub const MNT_FORCE: i32 = 1; // Force un-mount
+    pub const MS_NOATIME: u64 = 1024; // Do not update access times
+    pub const MNT_DETACH: i32 = 2; // Just detach from the tree
 }
 
 #[cfg(target_os = "macos")]
 mod param {
     // https://github.com/apple/darwin-xnu/blob/master/bsd/sys/mount.h#L288
     // TODO: use mount flags from libc
-    // pub const MNT_RDONLY: i32 = 0x00000001; // read only filesystem
+    pub const MNT_RDONLY: i32 = 0x00000001; // read only filesystem
     pub const MNT_NOSUID: i32 = 0x00000008; // don't honor setuid bits on fs
     pub const MNT_NODEV: i32 = 0x00000010; // don't interpret special files
     pub const MNT_FORCE: i32 = 0x00080000; // force unmount or readonly change
@@ -42,8 +44,12 @@ mod param {
     pub const FUSE_IOC_TYPE_MODE: u8 = 5;
 
     pub const FUSE_FSSUBTYPE_UNKNOWN: u32 = 0;
+    pub const FUSE_MOPT_ALLOW_OTHER: u64 = 0x0000000000000001;
+    pub const FUSE_MOPT_BLOCKSIZE: u64 = 0x0000000000000010;
     pub const FUSE_MOPT_DEBUG: u64 = 0x0000000000000040;
+    pub const FUSE_MOPT_DEFAULT_PERMISSIONS: u64 = 0x0000000000000080;
     pub const FUSE_MOPT_FSNAME: u64 = 0x0000000000001000;
+    pub const FUSE_MOPT_IOSIZE: u64 = 0x0000000000010000;
     pub const FUSE_MOPT_NO_APPLEXATTR: u64 = 0x0000000000800000;
 
     use libc::size_t;
@@ -67,8 +73,10 @@ mod param {
     }
 }
 
+/// Unmount FUSE, the lazy umount detaches the mount even if it is busy,
+/// otherwise the busy mount fails to umount
 #[cfg(target_os = "linux")]
-pub async fn umount(short_path: impl AsRef<Path>) -> anyhow::Result<()> {
+pub async fn umount(short_path: impl AsRef<Path>, lazy: bool) -> anyhow::Result<()> {
     use nix::unistd;
     use std::process::Command;
 
@@ -78,11 +86,12 @@ pub async fn umount(short_path: impl AsRef<Path>) -> anyhow::Result<()> {
 
         if unistd::geteuid().is_root() {
             // direct umount
+            let flags = if lazy { MNT_DETACH } else { 0 };
             #[cfg(target_arch = "aarch64")]
-            let result = unsafe { libc::umount2(mntpnt as *const _ as *const u8, MNT_FORCE) };
+            let result = unsafe { libc::umount2(mntpnt as *const _ as *const u8, flags) };
             #[cfg(target_arch = "x86_64")]
             let result =
-                unsafe { libc::umount2(mntpnt as *const _ as *const u8 as *const i8, MNT_FORCE) };
+                unsafe { libc::umount2(mntpnt as *const _ as *const u8 as *const i8, flags) };
 
             if result == 0 {
                 Ok(())
@@ -95,7 +104,7 @@ pub async fn umount(short_path: impl AsRef<Path>) -> anyhow::Result<()> {
         } else {
             // use fusermount to umount
             let umount_handle = Command::new("fusermount")
-                .arg("-uz") // lazy umount
+                .arg(if lazy { "-uz" } else { "-u" })
                 .arg(mntpnt)
                 .output()
                 .expect("fusermount command failed to start");
@@ -114,28 +123,39 @@ pub async fn umount(short_path: impl AsRef<Path>) -> anyhow::Result<()> {
 }
 
 #[cfg(target_os = "linux")]
-pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
+pub async fn mount(mount_point: impl AsRef<Path>, options: &MountOptions) -> anyhow::Result<RawFd> {
     use nix::unistd;
 
-    if unistd::geteuid().is_root() {
+    if options.blksize.is_some() {
+        warn!("the mount option blksize is only supported by fuseblk, ignore it");
+    }
+    // fusermount watches the daemon to unmount once it quits
+    if unistd::geteuid().is_root() && !options.auto_unmount {
         // direct umount
-        direct_mount(mount_point).await
+        direct_mount(mount_point, options).await
     } else {
         // use fusermount to mount
-        fuser_mount(mount_point).await
+        fuser_mount(mount_point, options).await
     }
 }
 
 #[cfg(target_os = "linux")]
-async fn fuser_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
+async fn fuser_mount(
+    mount_point: impl AsRef<Path>,
+    options: &MountOptions,
+) -> anyhow::Result<RawFd> {
     use nix::cmsg_space;
     use nix::sys::socket::{
         self, AddressFamily, ControlMessageOwned, MsgFlags, SockFlag, SockType,
     };
     use nix::sys::uio::IoVec;
-    use std::process::Command;
+    use std::process::{Command, Stdio};
 
     let mount_path = mount_point.as_ref().to_path_buf();
+    let mut fusermount_options = vec!["nosuid,nodev,noexec,nonempty".to_owned()];
+    fusermount_options.extend(options.fusermount_options());
+    let fusermount_options = fusermount_options.join(",");
+    debug!("fusermount options={:?}", fusermount_options);
 
     let (local, remote) = blocking!(socket::socketpair(
         AddressFamily::Unix,
@@ -145,20 +165,23 @@ async fn fuser_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
     ))
     .context("failed to create socket pair")?;
 
-    let mount_handle = blocking!(Command::new("fusermount")
+    // The fusermount of auto_unmount keeps running in background with the inherited
+    // stdio, so wait for its exit status rather than its output
+    let mount_status = blocking!(Command::new("fusermount")
         .arg("-o")
-        // fusermount option allow_other only allowed if user_allow_other is set in /etc/fuse.conf
-        .arg("nosuid,nodev,noexec,nonempty") // rw,async,noatime,auto_unmount,allow_other
+        .arg(&fusermount_options)
         .arg(mount_path.as_os_str())
         .env("_FUSE_COMMFD", remote.to_string())
-        .output())
+        .stdout(Stdio::null())
+        .status())
     .context("fusermount command failed to start")?;
 
-    assert!(
-        mount_handle.status.success(),
-        "failed to run fusermount, the error is: {}",
-        String::from_utf8_lossy(&mount_handle.stderr),
-    );
+    if !mount_status.success() {
+        return Err(anyhow::anyhow!(
+            "failed to run fusermount, the exit status is: {}",
+            mount_status,
+        ));
+    }
     info!(
         "fusermount path={:?} to FUSE device successfully!",
         mount_point.as_ref(),
@@ -187,7 +210,10 @@ async fn fuser_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
 }
 
 #[cfg(target_os = "linux")]
-async fn direct_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
+async fn direct_mount(
+    mount_point: impl AsRef<Path>,
+    options: &MountOptions,
+) -> anyhow::Result<RawFd> {
     use nix::sys::stat::SFlag;
     use nix::unistd;
 
@@ -204,27 +230,40 @@ async fn direct_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
         .expect("full mount path to string failed");
 
     let mntpath = CString::new(cstr_path).expect("CString::new failed");
-    let fstype = CString::new("fuse").expect("CString::new failed");
-    let fsname = CString::new("/dev/fuse").expect("CString::new failed");
+    let fstype = match options.subtype {
+        Some(ref subtype) => format!("fuse.{}", subtype),
+        None => "fuse".to_owned(),
+    };
+    let fstype = CString::new(fstype).context("invalid mount option subtype")?;
+    let fsname = options.fsname.as_deref().unwrap_or("/dev/fuse");
+    let fsname = CString::new(fsname).context("invalid mount option fsname")?;
 
     let mnt_sb =
         blocking!(stat::stat(&full_path)).context("failed to get the file stat of mount point")?;
 
-    let opts = format!(
+    let mut opts = vec![format!(
         "fd={},rootmode={:o},user_id={},group_id={}",
         dev_fd,
         mnt_sb.st_mode & SFlag::S_IFMT.bits(),
         unistd::getuid().as_raw(),
         unistd::getgid().as_raw()
-    );
-    let opts = CString::new(&*opts).expect("CString::new failed");
+    )];
+    opts.extend(options.kernel_options());
+    let opts = CString::new(opts.join(",")).expect("CString::new failed");
+    let mut flags = MS_NOSUID | MS_NODEV;
+    if options.read_only {
+        flags |= MS_RDONLY;
+    }
+    if options.noatime {
+        flags |= MS_NOATIME;
+    }
     debug!("direct mount opts={:?}", &opts);
     blocking!(
         let result = unsafe { libc::mount(
             fsname.as_ptr(),
             mntpath.as_ptr(),
             fstype.as_ptr(),
-            MS_NOSUID | MS_NODEV,
+            flags,
             opts.as_ptr() as *const c_void,
         )};
         if result == 0 {
@@ -247,12 +286,14 @@ async fn direct_mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
     )
 }
 
+/// Unmount FUSE, there is no lazy umount on macOS, so force umount instead
 #[cfg(any(target_os = "macos"))]
-pub async fn umount(mount_point: impl AsRef<Path>) -> nix::Result<()> {
+pub async fn umount(mount_point: impl AsRef<Path>, lazy: bool) -> nix::Result<()> {
     let mntpnt = mount_point.as_ref().to_path_buf();
+    let flags = if lazy { MNT_FORCE } else { 0 };
     blocking!(
         let mntpnt = mntpnt.as_os_str();
-        let res = unsafe { libc::unmount(mntpnt as *const _ as *const u8 as *const i8, MNT_FORCE) };
+        let res = unsafe { libc::unmount(mntpnt as *const _ as *const u8 as *const i8, flags) };
         if res < 0 {
             Err(nix::Error::last())
         } else {
@@ -262,7 +303,7 @@ pub async fn umount(mount_point: impl AsRef<Path>) -> nix::Result<()> {
 }
 
 #[cfg(any(target_os = "macos"))]
-pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
+pub async fn mount(mount_point: impl AsRef<Path>, options: &MountOptions) -> anyhow::Result<RawFd> {
     let mount_point = mount_point.as_ref().to_path_buf();
     let devpath = Path::new("/dev/osxfuse1");
 
@@ -291,7 +332,7 @@ pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
 
     let mntpath = CString::new(cstr_path)?;
     let fstype = CString::new("osxfuse")?;
-    let fsname = CString::new("macfuse")?;
+    let fsname = CString::new(options.fsname.as_deref().unwrap_or("macfuse"))?;
     let fstypename = CString::new("")?;
     let volname = CString::new("OSXFUSE Volume 0 (macfuse)")?;
 
@@ -322,17 +363,35 @@ pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
     let mut volname_slice = [0u8; MAXPATHLEN];
     copy_slice(volname.as_bytes(), &mut volname_slice);
 
+    let mut altflags = FUSE_MOPT_DEBUG | FUSE_MOPT_FSNAME | FUSE_MOPT_NO_APPLEXATTR;
+    if options.allow_other {
+        altflags |= FUSE_MOPT_ALLOW_OTHER;
+    }
+    if options.default_permissions {
+        altflags |= FUSE_MOPT_DEFAULT_PERMISSIONS;
+    }
+    if options.blksize.is_some() {
+        altflags |= FUSE_MOPT_BLOCKSIZE;
+    }
+    if options.max_read.is_some() {
+        altflags |= FUSE_MOPT_IOSIZE;
+    }
+    let mut flags = MNT_NOSUID | MNT_NODEV | MNT_NOUSERXATTR | MNT_NOATIME;
+    if options.read_only {
+        flags |= MNT_RDONLY;
+    }
+
     let mut mnt_args = FuseMountArgs {
         mntpath: mntpath_slice,
         fsname: fsname_slice,
         fstypename: fstypename_slice,
         volname: volname_slice,
-        altflags: FUSE_MOPT_DEBUG | FUSE_MOPT_FSNAME | FUSE_MOPT_NO_APPLEXATTR,
-        blocksize: FUSE_DEFAULT_BLOCKSIZE,
+        altflags,
+        blocksize: options.blksize.unwrap_or(FUSE_DEFAULT_BLOCKSIZE),
         daemon_timeout: FUSE_DEFAULT_DAEMON_TIMEOUT,
         fsid: 0,
         fssubtype: FUSE_FSSUBTYPE_UNKNOWN,
-        iosize: FUSE_DEFAULT_IOSIZE,
+        iosize: options.max_read.unwrap_or(FUSE_DEFAULT_IOSIZE),
         random: drandom,
         rdev: sb.st_rdev as u32,
     };
@@ -342,7 +401,7 @@ pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
             libc::mount(
                 fstype.as_ptr(),
                 mntpath.as_ptr(),
-                MNT_NOSUID | MNT_NODEV | MNT_NOUSERXATTR | MNT_NOATIME,
+                flags,
                 &mut mnt_args as *mut _ as *mut c_void,
             )
         };
@@ -362,3 +421,24 @@ pub async fn mount(mount_point: impl AsRef<Path>) -> anyhow::Result<RawFd> {
         }
     )
 }
+
+/// Lazily unmount the stale FUSE mount left by a crashed daemon, whose stat fails with
+/// ENOTCONN since no daemon serves it any more, return whether the mount was stale
+pub async fn umount_stale(mount_point: impl AsRef<Path>) -> anyhow::Result<bool> {
+    let mount_point = mount_point.as_ref().to_path_buf();
+    let stat_path = mount_point.clone();
+    match blocking!(stat::stat(&stat_path)) {
+        Err(nix::Error::Sys(nix::errno::Errno::ENOTCONN)) => {
+            warn!(
+                "found the stale FUSE mount at {:?}, lazily umount it",
+                mount_point,
+            );
+            umount(&mount_point, true).await.context(format!(
+                "failed to umount the stale mount={:?}",
+                mount_point
+            ))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
@@ -0,0 +1,522 @@
+//! The options to mount FUSE.
+//!
+//! The options are written as a comma separated list like the `-o` option of mount(8),
+//! e.g. `allow_other,ro,fsname=data,max_read=131072`, and read from the command line
+//! or a config file, where each line holds one or more options and `#` starts a comment.
+//! The same options feed both the direct mount and the fusermount paths.
+//!
+//! The TTLs of the kernel caches apply to the whole mount, or to the subtree of a
+//! directory relative to the root by suffixing the option name with `@<dir>`,
+//! e.g. `attr_timeout=60,strict_coherence@shared` caches the attributes for a minute
+//! but nothing under `shared`, whose files are changed by the others.
+//!
+//! The open policy chooses how the kernel caches the opened files by the globs of
+//! the paths relative to the root and the file sizes, e.g. `direct_io=*.db,keep_cache=
+//! datasets/**` lets the databases bypass the page cache while the datasets keep it.
+//! The globs without `/` match the file names in any directory.
+
+use anyhow::{self, Context};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "abi-7-10")]
+use super::protocol::FOPEN_NONSEEKABLE;
+use super::protocol::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
+
+/// The valid mount options, for the error message of unknown options
+const VALID_OPTIONS: &str = "allow_other, default_permissions, ro, rw, fsname=<name>, \
+    subtype=<name>, max_read=<bytes>, blksize=<bytes>, noatime, atime, auto_unmount, \
+    attr_timeout[@<dir>]=<secs>, entry_timeout[@<dir>]=<secs>, negative_timeout[@<dir>]=<secs>, \
+    strict_coherence[@<dir>], direct_io=<glob>, keep_cache=<glob>, nonseekable=<glob>, \
+    direct_io_size=<bytes>";
+
+/// The TTLs of the kernel caches, None to use the default of the filesystem
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheTimeouts {
+    /// How long the kernel caches the attributes
+    pub attr: Option<Duration>,
+    /// How long the kernel caches the names looked up
+    pub entry: Option<Duration>,
+    /// How long the kernel caches the names not found, not cached by default
+    pub negative: Option<Duration>,
+}
+
+impl CacheTimeouts {
+    /// Take the TTLs not set from `other`
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            attr: self.attr.or(other.attr),
+            entry: self.entry.or(other.entry),
+            negative: self.negative.or(other.negative),
+        }
+    }
+}
+
+/// The rules choosing the flags of the opened files replied to the kernel
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenPolicy {
+    /// The globs of the files bypassing the page cache
+    pub direct_io: Vec<String>,
+    /// The globs of the files keeping the page cache across opens
+    pub keep_cache: Vec<String>,
+    /// The globs of the files not seekable, e.g. FIFO-like files
+    pub nonseekable: Vec<String>,
+    /// The files at least this large bypass the page cache
+    pub direct_io_size: Option<u64>,
+}
+
+impl OpenPolicy {
+    /// The open flags of the file of the path relative to the root and the size
+    pub fn flags(&self, path: &Path, size: u64) -> u32 {
+        let matches = |globs: &[String]| globs.iter().any(|glob| glob_match_path(glob, path));
+        let mut flags = 0;
+        if matches(&self.direct_io) || self.direct_io_size.map_or(false, |min| size >= min) {
+            flags |= FOPEN_DIRECT_IO;
+        }
+        if matches(&self.keep_cache) {
+            flags |= FOPEN_KEEP_CACHE;
+        }
+        #[cfg(feature = "abi-7-10")]
+        {
+            if matches(&self.nonseekable) {
+                flags |= FOPEN_NONSEEKABLE;
+            }
+        }
+        flags
+    }
+}
+
+/// The options to mount FUSE
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Allow the users other than the mounting user to access the files
+    pub allow_other: bool,
+    /// Let the kernel check the permissions by the file modes
+    pub default_permissions: bool,
+    /// Mount read-only
+    pub read_only: bool,
+    /// The source name shown in the mount table, `/dev/fuse` if not set
+    pub fsname: Option<String>,
+    /// The filesystem type shown as `fuse.<subtype>` in the mount table
+    pub subtype: Option<String>,
+    /// The max size of read requests
+    pub max_read: Option<u32>,
+    /// The block size of the filesystem
+    pub blksize: Option<u32>,
+    /// Do not update the access times of the files
+    pub noatime: bool,
+    /// Unmount once the daemon quits, even if it is killed, which needs fusermount
+    pub auto_unmount: bool,
+    /// The TTLs of the kernel caches of the subtrees, indexed by the directories
+    /// relative to the root, the empty path for the whole mount
+    pub timeouts: BTreeMap<PathBuf, CacheTimeouts>,
+    /// The rules choosing the flags of the opened files
+    pub open_policy: OpenPolicy,
+}
+
+impl MountOptions {
+    /// Apply the comma separated options, the later ones override the former ones
+    pub fn parse(&mut self, options: &str) -> anyhow::Result<()> {
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            self.parse_option(option)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the options in the config file
+    pub fn parse_file(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .context(format!("failed to read the mount options file={:?}", path))?;
+        for (idx, line) in content.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => line,
+            };
+            self.parse(line).context(format!(
+                "failed to parse line {} of the mount options file={:?}",
+                idx + 1,
+                path,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn parse_option(&mut self, option: &str) -> anyhow::Result<()> {
+        let (key, value) = match option.find('=') {
+            Some(pos) => (&option[..pos], Some(&option[pos + 1..])),
+            None => (option, None),
+        };
+        // the cache options may apply to a subtree
+        if let Some(pos) = key.find('@') {
+            let dir = parse_subtree(option, &key[pos + 1..])?;
+            return self.parse_cache_option(option, &key[..pos], value, dir);
+        }
+        match (key, value) {
+            ("allow_other", None) => self.allow_other = true,
+            ("default_permissions", None) => self.default_permissions = true,
+            ("ro", None) => self.read_only = true,
+            ("rw", None) => self.read_only = false,
+            ("noatime", None) => self.noatime = true,
+            ("atime", None) => self.noatime = false,
+            ("auto_unmount", None) => self.auto_unmount = true,
+            ("fsname", Some(name)) => self.fsname = Some(parse_name(key, name)?),
+            ("subtype", Some(name)) => self.subtype = Some(parse_name(key, name)?),
+            ("max_read", Some(size)) => self.max_read = Some(parse_size(key, size)?),
+            ("blksize", Some(size)) => self.blksize = Some(parse_size(key, size)?),
+            ("direct_io", Some(glob)) => self.open_policy.direct_io.push(parse_glob(key, glob)?),
+            ("keep_cache", Some(glob)) => self.open_policy.keep_cache.push(parse_glob(key, glob)?),
+            ("nonseekable", Some(glob)) => {
+                self.open_policy.nonseekable.push(parse_glob(key, glob)?)
+            }
+            ("direct_io_size", Some(size)) => {
+                self.open_policy.direct_io_size = Some(parse_size(key, size)?)
+            }
+            _ => return self.parse_cache_option(option, key, value, PathBuf::new()),
+        }
+        Ok(())
+    }
+
+    /// Apply the option of the kernel cache TTLs to the subtree of `dir`
+    fn parse_cache_option(
+        &mut self,
+        option: &str,
+        key: &str,
+        value: Option<&str>,
+        dir: PathBuf,
+    ) -> anyhow::Result<()> {
+        let zero = Some(Duration::default());
+        let update = match (key, value) {
+            ("attr_timeout", Some(secs)) => CacheTimeouts {
+                attr: Some(parse_timeout(key, secs)?),
+                ..CacheTimeouts::default()
+            },
+            ("entry_timeout", Some(secs)) => CacheTimeouts {
+                entry: Some(parse_timeout(key, secs)?),
+                ..CacheTimeouts::default()
+            },
+            ("negative_timeout", Some(secs)) => CacheTimeouts {
+                negative: Some(parse_timeout(key, secs)?),
+                ..CacheTimeouts::default()
+            },
+            // cache nothing, the backing files are changed by the others
+            ("strict_coherence", None) => CacheTimeouts {
+                attr: zero,
+                entry: zero,
+                negative: zero,
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "invalid mount option={:?}, the valid options are: {}",
+                    option,
+                    VALID_OPTIONS,
+                ))
+            }
+        };
+        let timeouts = self.timeouts.entry(dir).or_default();
+        *timeouts = update.or(*timeouts);
+        Ok(())
+    }
+
+    /// The options passed to fusermount, besides the default `nosuid,nodev`
+    pub(crate) fn fusermount_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        options.push(if self.read_only { "ro" } else { "rw" }.to_owned());
+        if self.allow_other {
+            // only allowed if user_allow_other is set in /etc/fuse.conf
+            options.push("allow_other".to_owned());
+        }
+        if self.default_permissions {
+            options.push("default_permissions".to_owned());
+        }
+        if let Some(ref fsname) = self.fsname {
+            options.push(format!("fsname={}", fsname));
+        }
+        if let Some(ref subtype) = self.subtype {
+            options.push(format!("subtype={}", subtype));
+        }
+        if let Some(max_read) = self.max_read {
+            options.push(format!("max_read={}", max_read));
+        }
+        if self.noatime {
+            options.push("noatime".to_owned());
+        }
+        if self.auto_unmount {
+            options.push("auto_unmount".to_owned());
+        }
+        options
+    }
+
+    /// The options passed to the kernel by direct mount, besides the FUSE device fd,
+    /// the root mode and the owner
+    pub(crate) fn kernel_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if self.allow_other {
+            options.push("allow_other".to_owned());
+        }
+        if self.default_permissions {
+            options.push("default_permissions".to_owned());
+        }
+        if let Some(max_read) = self.max_read {
+            options.push(format!("max_read={}", max_read));
+        }
+        options
+    }
+}
+
+impl FromStr for MountOptions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut options = MountOptions::default();
+        options.parse(s)?;
+        Ok(options)
+    }
+}
+
+/// The name in the mount table, which cannot break the option list
+fn parse_name(key: &str, name: &str) -> anyhow::Result<String> {
+    if name.is_empty() || name.contains(|c: char| c == ',' || c.is_whitespace()) {
+        return Err(anyhow::anyhow!(
+            "invalid mount option {}={:?}, the name should be non-empty without commas or spaces",
+            key,
+            name,
+        ));
+    }
+    Ok(name.to_owned())
+}
+
+/// The directory of a subtree relative to the root
+fn parse_subtree(option: &str, dir: &str) -> anyhow::Result<PathBuf> {
+    let path = Path::new(dir.trim_matches('/'));
+    if dir.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(anyhow::anyhow!(
+            "invalid mount option={:?}, the directory should be relative to the root \
+                without `.` or `..`",
+            option,
+        ));
+    }
+    Ok(path.to_owned())
+}
+
+/// The non-negative TTL in seconds, may be fractional
+fn parse_timeout(key: &str, secs: &str) -> anyhow::Result<Duration> {
+    match secs.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(anyhow::anyhow!(
+            "invalid mount option {}={:?}, the timeout should be non-negative seconds",
+            key,
+            secs,
+        )),
+    }
+}
+
+/// The glob of the paths relative to the root
+fn parse_glob(key: &str, glob: &str) -> anyhow::Result<String> {
+    let glob = glob.trim_start_matches('/');
+    if glob.is_empty() {
+        return Err(anyhow::anyhow!(
+            "invalid mount option {}={:?}, the glob should be non-empty",
+            key,
+            glob,
+        ));
+    }
+    Ok(glob.to_owned())
+}
+
+/// Whether the path relative to the root matches the glob,
+/// the glob without `/` matches the file name
+fn glob_match_path(glob: &str, path: &Path) -> bool {
+    if glob.contains('/') {
+        glob_match(glob.as_bytes(), path.as_os_str().as_bytes())
+    } else {
+        path.file_name()
+            .map_or(false, |name| glob_match(glob.as_bytes(), name.as_bytes()))
+    }
+}
+
+/// Whether the text matches the glob, where `?` matches a character but `/`,
+/// `*` matches any characters but `/`, and `**` matches any characters,
+/// e.g. `data/**/*.csv` matches `data/a.csv` and `data/x/y/b.csv`
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+            let rest = &rest[1..];
+            // `**/` also matches no directory
+            (rest.first() == Some(&b'/') && glob_match(&rest[1..], text))
+                || (0..=text.len()).any(|idx| glob_match(rest, &text[idx..]))
+        }
+        Some((b'*', rest)) => {
+            let end = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=end).any(|idx| glob_match(rest, &text[idx..]))
+        }
+        Some((b'?', rest)) => match text.split_first() {
+            Some((&c, text)) => c != b'/' && glob_match(rest, text),
+            None => false,
+        },
+        Some((c, rest)) => match text.split_first() {
+            Some((t, text)) => c == t && glob_match(rest, text),
+            None => false,
+        },
+    }
+}
+
+/// The positive size in bytes
+fn parse_size<T: FromStr + Default + PartialOrd>(key: &str, size: &str) -> anyhow::Result<T> {
+    match size.parse::<T>() {
+        Ok(size) if size > T::default() => Ok(size),
+        _ => Err(anyhow::anyhow!(
+            "invalid mount option {}={:?}, the size should be a positive integer",
+            key,
+            size,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::super::protocol::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
+    use super::{glob_match, CacheTimeouts, MountOptions};
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let options: MountOptions =
+            "allow_other,ro,fsname=data,subtype=async,max_read=131072,noatime".parse()?;
+        assert!(options.allow_other);
+        assert!(options.read_only);
+        assert!(options.noatime);
+        assert!(!options.default_permissions);
+        assert_eq!(options.fsname.as_deref(), Some("data"));
+        assert_eq!(options.subtype.as_deref(), Some("async"));
+        assert_eq!(options.max_read, Some(131_072));
+        assert_eq!(options.blksize, None);
+        assert_eq!(
+            options.fusermount_options().join(","),
+            "ro,allow_other,fsname=data,subtype=async,max_read=131072,noatime",
+        );
+        assert_eq!(
+            options.kernel_options().join(","),
+            "allow_other,max_read=131072",
+        );
+
+        // the later options override the former ones
+        let options: MountOptions = "ro,rw".parse()?;
+        assert!(!options.read_only);
+
+        assert!("nonexistent".parse::<MountOptions>().is_err());
+        assert!("ro=1".parse::<MountOptions>().is_err());
+        assert!("max_read=0".parse::<MountOptions>().is_err());
+        assert!("fsname=".parse::<MountOptions>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timeouts() -> anyhow::Result<()> {
+        let mut options: MountOptions = "attr_timeout=1.5,negative_timeout=10".parse()?;
+        options.parse("strict_coherence@/shared/,attr_timeout@shared/logs=0")?;
+        assert_eq!(
+            options.timeouts.get(Path::new("")),
+            Some(&CacheTimeouts {
+                attr: Some(Duration::from_millis(1500)),
+                entry: None,
+                negative: Some(Duration::from_secs(10)),
+            }),
+        );
+        let zero = Some(Duration::default());
+        assert_eq!(
+            options.timeouts.get(Path::new("shared")),
+            Some(&CacheTimeouts {
+                attr: zero,
+                entry: zero,
+                negative: zero,
+            }),
+        );
+        assert_eq!(
+            options.timeouts.get(Path::new("shared/logs")),
+            Some(&CacheTimeouts {
+                attr: zero,
+                ..CacheTimeouts::default()
+            }),
+        );
+        // the cache options are not passed to the kernel
+        assert!(options.kernel_options().is_empty());
+
+        // the later options override the former ones
+        let options: MountOptions = "strict_coherence,entry_timeout=5".parse()?;
+        assert_eq!(
+            options.timeouts.get(Path::new("")).and_then(|t| t.entry),
+            Some(Duration::from_secs(5)),
+        );
+
+        assert!("attr_timeout=-1".parse::<MountOptions>().is_err());
+        assert!("attr_timeout=inf".parse::<MountOptions>().is_err());
+        assert!("entry_timeout".parse::<MountOptions>().is_err());
+        assert!("strict_coherence=1".parse::<MountOptions>().is_err());
+        assert!("attr_timeout@=1".parse::<MountOptions>().is_err());
+        assert!("attr_timeout@../up=1".parse::<MountOptions>().is_err());
+        assert!("ro@shared".parse::<MountOptions>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.db", b"a.db"));
+        assert!(!glob_match(b"*.db", b"dir/a.db"));
+        assert!(glob_match(b"dir/?.db", b"dir/a.db"));
+        assert!(!glob_match(b"dir/?.db", b"dir/ab.db"));
+        assert!(glob_match(b"data/**/*.csv", b"data/a.csv"));
+        assert!(glob_match(b"data/**/*.csv", b"data/x/y/b.csv"));
+        assert!(!glob_match(b"data/**/*.csv", b"other/b.csv"));
+        assert!(glob_match(b"data/**", b"data/x/y"));
+    }
+
+    #[test]
+    fn test_open_policy() -> anyhow::Result<()> {
+        let options: MountOptions =
+            "direct_io=*.db,keep_cache=/datasets/**,direct_io_size=1048576".parse()?;
+        let policy = &options.open_policy;
+        assert_eq!(policy.flags(Path::new("app/state.db"), 0), FOPEN_DIRECT_IO);
+        assert_eq!(
+            policy.flags(Path::new("datasets/x/y.csv"), 0),
+            FOPEN_KEEP_CACHE
+        );
+        assert_eq!(
+            policy.flags(Path::new("datasets/huge.bin"), 1 << 20),
+            FOPEN_DIRECT_IO | FOPEN_KEEP_CACHE,
+        );
+        assert_eq!(policy.flags(Path::new("notes.txt"), 10), 0);
+
+        assert!("direct_io=".parse::<MountOptions>().is_err());
+        assert!("direct_io_size=0".parse::<MountOptions>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("async_fuse_test_mount_options");
+        fs::write(
+            &path,
+            "# the mount options\nallow_other, default_permissions\n\nblksize=4096 # comment\n",
+        )?;
+        let mut options = MountOptions::default();
+        let res = options.parse_file(&path);
+        fs::remove_file(&path)?;
+        res?;
+        // the command line options apply after the config file
+        options.parse("auto_unmount")?;
+        assert!(options.allow_other);
+        assert!(options.default_permissions);
+        assert!(options.auto_unmount);
+        assert_eq!(options.blksize, Some(4096));
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! FUSE passthrough support.
+//!
+//! Since Linux 6.9, a FUSE daemon can register an open file as the backing file
+//! of the FUSE connection, and reply FOPEN_PASSTHROUGH with the backing id to an
+//! OPEN request, then the kernel forwards the reads and writes of the opened file
+//! to the backing file directly, without sending them to the daemon.
+//! Registering backing files requires CAP_SYS_ADMIN.
+
+use log::debug;
+use nix::ioctl_write_ptr;
+use smol::blocking;
+use std::os::unix::io::RawFd;
+
+use super::protocol::FuseBackingMap;
+
+ioctl_write_ptr!(fuse_dev_ioc_backing_open, 229, 1, FuseBackingMap);
+ioctl_write_ptr!(fuse_dev_ioc_backing_close, 229, 2, u32);
+
+/// The stacking depth of the backing files, which are on local filesystems
+pub const MAX_STACK_DEPTH: u32 = 1;
+
+/// A backing file registered to the FUSE connection,
+/// shared by all the passthrough opens of a node
+#[derive(Debug)]
+pub(crate) struct BackingFile {
+    /// The FUSE device fd the backing file registered to
+    fuse_fd: RawFd,
+    /// The backing id returned by the kernel
+    backing_id: i32,
+    /// The number of the passthrough opens using this backing file
+    open_count: u64,
+}
+
+impl BackingFile {
+    /// Register the file of `fd` as a backing file of the FUSE connection
+    pub async fn open(fuse_fd: RawFd, fd: RawFd) -> nix::Result<BackingFile> {
+        let backing_map = FuseBackingMap {
+            fd,
+            flags: 0,
+            padding: 0,
+        };
+        let backing_id = blocking!(unsafe { fuse_dev_ioc_backing_open(fuse_fd, &backing_map) })?;
+        debug!(
+            "BackingFile::open() registered fd={} as backing id={}",
+            fd, backing_id,
+        );
+        Ok(BackingFile {
+            fuse_fd,
+            backing_id,
+            open_count: 0,
+        })
+    }
+
+    /// The backing file registered by the old daemon on live upgrade,
+    /// which stays registered to the FUSE connection handed over
+    pub fn restore(fuse_fd: RawFd, backing_id: i32, open_count: u64) -> BackingFile {
+        BackingFile {
+            fuse_fd,
+            backing_id,
+            open_count,
+        }
+    }
+
+    /// Unregister the backing file from the FUSE connection
+    pub async fn close(self) -> nix::Result<()> {
+        let fuse_fd = self.fuse_fd;
+        let backing_id = self.backing_id as u32;
+        blocking!(unsafe { fuse_dev_ioc_backing_close(fuse_fd, &backing_id) })?;
+        debug!(
+            "BackingFile::close() unregistered backing id={}",
+            backing_id
+        );
+        Ok(())
+    }
+
+    pub fn backing_id(&self) -> i32 {
+        self.backing_id
+    }
+
+    pub fn open_count(&self) -> u64 {
+        self.open_count
+    }
+
+    pub fn inc_open_count(&mut self) -> u64 {
+        self.open_count += 1;
+        self.open_count
+    }
+
+    pub fn dec_open_count(&mut self) -> u64 {
+        debug_assert!(self.open_count > 0);
+        self.open_count -= 1;
+        self.open_count
+    }
+}
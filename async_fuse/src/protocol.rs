@@ -61,7 +61,7 @@ pub const FUSE_ROOT_ID: u64 = 1;
 pub type INum = u64;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct FuseAttr {
     // fuse_attr
     pub ino: INum,
@@ -174,6 +174,9 @@ pub mod fopen_flags {
     #[cfg(feature = "abi-7-31")]
     pub const FOPEN_STREAM: u32 = 1 << 4;
 
+    /// pass through reads and writes to the registered backing file
+    pub const FOPEN_PASSTHROUGH: u32 = 1 << 7;
+
     /// TODO: write documentation for FOPEN_PURGE_ATTR
     #[cfg(target_os = "macos")]
     pub const FOPEN_PURGE_ATTR: u32 = 1 << 30;
@@ -241,6 +244,11 @@ pub mod init_flags {
     //!
     //! FUSE_EXPLICIT_INVAL_DATA: only invalidate cached pages on explicit request
     //!
+    //! FUSE_MAP_ALIGNMENT: init_out.map_alignment contains log2(byte alignment) for
+    //! foffset and moffset fields in struct fuse_setupmapping_out and fuse_removemapping_one
+    //!
+    //! FUSE_INIT_EXT: extended fuse_init_in request, flags2 is valid
+    //!
 
     pub const FUSE_ASYNC_READ: u32 = 1;
 
@@ -296,6 +304,13 @@ pub mod init_flags {
     pub const FUSE_NO_OPENDIR_SUPPORT: u32 = 1 << 24;
     #[cfg(feature = "abi-7-30")]
     pub const FUSE_EXPLICIT_INVAL_DATA: u32 = 1 << 25;
+    #[cfg(feature = "abi-7-31")]
+    pub const FUSE_MAP_ALIGNMENT: u32 = 1 << 26;
+
+    // FUSE_INIT_EXT is probed at runtime, so it is not gated by the ABI features,
+    // the same bit is FUSE_VOL_RENAME on macOS
+    #[cfg(target_os = "linux")]
+    pub const FUSE_INIT_EXT: u32 = 1 << 30;
 
     #[cfg(target_os = "macos")]
     pub const FUSE_ALLOCATE: u32 = 1 << 27;
@@ -311,6 +326,23 @@ pub mod init_flags {
 
 pub use init_flags::*;
 
+#[allow(dead_code)]
+#[cfg(target_os = "linux")]
+pub mod init_flags2 {
+    //! INIT request/reply flags2, which are the high 32 bits of the 64-bit init flags,
+    //! only valid when FUSE_INIT_EXT is set
+    //!
+    //! FUSE_PASSTHROUGH: passthrough read/write I/O to registered backing files
+    //! FUSE_OVER_IO_URING: indicate that client supports io-uring
+    //!
+
+    pub const FUSE_PASSTHROUGH: u32 = 1 << (37 - 32);
+    pub const FUSE_OVER_IO_URING: u32 = 1 << (41 - 32);
+}
+
+#[cfg(target_os = "linux")]
+pub use init_flags2::*;
+
 // CUSE INIT request/reply flags
 //
 // CUSE_UNRESTRICTED_IOCTL:  use unrestricted ioctl
@@ -446,6 +478,10 @@ pub enum FuseOpCode {
     FUSE_LSEEK = 46,
     #[cfg(feature = "abi-7-28")]
     FUSE_COPY_FILE_RANGE = 47,
+    #[cfg(feature = "abi-7-31")]
+    FUSE_SETUPMAPPING = 48,
+    #[cfg(feature = "abi-7-31")]
+    FUSE_REMOVEMAPPING = 49,
 
     #[cfg(target_os = "macos")]
     FUSE_SETVOLNAME = 61,
@@ -489,13 +525,13 @@ pub mod fuse_compat_configs {
     #[cfg(all(target_os = "macos", feature = "abi-7-9"))]
     pub const FUSE_COMPAT_ENTRY_OUT_SIZE: usize = 136;
 
-    #[cfg(feature = "abi-7-9")]
+    #[cfg(all(not(target_os = "macos"), feature = "abi-7-9"))]
     pub const FUSE_COMPAT_ENTRY_OUT_SIZE: usize = 120;
 
     #[cfg(all(target_os = "macos", feature = "abi-7-9"))]
     pub const FUSE_COMPAT_ATTR_OUT_SIZE: usize = 112;
 
-    #[cfg(feature = "abi-7-9")]
+    #[cfg(all(not(target_os = "macos"), feature = "abi-7-9"))]
     pub const FUSE_COMPAT_ATTR_OUT_SIZE: usize = 96;
 
     #[cfg(feature = "abi-7-12")]
@@ -504,10 +540,8 @@ pub mod fuse_compat_configs {
     #[cfg(feature = "abi-7-9")]
     pub const FUSE_COMPAT_WRITE_IN_SIZE: usize = 24;
 
-    #[cfg(feature = "abi-7-23")]
     pub const FUSE_COMPAT_INIT_OUT_SIZE: usize = 8;
 
-    #[cfg(feature = "abi-7-23")]
     pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24;
 }
 
@@ -616,7 +650,7 @@ pub struct FuseRenameIn {
 #[cfg(feature = "abi-7-23")]
 #[repr(C)]
 #[derive(Debug)]
-struct FuseRename2In {
+pub struct FuseRename2In {
     // fuse_rename2_in
     pub newdir: u64,
     pub flags: u32,
@@ -711,7 +745,7 @@ pub struct FuseOpenOut {
     // fuse_open_out
     pub fh: u64,
     pub open_flags: u32,
-    pub padding: u32,
+    pub backing_id: i32, // padding before ABI 7.40, only valid with FOPEN_PASSTHROUGH
 }
 
 #[repr(C)]
@@ -860,6 +894,17 @@ pub struct FuseInitIn {
     pub flags: u32,
 }
 
+/// The extended part of fuse_init_in, which follows `FuseInitIn`
+/// when the kernel sets FUSE_INIT_EXT in the init flags
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseInitInExt {
+    // fuse_init_in since ABI 7.36
+    pub flags2: u32,
+    pub unused: [u32; 11],
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct FuseInitOut {
@@ -889,6 +934,28 @@ pub struct FuseInitOut {
     pub unused: [u32; 8],
 }
 
+/// The full fuse_init_out of ABI 7.40, which is used to reply flags2
+/// to the kernels supporting FUSE_INIT_EXT, no matter which ABI version compiled
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseInitOutExt {
+    // fuse_init_out since ABI 7.40
+    pub major: u32,
+    pub minor: u32,
+    pub max_readahead: u32,
+    pub flags: u32,
+    pub max_background: u16,
+    pub congestion_threshold: u16,
+    pub max_write: u32,
+    pub time_gran: u32,
+    pub max_pages: u16,
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub max_stack_depth: u32,
+    pub unused: [u32; 6],
+}
+
 #[cfg(feature = "abi-7-11")]
 pub const CUSE_INIT_INFO_MAX: u32 = 4096;
 
@@ -983,7 +1050,7 @@ pub struct FusePollIn {
     pub fh: u64,
     pub kh: u64,
     pub flags: u32,
-    #[cfg(feature = "abi-7-11")]
+    #[cfg(not(feature = "abi-7-21"))]
     pub padding: u32,
     #[cfg(feature = "abi-7-21")]
     pub events: u32,
@@ -1148,11 +1215,71 @@ pub struct FuseNotifyRetrieveIn {
 // TODO: re-define it
 // Device ioctls:
 // #define FUSE_DEV_IOC_CLONE    _IOR(229, 0, uint32_t)
+// #define FUSE_DEV_IOC_BACKING_OPEN    _IOW(229, 1, struct fuse_backing_map)
+// #define FUSE_DEV_IOC_BACKING_CLOSE    _IOW(229, 2, uint32_t)
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseBackingMap {
+    // fuse_backing_map
+    pub fd: i32,
+    pub flags: u32,
+    pub padding: u64,
+}
+
+// FUSE-over-io_uring commands, the uring_cmd_op of IORING_OP_URING_CMD
+//
+// FUSE_IO_URING_CMD_REGISTER: register the ring entry to the queue of qid
+// FUSE_IO_URING_CMD_COMMIT_AND_FETCH: commit the reply and fetch the next request
+#[cfg(target_os = "linux")]
+pub const FUSE_IO_URING_CMD_REGISTER: u32 = 1;
+#[cfg(target_os = "linux")]
+pub const FUSE_IO_URING_CMD_COMMIT_AND_FETCH: u32 = 2;
+
+/// The size of the in/out header area of a ring entry
+#[cfg(target_os = "linux")]
+pub const FUSE_URING_IN_OUT_HEADER_SZ: usize = 128;
+/// The size of the op specific in/out area of a ring entry
+#[cfg(target_os = "linux")]
+pub const FUSE_URING_OP_IN_OUT_SZ: usize = 128;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseUringEntInOut {
+    // fuse_uring_ent_in_out
+    pub flags: u64,
+    pub commit_id: u64,  // To be set by the kernel, returned on commit
+    pub payload_sz: u32, // Size of the request or reply data in the payload buffer
+    pub padding: u32,
+    pub reserved: u64,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseUringReqHeader {
+    // fuse_uring_req_header
+    pub in_out: [u8; FUSE_URING_IN_OUT_HEADER_SZ], // fuse_in_header or fuse_out_header
+    pub op_in: [u8; FUSE_URING_OP_IN_OUT_SZ],      // The first argument of the request
+    pub ring_ent_in_out: FuseUringEntInOut,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseUringCmdReq {
+    // fuse_uring_cmd_req, the command data of IORING_OP_URING_CMD
+    pub flags: u64,
+    pub commit_id: u64, // Entry identifier for commits
+    pub qid: u16,       // Queue the command is for
+    pub padding: [u8; 6],
+}
 
 #[cfg(feature = "abi-7-24")]
 #[repr(C)]
 #[derive(Debug)]
-struct FuseLSeekIn {
+pub struct FuseLSeekIn {
     // fuse_lseek_in
     pub fh: u64,
     pub offset: u64,
@@ -1163,7 +1290,7 @@ struct FuseLSeekIn {
 #[cfg(feature = "abi-7-24")]
 #[repr(C)]
 #[derive(Debug)]
-struct FuseLSeekOut {
+pub struct FuseLSeekOut {
     // fuse_lseek_out
     pub offset: u64,
 }
@@ -1171,7 +1298,7 @@ struct FuseLSeekOut {
 #[cfg(feature = "abi-7-28")]
 #[repr(C)]
 #[derive(Debug)]
-struct FuseCopyFileRangeIn {
+pub struct FuseCopyFileRangeIn {
     // fuse_copy_file_range_in
     pub fh_in: u64,
     pub off_in: u64,
@@ -1182,6 +1309,44 @@ struct FuseCopyFileRangeIn {
     pub flags: u64,
 }
 
+// Flags of fuse_setupmapping_in
+//
+// FUSE_SETUPMAPPING_FLAG_WRITE: the mapping is writable
+// FUSE_SETUPMAPPING_FLAG_READ: the mapping is readable
+#[cfg(feature = "abi-7-31")]
+pub const FUSE_SETUPMAPPING_FLAG_WRITE: u64 = 1 << 0;
+#[cfg(feature = "abi-7-31")]
+pub const FUSE_SETUPMAPPING_FLAG_READ: u64 = 1 << 1;
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseSetupMappingIn {
+    // fuse_setupmapping_in
+    pub fh: u64,      // An already open handle
+    pub foffset: u64, // Offset into the file to start the mapping
+    pub len: u64,     // Length of mapping required
+    pub flags: u64,   // Flags, FUSE_SETUPMAPPING_FLAG_*
+    pub moffset: u64, // Offset in Memory Window
+}
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseRemoveMappingIn {
+    // fuse_removemapping_in
+    pub count: u32, // number of fuse_removemapping_one follows
+}
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct FuseRemoveMappingOne {
+    // fuse_removemapping_one
+    pub moffset: u64, // Offset into the dax window start the unmapping
+    pub len: u64,     // Length of mapping required
+}
+
 /// FUSE ABI types.
 /// It is safe to transmute a `&[u8]` to `&T` when `T: FuseAbiData`.
 pub(crate) unsafe trait FuseAbiData: Sized {}
@@ -1233,6 +1398,7 @@ unsafe_impl_fuse_abi_data_for! {
     FuseInHeader,
     FuseOutHeader,
     FuseDirEnt,
+    FuseBackingMap,
 }
 
 #[cfg(feature = "abi-7-9")]
@@ -1304,6 +1470,22 @@ unsafe_impl_fuse_abi_data_for! {
     FuseCopyFileRangeIn,
 }
 
+#[cfg(feature = "abi-7-31")]
+unsafe_impl_fuse_abi_data_for! {
+    FuseSetupMappingIn,
+    FuseRemoveMappingIn,
+    FuseRemoveMappingOne,
+}
+
+#[cfg(target_os = "linux")]
+unsafe_impl_fuse_abi_data_for! {
+    FuseInitInExt,
+    FuseInitOutExt,
+    FuseUringEntInOut,
+    FuseUringReqHeader,
+    FuseUringCmdReq,
+}
+
 #[cfg(target_os = "macos")]
 unsafe_impl_fuse_abi_data_for! {
     FuseGetXTimesOut,
@@ -1,35 +1,43 @@
 use anyhow::{self, Context};
 use futures::lock::Mutex;
+use lazy_static::lazy_static;
+use libc::c_int;
 use log::{debug, error, info, warn};
 use nix::errno::Errno;
+use nix::poll::{self, PollFd, PollFlags};
 use nix::unistd;
-use smol::{self, blocking, Task};
-use std::os::unix::io::RawFd;
+use smol::{self, blocking, Task, Timer};
+use std::fs;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicBool, AtomicU32, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     Arc,
 };
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use super::aligned_bytes::AlignedBytes;
 use super::channel::Channel;
-use super::fs::*;
+use super::dir_lock::DirLocks;
+use super::filesystem::Filesystem;
+use super::fs::{FsError, FsResult};
+use super::fuse_conn::ConnInfo;
+#[cfg(feature = "abi-7-15")]
+use super::fuse_notify;
+#[cfg(feature = "abi-7-12")]
+use super::fuse_notify::Notifier;
 use super::fuse_reply::*;
 use super::fuse_request::*;
+#[cfg(target_os = "linux")]
+use super::fuse_uring::FuseUring;
 use super::mount;
+use super::mount_options::MountOptions;
+#[cfg(target_os = "linux")]
+use super::passthrough;
 use super::protocol::*;
-
-/// We generally support async reads
-#[cfg(not(target_os = "macos"))]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ;
-// TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
-
-/// On macOS, we additionally support case insensitiveness, volume renames and xtimes
-/// TODO: we should eventually let the filesystem implementation decide which flags to set
-#[cfg(target_os = "macos")]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAME | FUSE_XTIMES;
-// TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
+use super::signal::SignalReceiver;
+use super::upgrade::{self, Handoff};
 
 /// The max size of write requests from the kernel. The absolute minimum is 4k,
 /// FUSE recommends at least 128k, max 16M. The FUSE default is 16M on macOS
@@ -48,13 +56,41 @@ const PAGE_SIZE: usize = 4096;
 
 const MAX_BACKGROUND: u16 = 10;
 
+/// The kernel marks the connection congested when the pending background requests
+/// reach this threshold, 3/4 of MAX_BACKGROUND as libfuse does
+const CONGESTION_THRESHOLD: u16 = MAX_BACKGROUND * 3 / 4;
+
 /// Static variable to indicate whether FUSE is initialized or not
 static FUSE_INITIALIZED: AtomicBool = AtomicBool::new(false);
 /// Static variable to indicate whether FUSE is destroyed or not
 static FUSE_DESTROYED: AtomicBool = AtomicBool::new(false);
+/// Static variable to indicate whether the session is shutting down on a signal,
+/// which unmounts FUSE itself
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// Static variable to indicate whether FUSE is handed over to a new daemon,
+/// which keeps serving the mount
+static HANDED_OVER: AtomicBool = AtomicBool::new(false);
+/// The number of requests being processed
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// How long the shutdown waits for the requests in flight before unmount
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the shutdown checks the requests in flight
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+lazy_static! {
+    /// The locks of the directories of the operations being processed
+    static ref DIR_LOCKS: DirLocks = DirLocks::default();
+}
 
+/// The number of malformed requests received from the kernel
+static MALFORMED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+/// The number of requests failed to process
+static FAILED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// The FUSE session serving the requests by the filesystem
 #[derive(Debug)]
-pub(crate) struct Session {
+pub struct Session<FS: Filesystem> {
     mountpoint: PathBuf,
     fuse_fd: RawFd,
     /// FUSE protocol major version
@@ -62,13 +98,31 @@ pub(crate) struct Session {
     /// FUSE protocol minor version
     proto_minor: AtomicU32,
     /// The underlying FUSE file system
-    filesystem: Arc<Mutex<FileSystem>>,
+    filesystem: Arc<Mutex<FS>>,
+    /// Whether to keep running after malformed requests or failed operations
+    hardened: bool,
+    /// Whether to reply EROFS to the operations changing the filesystem
+    read_only: bool,
+    /// The Unix socket to listen on for a new daemon to take over on live upgrade
+    upgrade_socket: Option<PathBuf>,
+    /// The features negotiated by the old daemon, if taken over from it
+    taken_over: Option<ConnInfo>,
 }
 
-impl Drop for Session {
+impl<FS: Filesystem> Drop for Session<FS> {
     fn drop(&mut self) {
-        if !FUSE_DESTROYED.load(Ordering::Acquire) {
-            let res = smol::block_on(async { mount::umount(&self.mountpoint).await });
+        let (malformed, failed) = self.error_counts();
+        if malformed > 0 || failed > 0 {
+            warn!(
+                "session ended with {} malformed requests and {} failed requests",
+                malformed, failed,
+            );
+        }
+        if !FUSE_DESTROYED.load(Ordering::Acquire)
+            && !SHUTTING_DOWN.load(Ordering::Acquire)
+            && !HANDED_OVER.load(Ordering::Acquire)
+        {
+            let res = smol::block_on(async { mount::umount(&self.mountpoint, true).await });
             match res {
                 Ok(..) => info!("successfully umount {:?}", self.mountpoint),
                 Err(e) => error!(
@@ -80,23 +134,61 @@ impl Drop for Session {
     }
 }
 
-impl Session {
+impl<FS: Filesystem> Session<FS> {
     #[allow(dead_code)]
     pub fn fd(&self) -> RawFd {
         self.fuse_fd
     }
 
-    pub async fn new(mountpoint: impl AsRef<Path>) -> anyhow::Result<Session> {
-        if !mountpoint.as_ref().is_dir() {
-            panic!("the input mount path is not a directory");
-        }
+    /// Keep running after malformed requests or failed operations, which are
+    /// replied errors to the kernel, logged and counted, rather than fail fast
+    pub fn set_hardened(&mut self, hardened: bool) {
+        self.hardened = hardened;
+    }
+
+    /// Listen on the Unix socket of `path` for a new daemon to take over by
+    /// `Handoff::receive()`, then this session quits without unmount
+    pub fn set_upgrade_socket(&mut self, path: impl AsRef<Path>) {
+        self.upgrade_socket = Some(path.as_ref().to_path_buf());
+    }
+
+    /// The numbers of the malformed requests and the failed requests so far
+    pub fn error_counts(&self) -> (u64, u64) {
+        (
+            MALFORMED_REQUESTS.load(Ordering::Relaxed),
+            FAILED_REQUESTS.load(Ordering::Relaxed),
+        )
+    }
+
+    /// The notifier to invalidate or update the kernel caches of this session
+    #[cfg(feature = "abi-7-12")]
+    #[allow(dead_code)]
+    pub fn notifier(&self) -> Notifier {
+        Notifier::new(self.fuse_fd)
+    }
+
+    /// Mount FUSE at `mountpoint` to serve the requests by `filesystem`.
+    /// If the filesystem serves the files under the mountpoint itself,
+    /// it must be created before mount, since the mountpoint is covered by FUSE once mounted
+    pub async fn new(
+        mountpoint: impl AsRef<Path>,
+        filesystem: FS,
+        options: &MountOptions,
+    ) -> anyhow::Result<Session<FS>> {
         let mountpoint = mountpoint.as_ref().to_path_buf();
+        // Replace the mount left by a crashed daemon, which is not accessible any more
+        mount::umount_stale(&mountpoint).await?;
+        if !mountpoint.is_dir() {
+            return Err(anyhow::anyhow!(
+                "the mount path={:?} is not a directory",
+                mountpoint,
+            ));
+        }
         let full_mountpoint = mountpoint
             .canonicalize()
             .with_context(|| format!("failed to find the mount path={:?}", mountpoint))?;
-        let filesystem = FileSystem::new(&full_mountpoint).await?;
-        // Must create filesystem before mount
-        let fuse_fd = mount::mount(&full_mountpoint)
+        debug!("mount {:?} with options={:?}", full_mountpoint, options);
+        let fuse_fd = mount::mount(&full_mountpoint, options)
             .await
             .context("failed to mount fuse device")?;
         Ok(Session {
@@ -105,9 +197,60 @@ impl Session {
             proto_major: AtomicU32::new(7),
             proto_minor: AtomicU32::new(8),
             filesystem: Arc::new(Mutex::new(filesystem)),
+            hardened: false,
+            read_only: options.read_only,
+            upgrade_socket: None,
+            taken_over: None,
+        })
+    }
+
+    /// Take over the mount at `mountpoint` from the old daemon by `handoff`,
+    /// the filesystem restores the state of the old daemon and serves the same mount
+    /// without INIT. The old daemon keeps serving if failed to take over
+    pub async fn take_over(
+        mountpoint: impl AsRef<Path>,
+        mut filesystem: FS,
+        options: &MountOptions,
+        mut handoff: Handoff,
+    ) -> anyhow::Result<Session<FS>> {
+        let mountpoint = mountpoint.as_ref().to_path_buf();
+        let fuse_fd = handoff.fuse_fd;
+        let conn = handoff.conn;
+        debug!(
+            "take over {:?} of FUSE device fd={} with conn={:?}",
+            mountpoint, fuse_fd, conn,
+        );
+        filesystem.negotiated(&conn, fuse_fd);
+        let state = std::mem::take(&mut handoff.state);
+        filesystem
+            .restore_state(state)
+            .await
+            .context("failed to restore the filesystem state of the old daemon")?;
+        let proto_major = handoff.proto_major;
+        let proto_minor = handoff.proto_minor;
+        // The session umounts once dropped, so build it after the old daemon quits
+        handoff.ack().await?;
+        FUSE_INITIALIZED.store(true, Ordering::Release);
+        info!("took over {:?} from the old daemon", mountpoint);
+        Ok(Session {
+            mountpoint,
+            fuse_fd,
+            proto_major: AtomicU32::new(proto_major),
+            proto_minor: AtomicU32::new(proto_minor),
+            filesystem: Arc::new(Mutex::new(filesystem)),
+            hardened: false,
+            read_only: options.read_only,
+            upgrade_socket: None,
+            taken_over: Some(conn),
         })
     }
 
+    /// Serve the requests until FUSE is unmounted.
+    /// On SIGINT or SIGTERM, the session stops serving new requests, waits for the ones
+    /// in flight, flushes the filesystem and unmounts, then `run` returns.
+    /// If the clean shutdown fails or gets stuck, a second signal forces a lazy umount
+    /// and exits the process with the status of 128 plus the signal number.
+    /// If a new daemon takes over on live upgrade, `run` returns without unmount
     pub async fn run(&self) -> anyhow::Result<()> {
         let (pool_sender, pool_receiver) =
             crossbeam_channel::bounded::<(u16, AlignedBytes)>(MAX_BACKGROUND.into());
@@ -125,87 +268,159 @@ impl Session {
 
         let chan = Channel::new(self).await?;
         let fuse_fd = chan.fd();
-        let (idx, mut byte_vec) = pool_receiver.recv()?;
-        let read_result = blocking!(
-            let res = unistd::read(fuse_fd, &mut *byte_vec);
-            (res, byte_vec)
-        );
-        byte_vec = read_result.1;
-        if let Ok(read_size) = read_result.0 {
-            debug!("read successfully {} byte data from FUSE device", read_size);
-            if let Ok(req) = Request::new(&byte_vec) {
-                if let Operation::Init { arg } = req.operation() {
-                    let filesystem = self.filesystem.clone();
-                    self.init(arg, &req, filesystem, fuse_fd).await?;
+        #[cfg(target_os = "linux")]
+        let mut fuse_uring = None;
+        // The connection taken over from the old daemon is initialized already
+        let mut conn_info = self.taken_over;
+        if conn_info.is_none() {
+            let (idx, mut byte_vec) = pool_receiver.recv()?;
+            let read_result = blocking!(
+                let res = unistd::read(fuse_fd, &mut *byte_vec);
+                (res, byte_vec)
+            );
+            byte_vec = read_result.1;
+            if let Ok(read_size) = read_result.0 {
+                debug!("read successfully {} byte data from FUSE device", read_size);
+                if let Ok(req) = Request::new(&byte_vec) {
+                    #[cfg(target_os = "linux")]
+                    let init_args = match req.operation() {
+                        Operation::Init { arg, ext } => {
+                            let kernel_flags2 = ext.map_or(0, |ext| ext.flags2);
+                            let mut flags2 = kernel_flags2 & FUSE_PASSTHROUGH;
+                            // Probe io_uring support once the kernel offers FUSE-over-io_uring
+                            if kernel_flags2 & FUSE_OVER_IO_URING != 0 {
+                                match FuseUring::new(fuse_fd, BUFFER_SIZE, PAGE_SIZE) {
+                                    Ok(ring) => {
+                                        fuse_uring = Some(Arc::new(ring));
+                                        flags2 |= FUSE_OVER_IO_URING;
+                                    }
+                                    Err(e) => warn!(
+                                        "failed to set up FUSE io_uring, fall back to read FUSE device, \
+                                            the error is: {:?}",
+                                        e,
+                                    ),
+                                }
+                            }
+                            Some((arg, flags2))
+                        }
+                        _ => None,
+                    };
+                    #[cfg(not(target_os = "linux"))]
+                    let init_args = match req.operation() {
+                        Operation::Init { arg } => Some((arg, 0)),
+                        _ => None,
+                    };
+                    if let Some((arg, flags2)) = init_args {
+                        let filesystem = self.filesystem.clone();
+                        let conn = self.init(arg, flags2, &req, filesystem, fuse_fd).await?;
+                        conn_info = Some(conn);
+                        // The filesystem may refuse FUSE-over-io_uring
+                        #[cfg(target_os = "linux")]
+                        {
+                            if !conn.is_enabled2(FUSE_OVER_IO_URING) {
+                                fuse_uring = None;
+                            }
+                        }
+                    }
+                }
+            }
+            pool_sender.send((idx, byte_vec)).context(format!(
+                "failed to put buffer idx={} back to buffer pool after FUSE init",
+                idx,
+            ))?;
+        }
+        // The requests from FUSE io_uring cannot be paused for live upgrade
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut uring_registered = false;
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(fuse_uring) = fuse_uring {
+                // The requests before all the entries registered,
+                // FORGET and INTERRUPT still come from the FUSE device
+                match fuse_uring.register() {
+                    Ok(()) => {
+                        uring_registered = true;
+                        let fs = self.filesystem.clone();
+                        let sender = pool_sender.clone();
+                        let receiver = pool_receiver.clone();
+                        let hardened = self.hardened;
+                        let read_only = self.read_only;
+                        Task::spawn(run_uring(
+                            fuse_uring, fuse_fd, fs, sender, receiver, hardened, read_only,
+                        ))
+                        .detach();
+                    }
+                    Err(e) => warn!(
+                        "failed to register FUSE io_uring entries, \
+                            fall back to read FUSE device, the error is: {:?}",
+                        e,
+                    ),
                 }
             }
         }
-        pool_sender.send((idx, byte_vec)).context(format!(
-            "failed to put buffer idx={} back to buffer pool after FUSE init",
-            idx,
-        ))?;
         debug_assert!(FUSE_INITIALIZED.load(Ordering::Acquire));
+        FS::spawn_background(self.filesystem.clone());
+        match SignalReceiver::install() {
+            Ok(signals) => {
+                let mountpoint = self.mountpoint.clone();
+                let fs = self.filesystem.clone();
+                Task::spawn(handle_signals(signals, mountpoint, fs)).detach();
+            }
+            Err(e) => warn!(
+                "failed to install the signal handlers, \
+                    the session cannot shut down cleanly on signals, the error is: {:?}",
+                e,
+            ),
+        }
+        let listener = match self.upgrade_socket {
+            Some(ref path) => {
+                // The socket file of the old daemon is left for the new one to replace
+                if path.exists() {
+                    fs::remove_file(path)
+                        .context(format!("failed to remove the upgrade socket={:?}", path))?;
+                }
+                let listener = UnixListener::bind(path)
+                    .context(format!("failed to listen on the upgrade socket={:?}", path))?;
+                info!("listen on {:?} for live upgrade", path);
+                Some(listener)
+            }
+            None => None,
+        };
+        let upgrade_fd = listener.as_ref().map(|listener| listener.as_raw_fd());
 
         loop {
-            let (idx, mut byte_arr) = pool_receiver.recv()?;
+            // Wait for a free buffer off the executor, the requests holding the buffers
+            // may wait for the directory locks released by the other requests
+            let receiver = pool_receiver.clone();
+            let (idx, mut byte_arr) = blocking!(receiver.recv())?;
 
             let (res, byte_arr) = blocking!(
-                let res = unistd::read(fuse_fd, &mut *byte_arr);
+                let res = read_request(fuse_fd, upgrade_fd, &mut *byte_arr);
                 (res, byte_arr)
             );
 
             match res {
-                Ok(read_size) => {
+                Ok(None) => {
+                    pool_sender.send((idx, byte_arr)).context(format!(
+                        "failed to put buffer idx={} back to buffer pool before live upgrade",
+                        idx,
+                    ))?;
+                    if let Some(ref listener) = listener {
+                        if self.hand_over(listener, conn_info, uring_registered).await {
+                            break;
+                        }
+                    }
+                }
+                Ok(Some(read_size)) => {
                     debug!("read successfully {} byte data from FUSE device", read_size);
 
                     let fs = self.filesystem.clone();
                     let sender = pool_sender.clone();
+                    let hardened = self.hardened;
+                    let read_only = self.read_only;
                     Task::spawn(async move {
                         let bytes = &byte_arr[..read_size];
-                        let req = match Request::new(bytes) {
-                            // Dispatch request
-                            Ok(r) => r,
-                            // Quit on illegal request
-                            Err(e) => {
-                                // TODO: graceful handle request build failure
-                                panic!("failed to build FUSE request, the error is: {}", e);
-                            }
-                        };
-                        debug!("{}", req);
-                        let res = dispatch(&req, fuse_fd, fs).await;
-                        if let Err(e) = res {
-                            error!("failed to process request, the error is: {}", e);
-                            let unique = req.unique();
-                            let reply_error_to_fuse = ReplyEmpty::new(unique, fuse_fd);
-                            let error_num = match e.downcast_ref::<nix::Error>() {
-                                Some(nix_error) => match nix_error.as_errno() {
-                                    Some(nix_errno) => {
-                                        let std_io_error = std::io::Error::from(nix_errno);
-                                        match std_io_error.raw_os_error() {
-                                            Some(error_num) => error_num,
-                                            // TODO: consider more meaningful error code
-                                            None => libc::EINVAL,
-                                        }
-                                    }
-                                    None => libc::EINVAL,
-                                },
-                                _ => libc::EINVAL,
-                            };
-                            // TODO: there is a bug!
-                            // If the error from dispatch() is related to IO error with FUSE device,
-                            // then it'll fail to reply error to FUSE again.
-                            reply_error_to_fuse
-                                .error(error_num)
-                                .await
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "failed to send error reply for request, unique={}",
-                                        unique
-                                    )
-                                });
-                            // TODO: this panic is for fast fail, can be removed when stable
-                            panic!("failed to process request, the error is: {}", e);
-                        }
+                        process_request(bytes, fuse_fd, fs, hardened, read_only).await;
                         let res = sender.send((idx, byte_arr));
                         if let Err(e) = res {
                             panic!(
@@ -234,7 +449,9 @@ impl Session {
                         Some(Errno::EAGAIN) => info!("Explicitly retry"),
                         // Filesystem was unmounted, quit the loop
                         Some(Errno::ENODEV) => {
-                            if FUSE_DESTROYED.load(Ordering::Acquire) {
+                            if FUSE_DESTROYED.load(Ordering::Acquire)
+                                || SHUTTING_DOWN.load(Ordering::Acquire)
+                            {
                                 info!("FUSE unmounted, quit the run loop");
                             } else {
                                 error!("something wrong with FUSE device");
@@ -254,17 +471,94 @@ impl Session {
                 }
             }
         }
+        if let Some(ref path) = self.upgrade_socket {
+            if !HANDED_OVER.load(Ordering::Acquire) {
+                if let Err(e) = fs::remove_file(path) {
+                    warn!(
+                        "failed to remove the upgrade socket={:?}, the error is: {}",
+                        path, e,
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Hand FUSE over to the new daemon connecting to the upgrade socket,
+    /// return true if the new daemon takes over, otherwise keep serving
+    async fn hand_over(
+        &self,
+        listener: &UnixListener,
+        conn: Option<ConnInfo>,
+        uring_registered: bool,
+    ) -> bool {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(
+                    "failed to accept the connection of live upgrade, the error is: {}",
+                    e
+                );
+                return false;
+            }
+        };
+        info!("a new daemon connected to take over {:?}", self.mountpoint);
+        let res = async {
+            if uring_registered {
+                return Err(anyhow::anyhow!(
+                    "live upgrade is not supported with FUSE io_uring"
+                ));
+            }
+            let conn = conn.ok_or_else(|| anyhow::anyhow!("FUSE is not initialized"))?;
+            let in_flight = wait_in_flight().await;
+            if in_flight > 0 {
+                return Err(anyhow::anyhow!(
+                    "{} requests still in flight after {:?}",
+                    in_flight,
+                    DRAIN_TIMEOUT,
+                ));
+            }
+            // Hold the filesystem until the new daemon takes over,
+            // in case the background tasks change the state saved
+            let filesystem = self.filesystem.lock().await;
+            let state = filesystem.save_state()?;
+            upgrade::send_handoff(
+                stream,
+                self.fuse_fd,
+                self.proto_major.load(Ordering::Relaxed),
+                self.proto_minor.load(Ordering::Relaxed),
+                conn,
+                state,
+            )
+            .await
+        }
+        .await;
+        match res {
+            Ok(()) => {
+                HANDED_OVER.store(true, Ordering::Release);
+                info!("handed {:?} over to the new daemon", self.mountpoint);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "failed to hand {:?} over to the new daemon, keep serving, \
+                        the error is: {:?}",
+                    self.mountpoint, e,
+                );
+                false
+            }
+        }
+    }
+
     async fn init<'a>(
         &self,
         arg: &'a FuseInitIn,
+        flags2: u32,
         req: &'a Request<'a>,
-        fs: Arc<Mutex<FileSystem>>,
+        fs: Arc<Mutex<FS>>,
         fd: RawFd,
-    ) -> anyhow::Result<()> {
-        debug!("Init args={:?}", arg);
+    ) -> anyhow::Result<ConnInfo> {
+        debug!("Init args={:?}, flags2={:#x}", arg, flags2);
         // TODO: rewrite init based on do_init() in fuse_lowlevel.c
         // https://github.com/libfuse/libfuse/blob/master/lib/fuse_lowlevel.c#L1892
         let reply = ReplyInit::new(req.unique(), fd);
@@ -274,9 +568,23 @@ impl Session {
             reply.error(libc::EPROTO).await?;
             return Err(anyhow::anyhow!("FUSE ABI version too low"));
         }
-        // Call filesystem init method and give it a chance to return an error
+        debug_assert!(
+            arg.max_readahead <= MAX_WRITE_SIZE,
+            "the max readahead={} larger than max write size 16M={}",
+            arg.max_readahead,
+            MAX_WRITE_SIZE,
+        );
+        // Reply with the lower minor version of the kernel and ours,
+        // the reply layout and the following requests follow the selected version
+        let minor = arg.minor.min(FUSE_KERNEL_MINOR_VERSION);
+        let mut conn = ConnInfo::new(arg, flags2);
+        conn.max_write = MAX_WRITE_SIZE;
+        conn.max_background = MAX_BACKGROUND;
+        conn.congestion_threshold = CONGESTION_THRESHOLD;
+        // Call filesystem init method and give it a chance to
+        // choose the features or return an error
         let mut filesystem = fs.lock().await;
-        let res = filesystem.init(&req);
+        let res = filesystem.init(&req, &mut conn).await;
         if let Err(err) = res {
             reply.error(libc::ENOSYS).await?;
             return Err(anyhow::anyhow!(
@@ -284,24 +592,72 @@ impl Session {
                 err
             ));
         }
-        debug_assert!(
-            arg.max_readahead <= MAX_WRITE_SIZE,
-            "the max readahead={} larger than max write size 16M={}",
-            arg.max_readahead,
-            MAX_WRITE_SIZE,
-        );
-        let flags = arg.flags & INIT_FLAGS; // TODO: handle init flags properly
+        #[cfg(target_os = "linux")]
+        {
+            // The features in flags2 need the extended init reply
+            if arg.flags & FUSE_INIT_EXT == 0 {
+                conn.unwant2(conn.flags2());
+            }
+        }
+        filesystem.negotiated(&conn, fd);
+        let flags = conn.flags();
+        #[cfg(feature = "abi-7-28")]
+        let max_pages = if conn.is_enabled(FUSE_MAX_PAGES) {
+            conn.max_pages(PAGE_SIZE)
+        } else {
+            0
+        };
+        #[cfg(not(feature = "abi-7-28"))]
+        let max_pages = 0u16;
+        #[cfg(target_os = "linux")]
+        {
+            let flags2 = conn.flags2();
+            if flags2 != 0 {
+                // The features in flags2 need the extended init reply of ABI 7.40,
+                // the kernel accepts a longer init reply than its minor version
+                let max_stack_depth = if conn.is_enabled2(FUSE_PASSTHROUGH) {
+                    passthrough::MAX_STACK_DEPTH
+                } else {
+                    0
+                };
+                let init_out = FuseInitOutExt {
+                    major: FUSE_KERNEL_VERSION,
+                    minor,
+                    max_readahead: conn.max_readahead,
+                    flags: flags | FUSE_INIT_EXT,
+                    max_background: conn.max_background,
+                    congestion_threshold: conn.congestion_threshold,
+                    max_write: conn.max_write,
+                    time_gran: conn.time_gran,
+                    max_pages,
+                    map_alignment: 0,
+                    flags2,
+                    max_stack_depth,
+                    unused: [0; 6],
+                };
+                reply.init_ext(init_out).await?;
+                debug!(
+                    "INIT response: ABI version={}.{}, flags={:#x}, flags2={:#x}, \
+                        max readahead={}, max write={}, max pages={}",
+                    FUSE_KERNEL_VERSION,
+                    minor,
+                    flags | FUSE_INIT_EXT,
+                    flags2,
+                    conn.max_readahead,
+                    conn.max_write,
+                    max_pages,
+                );
+                self.proto_major.store(arg.major, Ordering::Relaxed);
+                self.proto_minor.store(minor, Ordering::Relaxed);
+                FUSE_INITIALIZED.store(true, Ordering::Relaxed);
+                return Ok(conn);
+            }
+        }
         #[cfg(not(feature = "abi-7-13"))]
         let unused = 0u32;
-        #[cfg(feature = "abi-7-13")]
-        let congestion_threshold = 100u16; // TODO: set congestion threshold
-        #[cfg(feature = "abi-7-23")]
-        let time_gran = 1u32; // TODO: set time_gran
         #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
         let unused = [0u32; 9];
         #[cfg(feature = "abi-7-28")]
-        let max_pages = 0u16; // TODO: max_pages = (max_write - 1) / getpagesize() + 1;
-        #[cfg(feature = "abi-7-28")]
         let padding = 0u16;
         #[cfg(feature = "abi-7-28")]
         let unused = [0u32; 8];
@@ -311,18 +667,18 @@ impl Session {
         reply
             .init(
                 FUSE_KERNEL_VERSION,
-                FUSE_KERNEL_MINOR_VERSION, // Do not change minor version, otherwise unknown panic
-                arg.max_readahead,         // accept FUSE kernel module max_readahead
-                flags, // TODO: use features given in INIT_FLAGS and reported as capable
+                minor,
+                conn.max_readahead, // accept FUSE kernel module max_readahead
+                flags,
                 #[cfg(not(feature = "abi-7-13"))]
                 unused,
                 #[cfg(feature = "abi-7-13")]
-                MAX_BACKGROUND,
+                conn.max_background,
                 #[cfg(feature = "abi-7-13")]
-                congestion_threshold,
-                MAX_WRITE_SIZE,
+                conn.congestion_threshold,
+                conn.max_write,
                 #[cfg(feature = "abi-7-23")]
-                time_gran,
+                conn.time_gran,
                 #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
                 unused,
                 #[cfg(feature = "abi-7-28")]
@@ -334,38 +690,346 @@ impl Session {
             )
             .await?;
         debug!(
-            "INIT response: ABI version={}.{}, flags={:#x}, max readahead={}, max write={}",
-            FUSE_KERNEL_VERSION,
-            FUSE_KERNEL_MINOR_VERSION,
-            flags,
-            arg.max_readahead,
-            MAX_WRITE_SIZE,
+            "INIT response: ABI version={}.{}, flags={:#x}, max readahead={}, \
+                max write={}, max pages={}",
+            FUSE_KERNEL_VERSION, minor, flags, conn.max_readahead, conn.max_write, max_pages,
         );
 
-        // Store the kernel FUSE major and minor version
+        // Store the kernel FUSE major version and the selected minor version
         self.proto_major.store(arg.major, Ordering::Relaxed);
-        self.proto_minor.store(arg.minor, Ordering::Relaxed);
+        self.proto_minor.store(minor, Ordering::Relaxed);
 
         FUSE_INITIALIZED.store(true, Ordering::Relaxed);
 
-        Ok(())
+        Ok(conn)
+    }
+}
+
+/// Reply the request whose operation failed to parse, if its header is intact
+async fn reply_malformed_request(bytes: &[u8], fuse_fd: RawFd, e: &anyhow::Error) {
+    let header = match Request::parse_header(bytes) {
+        Ok(header) => header,
+        Err(header_err) => {
+            error!(
+                "failed to parse the header of the malformed request, no reply, \
+                    the error is: {:?}",
+                header_err,
+            );
+            return;
+        }
+    };
+    if !Operation::need_reply(header.opcode) {
+        return;
+    }
+    // ENOSYS for unknown operations, EIO for malformed ones
+    let errno = match e.downcast_ref::<nix::Error>() {
+        Some(nix::Error::Sys(errno)) => *errno,
+        _ => Errno::EIO,
+    };
+    let reply = ReplyEmpty::new(header.unique, fuse_fd);
+    if let Err(reply_err) = reply.error(errno as c_int).await {
+        error!(
+            "failed to send error reply for malformed request, unique={}, the error is: {:?}",
+            header.unique, reply_err,
+        );
+    }
+}
+
+/// The request being processed, counted until dropped
+#[derive(Debug)]
+struct InFlight;
+
+impl InFlight {
+    fn new() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::AcqRel);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reply the errno to the request refused without dispatching
+async fn reply_errno(req: &Request<'_>, fuse_fd: RawFd, errno: c_int) {
+    if !Operation::need_reply(req.opcode()) {
+        return;
+    }
+    let unique = req.unique();
+    let reply = ReplyEmpty::new(unique, fuse_fd);
+    if let Err(reply_err) = reply.error(errno).await {
+        error!(
+            "failed to send error reply for refused request, unique={}, the error is: {:?}",
+            unique, reply_err,
+        );
+    }
+}
+
+/// Shut down the session on the first signal, and force lazy umount on the second one
+async fn handle_signals<FS: Filesystem>(
+    signals: SignalReceiver,
+    mountpoint: PathBuf,
+    fs: Arc<Mutex<FS>>,
+) {
+    let sig = match signals.recv().await {
+        Ok(sig) => sig,
+        Err(e) => {
+            error!("failed to receive signals, the error is: {:?}", e);
+            return;
+        }
+    };
+    info!("received {:?}, shut down the session", sig);
+    SHUTTING_DOWN.store(true, Ordering::Release);
+    let shutdown_mountpoint = mountpoint.clone();
+    Task::spawn(async move {
+        if let Err(e) = shutdown(&shutdown_mountpoint, fs).await {
+            error!(
+                "failed to shut down the session, send the signal again to force lazy umount, \
+                    the error is: {:?}",
+                e,
+            );
+        }
+    })
+    .detach();
+
+    let sig = match signals.recv().await {
+        Ok(sig) => sig,
+        Err(e) => {
+            error!("failed to receive signals, the error is: {:?}", e);
+            return;
+        }
+    };
+    warn!(
+        "received {:?} again, force lazy umount {:?}",
+        sig, mountpoint
+    );
+    if let Err(e) = mount::umount(&mountpoint, true).await {
+        error!(
+            "failed to lazily umount {:?}, the error is: {:?}",
+            mountpoint, e,
+        );
+    }
+    std::process::exit(128 + sig as i32);
+}
+
+/// Wait for the requests in flight to finish at most DRAIN_TIMEOUT,
+/// return the number of the requests still in flight
+async fn wait_in_flight() -> u64 {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    loop {
+        let in_flight = IN_FLIGHT.load(Ordering::Acquire);
+        if in_flight == 0 || Instant::now() >= deadline {
+            return in_flight;
+        }
+        Timer::after(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Read a request from the FUSE device, return None once the upgrade socket
+/// is readable, i.e. a new daemon is connecting to take over
+fn read_request(
+    fuse_fd: RawFd,
+    upgrade_fd: Option<RawFd>,
+    buf: &mut [u8],
+) -> nix::Result<Option<usize>> {
+    if let Some(upgrade_fd) = upgrade_fd {
+        let mut fds = [
+            PollFd::new(fuse_fd, PollFlags::POLLIN),
+            PollFd::new(upgrade_fd, PollFlags::POLLIN),
+        ];
+        poll::poll(&mut fds, -1)?;
+        if fds[1]
+            .revents()
+            .map_or(false, |revents| revents.contains(PollFlags::POLLIN))
+        {
+            return Ok(None);
+        }
+    }
+    unistd::read(fuse_fd, buf).map(Some)
+}
+
+/// Wait for the requests in flight, flush the filesystem and umount
+async fn shutdown<FS: Filesystem>(mountpoint: &Path, fs: Arc<Mutex<FS>>) -> anyhow::Result<()> {
+    let in_flight = wait_in_flight().await;
+    if in_flight > 0 {
+        warn!(
+            "{} requests still in flight after {:?}, umount anyway",
+            in_flight, DRAIN_TIMEOUT,
+        );
+    }
+    fs.lock()
+        .await
+        .sync_all()
+        .await
+        .context("failed to flush the filesystem")?;
+    mount::umount(mountpoint, false)
+        .await
+        .context(format!("failed to umount {:?}", mountpoint))?;
+    info!("successfully umount {:?} on shutdown", mountpoint);
+    Ok(())
+}
+
+/// Build the request from the bytes and dispatch it,
+/// reply error to the kernel if failed to process it.
+/// In hardened mode, the failures are logged and counted without quitting the daemon.
+/// In read-only mode, the operations changing the filesystem are replied EROFS
+async fn process_request<FS: Filesystem>(
+    bytes: &[u8],
+    fuse_fd: RawFd,
+    fs: Arc<Mutex<FS>>,
+    hardened: bool,
+    read_only: bool,
+) {
+    let req = match Request::new(bytes) {
+        // Dispatch request
+        Ok(r) => r,
+        Err(e) => {
+            let count = MALFORMED_REQUESTS.fetch_add(1, Ordering::Relaxed) + 1;
+            error!(
+                "failed to build FUSE request, {} malformed requests so far, \
+                    the error is: {:?}",
+                count, e,
+            );
+            reply_malformed_request(bytes, fuse_fd, &e).await;
+            // TODO: this panic is for fast fail, can be removed when stable
+            if !hardened {
+                panic!("failed to build FUSE request, the error is: {:?}", e);
+            }
+            return;
+        }
+    };
+    debug!("{}", req);
+    // The retrieval waiting for NOTIFY_REPLY may hold the filesystem lock,
+    // so hand the data to it without dispatching
+    #[cfg(feature = "abi-7-15")]
+    {
+        if let Operation::NotifyReply { data, .. } = req.operation() {
+            if !fuse_notify::reply_retrieve(req.unique(), data) {
+                warn!("no retrieval is waiting for the request={}", req);
+            }
+            return; // no reply
+        }
+    }
+    // Count the request before checking shutdown, so the shutdown waits for it
+    let _in_flight = InFlight::new();
+    if SHUTTING_DOWN.load(Ordering::Acquire) && !req.operation().is_releasing() {
+        debug!("reject request={} since the session is shutting down", req);
+        reply_errno(&req, fuse_fd, libc::ESHUTDOWN).await;
+        return;
+    }
+    if read_only && req.operation().is_mutating() {
+        debug!("reject request={} of read-only filesystem", req);
+        reply_errno(&req, fuse_fd, libc::EROFS).await;
+        return;
+    }
+    let res = dispatch(&req, fuse_fd, fs).await;
+    if let Err(e) = res {
+        let count = FAILED_REQUESTS.fetch_add(1, Ordering::Relaxed) + 1;
+        error!(
+            "failed to process request={}, {} failed requests so far, the error is: {:?}",
+            req, count, e,
+        );
+        // No error reply if failed to send the reply, which cannot be replied again
+        if let (Some(errno), true) = (e.errno(), Operation::need_reply(req.opcode())) {
+            let unique = req.unique();
+            let reply_error_to_fuse = ReplyEmpty::new(unique, fuse_fd);
+            if let Err(reply_err) = reply_error_to_fuse.error(errno as c_int).await {
+                error!(
+                    "failed to send error reply for request, unique={}, the error is: {:?}",
+                    unique, reply_err,
+                );
+            }
+        }
+        // TODO: this panic is for fast fail, can be removed when stable
+        if !hardened {
+            panic!("failed to process request, the error is: {:?}", e);
+        }
+    }
+}
+
+/// Receive requests from FUSE io_uring entries and dispatch them
+#[cfg(target_os = "linux")]
+async fn run_uring<FS: Filesystem>(
+    fuse_uring: Arc<FuseUring>,
+    fuse_fd: RawFd,
+    fs: Arc<Mutex<FS>>,
+    pool_sender: crossbeam_channel::Sender<(u16, AlignedBytes)>,
+    pool_receiver: crossbeam_channel::Receiver<(u16, AlignedBytes)>,
+    hardened: bool,
+    read_only: bool,
+) {
+    loop {
+        let ready = match fuse_uring.wait_requests().await {
+            Ok(Some(ready)) => ready,
+            Ok(None) => {
+                info!("FUSE io_uring stopped, quit the io_uring run loop");
+                break;
+            }
+            Err(e) => {
+                error!("FUSE io_uring failed, the error is: {:?}", e);
+                break;
+            }
+        };
+        for entry_idx in ready {
+            let receiver = pool_receiver.clone();
+            let (idx, mut byte_arr) = match blocking!(receiver.recv()) {
+                Ok(buf) => buf,
+                Err(e) => {
+                    error!("failed to get buffer from buffer pool, the error is: {}", e);
+                    return;
+                }
+            };
+            let req_size = match fuse_uring.take_request(entry_idx, &mut byte_arr) {
+                Ok(req_size) => req_size,
+                Err(e) => panic!("failed to take FUSE io_uring request, the error is: {}", e),
+            };
+            debug!("received {} byte data from FUSE io_uring", req_size);
+
+            let fs = fs.clone();
+            let sender = pool_sender.clone();
+            Task::spawn(async move {
+                let bytes = &byte_arr[..req_size];
+                process_request(bytes, fuse_fd, fs, hardened, read_only).await;
+                let res = sender.send((idx, byte_arr));
+                if let Err(e) = res {
+                    panic!(
+                        "failed to put buffer idx={} back to buffer pool, the error is: {}",
+                        idx, e,
+                    );
+                }
+            })
+            .detach();
+        }
     }
 }
 
 /// Dispatch request to the filesystem
 /// This calls the appropriate filesystem operation method for the
 /// request and sends back the returned reply to the kernel
-async fn dispatch<'a>(
+async fn dispatch<'a, FS: Filesystem>(
     req: &'a Request<'a>,
     fd: RawFd,
-    fs: Arc<Mutex<FileSystem>>,
-) -> anyhow::Result<()> {
+    fs: Arc<Mutex<FS>>,
+) -> FsResult<()> {
+    // Lock the directories of the operation before the filesystem, the lookups and the
+    // readdirs sharing the directory locks run in parallel without the filesystem lock
+    let _dir_guards = match req.operation().dir_access(req.nodeid()) {
+        Some((dirs, exclusive)) => DIR_LOCKS.lock(&dirs, exclusive).await,
+        None => Vec::new(),
+    };
     // TODO: consider remove this global lock to filesystem
     let mut filesystem = fs.lock().await;
 
     match req.operation() {
         // Filesystem initialization
-        Operation::Init { .. } => panic!("FUSE should have already initialized"),
+        Operation::Init { .. } => {
+            return Err(FsError::new(
+                Errno::EPROTO,
+                "FUSE should have already initialized".into(),
+            ));
+        }
         // Any operation is invalid before initialization
         _ if !FUSE_INITIALIZED.load(Ordering::Acquire) => {
             warn!("ignoring FUSE operation before init, the request={}", req);
@@ -374,7 +1038,7 @@ async fn dispatch<'a>(
         }
         // Filesystem destroyed
         Operation::Destroy => {
-            filesystem.destroy(&req);
+            filesystem.destroy(&req).await;
             FUSE_DESTROYED.fetch_or(true, Ordering::Release);
             let reply = ReplyEmpty::new(req.unique(), fd);
             reply.ok().await?;
@@ -393,11 +1057,12 @@ async fn dispatch<'a>(
         }
 
         Operation::Lookup { name } => {
+            drop(filesystem);
             let reply = ReplyEntry::new(req.unique(), fd);
-            filesystem.lookup(&req, req.nodeid(), &name, reply).await?;
+            FS::lookup_shared(&fs, &req, req.nodeid(), &name, reply).await?;
         }
         Operation::Forget { arg } => {
-            filesystem.forget(&req, req.nodeid(), arg.nlookup); // no reply
+            filesystem.forget(&req, req.nodeid(), arg.nlookup).await?; // no reply
         }
         Operation::GetAttr => {
             let reply = ReplyAttr::new(req.unique(), fd);
@@ -420,12 +1085,22 @@ async fn dispatch<'a>(
                 0 => None,
                 _ => Some(arg.size),
             };
+            // The times set to UTIME_NOW by utimensat(2) are set to the current time
+            #[cfg(feature = "abi-7-9")]
+            let (atime_now, mtime_now) = (
+                arg.valid & FATTR_ATIME_NOW != 0,
+                arg.valid & FATTR_MTIME_NOW != 0,
+            );
+            #[cfg(not(feature = "abi-7-9"))]
+            let (atime_now, mtime_now) = (false, false);
             let atime = match arg.valid & FATTR_ATIME {
                 0 => None,
+                _ if atime_now => Some(SystemTime::now()),
                 _ => Some(UNIX_EPOCH + Duration::new(arg.atime, arg.atimensec)),
             };
             let mtime = match arg.valid & FATTR_MTIME {
                 0 => None,
+                _ if mtime_now => Some(SystemTime::now()),
                 _ => Some(UNIX_EPOCH + Duration::new(arg.mtime, arg.mtimensec)),
             };
             let fh = match arg.valid & FATTR_FH {
@@ -611,7 +1286,7 @@ async fn dispatch<'a>(
                     flush,
                     reply,
                 )
-                .await;
+                .await?;
         }
         Operation::FSync { arg } => {
             let datasync = match arg.fsync_flags & 1 {
@@ -630,16 +1305,15 @@ async fn dispatch<'a>(
                 .await?;
         }
         Operation::ReadDir { arg } => {
+            drop(filesystem);
             let reply = ReplyDirectory::new(req.unique(), fd, arg.size as usize);
-            filesystem
-                .readdir(&req, req.nodeid(), arg.fh, arg.offset as i64, reply)
-                .await?;
+            FS::readdir_shared(&fs, &req, req.nodeid(), arg.fh, arg.offset as i64, reply).await?;
         }
         Operation::ReleaseDir { arg } => {
             let reply = ReplyEmpty::new(req.unique(), fd);
             filesystem
                 .releasedir(&req, req.nodeid(), arg.fh, arg.flags, reply)
-                .await;
+                .await?;
         }
         Operation::FSyncDir { arg } => {
             let datasync = match arg.fsync_flags & 1 {
@@ -656,7 +1330,16 @@ async fn dispatch<'a>(
             filesystem.statfs(&req, req.nodeid(), reply).await?;
         }
         Operation::SetXAttr { arg, name, value } => {
-            assert!(value.len() == arg.size as usize);
+            if value.len() != arg.size as usize {
+                return Err(FsError::new(
+                    Errno::EINVAL,
+                    format!(
+                        "the value size={} of setxattr mismatches the size={} in the argument",
+                        value.len(),
+                        arg.size,
+                    ),
+                ));
+            }
             #[cfg(target_os = "macos")]
             #[inline]
             fn get_position(arg: &FuseSetXAttrIn) -> u32 {
@@ -767,6 +1450,144 @@ async fn dispatch<'a>(
                 .await?;
         }
 
+        #[cfg(feature = "abi-7-11")]
+        Operation::IoCtl { arg, data } => {
+            let reply = ReplyIoCtl::new(req.unique(), fd);
+            filesystem
+                .ioctl(
+                    &req,
+                    req.nodeid(),
+                    arg.fh,
+                    arg.flags,
+                    arg.cmd,
+                    data,
+                    arg.out_size,
+                    reply,
+                )
+                .await?;
+        }
+        #[cfg(feature = "abi-7-11")]
+        Operation::Poll { arg } => {
+            #[cfg(feature = "abi-7-21")]
+            let events = arg.events;
+            #[cfg(not(feature = "abi-7-21"))]
+            let events = 0;
+            let reply = ReplyPoll::new(req.unique(), fd);
+            filesystem
+                .poll(&req, req.nodeid(), arg.fh, arg.kh, arg.flags, events, reply)
+                .await?;
+        }
+        #[cfg(feature = "abi-7-15")]
+        Operation::NotifyReply { .. } => {
+            // Handled by process_request() before dispatch, no reply
+        }
+        #[cfg(feature = "abi-7-16")]
+        Operation::BatchForget { nodes, .. } => {
+            // forget the rest of the nodes even if failed to forget one of them
+            let mut res = Ok(());
+            for node in nodes.iter() {
+                if let Err(e) = filesystem.forget(&req, node.nodeid, node.nlookup).await {
+                    warn!(
+                        "failed to forget the node of ino={}, the error is: {:?}",
+                        node.nodeid, e,
+                    );
+                    res = Err(e);
+                }
+            }
+            res?; // no reply
+        }
+        #[cfg(feature = "abi-7-19")]
+        Operation::FAllocate { arg } => {
+            let reply = ReplyEmpty::new(req.unique(), fd);
+            filesystem
+                .fallocate(
+                    &req,
+                    req.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.length as i64,
+                    arg.mode,
+                    reply,
+                )
+                .await?;
+        }
+        #[cfg(feature = "abi-7-21")]
+        Operation::ReadDirPlus { arg } => {
+            let reply = ReplyDirectoryPlus::new(req.unique(), fd, arg.size as usize);
+            filesystem
+                .readdirplus(&req, req.nodeid(), arg.fh, arg.offset as i64, reply)
+                .await?;
+        }
+        #[cfg(feature = "abi-7-23")]
+        Operation::Rename2 {
+            arg,
+            oldname,
+            newname,
+        } => {
+            let reply = ReplyEmpty::new(req.unique(), fd);
+            if arg.flags == 0 {
+                filesystem
+                    .rename(&req, req.nodeid(), &oldname, arg.newdir, &newname, reply)
+                    .await?;
+            } else {
+                filesystem
+                    .rename2(
+                        &req,
+                        req.nodeid(),
+                        &oldname,
+                        arg.newdir,
+                        &newname,
+                        arg.flags,
+                        reply,
+                    )
+                    .await?;
+            }
+        }
+        #[cfg(feature = "abi-7-24")]
+        Operation::LSeek { arg } => {
+            let reply = ReplyLSeek::new(req.unique(), fd);
+            filesystem
+                .lseek(
+                    &req,
+                    req.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.whence,
+                    reply,
+                )
+                .await?;
+        }
+        #[cfg(feature = "abi-7-28")]
+        Operation::CopyFileRange { arg } => {
+            let reply = ReplyWrite::new(req.unique(), fd);
+            filesystem
+                .copy_file_range(
+                    &req,
+                    req.nodeid(),
+                    arg.fh_in,
+                    arg.off_in as i64,
+                    arg.nodeid_out,
+                    arg.fh_out,
+                    arg.off_out as i64,
+                    arg.len,
+                    arg.flags,
+                    reply,
+                )
+                .await?;
+        }
+        #[cfg(feature = "abi-7-31")]
+        Operation::SetupMapping { .. } | Operation::RemoveMapping { .. } => {
+            // DAX mapping is only for virtiofs, not for the FUSE device
+            let reply = ReplyEmpty::new(req.unique(), fd);
+            reply.error(libc::ENOSYS).await?;
+        }
+        #[cfg(feature = "abi-7-11")]
+        Operation::CuseInit { .. } => {
+            // CUSE is not supported
+            let reply = ReplyEmpty::new(req.unique(), fd);
+            reply.error(libc::ENOSYS).await?;
+        }
+
         #[cfg(target_os = "macos")]
         Operation::SetVolName { name } => {
             let reply = ReplyEmpty::new(req.unique(), fd);
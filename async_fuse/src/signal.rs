@@ -0,0 +1,86 @@
+//! The signals to shut down the session.
+//!
+//! SIGINT and SIGTERM are caught by a handler writing the signal number to a self-pipe,
+//! which is the only async-signal-safe way to wake the session, then the session reads
+//! the pipe to stop serving and unmount. The first signal shuts down cleanly, the second
+//! one forces a lazy unmount in case the clean shutdown is stuck, e.g. the mount is busy.
+
+use anyhow::{self, Context};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd;
+use smol::blocking;
+use std::convert::TryFrom;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The signals to shut down the session
+const SHUTDOWN_SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGTERM];
+
+/// The write end of the self-pipe, -1 if the handlers are not installed
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Write the signal number to the self-pipe, nothing else is async-signal-safe
+extern "C" fn handle_signal(signo: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signo as u8;
+        // Nothing to do if the pipe is full, the pending signals already wake the session
+        let _ = unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// The receiver of the shutdown signals
+#[derive(Debug)]
+pub struct SignalReceiver {
+    /// The read end of the self-pipe
+    fd: RawFd,
+}
+
+impl SignalReceiver {
+    /// Install the handlers of SIGINT and SIGTERM, which can only be installed once
+    pub fn install() -> anyhow::Result<SignalReceiver> {
+        let (read_fd, write_fd) =
+            unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create the signal pipe")?;
+        if SIGNAL_PIPE
+            .compare_exchange(-1, write_fd, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            let _ = unistd::close(read_fd);
+            let _ = unistd::close(write_fd);
+            return Err(anyhow::anyhow!("the signal handlers are already installed"));
+        }
+        // Restart the interrupted syscalls, the signals are handled by the session
+        let action = SigAction::new(
+            SigHandler::Handler(handle_signal),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for &sig in &SHUTDOWN_SIGNALS {
+            unsafe { signal::sigaction(sig, &action) }
+                .context(format!("failed to install the handler of {:?}", sig))?;
+        }
+        Ok(SignalReceiver { fd: read_fd })
+    }
+
+    /// Wait for the next shutdown signal
+    pub async fn recv(&self) -> anyhow::Result<Signal> {
+        let fd = self.fd;
+        loop {
+            let res = blocking!(
+                let mut buf = [0_u8; 1];
+                unistd::read(fd, &mut buf).map(|size| (size, buf[0]))
+            );
+            match res {
+                Ok((1, signo)) => {
+                    return Signal::try_from(libc::c_int::from(signo))
+                        .context(format!("received unknown signal number={}", signo));
+                }
+                Ok(..) => return Err(anyhow::anyhow!("the signal pipe is closed")),
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("failed to read the signal pipe"),
+            }
+        }
+    }
+}
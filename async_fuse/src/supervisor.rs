@@ -0,0 +1,159 @@
+//! The supervisor restarting the daemon once it crashes.
+//!
+//! The supervisor runs the daemon as a child process with the same arguments and waits
+//! for it. Once the daemon crashes, the mount is left stale, so the supervisor restarts
+//! the daemon after a backoff, which replaces the stale mount at startup. The shutdown
+//! signals are forwarded to the daemon, which is not restarted after shutting down.
+
+use anyhow::{self, Context};
+use log::{error, info, warn};
+use nix::sys::signal;
+use nix::unistd::Pid;
+use smol::{blocking, Task, Timer};
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use super::signal::SignalReceiver;
+
+/// The backoff before the first restart, doubled once the daemon crashes again soon
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The max backoff before restarting the daemon
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// The daemon crashing sooner than this after startup fails to start
+const MIN_UPTIME: Duration = Duration::from_secs(10);
+/// Give up once the daemon fails to start so many times in a row, e.g. bad arguments
+const MAX_START_FAILURES: u32 = 5;
+/// How often to check for the shutdown during the backoff
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The pid of the running daemon, 0 if not running
+static DAEMON_PID: AtomicI32 = AtomicI32::new(0);
+/// Whether the supervisor received a shutdown signal, then the daemon is not restarted
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Run the daemon of `args` by the current executable and restart it once it crashes,
+/// until it quits successfully or shuts down on signals. Return the exit code of the
+/// supervisor
+pub async fn supervise(args: Vec<OsString>) -> anyhow::Result<i32> {
+    let program = std::env::current_exe().context("failed to find the current executable")?;
+    let signals = SignalReceiver::install()?;
+    Task::spawn(forward_signals(signals)).detach();
+
+    let mut backoff = INITIAL_BACKOFF;
+    let mut start_failures = 0;
+    loop {
+        let mut command = Command::new(&program);
+        command.args(&args);
+        // Keep the terminal signals, e.g. Ctrl-C, from reaching the daemon besides the
+        // forwarded ones, otherwise the daemon sees them twice and forces lazy umount
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+        let started = Instant::now();
+        let status = blocking!(
+            let mut child = command.spawn()?;
+            DAEMON_PID.store(child.id() as i32, Ordering::Release);
+            let status = child.wait();
+            DAEMON_PID.store(0, Ordering::Release);
+            status
+        )
+        .context("failed to run the daemon")?;
+
+        if status.success() {
+            info!("the daemon quit successfully");
+            return Ok(0);
+        }
+        if STOPPING.load(Ordering::Acquire) {
+            info!("the daemon shut down with {}", status);
+            return Ok(exit_code(status));
+        }
+        if started.elapsed() < MIN_UPTIME {
+            start_failures += 1;
+            if start_failures >= MAX_START_FAILURES {
+                return Err(anyhow::anyhow!(
+                    "the daemon failed {} times in a row soon after startup, the last {}",
+                    start_failures,
+                    status,
+                ));
+            }
+        } else {
+            start_failures = 0;
+            backoff = INITIAL_BACKOFF;
+        }
+        error!(
+            "the daemon crashed with {}, restart it after {:?}",
+            status, backoff,
+        );
+        let deadline = Instant::now() + backoff;
+        while Instant::now() < deadline {
+            if STOPPING.load(Ordering::Acquire) {
+                info!("shut down the supervisor during the backoff");
+                return Ok(exit_code(status));
+            }
+            Timer::after(STOP_POLL_INTERVAL).await;
+        }
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Forward the shutdown signals to the daemon
+async fn forward_signals(signals: SignalReceiver) {
+    loop {
+        let sig = match signals.recv().await {
+            Ok(sig) => sig,
+            Err(e) => {
+                error!("failed to receive signals, the error is: {:?}", e);
+                return;
+            }
+        };
+        STOPPING.store(true, Ordering::Release);
+        let pid = DAEMON_PID.load(Ordering::Acquire);
+        if pid == 0 {
+            continue;
+        }
+        info!("forward {:?} to the daemon of pid={}", sig, pid);
+        if let Err(e) = signal::kill(Pid::from_raw(pid), sig) {
+            warn!(
+                "failed to forward {:?} to the daemon of pid={}, the error is: {}",
+                sig, pid, e,
+            );
+        }
+    }
+}
+
+/// The exit code of the supervisor for the daemon quitting with `status`,
+/// 128 plus the signal number if the daemon was killed by a signal
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    use super::exit_code;
+
+    #[test]
+    fn test_exit_code() {
+        // the raw wait status of exit(2)
+        assert_eq!(exit_code(ExitStatus::from_raw(2 << 8)), 2);
+        // the raw wait status of being killed by SIGKILL
+        assert_eq!(
+            exit_code(ExitStatus::from_raw(libc::SIGKILL)),
+            128 + libc::SIGKILL
+        );
+    }
+}
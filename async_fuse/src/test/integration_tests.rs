@@ -346,10 +346,11 @@ fn run_test() -> anyhow::Result<()> {
     test_file_manipulation_nix_way(&mount_dir)?;
     test_dir_manipulation_nix_way(&mount_dir)?;
     test_deferred_deletion(&mount_dir)?;
-    // TODO: enable thiese test after implemented rename()
+    test_rename_file(&mount_dir)?;
+    test_rename_dir(&mount_dir)?;
+    // rename() replaces the existing target as POSIX requires,
+    // so no replace is left to renameat2() with RENAME_NOREPLACE
     // test_rename_file_no_replace(&mount_dir)?;
-    // test_rename_file(&mount_dir)?;
-    // test_rename_dir(&mount_dir)?;
 
     test_util::teardown(&mount_dir, th)?;
     Ok(())
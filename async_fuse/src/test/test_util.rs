@@ -5,7 +5,9 @@ use std::path::Path;
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use super::super::{mount, session::Session};
+use super::super::{
+    fs::FileSystem, fs::Revalidation, mount, mount_options::MountOptions, session::Session,
+};
 
 pub const DEFAULT_MOUNT_DIR: &str = "../fuse_test";
 pub const FILE_CONTENT: &str = "0123456789ABCDEF";
@@ -14,7 +16,7 @@ pub fn setup(mount_path: impl AsRef<Path>) -> anyhow::Result<JoinHandle<()>> {
     env_logger::init();
     let mut mount_dir = mount_path.as_ref().to_path_buf();
     mount_dir = smol::block_on(async move {
-        let result = mount::umount(&mount_dir).await;
+        let result = mount::umount(&mount_dir, true).await;
         if result.is_ok() {
             debug!("umounted {:?} before setup", mount_dir);
         }
@@ -29,7 +31,8 @@ pub fn setup(mount_path: impl AsRef<Path>) -> anyhow::Result<JoinHandle<()>> {
 
     let fs_task = Task::spawn(async move {
         async fn run_fs(mount_point: impl AsRef<Path>) -> anyhow::Result<()> {
-            let ss = Session::new(mount_point).await?;
+            let filesystem = FileSystem::new(&mount_point, Revalidation::default()).await?;
+            let ss = Session::new(mount_point, filesystem, &MountOptions::default()).await?;
             ss.run().await?;
             Ok(())
         };
@@ -58,7 +61,7 @@ pub fn teardown(mount_dir: impl AsRef<Path>, th: JoinHandle<()>) -> anyhow::Resu
     thread::sleep(Duration::new(seconds, 0));
 
     smol::block_on(async {
-        mount::umount(&mount_dir).await.unwrap(); // TODO: remove unwrap()
+        mount::umount(&mount_dir, true).await.unwrap(); // TODO: remove unwrap()
     });
     let abs_mount_path = fs::canonicalize(mount_dir)?;
     fs::remove_dir_all(&abs_mount_path)?;
@@ -0,0 +1,430 @@
+//! Live upgrade by handing the FUSE connection over to a new daemon.
+//!
+//! The running daemon listens on a Unix socket for upgrades. Once a new daemon
+//! connects, the running one stops reading the FUSE device, waits for the requests
+//! in flight, then sends the FUSE device fd and the saved filesystem state, along with
+//! the fds the state refers to, by SCM_RIGHTS. The new daemon restores the fds at their
+//! original numbers, since the file handles replied to the kernel may be fds, restores
+//! the filesystem and acknowledges, then the old daemon quits without unmount and the
+//! new one continues serving the same mount. If the handover fails before the
+//! acknowledgement, the old daemon keeps serving.
+
+use anyhow::{self, Context};
+use log::debug;
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg};
+use nix::sys::socket::{self, ControlMessage, MsgFlags};
+use nix::sys::uio::IoVec;
+use nix::unistd;
+use smol::blocking;
+use std::convert::TryInto;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use super::fuse_conn::ConnInfo;
+
+/// The magic number of the handover message, "AFUP"
+const HANDOFF_MAGIC: u32 = 0x4146_5550;
+/// The version of the handover message, bumped once the layout changes
+const HANDOFF_VERSION: u32 = 3;
+/// The max number of fds passed in one message, below SCM_MAX_FD of Linux
+const FDS_PER_MESSAGE: usize = 64;
+/// How long to wait for the other daemon during the handover
+pub(crate) const HANDOFF_TIMEOUT: Duration = Duration::from_secs(30);
+/// The acknowledgement of the new daemon once it takes over
+const HANDOFF_ACK: u8 = 1;
+
+/// The encoder of the handover message
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_i32(&mut self, value: i32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn put_os_str(&mut self, s: &OsStr) {
+        self.put_bytes(s.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// The decoder of the handover message
+#[derive(Debug)]
+pub(crate) struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Decoder { data }
+    }
+
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(anyhow::anyhow!(
+                "the handover message is truncated, need {} bytes but {} left",
+                len,
+                self.data.len(),
+            ));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn get_u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn get_u64(&mut self) -> anyhow::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn get_i32(&mut self) -> anyhow::Result<i32> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn get_i64(&mut self) -> anyhow::Result<i64> {
+        let bytes = self.take(8)?;
+        Ok(i64::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn get_bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.get_u64()?;
+        self.take(len as usize)
+    }
+
+    pub fn get_os_string(&mut self) -> anyhow::Result<OsString> {
+        Ok(OsString::from_vec(self.get_bytes()?.to_vec()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// The filesystem state handed over to the new daemon on live upgrade
+#[derive(Debug, Default)]
+pub struct SavedState {
+    /// The state encoded by the filesystem
+    data: Vec<u8>,
+    /// The fds the state refers to, which keep the same numbers in the new daemon
+    fds: Vec<RawFd>,
+}
+
+impl SavedState {
+    /// The state of `data` referring to `fds`, the fds are passed to the new daemon
+    /// and still owned by the old one
+    pub fn new(data: Vec<u8>, fds: Vec<RawFd>) -> Self {
+        SavedState { data, fds }
+    }
+
+    /// The state encoded by the old daemon
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The fds restored at the numbers of the old daemon, owned by the new daemon
+    pub fn fds(&self) -> &[RawFd] {
+        &self.fds
+    }
+}
+
+/// The FUSE connection and the filesystem state received from the old daemon
+#[derive(Debug)]
+pub struct Handoff {
+    /// The connection to the old daemon, to acknowledge the takeover
+    stream: UnixStream,
+    /// The FUSE device fd of the mount
+    pub(crate) fuse_fd: RawFd,
+    /// FUSE protocol major version negotiated by the old daemon
+    pub(crate) proto_major: u32,
+    /// FUSE protocol minor version negotiated by the old daemon
+    pub(crate) proto_minor: u32,
+    /// The features negotiated by the old daemon
+    pub(crate) conn: ConnInfo,
+    /// The filesystem state
+    pub(crate) state: SavedState,
+}
+
+impl Handoff {
+    /// Connect to the running daemon listening on `socket` and receive its FUSE
+    /// connection and filesystem state. Must be called before opening other files,
+    /// including the ones of the async runtime, so that the fds of the old daemon
+    /// can keep their numbers
+    pub fn receive(socket: impl AsRef<Path>) -> anyhow::Result<Handoff> {
+        let socket = socket.as_ref();
+        let stream = UnixStream::connect(socket).context(format!(
+            "failed to connect to the upgrade socket={:?}",
+            socket
+        ))?;
+        receive_handoff(stream)
+    }
+
+    /// Like `receive()`, but None if no daemon listens on `socket`, e.g. the daemon
+    /// crashed and left the socket file behind
+    pub fn try_receive(socket: impl AsRef<Path>) -> anyhow::Result<Option<Handoff>> {
+        let socket = socket.as_ref();
+        match UnixStream::connect(socket) {
+            Ok(stream) => receive_handoff(stream).map(Some),
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    || e.kind() == io::ErrorKind::ConnectionRefused =>
+            {
+                debug!("no daemon listens on the upgrade socket={:?}", socket);
+                Ok(None)
+            }
+            Err(e) => Err(e).context(format!(
+                "failed to connect to the upgrade socket={:?}",
+                socket
+            )),
+        }
+    }
+
+    /// Tell the old daemon to quit, the new daemon serves the mount since then
+    pub(crate) async fn ack(self) -> anyhow::Result<()> {
+        let mut stream = self.stream;
+        blocking!(stream.write_all(&[HANDOFF_ACK]))
+            .context("failed to acknowledge the old daemon")?;
+        Ok(())
+    }
+}
+
+/// Send the FUSE connection and the filesystem state to the new daemon,
+/// and wait for it to take over
+pub(crate) async fn send_handoff(
+    stream: UnixStream,
+    fuse_fd: RawFd,
+    proto_major: u32,
+    proto_minor: u32,
+    conn: ConnInfo,
+    state: SavedState,
+) -> anyhow::Result<()> {
+    blocking!(
+        let mut stream = stream;
+        stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+        let mut fds = vec![fuse_fd];
+        fds.extend_from_slice(state.fds());
+        let mut enc = Encoder::default();
+        enc.put_u32(HANDOFF_MAGIC);
+        enc.put_u32(HANDOFF_VERSION);
+        enc.put_u32(proto_major);
+        enc.put_u32(proto_minor);
+        conn.encode(&mut enc);
+        enc.put_bytes(state.data());
+        enc.put_u64(fds.len() as u64);
+        for &fd in &fds {
+            enc.put_i32(fd);
+        }
+        let payload = enc.into_bytes();
+        stream.write_all(&(payload.len() as u64).to_le_bytes())?;
+        stream.write_all(&payload)?;
+        for chunk in fds.chunks(FDS_PER_MESSAGE) {
+            let iov = [IoVec::from_slice(&[0_u8])];
+            let cmsgs = [ControlMessage::ScmRights(chunk)];
+            socket::sendmsg(stream.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None)
+                .context("failed to send fds to the new daemon")?;
+        }
+        debug!(
+            "send_handoff() sent {} bytes of state and {} fds",
+            payload.len(),
+            fds.len(),
+        );
+        let mut ack = [0_u8; 1];
+        stream
+            .read_exact(&mut ack)
+            .context("the new daemon failed to take over")?;
+        if ack[0] != HANDOFF_ACK {
+            return Err(anyhow::anyhow!("unexpected acknowledgement={}", ack[0]));
+        }
+        Ok(())
+    )
+}
+
+fn receive_handoff(mut stream: UnixStream) -> anyhow::Result<Handoff> {
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    let mut len = [0_u8; 8];
+    stream.read_exact(&mut len)?;
+    let mut payload = vec![0_u8; u64::from_le_bytes(len) as usize];
+    stream.read_exact(&mut payload)?;
+    let mut dec = Decoder::new(&payload);
+    let magic = dec.get_u32()?;
+    let version = dec.get_u32()?;
+    if magic != HANDOFF_MAGIC || version != HANDOFF_VERSION {
+        return Err(anyhow::anyhow!(
+            "unsupported handover message of magic={:#x} and version={}",
+            magic,
+            version,
+        ));
+    }
+    let proto_major = dec.get_u32()?;
+    let proto_minor = dec.get_u32()?;
+    let conn = ConnInfo::decode(&mut dec)?;
+    let data = dec.get_bytes()?.to_vec();
+    let fd_count = dec.get_u64()? as usize;
+    let mut old_fds = Vec::with_capacity(fd_count);
+    for _ in 0..fd_count {
+        old_fds.push(dec.get_i32()?);
+    }
+
+    let mut fds = Vec::with_capacity(fd_count);
+    while fds.len() < fd_count {
+        let received =
+            recv_fds(stream.as_raw_fd()).context("failed to receive fds from the old daemon")?;
+        match received {
+            Some(received) => fds.extend(received),
+            None => return Err(anyhow::anyhow!("the old daemon closed the connection")),
+        }
+    }
+    if fds.len() != fd_count {
+        return Err(anyhow::anyhow!(
+            "received {} fds from the old daemon, but {} expected",
+            fds.len(),
+            fd_count,
+        ));
+    }
+    let stream = restore_fds(stream, &fds, &old_fds)?;
+    debug!(
+        "receive_handoff() received {} bytes of state and {} fds",
+        payload.len(),
+        fd_count,
+    );
+    Ok(Handoff {
+        stream,
+        fuse_fd: old_fds[0],
+        proto_major,
+        proto_minor,
+        conn,
+        state: SavedState::new(data, old_fds[1..].to_vec()),
+    })
+}
+
+/// Receive one message of fds, None if the connection is closed.
+/// Not `socket::recvmsg()`, which dereferences a null pointer to parse the peer address
+fn recv_fds(sock: RawFd) -> nix::Result<Option<Vec<RawFd>>> {
+    let mut buf = [0_u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let fds_size = (FDS_PER_MESSAGE * mem::size_of::<RawFd>()) as libc::c_uint;
+    let mut cmsg_buf = vec![0_u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+    let mut mhdr: libc::msghdr = unsafe { mem::zeroed() };
+    mhdr.msg_iov = &mut iov;
+    mhdr.msg_iovlen = 1;
+    mhdr.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+    mhdr.msg_controllen = cmsg_buf.len();
+    let size = Errno::result(unsafe { libc::recvmsg(sock, &mut mhdr, libc::MSG_CMSG_CLOEXEC) })?;
+    if size == 0 {
+        return Ok(None);
+    }
+    let mut fds = Vec::new();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&mhdr) };
+    while let Some(hdr) = unsafe { cmsg.as_ref() } {
+        if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const RawFd;
+            let data_len = hdr.cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize;
+            for idx in 0..data_len / mem::size_of::<RawFd>() {
+                fds.push(unsafe { data.add(idx).read_unaligned() });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&mhdr, cmsg) };
+    }
+    Ok(Some(fds))
+}
+
+/// Move the received fds to their numbers in the old daemon. The received fds and the
+/// connection may take those numbers, so move them out of the way first
+fn restore_fds(stream: UnixStream, fds: &[RawFd], old_fds: &[RawFd]) -> anyhow::Result<UnixStream> {
+    let stream_fd = stream.into_raw_fd();
+    let base = fds
+        .iter()
+        .chain(old_fds)
+        .chain(std::iter::once(&stream_fd))
+        .max()
+        .map_or(0, |fd| fd + 1);
+    let move_away = |fd: RawFd| -> nix::Result<RawFd> {
+        let new_fd = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(base))?;
+        unistd::close(fd)?;
+        Ok(new_fd)
+    };
+    let stream = unsafe { UnixStream::from_raw_fd(move_away(stream_fd)?) };
+    let mut moved = Vec::with_capacity(fds.len());
+    for &fd in fds {
+        moved.push(move_away(fd)?);
+    }
+    for (&fd, &old_fd) in moved.iter().zip(old_fds) {
+        match fcntl::fcntl(old_fd, FcntlArg::F_GETFD) {
+            Err(nix::Error::Sys(Errno::EBADF)) => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "the fd={} of the old daemon is already taken",
+                    old_fd,
+                ))
+            }
+        }
+        unistd::dup2(fd, old_fd)?;
+        unistd::close(fd)?;
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::{Decoder, Encoder};
+
+    #[test]
+    fn test_encode_decode() -> anyhow::Result<()> {
+        let mut enc = Encoder::default();
+        enc.put_u32(7);
+        enc.put_u64(u64::max_value());
+        enc.put_i32(-1);
+        enc.put_i64(-42);
+        enc.put_os_str(OsStr::new("foo.txt"));
+        let bytes = enc.into_bytes();
+
+        let mut dec = Decoder::new(&bytes);
+        assert_eq!(dec.get_u32()?, 7);
+        assert_eq!(dec.get_u64()?, u64::max_value());
+        assert_eq!(dec.get_i32()?, -1);
+        assert_eq!(dec.get_i64()?, -42);
+        assert_eq!(dec.get_os_string()?, "foo.txt");
+        assert!(dec.is_empty());
+        assert!(dec.get_u32().is_err());
+        Ok(())
+    }
+}
@@ -15,6 +15,7 @@
 //! - supports ABI 7.18 since FUSE 2.9.0
 //! - supports ABI 7.19 since FUSE 2.9.1
 //! - supports ABI 7.26 since FUSE 3.0.0
+//! - supports ABI 7.31 since FUSE 3.10.0
 //!
 //! Items without a version annotation are valid with ABI 7.8 and later
 
@@ -47,8 +48,32 @@ pub const FUSE_KERNEL_MINOR_VERSION: u32 = 16;
 pub const FUSE_KERNEL_MINOR_VERSION: u32 = 17;
 #[cfg(all(feature = "abi-7-18", not(feature = "abi-7-19")))]
 pub const FUSE_KERNEL_MINOR_VERSION: u32 = 18;
-#[cfg(feature = "abi-7-19")]
+#[cfg(all(feature = "abi-7-19", not(feature = "abi-7-20")))]
 pub const FUSE_KERNEL_MINOR_VERSION: u32 = 19;
+#[cfg(all(feature = "abi-7-20", not(feature = "abi-7-21")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 20;
+#[cfg(all(feature = "abi-7-21", not(feature = "abi-7-22")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 21;
+#[cfg(all(feature = "abi-7-22", not(feature = "abi-7-23")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 22;
+#[cfg(all(feature = "abi-7-23", not(feature = "abi-7-24")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 23;
+#[cfg(all(feature = "abi-7-24", not(feature = "abi-7-25")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 24;
+#[cfg(all(feature = "abi-7-25", not(feature = "abi-7-26")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 25;
+#[cfg(all(feature = "abi-7-26", not(feature = "abi-7-27")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 26;
+#[cfg(all(feature = "abi-7-27", not(feature = "abi-7-28")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 27;
+#[cfg(all(feature = "abi-7-28", not(feature = "abi-7-29")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 28;
+#[cfg(all(feature = "abi-7-29", not(feature = "abi-7-30")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 29;
+#[cfg(all(feature = "abi-7-30", not(feature = "abi-7-31")))]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 30;
+#[cfg(feature = "abi-7-31")]
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
 
 pub const FUSE_ROOT_ID: u64 = 1;
 
@@ -121,6 +146,8 @@ pub mod consts {
     pub const FATTR_MTIME_NOW: u32 = 1 << 8;
     #[cfg(feature = "abi-7-9")]
     pub const FATTR_LOCKOWNER: u32 = 1 << 9;
+    #[cfg(feature = "abi-7-23")]
+    pub const FATTR_CTIME: u32 = 1 << 10;
 
     #[cfg(target_os = "macos")]
     pub const FATTR_CRTIME: u32 = 1 << 28;
@@ -136,6 +163,10 @@ pub mod consts {
     pub const FOPEN_KEEP_CACHE: u32 = 1 << 1; // don't invalidate the data cache on open
     #[cfg(feature = "abi-7-10")]
     pub const FOPEN_NONSEEKABLE: u32 = 1 << 2; // the file is not seekable
+    #[cfg(feature = "abi-7-28")]
+    pub const FOPEN_CACHE_DIR: u32 = 1 << 3; // allow caching this directory
+    #[cfg(feature = "abi-7-31")]
+    pub const FOPEN_STREAM: u32 = 1 << 4; // the file is stream-like (no file position at all)
 
     #[cfg(target_os = "macos")]
     pub const FOPEN_PURGE_ATTR: u32 = 1 << 30;
@@ -167,6 +198,36 @@ pub mod consts {
     pub const FUSE_FLOCK_LOCKS: u32 = 1 << 10; // remote locking for BSD style file locks
     #[cfg(feature = "abi-7-18")]
     pub const FUSE_HAS_IOCTL_DIR: u32 = 1 << 11; // kernel supports ioctl on directories
+    #[cfg(feature = "abi-7-20")]
+    pub const FUSE_AUTO_INVAL_DATA: u32 = 1 << 12; // automatically invalidate cached pages
+    #[cfg(feature = "abi-7-21")]
+    pub const FUSE_DO_READDIRPLUS: u32 = 1 << 13; // do READDIRPLUS (READDIR+LOOKUP in one)
+    #[cfg(feature = "abi-7-21")]
+    pub const FUSE_READDIRPLUS_AUTO: u32 = 1 << 14; // adaptive readdirplus
+    #[cfg(feature = "abi-7-22")]
+    pub const FUSE_ASYNC_DIO: u32 = 1 << 15; // asynchronous direct I/O submission
+    #[cfg(feature = "abi-7-23")]
+    pub const FUSE_WRITEBACK_CACHE: u32 = 1 << 16; // use writeback cache for buffered writes
+    #[cfg(feature = "abi-7-23")]
+    pub const FUSE_NO_OPEN_SUPPORT: u32 = 1 << 17; // kernel supports zero-message opens
+    #[cfg(feature = "abi-7-25")]
+    pub const FUSE_PARALLEL_DIROPS: u32 = 1 << 18; // allow parallel lookups and readdir
+    #[cfg(feature = "abi-7-26")]
+    pub const FUSE_HANDLE_KILLPRIV: u32 = 1 << 19; // filesystem resets suid/sgid/cap on write/chown/trunc
+    #[cfg(feature = "abi-7-26")]
+    pub const FUSE_POSIX_ACL: u32 = 1 << 20; // filesystem supports posix acls
+    #[cfg(feature = "abi-7-27")]
+    pub const FUSE_ABORT_ERROR: u32 = 1 << 21; // reading the device after abort returns ECONNABORTED
+    #[cfg(feature = "abi-7-28")]
+    pub const FUSE_MAX_PAGES: u32 = 1 << 22; // init_out.max_pages contains the max number of req pages
+    #[cfg(feature = "abi-7-28")]
+    pub const FUSE_CACHE_SYMLINKS: u32 = 1 << 23; // cache READLINK responses
+    #[cfg(feature = "abi-7-29")]
+    pub const FUSE_NO_OPENDIR_SUPPORT: u32 = 1 << 24; // kernel supports zero-message opendir
+    #[cfg(feature = "abi-7-30")]
+    pub const FUSE_EXPLICIT_INVAL_DATA: u32 = 1 << 25; // only invalidate cached pages on explicit request
+    #[cfg(feature = "abi-7-31")]
+    pub const FUSE_MAP_ALIGNMENT: u32 = 1 << 26; // init_out.map_alignment contains log2(byte alignment)
 
     #[cfg(target_os = "macos")]
     pub const FUSE_ALLOCATE: u32 = 1 << 27;
@@ -201,6 +262,8 @@ pub mod consts {
     pub const FUSE_WRITE_CACHE: u32 = 1 << 0; // delayed write from page cache, file handle is guessed
     #[cfg(feature = "abi-7-9")]
     pub const FUSE_WRITE_LOCKOWNER: u32 = 1 << 1; // lock_owner field is valid
+    #[cfg(feature = "abi-7-31")]
+    pub const FUSE_WRITE_KILL_PRIV: u32 = 1 << 2; // kill suid and sgid bits
 
     // Read flags
     #[cfg(feature = "abi-7-9")]
@@ -224,6 +287,15 @@ pub mod consts {
     #[cfg(feature = "abi-7-9")]
     pub const FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1 << 0; // request poll notify
 
+    // Setup mapping flags
+    #[cfg(feature = "abi-7-31")]
+    pub const FUSE_SETUPMAPPING_FLAG_WRITE: u64 = 1 << 0;
+    #[cfg(feature = "abi-7-31")]
+    pub const FUSE_SETUPMAPPING_FLAG_READ: u64 = 1 << 1;
+
+    // The size of the init reply before ABI 7.23
+    pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24;
+
     // The read buffer is required to be at least 8k, but may be much larger
     pub const FUSE_MIN_READ_BUFFER: usize = 8192;
 }
@@ -282,6 +354,18 @@ pub enum fuse_opcode {
     FUSE_BATCH_FORGET = 42,
     #[cfg(feature = "abi-7-19")]
     FUSE_FALLOCATE = 43,
+    #[cfg(feature = "abi-7-21")]
+    FUSE_READDIRPLUS = 44,
+    #[cfg(feature = "abi-7-23")]
+    FUSE_RENAME2 = 45,
+    #[cfg(feature = "abi-7-24")]
+    FUSE_LSEEK = 46,
+    #[cfg(feature = "abi-7-28")]
+    FUSE_COPY_FILE_RANGE = 47,
+    #[cfg(feature = "abi-7-31")]
+    FUSE_SETUPMAPPING = 48,
+    #[cfg(feature = "abi-7-31")]
+    FUSE_REMOVEMAPPING = 49,
 
     #[cfg(target_os = "macos")]
     FUSE_SETVOLNAME = 61,
@@ -345,6 +429,18 @@ impl TryFrom<u32> for fuse_opcode {
             42 => Ok(fuse_opcode::FUSE_BATCH_FORGET),
             #[cfg(feature = "abi-7-19")]
             43 => Ok(fuse_opcode::FUSE_FALLOCATE),
+            #[cfg(feature = "abi-7-21")]
+            44 => Ok(fuse_opcode::FUSE_READDIRPLUS),
+            #[cfg(feature = "abi-7-23")]
+            45 => Ok(fuse_opcode::FUSE_RENAME2),
+            #[cfg(feature = "abi-7-24")]
+            46 => Ok(fuse_opcode::FUSE_LSEEK),
+            #[cfg(feature = "abi-7-28")]
+            47 => Ok(fuse_opcode::FUSE_COPY_FILE_RANGE),
+            #[cfg(feature = "abi-7-31")]
+            48 => Ok(fuse_opcode::FUSE_SETUPMAPPING),
+            #[cfg(feature = "abi-7-31")]
+            49 => Ok(fuse_opcode::FUSE_REMOVEMAPPING),
 
             #[cfg(target_os = "macos")]
             61 => Ok(fuse_opcode::FUSE_SETVOLNAME),
@@ -507,6 +603,15 @@ pub struct fuse_exchange_in {
     pub options: u64,
 }
 
+#[cfg(feature = "abi-7-23")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_rename2_in {
+    pub newdir: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_link_in {
@@ -526,10 +631,16 @@ pub struct fuse_setattr_in {
     pub lock_owner: u64,
     pub atime: u64,
     pub mtime: u64,
+    #[cfg(not(feature = "abi-7-23"))]
     pub unused2: u64,
+    #[cfg(feature = "abi-7-23")]
+    pub ctime: u64,
     pub atimensec: u32,
     pub mtimensec: u32,
+    #[cfg(not(feature = "abi-7-23"))]
     pub unused3: u32,
+    #[cfg(feature = "abi-7-23")]
+    pub ctimensec: u32,
     pub mode: u32,
     pub unused4: u32,
     pub uid: u32,
@@ -724,6 +835,18 @@ pub struct fuse_init_out {
     #[cfg(feature = "abi-7-13")]
     pub congestion_threshold: u16,
     pub max_write: u32,
+    #[cfg(feature = "abi-7-23")]
+    pub time_gran: u32,
+    #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+    pub unused: [u32; 9],
+    #[cfg(feature = "abi-7-28")]
+    pub max_pages: u16,
+    #[cfg(all(feature = "abi-7-28", not(feature = "abi-7-31")))]
+    pub padding: u16,
+    #[cfg(feature = "abi-7-31")]
+    pub map_alignment: u16,
+    #[cfg(feature = "abi-7-28")]
+    pub unused: [u32; 8],
 }
 
 #[cfg(feature = "abi-7-12")]
@@ -808,7 +931,10 @@ pub struct fuse_poll_in {
     pub fh: u64,
     pub kh: u64,
     pub flags: u32,
+    #[cfg(not(feature = "abi-7-21"))]
     pub padding: u32,
+    #[cfg(feature = "abi-7-21")]
+    pub events: u32,
 }
 
 #[cfg(feature = "abi-7-11")]
@@ -830,11 +956,11 @@ pub struct fuse_notify_poll_wakeup_out {
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_fallocate_in {
-    fh: u64,
-    offset: u64,
-    length: u64,
-    mode: u32,
-    padding: u32,
+    pub fh: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub mode: u32,
+    pub padding: u32,
 }
 
 #[repr(C)]
@@ -868,6 +994,14 @@ pub struct fuse_dirent {
     // followed by name of namelen bytes
 }
 
+#[cfg(feature = "abi-7-21")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_direntplus {
+    pub entry_out: fuse_entry_out,
+    pub dirent: fuse_dirent,
+}
+
 #[cfg(feature = "abi-7-12")]
 #[repr(C)]
 #[derive(Debug)]
@@ -890,10 +1024,10 @@ pub struct fuse_notify_inval_entry_out {
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_delete_out {
-    parent: u64,
-    child: u64,
-    namelen: u32,
-    padding: u32,
+    pub parent: u64,
+    pub child: u64,
+    pub namelen: u32,
+    pub padding: u32,
 }
 
 #[cfg(feature = "abi-7-15")]
@@ -929,3 +1063,59 @@ pub struct fuse_notify_retrieve_in {
     pub dummy3: u64,
     pub dummy4: u64,
 }
+
+#[cfg(feature = "abi-7-24")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_lseek_in {
+    pub fh: u64,
+    pub offset: u64,
+    pub whence: u32,
+    pub padding: u32,
+}
+
+#[cfg(feature = "abi-7-24")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_lseek_out {
+    pub offset: u64,
+}
+
+#[cfg(feature = "abi-7-28")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_copy_file_range_in {
+    pub fh_in: u64,
+    pub off_in: u64,
+    pub nodeid_out: u64,
+    pub fh_out: u64,
+    pub off_out: u64,
+    pub len: u64,
+    pub flags: u64,
+}
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_setupmapping_in {
+    pub fh: u64,      // an already open handle
+    pub foffset: u64, // offset into the file to start the mapping
+    pub len: u64,     // length of mapping required
+    pub flags: u64,   // flags, FUSE_SETUPMAPPING_FLAG_*
+    pub moffset: u64, // offset in memory window
+}
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_removemapping_in {
+    pub count: u32, // number of fuse_removemapping_one follows
+}
+
+#[cfg(feature = "abi-7-31")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_removemapping_one {
+    pub moffset: u64, // offset into the dax window start the unmapping
+    pub len: u64,     // length of mapping required
+}
@@ -48,6 +48,19 @@ impl<'a> ArgumentIterator<'a> {
         (bytes.as_ptr() as *const T).as_ref()
     }
 
+    /// Fetch a slice of typed arguments of the given count. Returns `None` if there's not enough
+    /// data left. This function is unsafe because there is no guarantee that the data actually
+    /// contains the type T.
+    #[allow(dead_code)]
+    pub unsafe fn fetch_slice<T>(&mut self, count: usize) -> Option<&'a [T]> {
+        let len = mem::size_of::<T>().checked_mul(count)?;
+        let bytes = self.fetch_bytes(len)?;
+        Some(std::slice::from_raw_parts(
+            bytes.as_ptr() as *const T,
+            count,
+        ))
+    }
+
     /// Fetch a (zero-terminated) string (can be non-utf8). Returns `None` if there's not enough
     /// data left or no zero-termination could be found. This function is unsafe because there is
     /// no guarantee that the data actually contains a string.
@@ -158,28 +158,55 @@ pub enum Operation<'a> {
         arg: &'a fuse_bmap_in,
     },
     Destroy,
-    // TODO: FUSE_IOCTL since ABI 7.11
-    // IoCtl {
-    //     arg: &'a fuse_ioctl_in,
-    //     data: &'a [u8],
-    // },
-    // TODO: FUSE_POLL since ABI 7.11
-    // Poll {
-    //     arg: &'a fuse_poll_in,
-    // },
-    // TODO: FUSE_NOTIFY_REPLY since ABI 7.15
-    // NotifyReply {
-    //     data: &'a [u8],
-    // },
-    // TODO: FUSE_BATCH_FORGET since ABI 7.16
-    // BatchForget {
-    //     arg: &'a fuse_forget_in,
-    //     nodes: &'a [fuse_forget_one],
-    // },
-    // TODO: FUSE_FALLOCATE since ABI 7.19
-    // FAllocate {
-    //     arg: &'a fuse_fallocate_in,
-    // },
+    #[cfg(feature = "abi-7-11")]
+    IoCtl {
+        arg: &'a fuse_ioctl_in,
+        data: &'a [u8],
+    },
+    #[cfg(feature = "abi-7-11")]
+    Poll {
+        arg: &'a fuse_poll_in,
+    },
+    #[cfg(feature = "abi-7-15")]
+    NotifyReply {
+        data: &'a [u8],
+    },
+    #[cfg(feature = "abi-7-16")]
+    BatchForget {
+        arg: &'a fuse_batch_forget_in,
+        nodes: &'a [fuse_forget_one],
+    },
+    #[cfg(feature = "abi-7-19")]
+    FAllocate {
+        arg: &'a fuse_fallocate_in,
+    },
+    #[cfg(feature = "abi-7-21")]
+    ReadDirPlus {
+        arg: &'a fuse_read_in,
+    },
+    #[cfg(feature = "abi-7-23")]
+    Rename2 {
+        arg: &'a fuse_rename2_in,
+        name: &'a OsStr,
+        newname: &'a OsStr,
+    },
+    #[cfg(feature = "abi-7-24")]
+    LSeek {
+        arg: &'a fuse_lseek_in,
+    },
+    #[cfg(feature = "abi-7-28")]
+    CopyFileRange {
+        arg: &'a fuse_copy_file_range_in,
+    },
+    #[cfg(feature = "abi-7-31")]
+    SetupMapping {
+        arg: &'a fuse_setupmapping_in,
+    },
+    #[cfg(feature = "abi-7-31")]
+    RemoveMapping {
+        arg: &'a fuse_removemapping_in,
+        mappings: &'a [fuse_removemapping_one],
+    },
     #[cfg(target_os = "macos")]
     SetVolName {
         name: &'a OsStr,
@@ -192,10 +219,10 @@ pub enum Operation<'a> {
         oldname: &'a OsStr,
         newname: &'a OsStr,
     },
-    // TODO: CUSE_INIT since ABI 7.12
-    // CuseInit {
-    //     arg: &'a fuse_init_in,
-    // },
+    #[cfg(feature = "abi-7-12")]
+    CuseInit {
+        arg: &'a cuse_init_in,
+    },
 }
 
 impl<'a> fmt::Display for Operation<'a> {
@@ -237,6 +264,28 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::Interrupt { arg } => write!(f, "INTERRUPT unique {}", arg.unique),
             Operation::BMap { arg } => write!(f, "BMAP blocksize {}, ids {}", arg.blocksize, arg.block),
             Operation::Destroy => write!(f, "DESTROY"),
+            #[cfg(feature = "abi-7-11")]
+            Operation::IoCtl { arg, data } => write!(f, "IOCTL fh {}, flags {:#x}, cmd {}, arg {:#x}, in size {}, out size {}, data size {}", arg.fh, arg.flags, arg.cmd, arg.arg, arg.in_size, arg.out_size, data.len()),
+            #[cfg(feature = "abi-7-11")]
+            Operation::Poll { arg } => write!(f, "POLL fh {}, kh {}, flags {:#x}", arg.fh, arg.kh, arg.flags),
+            #[cfg(feature = "abi-7-15")]
+            Operation::NotifyReply { data } => write!(f, "NOTIFY REPLY data size {}", data.len()),
+            #[cfg(feature = "abi-7-16")]
+            Operation::BatchForget { arg, nodes } => write!(f, "BATCH FORGET count {}, nodes {:?}", arg.count, nodes),
+            #[cfg(feature = "abi-7-19")]
+            Operation::FAllocate { arg } => write!(f, "FALLOCATE fh {}, offset {}, length {}, mode {:#05o}", arg.fh, arg.offset, arg.length, arg.mode),
+            #[cfg(feature = "abi-7-21")]
+            Operation::ReadDirPlus { arg } => write!(f, "READDIRPLUS fh {}, offset {}, size {}", arg.fh, arg.offset, arg.size),
+            #[cfg(feature = "abi-7-23")]
+            Operation::Rename2 { arg, name, newname } => write!(f, "RENAME2 name {:?}, newdir {:#018x}, newname {:?}, flags {:#x}", name, arg.newdir, newname, arg.flags),
+            #[cfg(feature = "abi-7-24")]
+            Operation::LSeek { arg } => write!(f, "LSEEK fh {}, offset {}, whence {}", arg.fh, arg.offset, arg.whence),
+            #[cfg(feature = "abi-7-28")]
+            Operation::CopyFileRange { arg } => write!(f, "COPY_FILE_RANGE fh_in {}, off_in {}, nodeid_out {:#018x}, fh_out {}, off_out {}, len {}, flags {:#x}", arg.fh_in, arg.off_in, arg.nodeid_out, arg.fh_out, arg.off_out, arg.len, arg.flags),
+            #[cfg(feature = "abi-7-31")]
+            Operation::SetupMapping { arg } => write!(f, "SETUPMAPPING fh {}, foffset {}, len {}, flags {:#x}, moffset {}", arg.fh, arg.foffset, arg.len, arg.flags, arg.moffset),
+            #[cfg(feature = "abi-7-31")]
+            Operation::RemoveMapping { arg, .. } => write!(f, "REMOVEMAPPING count {}", arg.count),
 
             #[cfg(target_os = "macos")]
             Operation::SetVolName { name } => write!(f, "SETVOLNAME name {:?}", name),
@@ -244,6 +293,9 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::GetXTimes => write!(f, "GETXTIMES"),
             #[cfg(target_os = "macos")]
             Operation::Exchange { arg, oldname, newname } => write!(f, "EXCHANGE olddir {:#018x}, oldname {:?}, newdir {:#018x}, newname {:?}, options {:#x}", arg.olddir, oldname, arg.newdir, newname, arg.options),
+
+            #[cfg(feature = "abi-7-12")]
+            Operation::CuseInit { arg } => write!(f, "CUSE_INIT kernel ABI {}.{}, flags {:#x}", arg.major, arg.minor, arg.flags),
         }
     }
 }
@@ -325,6 +377,51 @@ impl<'a> Operation<'a> {
                 fuse_opcode::FUSE_INTERRUPT => Operation::Interrupt { arg: data.fetch()? },
                 fuse_opcode::FUSE_BMAP => Operation::BMap { arg: data.fetch()? },
                 fuse_opcode::FUSE_DESTROY => Operation::Destroy,
+                #[cfg(feature = "abi-7-11")]
+                fuse_opcode::FUSE_IOCTL => Operation::IoCtl {
+                    arg: data.fetch()?,
+                    data: data.fetch_all(),
+                },
+                #[cfg(feature = "abi-7-11")]
+                fuse_opcode::FUSE_POLL => Operation::Poll { arg: data.fetch()? },
+                #[cfg(feature = "abi-7-15")]
+                fuse_opcode::FUSE_NOTIFY_REPLY => Operation::NotifyReply {
+                    data: data.fetch_all(),
+                },
+                #[cfg(feature = "abi-7-16")]
+                fuse_opcode::FUSE_BATCH_FORGET => {
+                    let arg: &fuse_batch_forget_in = data.fetch()?;
+                    Operation::BatchForget {
+                        arg,
+                        nodes: data.fetch_slice(arg.count as usize)?,
+                    }
+                }
+                #[cfg(feature = "abi-7-19")]
+                fuse_opcode::FUSE_FALLOCATE => Operation::FAllocate { arg: data.fetch()? },
+                #[cfg(feature = "abi-7-21")]
+                fuse_opcode::FUSE_READDIRPLUS => Operation::ReadDirPlus { arg: data.fetch()? },
+                #[cfg(feature = "abi-7-23")]
+                fuse_opcode::FUSE_RENAME2 => Operation::Rename2 {
+                    arg: data.fetch()?,
+                    name: data.fetch_str()?,
+                    newname: data.fetch_str()?,
+                },
+                #[cfg(feature = "abi-7-24")]
+                fuse_opcode::FUSE_LSEEK => Operation::LSeek { arg: data.fetch()? },
+                #[cfg(feature = "abi-7-28")]
+                fuse_opcode::FUSE_COPY_FILE_RANGE => {
+                    Operation::CopyFileRange { arg: data.fetch()? }
+                }
+                #[cfg(feature = "abi-7-31")]
+                fuse_opcode::FUSE_SETUPMAPPING => Operation::SetupMapping { arg: data.fetch()? },
+                #[cfg(feature = "abi-7-31")]
+                fuse_opcode::FUSE_REMOVEMAPPING => {
+                    let arg: &fuse_removemapping_in = data.fetch()?;
+                    Operation::RemoveMapping {
+                        arg,
+                        mappings: data.fetch_slice(arg.count as usize)?,
+                    }
+                }
 
                 #[cfg(target_os = "macos")]
                 fuse_opcode::FUSE_SETVOLNAME => Operation::SetVolName {
@@ -338,6 +435,9 @@ impl<'a> Operation<'a> {
                     oldname: data.fetch_str()?,
                     newname: data.fetch_str()?,
                 },
+
+                #[cfg(feature = "abi-7-12")]
+                fuse_opcode::CUSE_INIT => Operation::CuseInit { arg: data.fetch()? },
             })
         }
     }
@@ -17,6 +17,10 @@ use std::time::SystemTime;
 pub use abi::consts;
 pub use abi::FUSE_ROOT_ID;
 pub use channel::unmount;
+#[cfg(feature = "abi-7-21")]
+pub use reply::ReplyDirectoryPlus;
+#[cfg(feature = "abi-7-24")]
+pub use reply::ReplyLseek;
 #[cfg(target_os = "macos")]
 pub use reply::ReplyXTimes;
 pub use reply::ReplyXattr;
@@ -24,6 +28,8 @@ pub use reply::{
     Reply, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
     ReplyLock, ReplyOpen, ReplyStatfs, ReplyStatfsParam, ReplyWrite,
 };
+#[cfg(feature = "abi-7-11")]
+pub use reply::{ReplyIoctl, ReplyPoll};
 pub use request::Request;
 pub use session::Session;
 // pub use session::{Session, BackgroundSession};
@@ -527,6 +533,116 @@ pub trait Filesystem {
         reply.error(ENOSYS);
     }
 
+    /// control device
+    #[cfg(feature = "abi-7-11")]
+    fn ioctl(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Poll for IO readiness events.
+    #[cfg(feature = "abi-7-11")]
+    fn poll(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _kh: u64,
+        _flags: u32,
+        _events: u32,
+        reply: ReplyPoll,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Preallocate or deallocate space to a file.
+    #[cfg(feature = "abi-7-19")]
+    fn fallocate(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: u32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Read directory with the attributes of the entries.
+    /// If the filesystem replies ENOSYS, the kernel falls back to readdir
+    #[cfg(feature = "abi-7-21")]
+    fn readdirplus(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Rename a file with the flags RENAME_EXCHANGE or RENAME_NOREPLACE.
+    /// The rename without flags is sent to rename()
+    #[cfg(feature = "abi-7-23")]
+    fn rename2(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Find the next data or hole after the specified offset.
+    /// If the filesystem replies ENOSYS, the kernel handles lseek by itself
+    #[cfg(feature = "abi-7-24")]
+    fn lseek(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _whence: u32,
+        reply: ReplyLseek,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Copy a range of data from one file to another.
+    /// If the filesystem replies ENOSYS, the kernel falls back to read and write
+    #[cfg(feature = "abi-7-28")]
+    fn copy_file_range(
+        &mut self,
+        _req: &Request<'_>,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u64,
+        reply: ReplyWrite,
+    ) {
+        reply.error(ENOSYS);
+    }
+
     /// macOS only: Rename the volume. Set fuse_init_out.flags during init to
     /// FUSE_VOL_RENAME to enable
     #[cfg(target_os = "macos")]
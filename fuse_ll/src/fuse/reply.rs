@@ -18,13 +18,19 @@ use std::os::unix::ffi::OsStrExt;
 use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 use std::{mem, ptr, slice};
 
+#[cfg(feature = "abi-7-21")]
+use super::abi::fuse_direntplus;
 #[cfg(target_os = "macos")]
 use super::abi::fuse_getxtimes_out;
+#[cfg(feature = "abi-7-24")]
+use super::abi::fuse_lseek_out;
 use super::abi::{
     fuse_attr, fuse_attr_out, fuse_bmap_out, fuse_dirent, fuse_entry_out, fuse_file_lock,
     fuse_getxattr_out, fuse_kstatfs, fuse_lk_out, fuse_open_out, fuse_out_header, fuse_statfs_out,
     fuse_write_out,
 };
+#[cfg(feature = "abi-7-11")]
+use super::abi::{fuse_ioctl_out, fuse_poll_out};
 use super::{FileAttr, FileType};
 
 /// Generic reply callback to send data
@@ -107,6 +113,10 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         gid: attr.gid,
         rdev: attr.rdev,
         flags: attr.flags,
+        #[cfg(feature = "abi-7-9")]
+        blksize: 0,
+        #[cfg(feature = "abi-7-9")]
+        padding: 0,
     }
 }
 
@@ -133,6 +143,10 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         uid: attr.uid,
         gid: attr.gid,
         rdev: attr.rdev,
+        #[cfg(feature = "abi-7-9")]
+        blksize: 0,
+        #[cfg(feature = "abi-7-9")]
+        padding: 0,
     }
 }
 
@@ -187,6 +201,16 @@ impl<T> ReplyRaw<T> {
         })
     }
 
+    /// Reply to a request with the first `size` bytes of the given type, for
+    /// the kernel of lower ABI version expecting a shorter reply
+    #[allow(dead_code)]
+    pub fn ok_prefix(mut self, data: &T, size: usize) {
+        as_bytes(data, |bytes| {
+            let bytes: Vec<&[u8]> = bytes.iter().map(|b| &b[..size.min(b.len())]).collect();
+            self.send(0, &bytes);
+        })
+    }
+
     /// Reply to a request with the given error code
     pub fn error(mut self, err: c_int) {
         self.send(err, &[]);
@@ -658,6 +682,190 @@ impl ReplyDirectory {
     }
 }
 
+///
+/// Directory plus reply
+///
+#[cfg(feature = "abi-7-21")]
+#[derive(Debug)]
+pub struct ReplyDirectoryPlus {
+    reply: ReplyRaw<()>,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "abi-7-21")]
+impl ReplyDirectoryPlus {
+    /// Creates a new ReplyDirectoryPlus with a specified buffer size.
+    pub fn new<S: ReplySender>(unique: u64, sender: S, size: usize) -> ReplyDirectoryPlus {
+        ReplyDirectoryPlus {
+            reply: Reply::new(unique, sender),
+            data: Vec::with_capacity(size),
+        }
+    }
+
+    /// Add an entry with its attributes to the directory reply buffer. Returns true if the
+    /// buffer is full. A transparent offset value can be provided for each entry. The kernel
+    /// uses these value to request the next entries in further readdirplus calls
+    pub fn add<T: AsRef<OsStr>>(
+        &mut self,
+        offset: i64,
+        name: T,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+    ) -> bool {
+        let name = name.as_ref().as_bytes();
+        let entlen = mem::size_of::<fuse_direntplus>() + name.len();
+        let entsize = (entlen + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1); // 64bit align
+        let padlen = entsize - entlen;
+        if self.data.len() + entsize > self.data.capacity() {
+            return true;
+        }
+        let direntplus = fuse_direntplus {
+            entry_out: fuse_entry_out {
+                nodeid: attr.ino,
+                generation,
+                entry_valid: ttl.as_secs(),
+                attr_valid: ttl.as_secs(),
+                entry_valid_nsec: ttl.subsec_nanos(),
+                attr_valid_nsec: ttl.subsec_nanos(),
+                attr: fuse_attr_from_attr(attr),
+            },
+            dirent: fuse_dirent {
+                ino: attr.ino,
+                off: offset as u64,
+                namelen: name.len() as u32,
+                typ: mode_from_kind_and_perm(attr.kind, 0) >> 12,
+            },
+        };
+        as_bytes(&direntplus, |bytes| {
+            for b in bytes {
+                self.data.extend_from_slice(b);
+            }
+        });
+        self.data.extend_from_slice(name);
+        self.data.resize(self.data.len() + padlen, 0u8);
+        false
+    }
+
+    /// Reply to a request with the filled directory buffer
+    pub fn ok(mut self) {
+        self.reply.send(0, &[&self.data]);
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Ioctl reply
+///
+#[cfg(feature = "abi-7-11")]
+#[derive(Debug)]
+pub struct ReplyIoctl {
+    reply: ReplyRaw<fuse_ioctl_out>,
+}
+
+#[cfg(feature = "abi-7-11")]
+impl Reply for ReplyIoctl {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyIoctl {
+        ReplyIoctl {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+#[cfg(feature = "abi-7-11")]
+impl ReplyIoctl {
+    /// Reply to a request with the ioctl result and the output data
+    pub fn ioctl(mut self, result: i32, data: &[u8]) {
+        let out = fuse_ioctl_out {
+            result,
+            flags: 0,
+            in_iovs: 0,
+            out_iovs: 0,
+        };
+        as_bytes(&out, |bytes| {
+            let mut sendbytes = bytes.to_vec();
+            sendbytes.push(data);
+            self.reply.send(0, &sendbytes);
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Poll reply
+///
+#[cfg(feature = "abi-7-11")]
+#[derive(Debug)]
+pub struct ReplyPoll {
+    reply: ReplyRaw<fuse_poll_out>,
+}
+
+#[cfg(feature = "abi-7-11")]
+impl Reply for ReplyPoll {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyPoll {
+        ReplyPoll {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+#[cfg(feature = "abi-7-11")]
+impl ReplyPoll {
+    /// Reply to a request with the ready events
+    pub fn poll(self, revents: u32) {
+        self.reply.ok(&fuse_poll_out {
+            revents,
+            padding: 0,
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Lseek reply
+///
+#[cfg(feature = "abi-7-24")]
+#[derive(Debug)]
+pub struct ReplyLseek {
+    reply: ReplyRaw<fuse_lseek_out>,
+}
+
+#[cfg(feature = "abi-7-24")]
+impl Reply for ReplyLseek {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyLseek {
+        ReplyLseek {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+#[cfg(feature = "abi-7-24")]
+impl ReplyLseek {
+    /// Reply to a request with the new file offset
+    pub fn offset(self, offset: i64) {
+        self.reply.ok(&fuse_lseek_out {
+            offset: offset as u64,
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
 ///
 /// Xattr reply
 ///
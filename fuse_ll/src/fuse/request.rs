@@ -15,6 +15,8 @@ use super::abi::consts::*;
 use super::abi::*;
 use super::channel::ChannelSender;
 use super::ll_request;
+#[cfg(feature = "abi-7-21")]
+use super::reply::ReplyDirectoryPlus;
 use super::reply::{Reply, ReplyDirectory, ReplyEmpty, ReplyRaw};
 use super::session::{Session, BUFFER_SIZE, MAX_WRITE_SIZE};
 use super::Filesystem;
@@ -73,9 +75,11 @@ impl<'a> Request<'a> {
                     reply.error(EPROTO);
                     return;
                 }
-                // Remember ABI version supported by kernel
+                // Reply with the lower minor version of the kernel and ours,
+                // remember the selected ABI version
+                let minor = arg.minor.min(FUSE_KERNEL_MINOR_VERSION);
                 se.proto_major = arg.major;
-                se.proto_minor = arg.minor;
+                se.proto_minor = minor;
                 // Call filesystem init method and give it a chance to return an error
                 let res = se.filesystem.init(self);
                 if let Err(err) = res {
@@ -87,7 +91,7 @@ impl<'a> Request<'a> {
                 // supports only lower major versions, we replied with an error above.
                 let init = fuse_init_out {
                     major: FUSE_KERNEL_VERSION,
-                    minor: FUSE_KERNEL_MINOR_VERSION,
+                    minor,
                     // max_readahead: arg.max_readahead, // accept any readahead size
                     max_readahead: if (BUFFER_SIZE as u32) < arg.max_readahead {
                         BUFFER_SIZE as u32
@@ -95,15 +99,37 @@ impl<'a> Request<'a> {
                         arg.max_readahead
                     }, // TODO: adjust BUFFER_SIZE according to max_readahead
                     flags: arg.flags & INIT_FLAGS, // use features given in INIT_FLAGS and reported as capable
+                    #[cfg(not(feature = "abi-7-13"))]
                     unused: 0,
+                    #[cfg(feature = "abi-7-13")]
+                    max_background: 0,
+                    #[cfg(feature = "abi-7-13")]
+                    congestion_threshold: 0,
                     max_write: MAX_WRITE_SIZE as u32, // TODO: use a max write size that fits into the session's buffer
+                    #[cfg(feature = "abi-7-23")]
+                    time_gran: 1,
+                    #[cfg(all(feature = "abi-7-23", not(feature = "abi-7-28")))]
+                    unused: [0; 9],
+                    #[cfg(feature = "abi-7-28")]
+                    max_pages: 0,
+                    #[cfg(all(feature = "abi-7-28", not(feature = "abi-7-31")))]
+                    padding: 0,
+                    #[cfg(feature = "abi-7-31")]
+                    map_alignment: 0,
+                    #[cfg(feature = "abi-7-28")]
+                    unused: [0; 8],
                 };
                 debug!(
                     "INIT response: ABI {}.{}, flags {:#x}, max readahead {}, max write {}",
                     init.major, init.minor, init.flags, init.max_readahead, init.max_write
                 );
                 se.initialized = true;
-                reply.ok(&init);
+                // The kernel before ABI 7.23 rejects the init reply longer than 24 bytes
+                if minor < 23 {
+                    reply.ok_prefix(&init, FUSE_COMPAT_22_INIT_OUT_SIZE);
+                } else {
+                    reply.ok(&init);
+                }
             }
             // Any operation is invalid before initialization
             _ if !se.initialized => {
@@ -156,12 +182,22 @@ impl<'a> Request<'a> {
                     0 => None,
                     _ => Some(arg.size),
                 };
+                // The times set to UTIME_NOW by utimensat(2) are set to the current time
+                #[cfg(feature = "abi-7-9")]
+                let (atime_now, mtime_now) = (
+                    arg.valid & FATTR_ATIME_NOW != 0,
+                    arg.valid & FATTR_MTIME_NOW != 0,
+                );
+                #[cfg(not(feature = "abi-7-9"))]
+                let (atime_now, mtime_now) = (false, false);
                 let atime = match arg.valid & FATTR_ATIME {
                     0 => None,
+                    _ if atime_now => Some(SystemTime::now()),
                     _ => Some(UNIX_EPOCH + Duration::new(arg.atime, arg.atimensec)),
                 };
                 let mtime = match arg.valid & FATTR_MTIME {
                     0 => None,
+                    _ if mtime_now => Some(SystemTime::now()),
                     _ => Some(UNIX_EPOCH + Duration::new(arg.mtime, arg.mtimensec)),
                 };
                 let fh = match arg.valid & FATTR_FH {
@@ -516,6 +552,128 @@ impl<'a> Request<'a> {
                     self.reply(),
                 );
             }
+
+            #[cfg(feature = "abi-7-11")]
+            ll_request::Operation::IoCtl { arg, data } => {
+                se.filesystem.ioctl(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.flags,
+                    arg.cmd,
+                    data,
+                    arg.out_size,
+                    self.reply(),
+                );
+            }
+            #[cfg(feature = "abi-7-11")]
+            ll_request::Operation::Poll { arg } => {
+                #[cfg(feature = "abi-7-21")]
+                let events = arg.events;
+                #[cfg(not(feature = "abi-7-21"))]
+                let events = 0;
+                se.filesystem.poll(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.kh,
+                    arg.flags,
+                    events,
+                    self.reply(),
+                );
+            }
+            #[cfg(feature = "abi-7-15")]
+            ll_request::Operation::NotifyReply { .. } => {
+                // TODO: handle the reply to retrieve notification, no reply
+            }
+            #[cfg(feature = "abi-7-16")]
+            ll_request::Operation::BatchForget { nodes, .. } => {
+                for node in nodes.iter() {
+                    se.filesystem.forget(self, node.nodeid, node.nlookup); // no reply
+                }
+            }
+            #[cfg(feature = "abi-7-19")]
+            ll_request::Operation::FAllocate { arg } => {
+                se.filesystem.fallocate(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.length as i64,
+                    arg.mode,
+                    self.reply(),
+                );
+            }
+            #[cfg(feature = "abi-7-21")]
+            ll_request::Operation::ReadDirPlus { arg } => {
+                se.filesystem.readdirplus(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    ReplyDirectoryPlus::new(self.request.unique(), self.ch, arg.size as usize),
+                );
+            }
+            #[cfg(feature = "abi-7-23")]
+            ll_request::Operation::Rename2 { arg, name, newname } => {
+                if arg.flags == 0 {
+                    se.filesystem.rename(
+                        self,
+                        self.request.nodeid(),
+                        &name,
+                        arg.newdir,
+                        &newname,
+                        self.reply(),
+                    );
+                } else {
+                    se.filesystem.rename2(
+                        self,
+                        self.request.nodeid(),
+                        &name,
+                        arg.newdir,
+                        &newname,
+                        arg.flags,
+                        self.reply(),
+                    );
+                }
+            }
+            #[cfg(feature = "abi-7-24")]
+            ll_request::Operation::LSeek { arg } => {
+                se.filesystem.lseek(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.whence,
+                    self.reply(),
+                );
+            }
+            #[cfg(feature = "abi-7-28")]
+            ll_request::Operation::CopyFileRange { arg } => {
+                se.filesystem.copy_file_range(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh_in,
+                    arg.off_in as i64,
+                    arg.nodeid_out,
+                    arg.fh_out,
+                    arg.off_out as i64,
+                    arg.len,
+                    arg.flags,
+                    self.reply(),
+                );
+            }
+            #[cfg(feature = "abi-7-31")]
+            ll_request::Operation::SetupMapping { .. }
+            | ll_request::Operation::RemoveMapping { .. } => {
+                // DAX mapping is only for virtiofs, not for the FUSE device
+                self.reply::<ReplyEmpty>().error(ENOSYS);
+            }
+            #[cfg(feature = "abi-7-12")]
+            ll_request::Operation::CuseInit { .. } => {
+                // CUSE is not supported
+                self.reply::<ReplyEmpty>().error(ENOSYS);
+            }
         }
     }
 
@@ -1,6 +1,7 @@
 use log::debug;
 use std::ffi::OsStr;
 use std::path::Path;
+use std::time::Duration;
 
 use clap::{App, Arg};
 
@@ -24,6 +25,14 @@ fn main() {
                 .validator(fuse::options_validator)
                 .number_of_values(1),
         )
+        .arg(
+            Arg::with_name("ttl")
+                .long("ttl")
+                .value_name("SECONDS")
+                .help("How long the kernel caches the attributes and the entries")
+                .takes_value(true)
+                .validator(ttl_validator),
+        )
         .get_matches();
 
     let mountpoint = OsStr::new(matches.value_of("mountpoint").unwrap()); // safe to use unwrap() here, because mountpoint is required
@@ -34,11 +43,26 @@ fn main() {
     debug!("{:?}", &options);
     // TODO: add check function for mutual exclusive options
 
-    let fs = MemoryFilesystem::new(&mountpoint);
+    let mut fs = MemoryFilesystem::new(&mountpoint);
+    if let Some(ttl) = matches.value_of("ttl") {
+        // safe to use unwrap() here, because ttl is validated
+        fs.set_ttl(Duration::from_secs_f64(ttl.parse().unwrap()));
+    }
     fuse::mount(fs, Path::new(&mountpoint), &options)
         .unwrap_or_else(|_| panic!("Couldn't mount filesystem {:?}", mountpoint));
 }
 
+/// The TTL should be non-negative seconds
+fn ttl_validator(ttl: String) -> Result<(), String> {
+    match ttl.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(()),
+        _ => Err(format!(
+            "Invalid TTL \"{}\", should be non-negative seconds",
+            ttl
+        )),
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
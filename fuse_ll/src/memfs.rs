@@ -23,7 +23,8 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::{self, AtomicI64};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const MY_TTL_SEC: u64 = 1; // TODO: should be a long value, say 1 hour
+/// The default TTL of the attributes and the entries replied to the kernel
+const MY_TTL_SEC: u64 = 1;
 const MY_GENERATION: u64 = 1;
 // const MY_DIR_MODE: u16 = 0o755;
 // const MY_FILE_MODE: u16 = 0o644;
@@ -169,6 +170,43 @@ mod util {
         };
         Ok(attr)
     }
+
+    /// Change the owner and the group of the file of `fd`, None to keep
+    pub fn fchown(fd: RawFd, uid: Option<u32>, gid: Option<u32>) -> Result<(), nix::Error> {
+        // -1 leaves the id unchanged
+        let uid = uid.map_or(libc::uid_t::max_value(), |uid| uid as libc::uid_t);
+        let gid = gid.map_or(libc::gid_t::max_value(), |gid| gid as libc::gid_t);
+        let res = unsafe { libc::fchown(fd, uid, gid) };
+        nix::errno::Errno::result(res).map(drop)
+    }
+
+    /// Change the access and the modification times of the file of `fd`, None to keep
+    pub fn futimens(
+        fd: RawFd,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<(), nix::Error> {
+        fn timespec(time: Option<SystemTime>) -> Result<libc::timespec, nix::Error> {
+            match time {
+                Some(time) => {
+                    let duration = time
+                        .duration_since(UNIX_EPOCH)
+                        .map_err(|_| nix::Error::Sys(nix::errno::Errno::EINVAL))?;
+                    Ok(libc::timespec {
+                        tv_sec: duration.as_secs() as libc::time_t,
+                        tv_nsec: duration.subsec_nanos().into(),
+                    })
+                }
+                None => Ok(libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                }),
+            }
+        }
+        let times = [timespec(atime)?, timespec(mtime)?];
+        let res = unsafe { libc::futimens(fd, times.as_ptr()) };
+        nix::errno::Errno::result(res).map(drop)
+    }
 }
 
 #[derive(Debug)]
@@ -557,11 +595,15 @@ impl INode {
         );
     }
 
-    fn helper_reload_attribute(&self) -> FileAttr {
-        let raw_fd = match self {
+    fn get_fd(&self) -> RawFd {
+        match self {
             INode::DIR(dir_node) => dir_node.dir_fd.borrow().as_raw_fd(),
             INode::FILE(file_node) => file_node.fd,
-        };
+        }
+    }
+
+    fn helper_reload_attribute(&self) -> FileAttr {
+        let raw_fd = self.get_fd();
         let attr = util::read_attr(raw_fd).unwrap_or_else(|_| {
             panic!(
                 "helper_reload_attribute() failed to get the attribute of the node ino={}",
@@ -865,6 +907,42 @@ impl INode {
         written_size
     }
 
+    // apply the attribute changes to the file on disk, None to keep
+    fn set_attr_on_disk(
+        &mut self,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<(), nix::Error> {
+        let fd = self.get_fd();
+        if let Some(size) = size {
+            let file_node = match self {
+                INode::DIR(_) => return Err(nix::Error::Sys(nix::errno::Errno::EISDIR)),
+                INode::FILE(file_node) => file_node,
+            };
+            unistd::ftruncate(fd, size as libc::off_t)?;
+            // the loaded data is truncated or extended with zeros,
+            // the unloaded is left to load
+            let file_data = file_node.data.get_mut();
+            if !file_data.is_empty() || file_node.attr.get_mut().size == 0 {
+                file_data.resize(size as usize, 0);
+            }
+        }
+        if uid.is_some() || gid.is_some() {
+            util::fchown(fd, uid, gid)?;
+        }
+        if let Some(b) = mode {
+            stat::fchmod(fd, util::parse_mode(b))?;
+        }
+        if atime.is_some() || mtime.is_some() {
+            util::futimens(fd, atime, mtime)?;
+        }
+        Ok(())
+    }
+
     fn helper_move_file(
         old_parent_inode: &INode,
         old_name: &OsStr,
@@ -895,6 +973,8 @@ pub struct MemoryFilesystem {
     // max_ino: AtomicU64,
     cache: BTreeMap<u64, INode>,
     trash: BTreeSet<u64>,
+    /// The TTL of the attributes and the entries replied to the kernel
+    ttl: Duration,
 }
 
 impl MemoryFilesystem {
@@ -954,8 +1034,7 @@ impl MemoryFilesystem {
         let new_attr = new_inode.get_attr();
         self.cache.insert(new_ino, new_inode);
 
-        let ttl = Duration::new(MY_TTL_SEC, 0);
-        reply.entry(&ttl, &new_attr, MY_GENERATION);
+        reply.entry(&self.ttl, &new_attr, MY_GENERATION);
         debug!(
             "helper_create_node() successfully created the new child name={:?}
                 of ino={} under parent ino={}",
@@ -1113,7 +1192,17 @@ impl MemoryFilesystem {
         cache.insert(FUSE_ROOT_ID, root_inode);
         let trash = BTreeSet::new(); // for deferred deletion
 
-        MemoryFilesystem { cache, trash }
+        MemoryFilesystem {
+            cache,
+            trash,
+            ttl: Duration::new(MY_TTL_SEC, 0),
+        }
+    }
+
+    /// Set the TTL of the attributes and the entries replied to the kernel,
+    /// zero to always ask the filesystem
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
     }
 }
 
@@ -1137,8 +1226,7 @@ impl Filesystem for MemoryFilesystem {
             "getattr() cache hit when searching the attribute of ino={}",
             ino,
         );
-        let ttl = Duration::new(MY_TTL_SEC, 0);
-        reply.attr(&ttl, &attr);
+        reply.attr(&self.ttl, &attr);
         debug!(
             "getattr() successfully got the attribute of ino={}, the attr is: {:?}",
             ino, &attr,
@@ -1385,8 +1473,8 @@ impl Filesystem for MemoryFilesystem {
             }
         }
 
+        let ttl = self.ttl;
         let lookup_helper = |attr: &FileAttr| {
-            let ttl = Duration::new(MY_TTL_SEC, 0);
             reply.entry(&ttl, &attr, MY_GENERATION);
             debug!(
                 "lookup() successfully found the file name={:?} of ino={}
@@ -1521,52 +1609,25 @@ impl Filesystem for MemoryFilesystem {
             req.request,
         );
 
-        let setattr_helper = |attr: &mut FileAttr| {
-            let ttl = Duration::new(MY_TTL_SEC, 0);
-            let ts = SystemTime::now();
-
-            if let Some(b) = mode {
-                attr.perm = util::parse_mode_bits(b);
-                debug!("setattr set permission as: {}", attr.perm);
-
-                let sflag = util::parse_sflag(b);
-                let kind = util::convert_sflag(sflag);
-                debug_assert_eq!(kind, attr.kind);
-            }
-            // no replace
-            attr.uid = uid.unwrap_or(attr.uid);
-            attr.gid = gid.unwrap_or(attr.gid);
-            attr.size = size.unwrap_or(attr.size);
-            attr.atime = atime.unwrap_or(attr.atime);
-            attr.mtime = mtime.unwrap_or(attr.mtime);
-            attr.crtime = crtime.unwrap_or(attr.crtime);
-            attr.flags = flags.unwrap_or(attr.flags);
-
-            if mode.is_some()
-                || uid.is_some()
-                || gid.is_some()
-                || size.is_some()
-                || atime.is_some()
-                || mtime.is_some()
-                || crtime.is_some()
-                || chgtime.is_some()
-                || bkuptime.is_some()
-                || flags.is_some()
-            {
-                attr.ctime = ts; // update ctime, since meta data might change in setattr
-                reply.attr(&ttl, attr);
-                debug!(
-                    "setattr successfully set the attribute of ino={}, the set attr is {:?}",
-                    ino, attr,
-                );
-            } else {
-                reply.error(ENODATA);
-                error!(
-                    "setattr found all the input attributes are empty for the file of ino={}",
-                    ino,
-                );
-            }
-        };
+        let ttl = self.ttl;
+        if mode.is_none()
+            && uid.is_none()
+            && gid.is_none()
+            && size.is_none()
+            && atime.is_none()
+            && mtime.is_none()
+            && crtime.is_none()
+            && chgtime.is_none()
+            && bkuptime.is_none()
+            && flags.is_none()
+        {
+            reply.error(ENODATA);
+            error!(
+                "setattr found all the input attributes are empty for the file of ino={}",
+                ino,
+            );
+            return;
+        }
 
         let inode = self.cache.get_mut(&ino).unwrap_or_else(|| {
             panic!(
@@ -1574,8 +1635,35 @@ impl Filesystem for MemoryFilesystem {
                 ino
             )
         });
-        inode.set_attr(setattr_helper);
-        // TODO: write attribute to disk
+        if let Some(b) = mode {
+            let kind = util::convert_sflag(util::parse_sflag(b));
+            debug_assert_eq!(kind, inode.get_attr().kind);
+        }
+        // apply the changes to the file on disk, then reload the attribute from it,
+        // so that the cached attribute never diverges from the one on disk
+        if let Err(e) = inode.set_attr_on_disk(mode, uid, gid, size, atime, mtime) {
+            let errno = match e {
+                nix::Error::Sys(errno) => errno as c_int,
+                _ => EINVAL,
+            };
+            reply.error(errno);
+            error!(
+                "setattr() failed to set the attribute of ino={}, the error is: {:?}",
+                ino, e,
+            );
+            return;
+        }
+        let mut new_attr = inode.helper_reload_attribute();
+        new_attr.ino = ino; // the root ino is not the one on disk
+                            // the creation time and the flags of macOS are kept in cache only
+        new_attr.crtime = crtime.unwrap_or(new_attr.crtime);
+        new_attr.flags = flags.unwrap_or(new_attr.flags);
+        inode.set_attr(|attr| *attr = new_attr);
+        reply.attr(&ttl, &new_attr);
+        debug!(
+            "setattr successfully set the attribute of ino={}, the set attr is {:?}",
+            ino, new_attr,
+        );
     }
 
     fn mknod(
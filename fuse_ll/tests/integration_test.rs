@@ -1,13 +1,15 @@
 use log::info; // debug, error, warn
 use nix::dir::Dir;
 use nix::fcntl::{self, OFlag};
-use nix::sys::stat::Mode;
+use nix::sys::stat::{self, Mode, UtimensatFlags};
+use nix::sys::time::{TimeSpec, TimeValLike};
 use nix::unistd::{self, Whence};
 use std::collections::HashSet;
 use std::env;
 use std::ffi::OsString;
 use std::fs;
 use std::iter;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 mod test_util;
@@ -117,6 +119,39 @@ fn test_deferred_deletion(mount_dir: &Path) {
     assert!(!file_path.exists());
 }
 
+fn test_setattr(mount_dir: &Path) {
+    info!("set attribute");
+    let file_path = Path::new(&mount_dir).join("setattr.txt");
+    fs::write(&file_path, FILE_CONTENT).unwrap();
+
+    fs::set_permissions(&file_path, fs::Permissions::from_mode(0o600)).unwrap();
+    let mode = fs::metadata(&file_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o600);
+
+    // the cached data is truncated and extended along with the file on disk
+    let file = fs::OpenOptions::new().write(true).open(&file_path).unwrap();
+    file.set_len(4).unwrap();
+    file.set_len(8).unwrap();
+    drop(file);
+    assert_eq!(fs::read(&file_path).unwrap(), b"0123\0\0\0\0");
+
+    let atime = TimeSpec::seconds(1_000_000);
+    let mtime = TimeSpec::seconds(2_000_000);
+    stat::utimensat(
+        None,
+        &file_path,
+        &atime,
+        &mtime,
+        UtimensatFlags::FollowSymlink,
+    )
+    .unwrap();
+    let st = stat::stat(&file_path).unwrap();
+    assert_eq!(st.st_atime, 1_000_000);
+    assert_eq!(st.st_mtime, 2_000_000);
+
+    fs::remove_file(&file_path).unwrap();
+}
+
 fn test_rename_file(mount_dir: &Path) {
     info!("rename file");
     let from_dir = Path::new(&mount_dir).join("from_dir");
@@ -236,6 +271,7 @@ fn run_test() {
     test_file_manipulation_nix_way(&mount_dir);
     test_dir_manipulation_nix_way(&mount_dir);
     test_deferred_deletion(&mount_dir);
+    test_setattr(&mount_dir);
     test_rename_file_no_replace(&mount_dir);
     test_rename_file(&mount_dir);
     test_rename_dir(&mount_dir);